@@ -1,6 +1,8 @@
 use libp2p_handshake_lib::{
+    cid::Cid,
     error::{self, Error},
-    net::Manager,
+    identity::PrivateKey,
+    net::{multiaddr_to_tcpaddr, Bitswap, Identify, Manager, Ping, PreSharedKey},
 };
 use std::{env, fs::File, io::Write};
 
@@ -12,7 +14,19 @@ async fn main() -> Result<(), Error> {
         Ok(addr) => addr,
         Err(_) => return Err(error::invalid_input("missing env HANDSHAKE_TARGET_ADDR")),
     };
-    let manager = Manager::from_key_path_and_addr("../ed25519.pem", &addr)?;
+    let socket_addr = multiaddr_to_tcpaddr(&addr.parse().map_err(|_| error::parse_error())?)?;
+    let private_key = PrivateKey::from_ed25519_pem_file("../ed25519.pem")?;
+    let identify = Identify::new(private_key.clone());
+    let bitswap = Bitswap::new();
+    let mut manager = Manager::new(private_key, socket_addr)
+        .with_protocol(identify.clone())
+        .with_protocol(bitswap.clone())
+        .with_protocol(Ping::new());
+
+    // join a private network if a swarm key is given
+    if let Ok(path) = env::var("HANDSHAKE_SWARM_KEY") {
+        manager = manager.with_pre_shared_key(PreSharedKey::from_swarm_key_file(path)?);
+    }
 
     // save peer id for log filtering
     let mut file = File::create("../peerid").unwrap();
@@ -20,8 +34,33 @@ async fn main() -> Result<(), Error> {
 
     // connect to target node
     log::info!("connecting to {}", addr);
-    manager.tcp_connect().await?;
-    log::info!("connection ended");
+    let connection = manager.connect(socket_addr).await?;
+
+    // the remote answering identify confirms it recognises us
+    let info = identify.identify(&connection).await?;
+    log::info!(
+        "remote {:?} runs {:?}, supports {:?}",
+        connection.info().peer_id,
+        info.agent_version,
+        info.protocols
+    );
+    log::info!("observed address {:?}", info.observed_addr);
+
+    // fetch a block from the remote if a cid is given
+    if let Ok(cid) = env::var("HANDSHAKE_FETCH_CID") {
+        let cid: Cid = cid.parse()?;
+        bitswap.add_peer(&connection).await?;
+        let block = bitswap.get(&cid).await?;
+        log::info!("fetched {} of {} bytes", cid, block.len());
+    }
+
+    // hold the connection as long as the remote answers pings
+    let ping = Ping::new();
+    let res = ping.run(&connection).await;
+    log::info!(
+        "connection ended, last rtt {:?}",
+        ping.rtt(&connection.info().peer_id)
+    );
 
-    Ok(())
+    res
 }
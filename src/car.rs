@@ -0,0 +1,253 @@
+use crate::{
+    cbor::{self, Value},
+    cid::Cid,
+    error::{self, Error},
+    io::{uvarint_decode, uvarint_encode},
+    net::Blockstore,
+};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::{collections::BTreeMap, sync::Arc};
+
+// the fixed header of version 2, followed by the characteristics and the
+// data and index positions
+const CARV2_PRAGMA: [u8; 11] = [
+    0x0a, 0xa1, 0x67, 0x76, 0x65, 0x72, 0x73, 0x69, 0x6f, 0x6e, 0x02,
+];
+const CARV2_HEADER_LEN: usize = 40;
+const MAX_HEADER_LEN: usize = 64 * 1024;
+const MAX_SECTION_LEN: usize = 8 * 1024 * 1024;
+
+// read a varint prefixed section, None at the end of the archive
+async fn read_section<R>(reader: &mut R, max_len: usize) -> Result<Option<Vec<u8>>, Error>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut prefix = Vec::new();
+    let len = loop {
+        let mut b = [0u8; 1];
+        if reader.read(&mut b).await? == 0 {
+            if prefix.is_empty() {
+                return Ok(None);
+            }
+            return Err(error::decode_error());
+        }
+        prefix.push(b[0]);
+        if let Some((len, _)) = uvarint_decode(&prefix)? {
+            break usize::try_from(len).map_err(|_| error::decode_error())?;
+        }
+    };
+    if len > max_len {
+        return Err(error::message_malformed());
+    }
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf).await?;
+    Ok(Some(buf))
+}
+
+// the blockstore may block, such as on the file system, so it is accessed
+// off the async tasks
+async fn get_block(blockstore: &Arc<dyn Blockstore>, cid: &Cid) -> Result<Vec<u8>, Error> {
+    let (blockstore, cid) = (blockstore.clone(), cid.clone());
+    async_std::task::spawn_blocking(move || blockstore.get(&cid))
+        .await?
+        .ok_or_else(|| error::not_found("block not found"))
+}
+
+async fn put_block(blockstore: &Arc<dyn Blockstore>, cid: Cid, data: Vec<u8>) -> Result<(), Error> {
+    let blockstore = blockstore.clone();
+    async_std::task::spawn_blocking(move || blockstore.put(&cid, &data)).await
+}
+
+fn header(roots: &[Cid]) -> Result<Vec<u8>, Error> {
+    let roots = roots.iter().cloned().map(Value::Link).collect();
+    cbor::encode(&Value::Map(BTreeMap::from([
+        ("roots".to_string(), Value::Array(roots)),
+        ("version".to_string(), Value::Integer(1)),
+    ])))
+}
+
+// read a version 1 or 2 archive into the blockstore, returns its roots,
+// blocks not matching their cids fail the read
+pub async fn read_car<R>(
+    reader: &mut R,
+    blockstore: &Arc<dyn Blockstore>,
+) -> Result<Vec<Cid>, Error>
+where
+    R: AsyncRead + Unpin,
+{
+    let header = read_section(reader, MAX_HEADER_LEN)
+        .await?
+        .ok_or_else(error::decode_error)?;
+    if [uvarint_encode(header.len() as u64), header.clone()].concat() == CARV2_PRAGMA {
+        let mut header = [0u8; CARV2_HEADER_LEN];
+        reader.read_exact(&mut header).await?;
+        let field =
+            |n: usize| u64::from_le_bytes(header[16 + 8 * n..24 + 8 * n].try_into().unwrap());
+        let (data_offset, data_size) = (field(0), field(1));
+        let skip = data_offset
+            .checked_sub((CARV2_PRAGMA.len() + CARV2_HEADER_LEN) as u64)
+            .ok_or_else(error::decode_error)?;
+        futures::io::copy(&mut (&mut *reader).take(skip), &mut futures::io::sink()).await?;
+        return read_car_v1(&mut reader.take(data_size), blockstore, None).await;
+    }
+    read_car_v1(reader, blockstore, Some(header)).await
+}
+
+async fn read_car_v1<R>(
+    reader: &mut R,
+    blockstore: &Arc<dyn Blockstore>,
+    header: Option<Vec<u8>>,
+) -> Result<Vec<Cid>, Error>
+where
+    R: AsyncRead + Unpin,
+{
+    let header = match header {
+        Some(header) => header,
+        None => read_section(reader, MAX_HEADER_LEN)
+            .await?
+            .ok_or_else(error::decode_error)?,
+    };
+    let header = cbor::decode(&header)?;
+    if header.get("version").and_then(Value::as_u64) != Some(1) {
+        return Err(error::unsupported("unsupported car version"));
+    }
+    let roots = header
+        .get("roots")
+        .and_then(Value::as_array)
+        .ok_or_else(error::decode_error)?
+        .iter()
+        .map(|root| root.as_link().cloned().ok_or_else(error::decode_error))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    while let Some(section) = read_section(reader, MAX_SECTION_LEN).await? {
+        let (cid, data) = Cid::read_bytes(&section)?;
+        cid.verify(data)?;
+        put_block(blockstore, cid, data.to_vec()).await?;
+    }
+    Ok(roots)
+}
+
+// write a version 1 archive of the blocks from the blockstore
+pub async fn write_car<W>(
+    writer: &mut W,
+    roots: &[Cid],
+    cids: &[Cid],
+    blockstore: &Arc<dyn Blockstore>,
+) -> Result<(), Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    let header = header(roots)?;
+    writer
+        .write_all(&uvarint_encode(header.len() as u64))
+        .await?;
+    writer.write_all(&header).await?;
+    for cid in cids {
+        let data = get_block(blockstore, cid).await?;
+        let cid = cid.to_bytes();
+        writer
+            .write_all(&uvarint_encode((cid.len() + data.len()) as u64))
+            .await?;
+        writer.write_all(&cid).await?;
+        writer.write_all(&data).await?;
+    }
+    writer.flush().await
+}
+
+// write a version 2 archive without an index, the data size is taken from
+// the blockstore first
+pub async fn write_car_v2<W>(
+    writer: &mut W,
+    roots: &[Cid],
+    cids: &[Cid],
+    blockstore: &Arc<dyn Blockstore>,
+) -> Result<(), Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    let header_len = header(roots)?.len();
+    let mut data_size = uvarint_encode(header_len as u64).len() + header_len;
+    for cid in cids {
+        let data = get_block(blockstore, cid).await?;
+        let len = cid.to_bytes().len() + data.len();
+        data_size += uvarint_encode(len as u64).len() + len;
+    }
+
+    let data_offset = (CARV2_PRAGMA.len() + CARV2_HEADER_LEN) as u64;
+    writer.write_all(&CARV2_PRAGMA).await?;
+    writer.write_all(&[0u8; 16]).await?;
+    writer.write_all(&data_offset.to_le_bytes()).await?;
+    writer.write_all(&(data_size as u64).to_le_bytes()).await?;
+    writer.write_all(&0u64.to_le_bytes()).await?;
+    write_car(writer, roots, cids, blockstore).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cid::{Multihash, CODEC_DAG_CBOR, CODEC_RAW},
+        net::MemoryBlockstore,
+    };
+    use futures::io::Cursor;
+
+    fn blocks() -> (Arc<dyn Blockstore>, Vec<Cid>) {
+        let blockstore = MemoryBlockstore::new();
+        let mut cids = Vec::new();
+        for n in 0..3u8 {
+            let data = vec![n; 1000];
+            let cid = Cid::new_v1(CODEC_RAW, Multihash::blake3(&data));
+            blockstore.put(&cid, &data).unwrap();
+            cids.push(cid);
+        }
+        let root = cbor::encode(&Value::Array(
+            cids.iter().cloned().map(Value::Link).collect(),
+        ))
+        .unwrap();
+        let cid = Cid::new_v1(CODEC_DAG_CBOR, Multihash::sha2_256(&root));
+        blockstore.put(&cid, &root).unwrap();
+        cids.insert(0, cid);
+        (Arc::new(blockstore), cids)
+    }
+
+    #[async_std::test]
+    async fn test_car_roundtrip() -> Result<(), Error> {
+        let (blockstore, cids) = blocks();
+        let roots = vec![cids[0].clone()];
+
+        let mut v1 = Vec::new();
+        write_car(&mut v1, &roots, &cids, &blockstore).await?;
+        let imported: Arc<dyn Blockstore> = Arc::new(MemoryBlockstore::new());
+        assert_eq!(read_car(&mut Cursor::new(&v1), &imported).await?, roots);
+        for cid in &cids {
+            assert_eq!(imported.get(cid)?, blockstore.get(cid)?);
+        }
+
+        let mut v2 = Vec::new();
+        write_car_v2(&mut v2, &roots, &cids, &blockstore).await?;
+        assert_eq!(v2[..11], CARV2_PRAGMA);
+        assert_eq!(v2[51..], v1[..]);
+        let imported: Arc<dyn Blockstore> = Arc::new(MemoryBlockstore::new());
+        assert_eq!(read_car(&mut Cursor::new(&v2), &imported).await?, roots);
+        assert_eq!(imported.cids()?.len(), cids.len());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_car_corrupt_block() -> Result<(), Error> {
+        let (blockstore, cids) = blocks();
+        let mut car = Vec::new();
+        write_car(&mut car, &[], &cids[1..], &blockstore).await?;
+        let last = car.len() - 1;
+        car[last] ^= 1;
+        let imported: Arc<dyn Blockstore> = Arc::new(MemoryBlockstore::new());
+        assert!(read_car(&mut Cursor::new(&car), &imported).await.is_err());
+        assert_eq!(imported.cids()?.len(), 2);
+
+        // truncated within a section
+        car.truncate(last);
+        let imported: Arc<dyn Blockstore> = Arc::new(MemoryBlockstore::new());
+        assert!(read_car(&mut Cursor::new(&car), &imported).await.is_err());
+        Ok(())
+    }
+}
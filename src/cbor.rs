@@ -0,0 +1,319 @@
+use crate::{
+    cid::Cid,
+    error::{self, Error},
+};
+use std::collections::BTreeMap;
+
+const TAG_CID: u64 = 42;
+const MAX_DEPTH: usize = 64;
+
+// Value
+//
+// The dag-cbor data model, map keys are strings and links are cids
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Integer(i128),
+    Float(f64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+    Link(Cid),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Self::Map(map) => map.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Self::Integer(n) => u64::try_from(*n).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Self::Array(array) => Some(array),
+            _ => None,
+        }
+    }
+
+    pub fn as_link(&self) -> Option<&Cid> {
+        match self {
+            Self::Link(cid) => Some(cid),
+            _ => None,
+        }
+    }
+}
+
+// encode in the canonical form, map keys sorted by length then bytes;
+// integers beyond 64 bits are out of the range of cbor
+pub fn encode(value: &Value) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::new();
+    encode_into(&mut buf, value)?;
+    Ok(buf)
+}
+
+fn encode_head(buf: &mut Vec<u8>, major: u8, n: u64) {
+    let major = major << 5;
+    match n {
+        0..=23 => buf.push(major | n as u8),
+        24..=0xff => buf.extend([major | 24, n as u8]),
+        0x100..=0xffff => {
+            buf.push(major | 25);
+            buf.extend((n as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            buf.push(major | 26);
+            buf.extend((n as u32).to_be_bytes());
+        }
+        _ => {
+            buf.push(major | 27);
+            buf.extend(n.to_be_bytes());
+        }
+    }
+}
+
+fn encode_into(buf: &mut Vec<u8>, value: &Value) -> Result<(), Error> {
+    match value {
+        Value::Null => buf.push(0xf6),
+        Value::Bool(b) => buf.push(if *b { 0xf5 } else { 0xf4 }),
+        Value::Integer(n) => {
+            let (major, n) = match *n >= 0 {
+                true => (0, u64::try_from(*n)),
+                false => (1, u64::try_from(-1 - *n)),
+            };
+            let n = n.map_err(|_| error::invalid_input("integer out of the cbor range"))?;
+            encode_head(buf, major, n);
+        }
+        Value::Float(f) => {
+            buf.push(0xfb);
+            buf.extend(f.to_be_bytes());
+        }
+        Value::Bytes(bytes) => {
+            encode_head(buf, 2, bytes.len() as u64);
+            buf.extend(bytes);
+        }
+        Value::Text(text) => {
+            encode_head(buf, 3, text.len() as u64);
+            buf.extend(text.as_bytes());
+        }
+        Value::Array(array) => {
+            encode_head(buf, 4, array.len() as u64);
+            for value in array {
+                encode_into(buf, value)?;
+            }
+        }
+        Value::Map(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by_key(|(key, _)| (key.len(), key.as_bytes()));
+            encode_head(buf, 5, entries.len() as u64);
+            for (key, value) in entries {
+                encode_head(buf, 3, key.len() as u64);
+                buf.extend(key.as_bytes());
+                encode_into(buf, value)?;
+            }
+        }
+        // the cid bytes behind the multibase identity prefix
+        Value::Link(cid) => {
+            let bytes = [vec![0], cid.to_bytes()].concat();
+            encode_head(buf, 6, TAG_CID);
+            encode_head(buf, 2, bytes.len() as u64);
+            buf.extend(bytes);
+        }
+    }
+    Ok(())
+}
+
+// decode a single value taking up all the bytes, indefinite lengths and
+// tags other than cids are rejected as dag-cbor does
+pub fn decode(bytes: &[u8]) -> Result<Value, Error> {
+    let mut decoder = Decoder { bytes, pos: 0 };
+    let value = decoder.value(0)?;
+    if decoder.pos != bytes.len() {
+        return Err(error::decode_error());
+    }
+    Ok(value)
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        let end = self.pos.checked_add(n).ok_or_else(error::decode_error)?;
+        let bytes = self
+            .bytes
+            .get(self.pos..end)
+            .ok_or_else(error::decode_error)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn head(&mut self) -> Result<(u8, u8, u64), Error> {
+        let b = self.take(1)?[0];
+        let (major, info) = (b >> 5, b & 0x1f);
+        let n = match info {
+            0..=23 => u64::from(info),
+            24 => u64::from(self.take(1)?[0]),
+            25 => u64::from(u16::from_be_bytes(self.take(2)?.try_into().unwrap())),
+            26 => u64::from(u32::from_be_bytes(self.take(4)?.try_into().unwrap())),
+            27 => u64::from_be_bytes(self.take(8)?.try_into().unwrap()),
+            _ => return Err(error::decode_error()),
+        };
+        Ok((major, info, n))
+    }
+
+    fn len(&mut self, n: u64) -> Result<usize, Error> {
+        let len = usize::try_from(n).map_err(|_| error::decode_error())?;
+        // every item takes a byte at least
+        if len > self.bytes.len() - self.pos {
+            return Err(error::decode_error());
+        }
+        Ok(len)
+    }
+
+    fn text(&mut self, n: u64) -> Result<String, Error> {
+        let len = self.len(n)?;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| error::decode_error())
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, Error> {
+        if depth > MAX_DEPTH {
+            return Err(error::decode_error());
+        }
+        let (major, info, n) = self.head()?;
+        match major {
+            0 => Ok(Value::Integer(i128::from(n))),
+            1 => Ok(Value::Integer(-1 - i128::from(n))),
+            2 => {
+                let len = self.len(n)?;
+                Ok(Value::Bytes(self.take(len)?.to_vec()))
+            }
+            3 => Ok(Value::Text(self.text(n)?)),
+            4 => {
+                let len = self.len(n)?;
+                let array = (0..len)
+                    .map(|_| self.value(depth + 1))
+                    .collect::<Result<_, _>>()?;
+                Ok(Value::Array(array))
+            }
+            5 => {
+                let mut map = BTreeMap::new();
+                for _ in 0..self.len(n)? {
+                    let key = match self.head()? {
+                        (3, _, n) => self.text(n)?,
+                        _ => return Err(error::decode_error()),
+                    };
+                    let value = self.value(depth + 1)?;
+                    if map.insert(key, value).is_some() {
+                        return Err(error::decode_error());
+                    }
+                }
+                Ok(Value::Map(map))
+            }
+            6 if n == TAG_CID => match self.value(depth + 1)? {
+                Value::Bytes(bytes) if bytes.first() == Some(&0) => {
+                    Ok(Value::Link(Cid::from_bytes(&bytes[1..])?))
+                }
+                _ => Err(error::decode_error()),
+            },
+            7 => match (info, n) {
+                (20, _) => Ok(Value::Bool(false)),
+                (21, _) => Ok(Value::Bool(true)),
+                (22, _) => Ok(Value::Null),
+                (26, n) => Ok(Value::Float(f64::from(f32::from_bits(n as u32)))),
+                (27, n) => Ok(Value::Float(f64::from_bits(n))),
+                _ => Err(error::decode_error()),
+            },
+            _ => Err(error::decode_error()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cid::{Multihash, CODEC_RAW};
+
+    #[test]
+    fn test_cbor_canonical() {
+        let value = Value::Map(BTreeMap::from([
+            ("version".to_string(), Value::Integer(1)),
+            ("roots".to_string(), Value::Array(vec![])),
+        ]));
+        let bytes = encode(&value).unwrap();
+        let expected = [
+            [0xa2, 0x65].as_slice(),
+            b"roots",
+            &[0x80, 0x67],
+            b"version",
+            &[0x01],
+        ]
+        .concat();
+        assert_eq!(bytes, expected);
+        assert_eq!(decode(&bytes).unwrap(), value);
+    }
+
+    #[test]
+    fn test_cbor_values() {
+        let cid = Cid::new_v1(CODEC_RAW, Multihash::sha2_256(b"data"));
+        let value = Value::Array(vec![
+            Value::Null,
+            Value::Bool(true),
+            Value::Integer(-500),
+            Value::Integer(u64::MAX.into()),
+            Value::Integer(-1 - i128::from(u64::MAX)),
+            Value::Float(1.5),
+            Value::Bytes(vec![1, 2, 3]),
+            Value::Text("text".to_string()),
+            Value::Link(cid.clone()),
+        ]);
+        let bytes = encode(&value).unwrap();
+        assert_eq!(decode(&bytes).unwrap(), value);
+        assert_eq!(
+            encode(&Value::Integer(-500)).unwrap(),
+            vec![0x39, 0x01, 0xf3]
+        );
+        assert_eq!(
+            decode(&bytes).unwrap().as_array().unwrap()[8].as_link(),
+            Some(&cid)
+        );
+
+        // integers beyond the range of cbor
+        assert!(encode(&Value::Integer(i128::from(u64::MAX) + 1)).is_err());
+        assert!(encode(&Value::Array(vec![Value::Integer(
+            -2 - i128::from(u64::MAX)
+        )]))
+        .is_err());
+
+        // trailing bytes, indefinite lengths and other tags
+        assert!(decode(&[bytes, vec![0]].concat()).is_err());
+        assert!(decode(&[0x9f, 0xff]).is_err());
+        assert!(decode(&[0xc1, 0x00]).is_err());
+        assert!(decode(&[0x5b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).is_err());
+    }
+}
@@ -0,0 +1,332 @@
+use crate::{
+    error::{self, Error},
+    io::{uvarint_decode, uvarint_encode},
+};
+use ring::digest;
+use std::{fmt, str::FromStr};
+
+pub const CODEC_RAW: u64 = 0x55;
+pub const CODEC_DAG_PB: u64 = 0x70;
+pub const CODEC_DAG_CBOR: u64 = 0x71;
+pub const MULTIHASH_SHA2_256: u64 = 0x12;
+pub const MULTIHASH_BLAKE3: u64 = 0x1e;
+const SHA2_256_LEN: usize = 32;
+
+// read an unsigned varint from the front of the bytes
+fn read_uvarint(bytes: &[u8]) -> Result<(u64, &[u8]), Error> {
+    let (n, len) = uvarint_decode(bytes)?.ok_or_else(error::decode_error)?;
+    Ok((n, &bytes[len..]))
+}
+
+// Multihash
+//
+// The hash function code and the digest of some data
+#[derive(PartialEq, Eq, Hash, Clone)]
+pub struct Multihash {
+    code: u64,
+    digest: Vec<u8>,
+}
+
+impl Multihash {
+    pub fn sha2_256(data: &[u8]) -> Self {
+        Self {
+            code: MULTIHASH_SHA2_256,
+            digest: digest::digest(&digest::SHA256, data).as_ref().to_vec(),
+        }
+    }
+
+    // the default 32 bytes output
+    pub fn blake3(data: &[u8]) -> Self {
+        Self {
+            code: MULTIHASH_BLAKE3,
+            digest: blake3::hash(data).as_bytes().to_vec(),
+        }
+    }
+
+    // hash the data with the hash function of the code
+    pub fn digest(code: u64, data: &[u8]) -> Result<Self, Error> {
+        match code {
+            MULTIHASH_SHA2_256 => Ok(Self::sha2_256(data)),
+            MULTIHASH_BLAKE3 => Ok(Self::blake3(data)),
+            _ => Err(error::unsupported("unsupported multihash")),
+        }
+    }
+
+    pub fn code(&self) -> u64 {
+        self.code
+    }
+
+    pub fn digest_bytes(&self) -> &[u8] {
+        &self.digest
+    }
+
+    // parse a multihash from the front of the bytes, returns the rest
+    pub fn read_bytes(bytes: &[u8]) -> Result<(Self, &[u8]), Error> {
+        let (code, rest) = read_uvarint(bytes)?;
+        let (len, rest) = read_uvarint(rest)?;
+        let len = usize::try_from(len).map_err(|_| error::decode_error())?;
+        if rest.len() < len {
+            return Err(error::decode_error());
+        }
+        let (digest, rest) = rest.split_at(len);
+        let multihash = Self {
+            code,
+            digest: digest.to_vec(),
+        };
+        Ok((multihash, rest))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [
+            uvarint_encode(self.code),
+            uvarint_encode(self.digest.len() as u64),
+            self.digest.clone(),
+        ]
+        .concat()
+    }
+
+    // check the data hashes to the digest, which must be of the full length
+    // of the hash function, as a truncated one would match unrelated data
+    pub fn verify(&self, data: &[u8]) -> Result<(), Error> {
+        let hash = Self::digest(self.code, data)?;
+        if hash.digest != self.digest {
+            return Err(error::verification_failed());
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Multihash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Multihash({:#x}, ", self.code)?;
+        self.digest
+            .iter()
+            .try_for_each(|b| write!(f, "{:02x}", b))?;
+        write!(f, ")")
+    }
+}
+
+// Cid
+//
+// A content identifier, version 0 is a bare sha2-256 multihash of dag-pb
+// data while version 1 also carries the codec of the data
+#[derive(PartialEq, Eq, Hash, Clone)]
+pub struct Cid {
+    version: u64,
+    codec: u64,
+    hash: Multihash,
+}
+
+impl Cid {
+    pub fn new_v0(hash: Multihash) -> Result<Self, Error> {
+        if hash.code != MULTIHASH_SHA2_256 || hash.digest.len() != SHA2_256_LEN {
+            return Err(error::invalid_input("cid v0 requires a sha2-256 multihash"));
+        }
+        Ok(Self {
+            version: 0,
+            codec: CODEC_DAG_PB,
+            hash,
+        })
+    }
+
+    pub fn new_v1(codec: u64, hash: Multihash) -> Self {
+        Self {
+            version: 1,
+            codec,
+            hash,
+        }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn codec(&self) -> u64 {
+        self.codec
+    }
+
+    pub fn hash(&self) -> &Multihash {
+        &self.hash
+    }
+
+    // the same block as version 1
+    pub fn to_v1(&self) -> Self {
+        Self::new_v1(self.codec, self.hash.clone())
+    }
+
+    // the string form in the base, version 0 is base58 only
+    pub fn encode(&self, base: multibase::Base) -> Result<String, Error> {
+        match (self.version, base) {
+            (0, multibase::Base::Base58Btc) => Ok(self.to_string()),
+            (0, _) => Err(error::invalid_input("cid v0 is base58 only")),
+            _ => Ok(multibase::encode(base, self.to_bytes())),
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        match Self::read_bytes(bytes)? {
+            (cid, []) => Ok(cid),
+            _ => Err(error::decode_error()),
+        }
+    }
+
+    // parse a cid from the front of the bytes, returns the rest
+    pub fn read_bytes(bytes: &[u8]) -> Result<(Self, &[u8]), Error> {
+        // v0 starts with the sha2-256 code and length
+        if bytes.len() >= 2 + SHA2_256_LEN && bytes[..2] == [0x12, 0x20] {
+            let (hash, rest) = Multihash::read_bytes(bytes)?;
+            return Ok((Self::new_v0(hash)?, rest));
+        }
+        let (version, rest) = read_uvarint(bytes)?;
+        if version != 1 {
+            return Err(error::unsupported("unsupported cid version"));
+        }
+        let (codec, rest) = read_uvarint(rest)?;
+        let (hash, rest) = Multihash::read_bytes(rest)?;
+        Ok((Self::new_v1(codec, hash), rest))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self.version {
+            0 => self.hash.to_bytes(),
+            _ => [
+                uvarint_encode(self.version),
+                uvarint_encode(self.codec),
+                self.hash.to_bytes(),
+            ]
+            .concat(),
+        }
+    }
+
+    // the cid fields but the digest, as sent along with bitswap blocks
+    pub fn prefix(&self) -> Vec<u8> {
+        [
+            uvarint_encode(self.version),
+            uvarint_encode(self.codec),
+            uvarint_encode(self.hash.code),
+            uvarint_encode(self.hash.digest.len() as u64),
+        ]
+        .concat()
+    }
+
+    // the cid of the data hashed as the prefix describes, with a digest of
+    // the full length
+    pub fn from_prefix(prefix: &[u8], data: &[u8]) -> Result<Self, Error> {
+        let (version, rest) = read_uvarint(prefix)?;
+        let (codec, rest) = read_uvarint(rest)?;
+        let (code, rest) = read_uvarint(rest)?;
+        let (len, _) = read_uvarint(rest)?;
+        let hash = Multihash::digest(code, data)?;
+        if usize::try_from(len).ok() != Some(hash.digest.len()) {
+            return Err(error::unsupported("truncated multihash"));
+        }
+        match version {
+            0 => Self::new_v0(hash),
+            1 => Ok(Self::new_v1(codec, hash)),
+            _ => Err(error::unsupported("unsupported cid version")),
+        }
+    }
+
+    // check the data hashes to the cid
+    pub fn verify(&self, data: &[u8]) -> Result<(), Error> {
+        self.hash.verify(data)
+    }
+}
+
+impl fmt::Display for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.version {
+            0 => write!(f, "{}", multibase::Base::Base58Btc.encode(self.to_bytes())),
+            _ => write!(
+                f,
+                "{}",
+                multibase::encode(multibase::Base::Base32Lower, self.to_bytes())
+            ),
+        }
+    }
+}
+
+impl fmt::Debug for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cid({})", self)
+    }
+}
+
+impl FromStr for Cid {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // v0 is base58 without a multibase prefix
+        let bytes = if s.len() == 46 && s.starts_with("Qm") {
+            multibase::Base::Base58Btc
+                .decode(s)
+                .map_err(|_| error::parse_error())?
+        } else {
+            multibase::decode(s).map_err(|_| error::parse_error())?.1
+        };
+        Self::from_bytes(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMPTY_RAW_CID: &str = "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku";
+
+    #[test]
+    fn test_cid_string_and_bytes() {
+        let cid = Cid::new_v1(CODEC_RAW, Multihash::sha2_256(b""));
+        assert_eq!(cid.to_string(), EMPTY_RAW_CID);
+        assert_eq!(EMPTY_RAW_CID.parse::<Cid>().unwrap(), cid);
+        assert_eq!(Cid::from_bytes(&cid.to_bytes()).unwrap(), cid);
+
+        let cid = Cid::new_v0(Multihash::sha2_256(b"hello")).unwrap();
+        let s = cid.to_string();
+        assert!(s.starts_with("Qm") && s.len() == 46);
+        assert_eq!(s.parse::<Cid>().unwrap(), cid);
+        assert_eq!(cid.to_bytes().len(), 34);
+        assert!(Cid::from_bytes(&[cid.to_bytes(), vec![0]].concat()).is_err());
+    }
+
+    #[test]
+    fn test_cid_formats() {
+        // blake3 of no data
+        let hash = Multihash::blake3(b"");
+        assert_eq!(
+            hash.digest_bytes()[..8],
+            [0xaf, 0x13, 0x49, 0xb9, 0xf5, 0xf9, 0xa1, 0xa6]
+        );
+        let cid = Cid::new_v1(CODEC_DAG_CBOR, hash);
+        assert!(cid.to_string().starts_with("bafyr4i"));
+        let base58 = cid.encode(multibase::Base::Base58Btc).unwrap();
+        assert!(base58.starts_with('z'));
+        assert_eq!(base58.parse::<Cid>().unwrap(), cid);
+        assert!(cid.verify(b"").is_ok());
+        assert!(Cid::new_v0(Multihash::blake3(b"")).is_err());
+
+        let v0 = Cid::new_v0(Multihash::sha2_256(b"node")).unwrap();
+        assert_eq!(v0.to_v1().codec(), CODEC_DAG_PB);
+        assert!(v0.to_v1().to_string().starts_with("bafybei"));
+        assert!(v0.encode(multibase::Base::Base32Lower).is_err());
+        assert_eq!(Cid::from_bytes(&v0.to_v1().to_bytes()).unwrap(), v0.to_v1());
+    }
+
+    #[test]
+    fn test_cid_prefix() {
+        let data = b"block data";
+        let cid = Cid::new_v1(CODEC_DAG_PB, Multihash::sha2_256(data));
+        assert_eq!(Cid::from_prefix(&cid.prefix(), data).unwrap(), cid);
+        assert!(cid.verify(data).is_ok());
+        assert!(cid.verify(b"other data").is_err());
+
+        let cid = Cid::new_v0(Multihash::sha2_256(data)).unwrap();
+        assert_eq!(cid.prefix(), vec![0, 0x70, 0x12, 0x20]);
+        assert_eq!(Cid::from_prefix(&cid.prefix(), data).unwrap(), cid);
+
+        // truncated digests match nothing
+        let truncated = Cid::from_bytes(&[0x01, 0x55, 0x12, 0x01, cid.hash().digest_bytes()[0]]);
+        assert!(truncated.unwrap().verify(data).is_err());
+        assert!(Cid::from_prefix(&[0x01, 0x55, 0x12, 0x01], data).is_err());
+    }
+}
@@ -3,7 +3,7 @@ use std::io::{Error as IoError, ErrorKind};
 pub type Error = IoError;
 
 pub fn other(msg: &str) -> Error {
-    IoError::new(ErrorKind::Other, msg)
+    IoError::other(msg)
 }
 
 pub fn invalid_input(msg: &str) -> Error {
@@ -41,3 +41,11 @@ pub fn verification_failed() -> Error {
 pub fn message_malformed() -> Error {
     invalid_data("message malformed")
 }
+
+pub fn timed_out() -> Error {
+    IoError::new(ErrorKind::TimedOut, "timed out")
+}
+
+pub fn not_found(msg: &str) -> Error {
+    IoError::new(ErrorKind::NotFound, msg)
+}
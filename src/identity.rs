@@ -8,6 +8,7 @@ use std::path::Path;
 pub const MULTIHASH_IDENTITY_CODE: u8 = 0x00;
 
 // PrivateKey types
+#[allow(clippy::large_enum_variant)]
 #[derive(Clone)]
 pub enum PrivateKey {
     None,
@@ -28,7 +29,7 @@ impl PrivateKey {
 
         ed25519_dalek::SigningKey::read_pkcs8_pem_file(path)
             .map_err(|_| error::parse_error())
-            .map(|key| Self::Ed25519(key))
+            .map(Self::Ed25519)
     }
 
     pub fn public(&self) -> PublicKey {
@@ -84,8 +85,7 @@ pub enum PublicKey {
 
 impl PublicKey {
     pub fn from_ed25519_bytes(bytes: &[u8]) -> Result<Self, Error> {
-        let mut buf = [0u8; 32];
-        buf.copy_from_slice(&bytes);
+        let buf: [u8; 32] = bytes.try_into().map_err(|_| error::parse_error())?;
         let key =
             ed25519_dalek::VerifyingKey::from_bytes(&buf).map_err(|_| error::parse_error())?;
         Ok(Self::Ed25519(key))
@@ -107,22 +107,15 @@ impl PublicKey {
 
     pub fn from_protobuf_bytes(bytes: &[u8]) -> Result<Self, Error> {
         // verify multihash format
-        if bytes[0] != MULTIHASH_IDENTITY_CODE && usize::from(bytes[1]) != bytes.len() - 2 {
+        if bytes.len() < 2
+            || bytes[0] != MULTIHASH_IDENTITY_CODE
+            || usize::from(bytes[1]) != bytes.len() - 2
+        {
             return Err(error::parse_error());
         }
 
         let pb: PublicKeyProto = protobuf_decode(&bytes[2..])?;
-        match pb.Type {
-            KeyTypeProto::Ed25519 => {
-                let mut buf = [0u8; 32];
-                buf.copy_from_slice(&pb.Data);
-                // verify public key and then return
-                ed25519_dalek::VerifyingKey::from_bytes(&buf)
-                    .map_err(|_| error::parse_error())
-                    .and_then(|key| Ok(Self::Ed25519(key)))
-            }
-            _ => todo!(),
-        }
+        Self::try_from(pb)
     }
 
     pub fn to_protobuf_bytes(&self) -> Result<Vec<u8>, Error> {
@@ -152,6 +145,17 @@ impl PublicKey {
     }
 }
 
+impl TryFrom<PublicKeyProto> for PublicKey {
+    type Error = Error;
+
+    fn try_from(pb: PublicKeyProto) -> Result<Self, Self::Error> {
+        match pb.Type {
+            KeyTypeProto::Ed25519 => Self::from_ed25519_bytes(&pb.Data),
+            _ => Err(error::unsupported("key type")),
+        }
+    }
+}
+
 impl TryFrom<PeerId> for PublicKey {
     type Error = Error;
 
@@ -182,13 +186,27 @@ impl TryInto<PeerId> for PublicKey {
 }
 
 // PeerId which should be generated from PublicKey
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct PeerId(String);
 
 impl PeerId {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < 2 || usize::from(bytes[1]) != bytes.len() - 2 {
+            return Err(error::parse_error());
+        }
+        Ok(Self(multibase::Base::Base58Btc.encode(bytes)))
+    }
+
     pub fn as_bytes(&self) -> &[u8] {
         self.0.as_bytes()
     }
+
+    // multihash bytes of the peer id
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        multibase::Base::Base58Btc
+            .decode(&self.0)
+            .map_err(|_| error::parse_error())
+    }
 }
 
 #[cfg(test)]
@@ -212,6 +230,16 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_peer_id_bytes() {
+        let peer_id = PeerId(self::TEST_PEER_ID.to_string());
+        assert_eq!(
+            PeerId::from_bytes(&peer_id.to_bytes().unwrap()).unwrap(),
+            peer_id
+        );
+        assert!(PeerId::from_bytes(&[MULTIHASH_IDENTITY_CODE, 3, 0]).is_err());
+    }
+
     #[test]
     fn test_peer_id_into_public_key() {
         assert_eq!(
@@ -1,7 +1,23 @@
+mod boxed;
 mod codec;
+mod mplex;
 mod noise;
+mod pnet;
 mod protobuf;
+mod secured;
+mod timeout;
+mod tls;
+mod uvarint;
+mod yamux;
 
+pub use boxed::*;
 pub use codec::*;
+pub use mplex::*;
 pub use noise::*;
+pub use pnet::*;
 pub use protobuf::*;
+pub use secured::*;
+pub use timeout::*;
+pub use tls::*;
+pub use uvarint::*;
+pub use yamux::*;
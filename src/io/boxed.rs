@@ -0,0 +1,9 @@
+use futures::{AsyncRead, AsyncWrite};
+
+// AsyncReadWrite for type erasure of streams
+pub trait AsyncReadWrite: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T> AsyncReadWrite for T where T: AsyncRead + AsyncWrite + Send + Unpin {}
+
+// BoxedStream
+pub type BoxedStream = Box<dyn AsyncReadWrite>;
@@ -3,6 +3,7 @@ use asynchronous_codec::{Decoder, Encoder};
 use bytes::BytesMut;
 
 // U16LengthCodec
+#[derive(Default)]
 pub struct U16LengthCodec {}
 
 impl U16LengthCodec {
@@ -44,6 +45,7 @@ impl Decoder for U16LengthCodec {
 }
 
 // U8LengthLineCodec
+#[derive(Default)]
 pub struct U8LengthLineCodec {}
 
 impl U8LengthLineCodec {
@@ -72,7 +74,7 @@ impl Decoder for U8LengthLineCodec {
     type Error = Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        if src.len() == 0 {
+        if src.is_empty() {
             return Ok(None);
         }
         let prefix = usize::from(src[0]);
@@ -0,0 +1,141 @@
+use super::{uvarint_decode, uvarint_encode};
+use crate::error::{self, Error};
+use asynchronous_codec::{Decoder, Encoder};
+use bytes::{Buf, BytesMut};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MplexFlag {
+    NewStream = 0,
+    MessageReceiver = 1,
+    MessageInitiator = 2,
+    CloseReceiver = 3,
+    CloseInitiator = 4,
+    ResetReceiver = 5,
+    ResetInitiator = 6,
+}
+
+impl MplexFlag {
+    // whether the frame is sent by the side which opened the stream
+    pub fn is_initiator(&self) -> bool {
+        matches!(
+            self,
+            Self::NewStream | Self::MessageInitiator | Self::CloseInitiator | Self::ResetInitiator
+        )
+    }
+}
+
+impl TryFrom<u64> for MplexFlag {
+    type Error = Error;
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::NewStream),
+            1 => Ok(Self::MessageReceiver),
+            2 => Ok(Self::MessageInitiator),
+            3 => Ok(Self::CloseReceiver),
+            4 => Ok(Self::CloseInitiator),
+            5 => Ok(Self::ResetReceiver),
+            6 => Ok(Self::ResetInitiator),
+            _ => Err(error::message_malformed()),
+        }
+    }
+}
+
+// MplexFrame
+#[derive(Debug, Clone, PartialEq)]
+pub struct MplexFrame {
+    pub stream_id: u64,
+    pub flag: MplexFlag,
+    pub data: Vec<u8>,
+}
+
+impl MplexFrame {
+    pub fn new(stream_id: u64, flag: MplexFlag, data: Vec<u8>) -> Self {
+        Self {
+            stream_id,
+            flag,
+            data,
+        }
+    }
+}
+
+// MplexCodec
+pub struct MplexCodec {
+    max_message_size: usize,
+}
+
+impl MplexCodec {
+    pub fn new(max_message_size: usize) -> Self {
+        Self { max_message_size }
+    }
+}
+
+impl Encoder for MplexCodec {
+    type Item<'a> = &'a MplexFrame;
+    type Error = Error;
+
+    fn encode(&mut self, item: Self::Item<'_>, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        if item.data.len() > self.max_message_size || item.stream_id >> 60 != 0 {
+            return Err(error::encode_error());
+        }
+        dst.extend_from_slice(&uvarint_encode(item.stream_id << 3 | item.flag as u64));
+        dst.extend_from_slice(&uvarint_encode(item.data.len() as u64));
+        dst.extend_from_slice(&item.data);
+        Ok(())
+    }
+}
+
+impl Decoder for MplexCodec {
+    type Item = MplexFrame;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let (header, header_len) = match uvarint_decode(src)? {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+        let (len, len_len) = match uvarint_decode(&src[header_len..])? {
+            Some((len, len_len)) => (len as usize, len_len),
+            None => return Ok(None),
+        };
+        if len > self.max_message_size {
+            return Err(error::message_malformed());
+        }
+        if src.len() < header_len + len_len + len {
+            return Ok(None);
+        }
+        let flag = MplexFlag::try_from(header & 0x07)?;
+        src.advance(header_len + len_len);
+        let data = src.split_to(len).to_vec();
+        Ok(Some(MplexFrame {
+            stream_id: header >> 3,
+            flag,
+            data,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mplex_codec() -> Result<(), Error> {
+        let mut codec = MplexCodec::new(1024);
+
+        let mut buf = BytesMut::new();
+        let frame = MplexFrame::new(17, MplexFlag::MessageInitiator, b"abc".to_vec());
+        codec.encode(&frame, &mut buf)?;
+        assert_eq!(&buf[..], b"\x8a\x01\x03abc");
+
+        let mut partial = buf.split_to(3);
+        assert_eq!(codec.decode(&mut partial)?, None);
+        partial.unsplit(buf);
+        assert_eq!(codec.decode(&mut partial)?, Some(frame));
+        assert!(partial.is_empty());
+
+        let mut invalid = BytesMut::from(&b"\x07\x00"[..]);
+        assert!(codec.decode(&mut invalid).is_err());
+        Ok(())
+    }
+}
@@ -4,7 +4,7 @@ use crate::{
     payload::noise::NoiseHandshakePayload,
 };
 use asynchronous_codec::{Decoder, Encoder};
-use bytes::BytesMut;
+use bytes::{Buf, BytesMut};
 use futures::{
     task::{Context, Poll},
     AsyncRead, AsyncWrite,
@@ -13,6 +13,8 @@ use snow::{HandshakeState, TransportState};
 use std::{cmp::min, pin::Pin};
 
 const MAX_BUFFER_SIZE: usize = 65535 + 2;
+// noise message limit minus the authentication tag
+const MAX_PLAINTEXT_SIZE: usize = 65535 - 16;
 
 // NoiseCodec
 pub struct NoiseCodec<T> {
@@ -80,7 +82,7 @@ impl Decoder for NoiseCodec<HandshakeState> {
             Ok(None) => return Ok(None),
             Err(err) => return Err(err),
         };
-        if msg.len() == 0 {
+        if msg.is_empty() {
             return Ok(None);
         }
         let mut dec_buf = [0u8; MAX_BUFFER_SIZE];
@@ -127,7 +129,7 @@ impl Decoder for NoiseCodec<TransportState> {
             Ok(None) => return Ok(None),
             Err(err) => return Err(err),
         };
-        if msg.len() == 0 {
+        if msg.is_empty() {
             return Ok(None);
         }
         let mut dec_buf = [0u8; MAX_BUFFER_SIZE];
@@ -150,6 +152,7 @@ pub struct NoiseUpgradedStream<T> {
     codec: NoiseCodec<TransportState>,
     read_buffer: BytesMut,
     dec_buffer: BytesMut,
+    write_buffer: BytesMut,
 }
 
 impl<T> NoiseUpgradedStream<T> {
@@ -159,8 +162,15 @@ impl<T> NoiseUpgradedStream<T> {
             codec,
             read_buffer: BytesMut::new(),
             dec_buffer: BytesMut::new(),
+            write_buffer: BytesMut::new(),
         }
     }
+
+    // bytes already read from io but not yet decoded
+    pub fn with_read_buffer(mut self, read_buffer: BytesMut) -> Self {
+        self.read_buffer = read_buffer;
+        self
+    }
 }
 
 impl<T> AsyncRead for NoiseUpgradedStream<T>
@@ -176,15 +186,25 @@ where
         let mut io = Pin::new(&mut this.io);
         loop {
             // exhaust the buffer
-            if this.dec_buffer.len() > 0 {
+            if !this.dec_buffer.is_empty() {
                 let n = min(buf.len(), this.dec_buffer.len());
                 buf[..n].copy_from_slice(&this.dec_buffer.split_to(n));
                 log::debug!("NoiseUpgradedStream::poll_read - copy - n:{:?}", n);
                 return Poll::Ready(Ok(n));
             }
+            // decode by codec
+            match this.codec.decode(&mut this.read_buffer) {
+                Ok(Some(msg)) => {
+                    this.dec_buffer.extend_from_slice(&msg);
+                    continue;
+                }
+                Ok(None) => {}
+                Err(err) => return Poll::Ready(Err(futures::io::Error::other(err))),
+            }
             // read from io
             let mut read_buf = [0u8; MAX_BUFFER_SIZE];
             match io.as_mut().poll_read(cx, &mut read_buf) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Ok(0)),
                 Poll::Ready(Ok(read_len)) => {
                     this.read_buffer.extend_from_slice(&read_buf[..read_len])
                 }
@@ -195,13 +215,27 @@ where
                 "NoiseUpgradedStream::poll_read - read - read_len:{:?}",
                 this.read_buffer.len()
             );
-            // decode by codec
-            match this.codec.decode(&mut this.read_buffer) {
-                Ok(Some(msg)) => this.dec_buffer.extend_from_slice(&msg),
-                Ok(None) => return Poll::Ready(Ok(0)),
-                Err(err) => return Poll::Ready(Err(futures::io::Error::other(err))),
+        }
+    }
+}
+
+impl<T> NoiseUpgradedStream<T>
+where
+    T: AsyncWrite + Unpin,
+{
+    // write out the encoded bytes which are not yet accepted by io
+    fn poll_write_buffer(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), futures::io::Error>> {
+        while !self.write_buffer.is_empty() {
+            match Pin::new(&mut self.io).poll_write(cx, &self.write_buffer) {
+                Poll::Ready(Ok(0)) => {
+                    return Poll::Ready(Err(futures::io::ErrorKind::WriteZero.into()))
+                }
+                Poll::Ready(Ok(n)) => self.write_buffer.advance(n),
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
             }
         }
+        Poll::Ready(Ok(()))
     }
 }
 
@@ -215,43 +249,53 @@ where
         buf: &[u8],
     ) -> Poll<Result<usize, futures::io::Error>> {
         let this = self.get_mut();
-        let mut io = Pin::new(&mut this.io);
         log::debug!(
             "NoiseUpgradedStream::poll_write - write - buf_len:{:?}",
             buf.len()
         );
-        let mut write_buf = BytesMut::new();
+        // a previous message must be written out before encoding the next one
+        match this.poll_write_buffer(cx) {
+            Poll::Ready(Ok(())) => {}
+            res => return res.map_ok(|_| 0),
+        }
+        // a noise message carries at most MAX_PLAINTEXT_SIZE bytes, the
+        // rest of buf is left to the next call
+        let n = min(buf.len(), MAX_PLAINTEXT_SIZE);
         // encode buf into noise format & U16 prefixed
-        if let Err(err) = this.codec.encode(buf, &mut write_buf) {
+        if let Err(err) = this.codec.encode(&buf[..n], &mut this.write_buffer) {
             return Poll::Ready(Err(futures::io::Error::other(err)));
         }
         log::debug!(
             "NoiseUpgradedStream::poll_write - encoded - enc_len:{:?}",
-            write_buf.len()
+            this.write_buffer.len()
         );
-        // write entire buffer to the stream
-        if write_buf.len() > 0 {
-            match io.as_mut().poll_write(cx, &write_buf.split()) {
-                // return the len of buf instead of write_buf, due to
-                // Framed will verify the lengths of buf and size returned
-                Poll::Ready(Ok(_)) => return Poll::Ready(Ok(buf.len())),
-                res => return res,
-            }
+        // the encoded message is owned by the stream now, so n is reported
+        // as written even if io has not accepted all of it yet
+        if let Poll::Ready(Err(err)) = this.poll_write_buffer(cx) {
+            return Poll::Ready(Err(err));
         }
-        Poll::Ready(Ok(0))
+        Poll::Ready(Ok(n))
     }
 
     fn poll_flush(
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
     ) -> Poll<Result<(), futures::io::Error>> {
-        Pin::new(&mut self.get_mut().io).poll_flush(cx)
+        let this = self.get_mut();
+        match this.poll_write_buffer(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.io).poll_flush(cx),
+            res => res,
+        }
     }
 
     fn poll_close(
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
     ) -> Poll<Result<(), futures::io::Error>> {
-        Pin::new(&mut self.get_mut().io).poll_close(cx)
+        let this = self.get_mut();
+        match this.poll_write_buffer(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.io).poll_close(cx),
+            res => res,
+        }
     }
 }
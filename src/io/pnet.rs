@@ -0,0 +1,119 @@
+use bytes::{Buf, BytesMut};
+use futures::{
+    task::{Context, Poll},
+    AsyncRead, AsyncWrite,
+};
+use salsa20::{
+    cipher::{KeyIvInit, StreamCipher},
+    XSalsa20,
+};
+use std::pin::Pin;
+
+// PnetStream
+pub struct PnetStream<T> {
+    io: T,
+    read_cipher: XSalsa20,
+    write_cipher: XSalsa20,
+    write_buffer: BytesMut,
+}
+
+impl<T> PnetStream<T> {
+    pub fn new(io: T, psk: &[u8; 32], local_nonce: &[u8; 24], remote_nonce: &[u8; 24]) -> Self {
+        Self {
+            io,
+            read_cipher: XSalsa20::new(psk.into(), remote_nonce.into()),
+            write_cipher: XSalsa20::new(psk.into(), local_nonce.into()),
+            write_buffer: BytesMut::new(),
+        }
+    }
+}
+
+impl<T> PnetStream<T>
+where
+    T: AsyncWrite + Unpin,
+{
+    // write out the encrypted bytes which are not yet accepted by io
+    fn poll_write_buffer(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), futures::io::Error>> {
+        while !self.write_buffer.is_empty() {
+            match Pin::new(&mut self.io).poll_write(cx, &self.write_buffer) {
+                Poll::Ready(Ok(0)) => {
+                    return Poll::Ready(Err(futures::io::ErrorKind::WriteZero.into()))
+                }
+                Poll::Ready(Ok(n)) => self.write_buffer.advance(n),
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T> AsyncRead for PnetStream<T>
+where
+    T: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, futures::io::Error>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.io).poll_read(cx, buf) {
+            Poll::Ready(Ok(n)) => {
+                this.read_cipher.apply_keystream(&mut buf[..n]);
+                Poll::Ready(Ok(n))
+            }
+            res => res,
+        }
+    }
+}
+
+impl<T> AsyncWrite for PnetStream<T>
+where
+    T: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, futures::io::Error>> {
+        let this = self.get_mut();
+        // the keystream advances on encrypt, so pending bytes are kept aside
+        if let Poll::Ready(Err(err)) = this.poll_write_buffer(cx) {
+            return Poll::Ready(Err(err));
+        }
+        if !this.write_buffer.is_empty() {
+            return Poll::Pending;
+        }
+        let start = this.write_buffer.len();
+        this.write_buffer.extend_from_slice(buf);
+        this.write_cipher
+            .apply_keystream(&mut this.write_buffer[start..]);
+        if let Poll::Ready(Err(err)) = this.poll_write_buffer(cx) {
+            return Poll::Ready(Err(err));
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), futures::io::Error>> {
+        let this = self.get_mut();
+        match this.poll_write_buffer(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.io).poll_flush(cx),
+            res => res,
+        }
+    }
+
+    fn poll_close(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), futures::io::Error>> {
+        let this = self.get_mut();
+        match this.poll_write_buffer(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.io).poll_close(cx),
+            res => res,
+        }
+    }
+}
@@ -1,4 +1,6 @@
+use super::{read_uvarint_prefixed, write_uvarint_prefixed};
 use crate::error::{self, Error};
+use futures::{AsyncRead, AsyncWrite};
 
 // protobuf encode
 pub fn protobuf_encode<T>(payload: &T) -> Result<Vec<u8>, Error>
@@ -22,6 +24,25 @@ where
     T::from_reader(&mut reader, buf).map_err(|_| error::decode_error())
 }
 
+// write a protobuf message prefixed by its uvarint length
+pub async fn write_protobuf_prefixed<T, M>(io: &mut T, payload: &M) -> Result<(), Error>
+where
+    T: AsyncWrite + Unpin,
+    M: quick_protobuf::MessageWrite,
+{
+    write_uvarint_prefixed(io, &protobuf_encode(payload)?).await
+}
+
+// read a protobuf message prefixed by its uvarint length
+pub async fn read_protobuf_prefixed<T, M>(io: &mut T, max_len: usize) -> Result<M, Error>
+where
+    T: AsyncRead + Unpin,
+    M: for<'a> quick_protobuf::MessageRead<'a>,
+{
+    let buf = read_uvarint_prefixed(io, max_len).await?;
+    protobuf_decode(&buf)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,69 @@
+use super::{NoiseUpgradedStream, TlsUpgradedStream};
+use futures::{
+    task::{Context, Poll},
+    AsyncRead, AsyncWrite,
+};
+use std::pin::Pin;
+
+// SecuredStream of any negotiated security protocol
+pub enum SecuredStream<T> {
+    Noise(Box<NoiseUpgradedStream<T>>),
+    Tls(Box<TlsUpgradedStream<T>>),
+    Plaintext(T),
+}
+
+impl<T> AsyncRead for SecuredStream<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, futures::io::Error>> {
+        match self.get_mut() {
+            Self::Noise(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Plaintext(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl<T> AsyncWrite for SecuredStream<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, futures::io::Error>> {
+        match self.get_mut() {
+            Self::Noise(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Plaintext(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), futures::io::Error>> {
+        match self.get_mut() {
+            Self::Noise(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Tls(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Plaintext(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_close(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), futures::io::Error>> {
+        match self.get_mut() {
+            Self::Noise(stream) => Pin::new(stream).poll_close(cx),
+            Self::Tls(stream) => Pin::new(stream).poll_close(cx),
+            Self::Plaintext(stream) => Pin::new(stream).poll_close(cx),
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use crate::error::{self, Error};
+use async_io::Timer;
+use futures::{
+    future::{self, Either},
+    Future,
+};
+use std::time::Duration;
+
+// timeout fails the future if it is not ready within the duration
+pub async fn timeout<T, F>(duration: Duration, future: F) -> Result<T, Error>
+where
+    F: Future<Output = Result<T, Error>>,
+{
+    let future = std::pin::pin!(future);
+    match future::select(future, Timer::after(duration)).await {
+        Either::Left((res, _)) => res,
+        Either::Right(_) => Err(error::timed_out()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+
+    #[async_std::test]
+    async fn test_timeout() {
+        assert_eq!(
+            timeout(Duration::from_secs(1), async { Ok(1) })
+                .await
+                .unwrap(),
+            1
+        );
+        let res: Result<(), Error> = timeout(Duration::from_millis(10), future::pending()).await;
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::TimedOut);
+    }
+}
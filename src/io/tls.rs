@@ -0,0 +1,2 @@
+// TlsUpgradedStream
+pub type TlsUpgradedStream<T> = futures_rustls::TlsStream<T>;
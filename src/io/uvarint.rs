@@ -0,0 +1,143 @@
+use crate::error::{self, Error};
+use asynchronous_codec::{Decoder, Encoder};
+use bytes::BytesMut;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const MAX_UVARINT_LEN: usize = 10;
+
+// unsigned varint encode
+pub fn uvarint_encode(mut n: u64) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(MAX_UVARINT_LEN);
+    loop {
+        let b = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            buf.push(b);
+            return buf;
+        }
+        buf.push(b | 0x80);
+    }
+}
+
+// unsigned varint decode, returns the value and the number of bytes consumed
+pub fn uvarint_decode(buf: &[u8]) -> Result<Option<(u64, usize)>, Error> {
+    let mut n = 0u64;
+    for (i, b) in buf.iter().enumerate() {
+        if i >= MAX_UVARINT_LEN {
+            return Err(error::decode_error());
+        }
+        n |= u64::from(b & 0x7f) << (7 * i);
+        if b & 0x80 == 0 {
+            return Ok(Some((n, i + 1)));
+        }
+    }
+    if buf.len() >= MAX_UVARINT_LEN {
+        return Err(error::decode_error());
+    }
+    Ok(None)
+}
+
+// read an unsigned varint prefixed message without reading past its end
+pub async fn read_uvarint_prefixed<T>(io: &mut T, max_len: usize) -> Result<Vec<u8>, Error>
+where
+    T: AsyncRead + Unpin,
+{
+    let mut prefix = Vec::with_capacity(MAX_UVARINT_LEN);
+    let len = loop {
+        let mut b = [0u8; 1];
+        io.read_exact(&mut b).await?;
+        prefix.push(b[0]);
+        if let Some((len, _)) = uvarint_decode(&prefix)? {
+            break usize::try_from(len).map_err(|_| error::decode_error())?;
+        }
+    };
+    if len > max_len {
+        return Err(error::message_malformed());
+    }
+    let mut buf = vec![0u8; len];
+    io.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+// write an unsigned varint prefixed message
+pub async fn write_uvarint_prefixed<T>(io: &mut T, msg: &[u8]) -> Result<(), Error>
+where
+    T: AsyncWrite + Unpin,
+{
+    io.write_all(&[uvarint_encode(msg.len() as u64), msg.to_vec()].concat())
+        .await?;
+    io.flush().await
+}
+
+// UviLengthCodec
+pub struct UviLengthCodec {
+    max_len: usize,
+}
+
+impl UviLengthCodec {
+    pub fn new(max_len: usize) -> Self {
+        Self { max_len }
+    }
+}
+
+impl Encoder for UviLengthCodec {
+    type Item<'a> = &'a [u8];
+    type Error = Error;
+
+    fn encode(&mut self, item: Self::Item<'_>, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        if item.len() > self.max_len {
+            return Err(error::encode_error());
+        }
+        dst.extend_from_slice(&uvarint_encode(item.len() as u64));
+        dst.extend_from_slice(item);
+        Ok(())
+    }
+}
+
+impl Decoder for UviLengthCodec {
+    type Item = Vec<u8>;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let (len, prefix_len) = match uvarint_decode(src)? {
+            Some((len, prefix_len)) => (len as usize, prefix_len),
+            None => return Ok(None),
+        };
+        if len > self.max_len {
+            return Err(error::message_malformed());
+        }
+        if src.len() < prefix_len + len {
+            return Ok(None);
+        }
+        let msg = src.split_to(prefix_len + len);
+        Ok(Some(msg[prefix_len..].to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uvarint() -> Result<(), Error> {
+        assert_eq!(uvarint_encode(1), vec![0x01]);
+        assert_eq!(uvarint_encode(300), vec![0xac, 0x02]);
+        assert_eq!(uvarint_decode(&[0xac, 0x02, 0xff])?, Some((300, 2)));
+        assert_eq!(uvarint_decode(&[0xac])?, None);
+        assert!(uvarint_decode(&[0xff; 11]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_uvi_length_codec() -> Result<(), Error> {
+        let mut codec = UviLengthCodec::new(1024);
+
+        let mut buf = BytesMut::new();
+        codec.encode("abc".as_bytes(), &mut buf)?;
+        assert_eq!(&buf[..], b"\x03abc");
+        let dec = codec.decode(&mut buf)?;
+        assert_eq!(dec, Some(b"abc".to_vec()));
+        assert!(buf.is_empty());
+        Ok(())
+    }
+}
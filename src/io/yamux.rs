@@ -0,0 +1,194 @@
+use crate::error::{self, Error};
+use asynchronous_codec::{Decoder, Encoder};
+use bytes::{Buf, BytesMut};
+
+pub const YAMUX_VERSION: u8 = 0;
+pub const YAMUX_HEADER_SIZE: usize = 12;
+
+pub const YAMUX_FLAG_SYN: u16 = 0x1;
+pub const YAMUX_FLAG_ACK: u16 = 0x2;
+pub const YAMUX_FLAG_FIN: u16 = 0x4;
+pub const YAMUX_FLAG_RST: u16 = 0x8;
+
+pub const YAMUX_GO_AWAY_NORMAL: u32 = 0;
+pub const YAMUX_GO_AWAY_PROTOCOL_ERROR: u32 = 1;
+pub const YAMUX_GO_AWAY_INTERNAL_ERROR: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YamuxFrameType {
+    Data = 0,
+    WindowUpdate = 1,
+    Ping = 2,
+    GoAway = 3,
+}
+
+impl TryFrom<u8> for YamuxFrameType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Data),
+            1 => Ok(Self::WindowUpdate),
+            2 => Ok(Self::Ping),
+            3 => Ok(Self::GoAway),
+            _ => Err(error::message_malformed()),
+        }
+    }
+}
+
+// YamuxFrame
+//
+// `length` is the body length of data frames, the window delta of window
+// updates, the opaque value of pings and the error code of go away
+#[derive(Debug, Clone, PartialEq)]
+pub struct YamuxFrame {
+    pub frame_type: YamuxFrameType,
+    pub flags: u16,
+    pub stream_id: u32,
+    pub length: u32,
+    pub body: Vec<u8>,
+}
+
+impl YamuxFrame {
+    pub fn data(stream_id: u32, flags: u16, body: Vec<u8>) -> Self {
+        Self {
+            frame_type: YamuxFrameType::Data,
+            flags,
+            stream_id,
+            length: body.len() as u32,
+            body,
+        }
+    }
+
+    pub fn window_update(stream_id: u32, flags: u16, delta: u32) -> Self {
+        Self {
+            frame_type: YamuxFrameType::WindowUpdate,
+            flags,
+            stream_id,
+            length: delta,
+            body: Vec::new(),
+        }
+    }
+
+    pub fn ping(flags: u16, opaque: u32) -> Self {
+        Self {
+            frame_type: YamuxFrameType::Ping,
+            flags,
+            stream_id: 0,
+            length: opaque,
+            body: Vec::new(),
+        }
+    }
+
+    pub fn go_away(code: u32) -> Self {
+        Self {
+            frame_type: YamuxFrameType::GoAway,
+            flags: 0,
+            stream_id: 0,
+            length: code,
+            body: Vec::new(),
+        }
+    }
+
+    pub fn has_flag(&self, flag: u16) -> bool {
+        self.flags & flag != 0
+    }
+}
+
+// YamuxCodec
+pub struct YamuxCodec {
+    max_body_size: usize,
+}
+
+impl YamuxCodec {
+    pub fn new(max_body_size: usize) -> Self {
+        Self { max_body_size }
+    }
+}
+
+impl Encoder for YamuxCodec {
+    type Item<'a> = &'a YamuxFrame;
+    type Error = Error;
+
+    fn encode(&mut self, item: Self::Item<'_>, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        dst.reserve(YAMUX_HEADER_SIZE + item.body.len());
+        dst.extend_from_slice(&[YAMUX_VERSION, item.frame_type as u8]);
+        dst.extend_from_slice(&item.flags.to_be_bytes());
+        dst.extend_from_slice(&item.stream_id.to_be_bytes());
+        dst.extend_from_slice(&item.length.to_be_bytes());
+        dst.extend_from_slice(&item.body);
+        Ok(())
+    }
+}
+
+impl Decoder for YamuxCodec {
+    type Item = YamuxFrame;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if src.len() < YAMUX_HEADER_SIZE {
+            return Ok(None);
+        }
+        if src[0] != YAMUX_VERSION {
+            return Err(error::unsupported("yamux version"));
+        }
+        let frame_type = YamuxFrameType::try_from(src[1])?;
+        let flags = u16::from_be_bytes([src[2], src[3]]);
+        let stream_id = u32::from_be_bytes([src[4], src[5], src[6], src[7]]);
+        let length = u32::from_be_bytes([src[8], src[9], src[10], src[11]]);
+
+        let body_len = match frame_type {
+            YamuxFrameType::Data => length as usize,
+            _ => 0,
+        };
+        if body_len > self.max_body_size {
+            return Err(error::message_malformed());
+        }
+        if src.len() < YAMUX_HEADER_SIZE + body_len {
+            return Ok(None);
+        }
+        src.advance(YAMUX_HEADER_SIZE);
+        let body = src.split_to(body_len).to_vec();
+        Ok(Some(YamuxFrame {
+            frame_type,
+            flags,
+            stream_id,
+            length,
+            body,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_yamux_codec() -> Result<(), Error> {
+        let mut codec = YamuxCodec::new(1024);
+
+        let mut buf = BytesMut::new();
+        let frame = YamuxFrame::data(3, YAMUX_FLAG_SYN, b"abc".to_vec());
+        codec.encode(&frame, &mut buf)?;
+        assert_eq!(
+            &buf[..],
+            b"\x00\x00\x00\x01\x00\x00\x00\x03\x00\x00\x00\x03abc"
+        );
+
+        let ping = YamuxFrame::ping(YAMUX_FLAG_ACK, 7);
+        codec.encode(&ping, &mut buf)?;
+
+        // partial frames are left in the buffer
+        let mut partial = buf.split_to(13);
+        assert_eq!(codec.decode(&mut partial)?, None);
+        partial.unsplit(buf);
+        assert_eq!(codec.decode(&mut partial)?, Some(frame));
+        assert_eq!(codec.decode(&mut partial)?, Some(ping));
+        assert!(partial.is_empty());
+
+        let mut oversized = BytesMut::new();
+        codec.encode(&YamuxFrame::data(1, 0, vec![0u8; 2048]), &mut oversized)?;
+        assert!(codec.decode(&mut oversized).is_err());
+        Ok(())
+    }
+}
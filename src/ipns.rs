@@ -0,0 +1,498 @@
+use crate::{
+    cbor::{self, Value},
+    error::{self, Error},
+    identity::{PeerId, PrivateKey, PublicKey},
+    io::{protobuf_decode, protobuf_encode},
+    net::{Kademlia, Manager, RecordValidator},
+    payload::{
+        ipns::{mod_IpnsEntry::ValidityType, IpnsEntry},
+        keys::PublicKey as PublicKeyProto,
+    },
+};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+pub const IPNS_NAMESPACE: &str = "ipns";
+const MAX_RECORD_SIZE: usize = 10 * 1024;
+const SIGNATURE_V2_PREFIX: &[u8] = b"ipns-signature:";
+const DEFAULT_VALIDITY: Duration = Duration::from_secs(48 * 60 * 60);
+const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60);
+
+// the dht key of the records of the peer, /ipns/ then the peer id bytes
+pub fn ipns_key(peer_id: &PeerId) -> Result<Vec<u8>, Error> {
+    Ok([b"/ipns/".to_vec(), peer_id.to_bytes()?].concat())
+}
+
+fn key_peer_id(key: &[u8]) -> Result<PeerId, Error> {
+    let peer_id = key
+        .strip_prefix(b"/ipns/")
+        .ok_or_else(|| error::invalid_input("not an ipns key"))?;
+    PeerId::from_bytes(peer_id)
+}
+
+// IpnsRecord
+//
+// A value, usually an /ipfs/<cid> path, signed by a peer and valid until
+// its end of life. The data signed is the dag-cbor of the fields, the V1
+// signature is kept for older readers only.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IpnsRecord {
+    pub value: Vec<u8>,
+    pub sequence: u64,
+    pub validity: SystemTime,
+    // how long the record may be cached
+    pub ttl: Duration,
+    entry: IpnsEntry,
+}
+
+impl IpnsRecord {
+    pub fn new(
+        private_key: &PrivateKey,
+        value: impl Into<Vec<u8>>,
+        sequence: u64,
+        validity: SystemTime,
+        ttl: Duration,
+    ) -> Result<Self, Error> {
+        let value = value.into();
+        let validity_bytes = format_time(validity)?.into_bytes();
+        let ttl_nanos = u64::try_from(ttl.as_nanos()).map_err(|_| error::encode_error())?;
+        let data = cbor::encode(&Value::Map(BTreeMap::from([
+            ("Value".to_string(), Value::Bytes(value.clone())),
+            ("Validity".to_string(), Value::Bytes(validity_bytes.clone())),
+            ("ValidityType".to_string(), Value::Integer(0)),
+            ("Sequence".to_string(), Value::Integer(sequence.into())),
+            ("TTL".to_string(), Value::Integer(ttl_nanos.into())),
+        ])))?;
+        let signature_v1 = [value.as_slice(), &validity_bytes, b"EOL"].concat();
+        let signature_v2 = [SIGNATURE_V2_PREFIX, &data].concat();
+        // the key of ed25519 peer ids is inlined in the peer id
+        let entry = IpnsEntry {
+            value: Some(value.clone()),
+            signatureV1: Some(private_key.sign(&signature_v1)),
+            validityType: Some(ValidityType::EOL),
+            validity: Some(validity_bytes),
+            sequence: Some(sequence),
+            ttl: Some(ttl_nanos),
+            pubKey: None,
+            signatureV2: Some(private_key.sign(&signature_v2)),
+            data: Some(data),
+        };
+        Ok(Self {
+            value,
+            sequence,
+            validity,
+            ttl,
+            entry,
+        })
+    }
+
+    // a record of the peer, its signature, key and validity are checked
+    pub fn from_bytes(bytes: &[u8], peer_id: &PeerId) -> Result<Self, Error> {
+        if bytes.len() > MAX_RECORD_SIZE {
+            return Err(error::invalid_data("ipns record too large"));
+        }
+        let entry: IpnsEntry = protobuf_decode(bytes)?;
+        let (Some(signature), Some(data)) = (&entry.signatureV2, &entry.data) else {
+            return Err(error::unsupported("ipns record without a V2 signature"));
+        };
+        let public_key = match &entry.pubKey {
+            Some(public_key) => {
+                PublicKey::try_from(protobuf_decode::<PublicKeyProto>(public_key)?)?
+            }
+            None => PublicKey::try_from(peer_id.clone())?,
+        };
+        if &TryInto::<PeerId>::try_into(public_key.clone())? != peer_id {
+            return Err(error::verification_failed());
+        }
+        public_key.verify(&[SIGNATURE_V2_PREFIX, data].concat(), signature)?;
+
+        let data = cbor::decode(data)?;
+        let field = |name| data.get(name).ok_or_else(error::decode_error);
+        let value = field("Value")?.as_bytes().ok_or_else(error::decode_error)?;
+        let validity = field("Validity")?
+            .as_bytes()
+            .ok_or_else(error::decode_error)?;
+        let sequence = field("Sequence")?
+            .as_u64()
+            .ok_or_else(error::decode_error)?;
+        let ttl = field("TTL")?.as_u64().ok_or_else(error::decode_error)?;
+        if field("ValidityType")?.as_u64() != Some(0) {
+            return Err(error::unsupported("ipns validity type"));
+        }
+        // the fields outside the data are not signed, they must match it
+        if entry.value.as_ref().is_some_and(|v| v != value)
+            || entry.validity.as_ref().is_some_and(|v| v != validity)
+            || entry.sequence.is_some_and(|s| s != sequence)
+            || entry.ttl.is_some_and(|t| t != ttl)
+            || entry.validityType.is_some_and(|t| t != ValidityType::EOL)
+        {
+            return Err(error::verification_failed());
+        }
+
+        let validity = std::str::from_utf8(validity)
+            .map_err(|_| error::decode_error())
+            .and_then(parse_time)?;
+        if validity <= SystemTime::now() {
+            return Err(error::invalid_data("ipns record expired"));
+        }
+        Ok(Self {
+            value: value.to_vec(),
+            sequence,
+            validity,
+            ttl: Duration::from_nanos(ttl),
+            entry,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        protobuf_encode(&self.entry)
+    }
+}
+
+// IpnsValidator of the /ipns/ records of the DHT, the highest sequence is
+// the best record and then the latest validity
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IpnsValidator;
+
+impl RecordValidator for IpnsValidator {
+    fn validate(&self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        IpnsRecord::from_bytes(value, &key_peer_id(key)?).map(|_| ())
+    }
+
+    fn select(&self, key: &[u8], values: &[Vec<u8>]) -> Result<usize, Error> {
+        let peer_id = key_peer_id(key)?;
+        let mut best: Option<(usize, IpnsRecord)> = None;
+        for (index, value) in values.iter().enumerate() {
+            let Ok(record) = IpnsRecord::from_bytes(value, &peer_id) else {
+                continue;
+            };
+            let better = match &best {
+                Some((_, best)) => {
+                    (record.sequence, record.validity) > (best.sequence, best.validity)
+                }
+                None => true,
+            };
+            if better {
+                best = Some((index, record));
+            }
+        }
+        best.map(|(index, _)| index)
+            .ok_or_else(|| error::not_found("no valid ipns record"))
+    }
+}
+
+// Ipns
+//
+// Publishes the records of the local peer and resolves the records of any
+// peer through the DHT
+#[derive(Clone)]
+pub struct Ipns {
+    kademlia: Kademlia,
+    private_key: PrivateKey,
+    validity: Duration,
+    ttl: Duration,
+    // the sequence published last, not to go back when the DHT lost it
+    sequence: Arc<Mutex<Option<u64>>>,
+}
+
+impl Ipns {
+    pub fn new(kademlia: Kademlia, private_key: PrivateKey) -> Self {
+        Self {
+            kademlia,
+            private_key,
+            validity: DEFAULT_VALIDITY,
+            ttl: DEFAULT_TTL,
+            sequence: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    // how long the records published are valid
+    pub fn with_validity(mut self, validity: Duration) -> Self {
+        self.validity = validity;
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    // publish the value under the local peer id, with a sequence above the
+    // one of the record found in the DHT
+    pub async fn publish(
+        &self,
+        manager: &Manager,
+        value: impl Into<Vec<u8>>,
+    ) -> Result<IpnsRecord, Error> {
+        let peer_id: PeerId = self.private_key.public().try_into()?;
+        let found = match self.resolve(manager, &peer_id).await {
+            Ok(record) => Some(record.sequence + 1),
+            Err(err) => {
+                log::debug!("no ipns record of {:?} found, {:?}", peer_id, err);
+                None
+            }
+        };
+        let published = self.sequence.lock().unwrap().map(|sequence| sequence + 1);
+        let sequence = found.max(published).unwrap_or(0);
+
+        let validity = SystemTime::now() + self.validity;
+        let record = IpnsRecord::new(&self.private_key, value, sequence, validity, self.ttl)?;
+        let stored = self
+            .kademlia
+            .put_value(manager, &ipns_key(&peer_id)?, record.to_bytes()?)
+            .await?;
+        if stored == 0 {
+            return Err(error::other("ipns record stored by no peer"));
+        }
+        log::debug!("ipns record {} stored by {} peers", sequence, stored);
+        *self.sequence.lock().unwrap() = Some(sequence);
+        Ok(record)
+    }
+
+    // the best valid record of the peer in the DHT
+    pub async fn resolve(&self, manager: &Manager, peer_id: &PeerId) -> Result<IpnsRecord, Error> {
+        let value = self
+            .kademlia
+            .get_value(manager, &ipns_key(peer_id)?)
+            .await?;
+        IpnsRecord::from_bytes(&value, peer_id)
+    }
+}
+
+// the validity of the records, RFC 3339 in UTC with the trailing zeros of
+// the nanoseconds dropped
+fn format_time(time: SystemTime) -> Result<String, Error> {
+    let since_epoch = time
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| error::invalid_input("time before the epoch"))?;
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let secs = secs % 86400;
+    let mut time = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    );
+    if since_epoch.subsec_nanos() > 0 {
+        let nanos = format!(".{:09}", since_epoch.subsec_nanos());
+        time.push_str(nanos.trim_end_matches('0'));
+    }
+    time.push('Z');
+    Ok(time)
+}
+
+fn parse_time(time: &str) -> Result<SystemTime, Error> {
+    let number = |range: std::ops::Range<usize>| {
+        time.get(range)
+            .filter(|digits| digits.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|digits| digits.parse::<i64>().ok())
+            .ok_or_else(error::parse_error)
+    };
+    let separators = [(4, b'-'), (7, b'-'), (10, b'T'), (13, b':'), (16, b':')];
+    if time.len() < 20 || separators.iter().any(|(i, c)| time.as_bytes()[*i] != *c) {
+        return Err(error::parse_error());
+    }
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    // a second of 60 is a leap second
+    if !(1..=12).contains(&month)
+        || !(1..=days_in_month(year, month)).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return Err(error::parse_error());
+    }
+
+    let mut rest = &time[19..];
+    let mut nanos = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if !(1..=9).contains(&digits) {
+            return Err(error::parse_error());
+        }
+        nanos = number(20..20 + digits)? * 10i64.pow(9 - digits as u32);
+        rest = &fraction[digits..];
+    }
+    let offset = match rest.as_bytes() {
+        b"Z" => 0,
+        [sign @ (b'+' | b'-'), _, _, b':', _, _] => {
+            let start = time.len() - 5;
+            let offset = number(start..start + 2)? * 3600 + number(start + 3..start + 5)? * 60;
+            if *sign == b'+' {
+                offset
+            } else {
+                -offset
+            }
+        }
+        _ => return Err(error::parse_error()),
+    };
+
+    let secs =
+        days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+    let secs = u64::try_from(secs).map_err(|_| error::parse_error())?;
+    Ok(UNIX_EPOCH + Duration::new(secs, nanos as u32))
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// the date of the days since the epoch, in the proleptic gregorian calendar
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * shifted_month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::test_support::spawn_kad_servers;
+
+    fn record(private_key: &PrivateKey, sequence: u64, validity: Duration) -> IpnsRecord {
+        let validity = SystemTime::now() + validity;
+        IpnsRecord::new(private_key, "/ipfs/value", sequence, validity, DEFAULT_TTL).unwrap()
+    }
+
+    #[test]
+    fn test_ipns_time() -> Result<(), Error> {
+        let time = UNIX_EPOCH + Duration::new(1672628645, 500_000_000);
+        assert_eq!(format_time(time)?, "2023-01-02T03:04:05.5Z");
+        assert_eq!(parse_time("2023-01-02T03:04:05.5Z")?, time);
+        assert_eq!(parse_time("2023-01-02T04:04:05.500+01:00")?, time);
+        assert_eq!(format_time(UNIX_EPOCH)?, "1970-01-01T00:00:00Z");
+        assert_eq!(
+            format_time(UNIX_EPOCH + Duration::new(951782400, 1))?,
+            "2000-02-29T00:00:00.000000001Z"
+        );
+        assert!(parse_time("2023-01-02 03:04:05Z").is_err());
+        assert!(parse_time("2023-13-02T03:04:05Z").is_err());
+        assert!(parse_time("2023-02-29T03:04:05Z").is_err());
+        assert!(parse_time("2023-04-31T03:04:05Z").is_err());
+        assert!(parse_time("2023-01-02T03:04:61Z").is_err());
+        assert!(parse_time("2024-02-29T03:04:05Z").is_ok());
+        assert_eq!(
+            parse_time("2016-12-31T23:59:60Z")?,
+            parse_time("2017-01-01T00:00:00Z")?
+        );
+        assert!(parse_time("2023-01-02T03:04:05.Z").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ipns_record() -> Result<(), Error> {
+        let private_key = PrivateKey::generate_ed25519();
+        let peer_id: PeerId = private_key.public().try_into()?;
+        let record = record(&private_key, 3, Duration::from_secs(60));
+        let bytes = record.to_bytes()?;
+        let parsed = IpnsRecord::from_bytes(&bytes, &peer_id)?;
+        assert_eq!(parsed.value, b"/ipfs/value");
+        assert_eq!((parsed.sequence, parsed.ttl), (3, DEFAULT_TTL));
+        assert_eq!(format_time(parsed.validity)?, format_time(record.validity)?);
+
+        // another peer, fields not matching the signed data, an expired record
+        let other: PeerId = PrivateKey::generate_ed25519().public().try_into()?;
+        assert!(IpnsRecord::from_bytes(&bytes, &other).is_err());
+        let mut entry = record.entry.clone();
+        entry.sequence = Some(4);
+        assert!(IpnsRecord::from_bytes(&protobuf_encode(&entry)?, &peer_id).is_err());
+        entry.sequence = None;
+        assert!(IpnsRecord::from_bytes(&protobuf_encode(&entry)?, &peer_id).is_ok());
+        entry.signatureV2 = None;
+        assert!(IpnsRecord::from_bytes(&protobuf_encode(&entry)?, &peer_id).is_err());
+        let expired = IpnsRecord::new(&private_key, "/ipfs/value", 4, UNIX_EPOCH, DEFAULT_TTL)?;
+        assert!(IpnsRecord::from_bytes(&expired.to_bytes()?, &peer_id).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ipns_validator() -> Result<(), Error> {
+        let private_key = PrivateKey::generate_ed25519();
+        let key = ipns_key(&private_key.public().try_into()?)?;
+        let values = [
+            record(&private_key, 1, Duration::from_secs(60)).to_bytes()?,
+            record(&private_key, 2, Duration::from_secs(60)).to_bytes()?,
+            record(&private_key, 2, Duration::from_secs(120)).to_bytes()?,
+            record(&private_key, 5, Duration::ZERO).to_bytes()?,
+        ];
+        assert_eq!(IpnsValidator.select(&key, &values)?, 2);
+        assert_eq!(IpnsValidator.select(&key, &values[..2])?, 1);
+        assert!(IpnsValidator.validate(&key, &values[0]).is_ok());
+        assert!(IpnsValidator.validate(&key, &values[3]).is_err());
+        assert!(IpnsValidator.validate(b"/ipns/key", &values[0]).is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_ipns_dht() -> Result<(), Error> {
+        let nodes = spawn_kad_servers(4).await?;
+        // the names are of keys other than those of the nodes
+        let private_key = PrivateKey::generate_ed25519();
+        let other_key = PrivateKey::generate_ed25519();
+        let (manager, kad, _) = &nodes[0];
+        let peer_id: PeerId = private_key.public().try_into()?;
+        let ipns = Ipns::new(kad.clone(), private_key.clone());
+        assert_eq!(ipns.publish(manager, "/ipfs/first").await?.sequence, 0);
+
+        let (other_manager, other_kad, _) = &nodes[1];
+        let resolver = Ipns::new(other_kad.clone(), other_key.clone());
+        let resolved = resolver.resolve(other_manager, &peer_id).await?;
+        assert_eq!(resolved.value, b"/ipfs/first");
+
+        // published again with the next sequence, the older record is
+        // refused by the servers
+        let stale = record(&private_key, 0, Duration::from_secs(60)).to_bytes()?;
+        assert_eq!(ipns.publish(manager, "/ipfs/second").await?.sequence, 1);
+        let resolved = resolver.resolve(other_manager, &peer_id).await?;
+        assert_eq!(
+            (resolved.value, resolved.sequence),
+            (b"/ipfs/second".to_vec(), 1)
+        );
+        let key = ipns_key(&peer_id)?;
+        assert_eq!(kad.put_value(manager, &key, stale).await?, 0);
+
+        // records of another peer under the key
+        let forged = record(&other_key, 2, Duration::from_secs(60)).to_bytes()?;
+        assert!(other_kad
+            .put_value(other_manager, &key, forged)
+            .await
+            .is_err());
+        Ok(())
+    }
+}
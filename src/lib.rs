@@ -1,5 +1,10 @@
+pub mod car;
+pub mod cbor;
+pub mod cid;
 pub mod error;
 pub mod identity;
 pub mod io;
+pub mod ipns;
 pub mod net;
 pub mod payload;
+pub mod unixfs;
@@ -1,11 +1,55 @@
+mod autonat;
+mod bitswap;
 mod connection;
+mod dcutr;
+mod envelope;
+mod identify;
+mod kad;
+mod mplex;
 mod multiaddr;
 mod multistream;
+mod muxer;
 mod noise;
+mod ping;
+#[cfg(feature = "plaintext")]
+mod plaintext;
+mod pnet;
+mod pubsub;
+mod registry;
+mod relay;
+mod rendezvous;
+mod security;
+mod tcp;
+#[cfg(test)]
+pub(crate) mod test_support;
+mod tls;
 mod upgrade;
+mod writer;
+mod yamux;
 
+pub use autonat::*;
+pub use bitswap::*;
 pub use connection::*;
+pub use dcutr::*;
+pub use envelope::*;
+pub use identify::*;
+pub use kad::*;
+pub use mplex::*;
 pub use multiaddr::*;
 pub use multistream::*;
+pub use muxer::*;
 pub use noise::*;
+pub use ping::*;
+#[cfg(feature = "plaintext")]
+pub use plaintext::*;
+pub use pnet::*;
+pub use pubsub::*;
+pub use registry::*;
+pub use relay::*;
+pub use rendezvous::*;
+pub use security::*;
+pub use tcp::*;
+pub use tls::*;
 pub use upgrade::*;
+pub(crate) use writer::*;
+pub use yamux::*;
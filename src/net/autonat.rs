@@ -0,0 +1,18 @@
+mod client;
+mod server;
+
+use crate::{error::Error, io, payload::autonat::Message};
+
+pub use client::{AutoNat, NatStatus};
+pub use server::AutoNatServer;
+
+pub const PROTOCOL_AUTONAT: &str = "/libp2p/autonat/1.0.0";
+const MAX_AUTONAT_MESSAGE_SIZE: usize = 4096;
+
+async fn write_message(stream: &mut io::BoxedStream, message: &Message) -> Result<(), Error> {
+    io::write_protobuf_prefixed(stream, message).await
+}
+
+async fn read_message(stream: &mut io::BoxedStream) -> Result<Message, Error> {
+    io::read_protobuf_prefixed(stream, MAX_AUTONAT_MESSAGE_SIZE).await
+}
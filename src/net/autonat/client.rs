@@ -0,0 +1,305 @@
+use super::{read_message, write_message, PROTOCOL_AUTONAT};
+use crate::{
+    error::{self, Error},
+    identity::PeerId,
+    io,
+    net::{Connection, Identify, Manager},
+    payload::autonat::{
+        mod_Message::{Dial, MessageType, PeerInfo, ResponseStatus},
+        Message,
+    },
+};
+use async_io::Timer;
+use futures::{channel::mpsc, AsyncWriteExt};
+use multiaddr::Multiaddr;
+use rand::seq::SliceRandom;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(15 * 60);
+// while the status is unknown
+const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_secs(90);
+const DEFAULT_MAX_CONFIDENCE: usize = 3;
+const DEFAULT_PEERS_PER_ROUND: usize = 3;
+
+// NatStatus of the reachability of the local peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NatStatus {
+    #[default]
+    Unknown,
+    // reachable at its observed addresses
+    Public,
+    // behind a NAT or a firewall, to be reached through relays
+    Private,
+}
+
+#[derive(Default)]
+struct Reachability {
+    status: NatStatus,
+    confidence: usize,
+    public_addr: Option<Multiaddr>,
+    subscribers: Vec<mpsc::UnboundedSender<NatStatus>>,
+}
+
+// AutoNat
+//
+// Asks connected peers to dial back the observed addresses of the local
+// peer, the status changes only once the results against the current one
+// outweigh the confidence built up in it
+#[derive(Clone)]
+pub struct AutoNat {
+    local_peer_id: PeerId,
+    observed_addrs: Arc<Mutex<Vec<Multiaddr>>>,
+    timeout: Duration,
+    interval: Duration,
+    retry_interval: Duration,
+    max_confidence: usize,
+    peers_per_round: usize,
+    identify: Option<Identify>,
+    reachability: Arc<Mutex<Reachability>>,
+}
+
+impl AutoNat {
+    pub fn new(local_peer_id: PeerId) -> Self {
+        Self {
+            local_peer_id,
+            observed_addrs: Arc::new(Mutex::new(Vec::new())),
+            timeout: DEFAULT_TIMEOUT,
+            interval: DEFAULT_INTERVAL,
+            retry_interval: DEFAULT_RETRY_INTERVAL,
+            max_confidence: DEFAULT_MAX_CONFIDENCE,
+            peers_per_round: DEFAULT_PEERS_PER_ROUND,
+            identify: None,
+            reachability: Arc::new(Mutex::new(Reachability::default())),
+        }
+    }
+
+    // timeout of a probe, including the dial back
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // probes are repeated after the interval, or the retry interval while
+    // the status is unknown
+    pub fn with_interval(mut self, interval: Duration, retry_interval: Duration) -> Self {
+        self.interval = interval;
+        self.retry_interval = retry_interval;
+        self
+    }
+
+    pub fn with_max_confidence(mut self, max_confidence: usize) -> Self {
+        self.max_confidence = max_confidence;
+        self
+    }
+
+    // peers probed in a round of run
+    pub fn with_peers_per_round(mut self, peers_per_round: usize) -> Self {
+        self.peers_per_round = peers_per_round;
+        self
+    }
+
+    // addresses of the local peer as observed by others, such as through identify
+    pub fn with_observed_addrs(self, observed_addrs: Vec<Multiaddr>) -> Self {
+        *self.observed_addrs.lock().unwrap() = observed_addrs;
+        self
+    }
+
+    // the observed addresses are learned by identifying the probed peers
+    pub fn with_identify(mut self, identify: Identify) -> Self {
+        self.identify = Some(identify);
+        self
+    }
+
+    pub fn add_observed_addr(&self, addr: Multiaddr) {
+        let mut observed_addrs = self.observed_addrs.lock().unwrap();
+        if !observed_addrs.contains(&addr) {
+            observed_addrs.push(addr);
+        }
+    }
+
+    pub fn status(&self) -> NatStatus {
+        self.reachability.lock().unwrap().status
+    }
+
+    pub fn confidence(&self) -> usize {
+        self.reachability.lock().unwrap().confidence
+    }
+
+    // the address dialed back while public
+    pub fn public_addr(&self) -> Option<Multiaddr> {
+        self.reachability.lock().unwrap().public_addr.clone()
+    }
+
+    // changes of the status, such as to reserve on relays once private
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<NatStatus> {
+        let (tx, rx) = mpsc::unbounded();
+        self.reachability.lock().unwrap().subscribers.push(tx);
+        rx
+    }
+
+    // ask the remote to dial back, refusals do not count towards the status
+    pub async fn probe(&self, connection: &Connection) -> Result<NatStatus, Error> {
+        if connection.info().relay.is_some() {
+            return Err(error::invalid_input("connection is relayed"));
+        }
+        let addrs: Vec<Vec<u8>> = self
+            .observed_addrs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|addr| addr.to_vec())
+            .collect();
+        if addrs.is_empty() {
+            return Err(error::not_found("no observed address"));
+        }
+
+        let response = io::timeout(self.timeout, async {
+            let mut stream = connection.open_stream(PROTOCOL_AUTONAT).await?;
+            let request = Message {
+                type_pb: Some(MessageType::DIAL),
+                dial: Some(Dial {
+                    peer: Some(PeerInfo {
+                        id: Some(self.local_peer_id.to_bytes()?),
+                        addrs,
+                    }),
+                }),
+                dialResponse: None,
+            };
+            write_message(&mut stream, &request).await?;
+            let response = read_message(&mut stream).await?;
+            stream.close().await?;
+            Ok(response)
+        })
+        .await?;
+        if response.type_pb != Some(MessageType::DIAL_RESPONSE) {
+            return Err(error::message_malformed());
+        }
+        let response = response.dialResponse.ok_or(error::message_malformed())?;
+
+        let status = match response.status {
+            Some(ResponseStatus::OK) => NatStatus::Public,
+            Some(ResponseStatus::E_DIAL_ERROR) => NatStatus::Private,
+            status => {
+                return Err(error::other(&format!(
+                    "autonat {:?} {}",
+                    status,
+                    response.statusText.unwrap_or_default()
+                )))
+            }
+        };
+        let addr = response
+            .addr
+            .and_then(|addr| Multiaddr::try_from(addr).ok());
+        log::debug!(
+            "autonat {:?} by {:?} at {:?}",
+            status,
+            connection.info().peer_id,
+            addr
+        );
+        self.record(status, addr);
+        Ok(status)
+    }
+
+    // probe some of the direct connections at every interval, picked at
+    // random so that every peer gets its turn
+    pub async fn run(&self, manager: &Manager) {
+        loop {
+            let mut connections: Vec<Connection> = manager
+                .connections()
+                .into_iter()
+                .filter(|connection| connection.info().relay.is_none())
+                .collect();
+            connections.shuffle(&mut rand::thread_rng());
+            connections.truncate(self.peers_per_round);
+            for connection in connections {
+                if let Err(err) = self.observe(&connection).await {
+                    log::debug!("autonat identify failed, {:?}", err);
+                }
+                if let Err(err) = self.probe(&connection).await {
+                    log::debug!("autonat probe failed, {:?}", err);
+                }
+            }
+            let interval = match self.status() {
+                NatStatus::Unknown => self.retry_interval,
+                _ => self.interval,
+            };
+            Timer::after(interval).await;
+        }
+    }
+
+    // take the address the remote observed us at, identified before or now
+    async fn observe(&self, connection: &Connection) -> Result<(), Error> {
+        let Some(identify) = &self.identify else {
+            return Ok(());
+        };
+        let info = match identify.peer_info(&connection.info().peer_id) {
+            Some(info) => info,
+            None => identify.identify(connection).await?,
+        };
+        if let Some(addr) = info.observed_addr {
+            self.add_observed_addr(addr);
+        }
+        Ok(())
+    }
+
+    // count the result of a probe towards the status
+    pub(crate) fn record(&self, status: NatStatus, addr: Option<Multiaddr>) {
+        let mut reachability = self.reachability.lock().unwrap();
+        if reachability.status == status {
+            reachability.confidence = (reachability.confidence + 1).min(self.max_confidence);
+            reachability.public_addr = addr;
+            return;
+        }
+        if reachability.status != NatStatus::Unknown && reachability.confidence > 0 {
+            reachability.confidence -= 1;
+            return;
+        }
+
+        log::info!("nat status {:?}", status);
+        reachability.status = status;
+        reachability.confidence = 0;
+        reachability.public_addr = addr;
+        reachability
+            .subscribers
+            .retain(|subscriber| subscriber.unbounded_send(status).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::PrivateKey;
+    use futures::StreamExt;
+
+    #[async_std::test]
+    async fn test_autonat_confidence() -> Result<(), Error> {
+        let autonat = AutoNat::new(PrivateKey::generate_ed25519().public().try_into()?)
+            .with_max_confidence(2);
+        let mut changes = autonat.subscribe();
+        let addr: Multiaddr = "/ip4/1.2.3.4/tcp/4001".parse().unwrap();
+
+        autonat.record(NatStatus::Public, Some(addr.clone()));
+        assert_eq!(autonat.status(), NatStatus::Public);
+        assert_eq!(autonat.public_addr(), Some(addr.clone()));
+        autonat.record(NatStatus::Public, Some(addr.clone()));
+        autonat.record(NatStatus::Public, Some(addr.clone()));
+        autonat.record(NatStatus::Public, Some(addr));
+        assert_eq!(autonat.confidence(), 2);
+
+        // one failure more than the confidence flips the status
+        autonat.record(NatStatus::Private, None);
+        autonat.record(NatStatus::Private, None);
+        assert_eq!(autonat.status(), NatStatus::Public);
+        autonat.record(NatStatus::Private, None);
+        assert_eq!(autonat.status(), NatStatus::Private);
+        assert_eq!(autonat.public_addr(), None);
+
+        assert_eq!(changes.next().await, Some(NatStatus::Public));
+        assert_eq!(changes.next().await, Some(NatStatus::Private));
+        Ok(())
+    }
+}
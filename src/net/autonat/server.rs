@@ -0,0 +1,394 @@
+use super::{read_message, write_message, PROTOCOL_AUTONAT};
+use crate::{
+    error::{self, Error},
+    identity::{PeerId, PrivateKey},
+    io,
+    net::{
+        tcpaddr_to_multiaddr, Connection, Pnet, PreSharedKey, Protocol, ProtocolRegistry,
+        SecurityProtocol, SecurityUpgrader, UpgradeOutbound,
+    },
+    payload::autonat::{
+        mod_Message::{DialResponse, MessageType, ResponseStatus},
+        Message,
+    },
+};
+use async_io::Async;
+use futures::AsyncWriteExt;
+use multiaddr::{Multiaddr, Protocol as AddrProtocol};
+use std::{
+    collections::VecDeque,
+    net::{IpAddr, SocketAddr, TcpStream},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+const DEFAULT_DIAL_TIMEOUT: Duration = Duration::from_secs(15);
+const DEFAULT_THROTTLE_INTERVAL: Duration = Duration::from_secs(60);
+const DEFAULT_MAX_DIALS: usize = 30;
+const DEFAULT_MAX_DIALS_PER_PEER: usize = 3;
+// addresses dialed back per request, all within the dial timeout
+const MAX_DIAL_ADDRS: usize = 4;
+
+// AutoNatServer
+//
+// Dials back the addresses of peers asking for their reachability, only
+// those at the ip address the request came from so that it can not be used
+// to dial third parties
+#[derive(Clone)]
+pub struct AutoNatServer {
+    private_key: PrivateKey,
+    pre_shared_key: Option<PreSharedKey>,
+    security_protocols: Vec<SecurityProtocol>,
+    dial_timeout: Duration,
+    throttle_interval: Duration,
+    max_dials: usize,
+    max_dials_per_peer: usize,
+    allow_private_addrs: bool,
+    dials: Arc<Mutex<VecDeque<(Instant, PeerId)>>>,
+}
+
+impl AutoNatServer {
+    pub fn new(private_key: PrivateKey) -> Self {
+        Self {
+            private_key,
+            pre_shared_key: None,
+            security_protocols: SecurityProtocol::defaults(),
+            dial_timeout: DEFAULT_DIAL_TIMEOUT,
+            throttle_interval: DEFAULT_THROTTLE_INTERVAL,
+            max_dials: DEFAULT_MAX_DIALS,
+            max_dials_per_peer: DEFAULT_MAX_DIALS_PER_PEER,
+            allow_private_addrs: false,
+            dials: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    // dial back within the private network
+    pub fn with_pre_shared_key(mut self, pre_shared_key: PreSharedKey) -> Self {
+        self.pre_shared_key = Some(pre_shared_key);
+        self
+    }
+
+    pub fn with_security_protocols(mut self, security_protocols: Vec<SecurityProtocol>) -> Self {
+        self.security_protocols = security_protocols;
+        self
+    }
+
+    pub fn with_dial_timeout(mut self, dial_timeout: Duration) -> Self {
+        self.dial_timeout = dial_timeout;
+        self
+    }
+
+    // dial backs within the interval, of all peers and of each one
+    pub fn with_throttle(
+        mut self,
+        throttle_interval: Duration,
+        max_dials: usize,
+        max_dials_per_peer: usize,
+    ) -> Self {
+        self.throttle_interval = throttle_interval;
+        self.max_dials = max_dials;
+        self.max_dials_per_peer = max_dials_per_peer;
+        self
+    }
+
+    // dial back private and loopback addresses, such as on a local network
+    pub fn with_private_addrs(mut self, allow_private_addrs: bool) -> Self {
+        self.allow_private_addrs = allow_private_addrs;
+        self
+    }
+
+    async fn respond(
+        self,
+        connection: Connection,
+        mut stream: io::BoxedStream,
+    ) -> Result<(), Error> {
+        let request = io::timeout(self.dial_timeout, read_message(&mut stream)).await?;
+        let response = self.handle_dial(&connection, request).await;
+        log::debug!(
+            "autonat {:?} for {:?}",
+            response.status,
+            connection.info().peer_id
+        );
+        let response = Message {
+            type_pb: Some(MessageType::DIAL_RESPONSE),
+            dial: None,
+            dialResponse: Some(response),
+        };
+        write_message(&mut stream, &response).await?;
+        stream.close().await
+    }
+
+    async fn handle_dial(&self, connection: &Connection, request: Message) -> DialResponse {
+        let peer = match (request.type_pb, request.dial.and_then(|dial| dial.peer)) {
+            (Some(MessageType::DIAL), Some(peer)) => peer,
+            _ => return dial_response(ResponseStatus::E_BAD_REQUEST, "malformed dial"),
+        };
+        let peer_id = &connection.info().peer_id;
+        if peer
+            .id
+            .map(|id| PeerId::from_bytes(&id))
+            .transpose()
+            .ok()
+            .flatten()
+            != Some(peer_id.clone())
+        {
+            return dial_response(ResponseStatus::E_BAD_REQUEST, "peer id mismatch");
+        }
+        if connection.info().relay.is_some() {
+            return dial_response(ResponseStatus::E_DIAL_REFUSED, "relayed connection");
+        }
+
+        let addrs = self.dialable_addrs(connection.info().remote_addr.ip(), &peer.addrs);
+        if addrs.is_empty() {
+            return dial_response(ResponseStatus::E_DIAL_REFUSED, "no dialable address");
+        }
+        if !self.throttle(peer_id) {
+            return dial_response(ResponseStatus::E_DIAL_REFUSED, "too many dials");
+        }
+
+        let dial_back = async {
+            for addr in addrs {
+                match self.dial_back(peer_id, addr).await {
+                    Ok(()) => return Ok(addr),
+                    Err(err) => log::debug!("autonat dial back to {:?} failed, {:?}", addr, err),
+                }
+            }
+            Err(error::other("dial back failed"))
+        };
+        match io::timeout(self.dial_timeout, dial_back).await {
+            Ok(addr) => DialResponse {
+                status: Some(ResponseStatus::OK),
+                statusText: None,
+                addr: Some(tcpaddr_to_multiaddr(&addr).to_vec()),
+            },
+            Err(_) => dial_response(ResponseStatus::E_DIAL_ERROR, "dial back failed"),
+        }
+    }
+
+    // ip addresses of the requester only, the others are dropped, up to
+    // the max dial addresses
+    fn dialable_addrs(&self, observed_ip: IpAddr, addrs: &[Vec<u8>]) -> Vec<SocketAddr> {
+        let mut dialable: Vec<SocketAddr> = Vec::new();
+        for addr in addrs {
+            let Ok(addr) = Multiaddr::try_from(addr.clone()) else {
+                continue;
+            };
+            let mut ip = None;
+            let mut port = None;
+            for protocol in addr.iter() {
+                match protocol {
+                    AddrProtocol::Ip4(ipv4) => ip = Some(IpAddr::V4(ipv4)),
+                    AddrProtocol::Ip6(ipv6) => ip = Some(IpAddr::V6(ipv6)),
+                    AddrProtocol::Tcp(tcp_port) => port = Some(tcp_port),
+                    AddrProtocol::P2p(_) => {}
+                    _ => {
+                        ip = None;
+                        break;
+                    }
+                }
+            }
+            let (Some(ip), Some(port)) = (ip, port) else {
+                continue;
+            };
+            if ip != observed_ip || !(self.allow_private_addrs || is_public_ip(&ip)) {
+                continue;
+            }
+            let addr = SocketAddr::new(ip, port);
+            if !dialable.contains(&addr) {
+                dialable.push(addr);
+            }
+            if dialable.len() == MAX_DIAL_ADDRS {
+                break;
+            }
+        }
+        dialable
+    }
+
+    fn throttle(&self, peer_id: &PeerId) -> bool {
+        let mut dials = self.dials.lock().unwrap();
+        while dials
+            .front()
+            .is_some_and(|(at, _)| at.elapsed() > self.throttle_interval)
+        {
+            dials.pop_front();
+        }
+        let peer_dials = dials.iter().filter(|(_, peer)| peer == peer_id).count();
+        if dials.len() >= self.max_dials || peer_dials >= self.max_dials_per_peer {
+            return false;
+        }
+        dials.push_back((Instant::now(), peer_id.clone()));
+        true
+    }
+
+    // the dial back succeeds once the peer is authenticated
+    async fn dial_back(&self, peer_id: &PeerId, addr: SocketAddr) -> Result<(), Error> {
+        let stream = Async::<TcpStream>::connect(addr).await?;
+        let stream: io::BoxedStream = match &self.pre_shared_key {
+            Some(psk) => Box::new(Pnet::new(psk.clone()).upgrade_outbound(stream).await?),
+            None => Box::new(stream),
+        };
+        let (mut stream, remote_peer_id, _) =
+            SecurityUpgrader::new(self.private_key.clone(), self.security_protocols.clone())
+                .upgrade_outbound(stream)
+                .await?;
+        let _ = stream.close().await;
+        if &remote_peer_id != peer_id {
+            return Err(error::verification_failed());
+        }
+        Ok(())
+    }
+}
+
+impl Protocol for AutoNatServer {
+    fn register(self, registry: &mut ProtocolRegistry) {
+        registry.register(PROTOCOL_AUTONAT, move |connection, stream| {
+            self.clone().respond(connection, stream)
+        });
+    }
+}
+
+fn dial_response(status: ResponseStatus, status_text: &str) -> DialResponse {
+    DialResponse {
+        status: Some(status),
+        statusText: Some(status_text.to_string()),
+        addr: None,
+    }
+}
+
+fn is_public_ip(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ipv4) => {
+            !(ipv4.is_private()
+                || ipv4.is_loopback()
+                || ipv4.is_link_local()
+                || ipv4.is_unspecified()
+                || ipv4.is_broadcast())
+        }
+        // unique local addresses are fc00::/7
+        IpAddr::V6(ipv6) => {
+            !(ipv6.is_loopback()
+                || ipv6.is_unspecified()
+                || (ipv6.segments()[0] & 0xfe00) == 0xfc00
+                || (ipv6.segments()[0] & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::{AutoNat, Identify, Manager, NatStatus};
+    use async_io::Timer;
+    use std::{net::TcpListener, sync::Arc};
+
+    fn spawn_server(server: AutoNatServer) -> Result<SocketAddr, Error> {
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let socket_addr = listener.get_ref().local_addr()?;
+        let manager =
+            Manager::new(PrivateKey::generate_ed25519(), socket_addr).with_protocol(server);
+        async_std::task::spawn(async move { manager.listen(listener).await });
+        Ok(socket_addr)
+    }
+
+    // a peer listening on localhost, and its connection to the server
+    async fn spawn_client(server_addr: SocketAddr) -> Result<(AutoNat, Connection), Error> {
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let socket_addr = listener.get_ref().local_addr()?;
+        let manager = Manager::new(PrivateKey::generate_ed25519(), socket_addr);
+        let autonat = AutoNat::new(manager.peer_id()?)
+            .with_observed_addrs(vec![tcpaddr_to_multiaddr(&socket_addr)]);
+        let connection = manager.connect(server_addr).await?;
+        async_std::task::spawn(async move { manager.listen(listener).await });
+        Ok((autonat, connection))
+    }
+
+    #[async_std::test]
+    async fn test_autonat_dial_back() -> Result<(), Error> {
+        let server_addr = spawn_server(
+            AutoNatServer::new(PrivateKey::generate_ed25519()).with_private_addrs(true),
+        )?;
+
+        let (autonat, connection) = spawn_client(server_addr).await?;
+        assert_eq!(autonat.probe(&connection).await?, NatStatus::Public);
+        assert_eq!(autonat.status(), NatStatus::Public);
+        assert!(autonat.public_addr().is_some());
+
+        // nothing listens at the observed address
+        let (autonat, connection) = spawn_client(server_addr).await?;
+        let closed = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?
+            .get_ref()
+            .local_addr()?;
+        let autonat = autonat.with_observed_addrs(vec![tcpaddr_to_multiaddr(&closed)]);
+        assert_eq!(autonat.probe(&connection).await?, NatStatus::Private);
+        Ok(())
+    }
+
+    #[test]
+    fn test_autonat_dialable_addrs() {
+        let server = AutoNatServer::new(PrivateKey::generate_ed25519());
+        let observed_ip: IpAddr = [8, 8, 8, 8].into();
+        let addr = |ip: [u8; 4], port| tcpaddr_to_multiaddr(&SocketAddr::new(ip.into(), port));
+        let mut addrs = vec![
+            addr([8, 8, 4, 4], 1).to_vec(),
+            addr([8, 8, 8, 8], 1).to_vec(),
+            addr([8, 8, 8, 8], 1).to_vec(),
+        ];
+        addrs.extend((2..10).map(|port| addr([8, 8, 8, 8], port).to_vec()));
+        let dialable = server.dialable_addrs(observed_ip, &addrs);
+        let ports: Vec<u16> = dialable.iter().map(|addr| addr.port()).collect();
+        assert_eq!(ports, (1..=MAX_DIAL_ADDRS as u16).collect::<Vec<_>>());
+    }
+
+    #[async_std::test]
+    async fn test_autonat_refusals() -> Result<(), Error> {
+        // loopback addresses are not dialed back by default
+        let server_addr = spawn_server(AutoNatServer::new(PrivateKey::generate_ed25519()))?;
+        let (autonat, connection) = spawn_client(server_addr).await?;
+        assert!(autonat.probe(&connection).await.is_err());
+        assert_eq!(autonat.status(), NatStatus::Unknown);
+
+        let server_addr = spawn_server(
+            AutoNatServer::new(PrivateKey::generate_ed25519())
+                .with_private_addrs(true)
+                .with_throttle(Duration::from_secs(60), 30, 1),
+        )?;
+        let (autonat, connection) = spawn_client(server_addr).await?;
+        assert_eq!(autonat.probe(&connection).await?, NatStatus::Public);
+        assert!(autonat.probe(&connection).await.is_err());
+        assert_eq!(autonat.confidence(), 0);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_autonat_run_with_identify() -> Result<(), Error> {
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let server_addr = listener.get_ref().local_addr()?;
+        let server_key = PrivateKey::generate_ed25519();
+        let server = Manager::new(server_key.clone(), server_addr)
+            .with_protocol(AutoNatServer::new(server_key.clone()).with_private_addrs(true))
+            .with_protocol(Identify::new(server_key));
+        async_std::task::spawn(async move { server.listen(listener).await });
+
+        // the address observed by the server is the ephemeral port of the
+        // outbound connection, which nothing listens at
+        let private_key = PrivateKey::generate_ed25519();
+        let identify = Identify::new(private_key.clone());
+        let autonat = AutoNat::new(private_key.public().try_into()?)
+            .with_identify(identify)
+            .with_interval(Duration::from_millis(50), Duration::from_millis(50));
+        let manager =
+            Arc::new(Manager::new(private_key, server_addr).with_autonat(autonat.clone()));
+        manager.connect(server_addr).await?;
+        let running = manager.clone();
+        async_std::task::spawn(async move { autonat.run(&running).await });
+
+        for _ in 0..50 {
+            if manager.nat_status() != NatStatus::Unknown {
+                break;
+            }
+            Timer::after(Duration::from_millis(20)).await;
+        }
+        assert_eq!(manager.nat_status(), NatStatus::Private);
+        Ok(())
+    }
+}
@@ -0,0 +1,868 @@
+mod blockstore;
+mod ledger;
+mod session;
+
+pub use blockstore::{Blockstore, FsBlockstore, MemoryBlockstore};
+pub use ledger::Ledger;
+pub use session::Session;
+
+use crate::{
+    cid::{Cid, Multihash},
+    error::{self, Error},
+    identity::PeerId,
+    net::{
+        serve_peer, spawn_writer, Connection, PeerProtocol, PeerSender, Protocol, ProtocolRegistry,
+    },
+    payload::bitswap::{
+        mod_Message::{
+            mod_Wantlist::{Entry, WantType},
+            Block, BlockPresence, BlockPresenceType, Wantlist,
+        },
+        Message,
+    },
+};
+use futures::{channel::mpsc, stream, AsyncWriteExt, Future, FutureExt, SinkExt, StreamExt};
+use std::{
+    collections::{HashMap, HashSet},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+pub const PROTOCOL_BITSWAP: &str = "/ipfs/bitswap/1.2.0";
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+const DEFAULT_REBROADCAST_INTERVAL: Duration = Duration::from_secs(5);
+const DEFAULT_MAX_QUEUED_BLOCKS: usize = 32;
+const DEFAULT_MAX_WANTLIST_ENTRIES: usize = 1024;
+// messages other than blocks waiting to be written to a peer
+const MAX_QUEUED_MESSAGES: usize = 128;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+// what peers told about a wanted block
+#[derive(Clone)]
+enum WantEvent {
+    Have(PeerId),
+    DontHave(PeerId),
+    Block(PeerId, Vec<u8>),
+}
+
+struct Want {
+    priority: i32,
+    // the pending gets of the block by id
+    waiters: HashMap<u64, mpsc::UnboundedSender<WantEvent>>,
+    // the peers sent the want, told when it is cancelled
+    asked: HashSet<PeerId>,
+}
+
+struct PeerState {
+    sender: PeerSender<Message>,
+    ledger: Ledger,
+    // wakes the task sending the queued blocks
+    notify: mpsc::UnboundedSender<()>,
+}
+
+struct State {
+    peers: HashMap<PeerId, PeerState>,
+    wants: HashMap<Cid, Want>,
+    // earlier wants are sent with higher priorities
+    next_priority: i32,
+    next_waiter_id: u64,
+}
+
+impl State {
+    fn send(&mut self, peer_id: &PeerId, message: Message) {
+        if let Some(peer) = self.peers.get_mut(peer_id) {
+            peer.sender.send(message);
+        }
+    }
+
+    fn send_presences(&mut self, peer_id: &PeerId, presences: Vec<BlockPresence>) {
+        if presences.is_empty() {
+            return;
+        }
+        let message = Message {
+            blockPresences: presences,
+            ..Default::default()
+        };
+        self.send(peer_id, message);
+    }
+
+    // ask the peer for the block or whether it has the block, it replies
+    // DONT_HAVE rather than keeping silent
+    fn send_want(&mut self, cid: &Cid, peer_id: &PeerId, want_type: WantType) {
+        let Some(want) = self.wants.get_mut(cid) else {
+            return;
+        };
+        want.asked.insert(peer_id.clone());
+        let entry = Entry {
+            block: cid.to_bytes(),
+            priority: want.priority,
+            wantType: want_type,
+            sendDontHave: true,
+            ..Default::default()
+        };
+        self.send(peer_id, wantlist(vec![entry], false));
+    }
+
+    // drop the want and cancel it at the peers asked
+    fn cancel(&mut self, cid: &Cid) {
+        let Some(want) = self.wants.remove(cid) else {
+            return;
+        };
+        let entry = Entry {
+            block: cid.to_bytes(),
+            cancel: true,
+            ..Default::default()
+        };
+        for peer_id in &want.asked {
+            self.send(peer_id, wantlist(vec![entry.clone()], false));
+        }
+    }
+}
+
+// Bitswap
+//
+// Exchanges blocks with the peers over a stream each way, blocks are fetched
+// in sessions which learn the peers having them and served from the
+// blockstore, if any, to the peers wanting them
+#[derive(Clone)]
+pub struct Bitswap {
+    blockstore: Option<Arc<dyn Blockstore>>,
+    max_message_size: usize,
+    max_queued_blocks: usize,
+    max_wantlist_entries: usize,
+    rebroadcast_interval: Duration,
+    timeout: Duration,
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for Bitswap {
+    fn default() -> Self {
+        Self {
+            blockstore: None,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_queued_blocks: DEFAULT_MAX_QUEUED_BLOCKS,
+            max_wantlist_entries: DEFAULT_MAX_WANTLIST_ENTRIES,
+            rebroadcast_interval: DEFAULT_REBROADCAST_INTERVAL,
+            timeout: DEFAULT_TIMEOUT,
+            state: Arc::new(Mutex::new(State {
+                peers: HashMap::new(),
+                wants: HashMap::new(),
+                next_priority: i32::MAX,
+                next_waiter_id: 0,
+            })),
+        }
+    }
+}
+
+impl Bitswap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // blocks are served from the blockstore and those fetched are kept there
+    pub fn with_blockstore(mut self, blockstore: impl Blockstore + 'static) -> Self {
+        self.blockstore = Some(Arc::new(blockstore));
+        self
+    }
+
+    // blocks wanted by a peer beyond the bound wait for the queued ones to
+    // be sent
+    pub fn with_max_queued_blocks(mut self, max_queued_blocks: usize) -> Self {
+        self.max_queued_blocks = max_queued_blocks;
+        self
+    }
+
+    // wants of a peer beyond the bound are ignored until some are answered
+    // or cancelled
+    pub fn with_max_wantlist_entries(mut self, max_wantlist_entries: usize) -> Self {
+        self.max_wantlist_entries = max_wantlist_entries;
+        self
+    }
+
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    // wants not answered within the interval are sent to all the peers again
+    pub fn with_rebroadcast_interval(mut self, rebroadcast_interval: Duration) -> Self {
+        self.rebroadcast_interval = rebroadcast_interval;
+        self
+    }
+
+    // gets fail once the block did not arrive within the timeout
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // open the stream to the peer over the connection, inbound streams of
+    // peers not added yet add them
+    pub async fn add_peer(&self, connection: &Connection) -> Result<(), Error> {
+        let peer_id = connection.info().peer_id.clone();
+        if self.state.lock().unwrap().peers.contains_key(&peer_id) {
+            return Ok(());
+        }
+        let mut stream = connection.open_stream(PROTOCOL_BITSWAP).await?;
+        let (sender, receiver) = PeerSender::channel(MAX_QUEUED_MESSAGES);
+        let stream_id = sender.stream_id();
+        let (notify, notified) = mpsc::unbounded();
+        if !self.insert_peer(peer_id.clone(), sender, notify) {
+            return stream.close().await;
+        }
+
+        // blocks are sent one at a time behind the other messages
+        let (blocks, blocks_receiver) = mpsc::channel(0);
+        async_std::task::spawn(self.clone().send_blocks(
+            peer_id.clone(),
+            stream_id,
+            notified,
+            blocks,
+        ));
+        let messages = stream::select(receiver, blocks_receiver);
+        spawn_writer(self.clone(), peer_id, stream_id, stream, messages);
+        Ok(())
+    }
+
+    pub fn peers(&self) -> Vec<PeerId> {
+        self.state.lock().unwrap().peers.keys().cloned().collect()
+    }
+
+    // the blocks being fetched
+    pub fn wantlist(&self) -> Vec<Cid> {
+        self.state.lock().unwrap().wants.keys().cloned().collect()
+    }
+
+    // what the peer wants from us and the blocks exchanged with it
+    pub fn ledger(&self, peer_id: &PeerId) -> Option<Ledger> {
+        let state = self.state.lock().unwrap();
+        state.peers.get(peer_id).map(|peer| peer.ledger.clone())
+    }
+
+    pub fn blockstore(&self) -> Option<Arc<dyn Blockstore>> {
+        self.blockstore.clone()
+    }
+
+    // store the block and send it to the peers wanting it
+    pub fn put_block(&self, cid: &Cid, data: &[u8]) -> Result<(), Error> {
+        let Some(blockstore) = &self.blockstore else {
+            return Err(error::unsupported("no blockstore"));
+        };
+        cid.verify(data)?;
+        blockstore.put(cid, data)?;
+        self.announce_block(&mut self.state.lock().unwrap(), cid);
+        Ok(())
+    }
+
+    pub fn session(&self) -> Session {
+        Session::new(self.clone())
+    }
+
+    // fetch a single block in a session of its own
+    pub async fn get(&self, cid: &Cid) -> Result<Vec<u8>, Error> {
+        self.session().get(cid).await
+    }
+
+    // false if the peer has a stream already, the pending wants are sent
+    // first as the full wantlist
+    fn insert_peer(
+        &self,
+        peer_id: PeerId,
+        mut sender: PeerSender<Message>,
+        notify: mpsc::UnboundedSender<()>,
+    ) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.peers.contains_key(&peer_id) {
+            return false;
+        }
+        let entries: Vec<Entry> = state
+            .wants
+            .iter_mut()
+            .map(|(cid, want)| {
+                want.asked.insert(peer_id.clone());
+                Entry {
+                    block: cid.to_bytes(),
+                    priority: want.priority,
+                    wantType: WantType::Have,
+                    sendDontHave: true,
+                    ..Default::default()
+                }
+            })
+            .collect();
+        if !entries.is_empty() {
+            sender.send(wantlist(entries, true));
+        }
+        let peer = PeerState {
+            sender,
+            ledger: Ledger::default(),
+            notify,
+        };
+        state.peers.insert(peer_id, peer);
+        true
+    }
+
+    // the local block, if any
+    fn local_block(&self, cid: &Cid) -> Option<Vec<u8>> {
+        let blockstore = self.blockstore.as_ref()?;
+        blockstore.get(cid).unwrap_or_else(|err| {
+            log::debug!("bitswap blockstore get {:?} failed, {:?}", cid, err);
+            None
+        })
+    }
+
+    fn has_block(&self, cid: &Cid) -> bool {
+        self.blockstore.as_ref().is_some_and(|blockstore| {
+            blockstore.has(cid).unwrap_or_else(|err| {
+                log::debug!("bitswap blockstore has {:?} failed, {:?}", cid, err);
+                false
+            })
+        })
+    }
+
+    // send the queued blocks of the peer until its stream is replaced or
+    // closed
+    async fn send_blocks(
+        self,
+        peer_id: PeerId,
+        stream_id: u64,
+        mut notified: mpsc::UnboundedReceiver<()>,
+        mut blocks: mpsc::Sender<Message>,
+    ) {
+        loop {
+            // the blockstore may block, such as on the file system
+            let next = async_std::task::spawn_blocking({
+                let (bitswap, peer_id) = (self.clone(), peer_id.clone());
+                move || bitswap.next_block(&peer_id, stream_id)
+            })
+            .await;
+            match next {
+                Some(message) => {
+                    if blocks.send(message).await.is_err() {
+                        break;
+                    }
+                }
+                None => {
+                    if notified.next().await.is_none() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    // the blockstore is read outside the lock, blocks deleted since they
+    // were queued are skipped, or answered by DONT_HAVE if the peer asked
+    fn next_block(&self, peer_id: &PeerId, stream_id: u64) -> Option<Message> {
+        loop {
+            let (cid, send_dont_have) = {
+                let mut state = self.state.lock().unwrap();
+                let peer = state
+                    .peers
+                    .get_mut(peer_id)
+                    .filter(|peer| peer.sender.stream_id() == stream_id)?;
+                peer.ledger.pop(self.max_queued_blocks)?
+            };
+            let Some(data) = self.local_block(&cid) else {
+                if !send_dont_have {
+                    continue;
+                }
+                return Some(Message {
+                    blockPresences: vec![presence(&cid, BlockPresenceType::DontHave)],
+                    ..Default::default()
+                });
+            };
+            if let Some(peer) = self.state.lock().unwrap().peers.get_mut(peer_id) {
+                peer.ledger.blocks_sent += 1;
+                peer.ledger.bytes_sent += data.len() as u64;
+            }
+            let block = Block {
+                prefix: cid.prefix(),
+                data,
+            };
+            return Some(Message {
+                payload: vec![block],
+                ..Default::default()
+            });
+        }
+    }
+
+    // answer the wants new in the wantlist, blocks at hand are queued and
+    // the others wait in the ledger until they are put
+    fn handle_wantlist(&self, state: &mut State, from: &PeerId, haves: Vec<(Cid, bool)>) {
+        let Some(peer) = state.peers.get_mut(from) else {
+            return;
+        };
+        let mut presences = Vec::new();
+        for (cid, has_block) in haves {
+            // cancelled meanwhile
+            let Some((want_type, send_dont_have)) = peer.ledger.entry(&cid) else {
+                continue;
+            };
+            match (has_block, want_type) {
+                (true, WantType::Have) => {
+                    presences.push(presence(&cid, BlockPresenceType::Have));
+                }
+                (true, WantType::Block) => {
+                    peer.ledger.have_block(&cid, self.max_queued_blocks);
+                }
+                (false, _) if send_dont_have => {
+                    presences.push(presence(&cid, BlockPresenceType::DontHave));
+                }
+                (false, _) => (),
+            }
+        }
+        let _ = peer.notify.unbounded_send(());
+        state.send_presences(from, presences);
+    }
+
+    // tell the peers wanting the block that it is at hand
+    fn announce_block(&self, state: &mut State, cid: &Cid) {
+        let mut haves = Vec::new();
+        for (peer_id, peer) in state.peers.iter_mut() {
+            match peer.ledger.entry(cid) {
+                Some((WantType::Have, _)) => haves.push(peer_id.clone()),
+                Some((WantType::Block, _))
+                    if peer.ledger.have_block(cid, self.max_queued_blocks) =>
+                {
+                    let _ = peer.notify.unbounded_send(());
+                }
+                _ => (),
+            }
+        }
+        for peer_id in haves {
+            state.send_presences(&peer_id, vec![presence(cid, BlockPresenceType::Have)]);
+        }
+    }
+
+    // the state is updated under the lock, and the blockstore is accessed
+    // between the two rounds of it, off the async tasks
+    async fn handle_message(&self, from: &PeerId, message: Message) {
+        let (wanted, received) = self.receive(from, message);
+        let (haves, stored) = async_std::task::spawn_blocking({
+            let bitswap = self.clone();
+            move || bitswap.access_blockstore(wanted, received)
+        })
+        .await;
+
+        let mut state = self.state.lock().unwrap();
+        self.handle_wantlist(&mut state, from, haves);
+        for cid in stored {
+            self.announce_block(&mut state, &cid);
+        }
+    }
+
+    // whether the wanted cids are at hand, and the received blocks stored
+    fn access_blockstore(
+        &self,
+        wanted: Vec<Cid>,
+        received: Vec<(Cid, Vec<u8>)>,
+    ) -> (Vec<(Cid, bool)>, Vec<Cid>) {
+        let haves: Vec<(Cid, bool)> = wanted
+            .into_iter()
+            .map(|cid| {
+                let has_block = self.has_block(&cid);
+                (cid, has_block)
+            })
+            .collect();
+        let mut stored = Vec::new();
+        if let Some(blockstore) = &self.blockstore {
+            for (cid, data) in received {
+                match blockstore.put(&cid, &data) {
+                    Ok(()) => stored.push(cid),
+                    Err(err) => log::debug!("bitswap blockstore put {:?} failed, {:?}", cid, err),
+                }
+            }
+        }
+        (haves, stored)
+    }
+
+    // returns the cids wanted anew by the peer and the blocks received
+    fn receive(&self, from: &PeerId, message: Message) -> (Vec<Cid>, Vec<(Cid, Vec<u8>)>) {
+        let mut state = self.state.lock().unwrap();
+        let wanted = match (message.wantlist, state.peers.get_mut(from)) {
+            (Some(wantlist), Some(peer)) => peer.ledger.update(wantlist, self.max_wantlist_entries),
+            _ => Vec::new(),
+        };
+        for presence in message.blockPresences {
+            let Ok(cid) = Cid::from_bytes(&presence.cid) else {
+                continue;
+            };
+            let Some(want) = state.wants.get_mut(&cid) else {
+                continue;
+            };
+            let event = match presence.type_pb {
+                BlockPresenceType::Have => WantEvent::Have(from.clone()),
+                BlockPresenceType::DontHave => WantEvent::DontHave(from.clone()),
+            };
+            for waiter in want.waiters.values() {
+                let _ = waiter.unbounded_send(event.clone());
+            }
+        }
+
+        // the cid of a block is derived from its data, so data not matching
+        // the multihash is taken for an unwanted block
+        let blocks = message
+            .payload
+            .into_iter()
+            .map(|block| (Cid::from_prefix(&block.prefix, &block.data), block.data))
+            .chain(
+                message
+                    .blocks
+                    .into_iter()
+                    .map(|data| (Cid::new_v0(Multihash::sha2_256(&data)), data)),
+            );
+        let mut received = Vec::new();
+        for (cid, data) in blocks {
+            let cid = match cid {
+                Ok(cid) => cid,
+                Err(err) => {
+                    log::debug!("bitswap block of {:?} dropped, {:?}", from, err);
+                    continue;
+                }
+            };
+            let Some(want) = state.wants.get(&cid) else {
+                log::debug!("bitswap unwanted block {:?} from {:?}", cid, from);
+                continue;
+            };
+            for waiter in want.waiters.values() {
+                let _ = waiter.unbounded_send(WantEvent::Block(from.clone(), data.clone()));
+            }
+            state.cancel(&cid);
+            if let Some(peer) = state.peers.get_mut(from) {
+                peer.ledger.blocks_received += 1;
+                peer.ledger.bytes_received += data.len() as u64;
+            }
+            received.push((cid, data));
+        }
+        (wanted, received)
+    }
+}
+
+impl Protocol for Bitswap {
+    fn register(self, registry: &mut ProtocolRegistry) {
+        registry.register(PROTOCOL_BITSWAP, move |connection, stream| {
+            serve_peer(self.clone(), connection, stream)
+        });
+    }
+}
+
+impl PeerProtocol for Bitswap {
+    type Message = Message;
+
+    fn name(&self) -> &'static str {
+        PROTOCOL_BITSWAP
+    }
+
+    fn max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+
+    fn add_peer<'a>(
+        &'a self,
+        connection: &'a Connection,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+        Bitswap::add_peer(self, connection).boxed()
+    }
+
+    fn stream_id(&self, peer_id: &PeerId) -> Option<u64> {
+        let state = self.state.lock().unwrap();
+        state.peers.get(peer_id).map(|peer| peer.sender.stream_id())
+    }
+
+    fn remove_peer(&self, peer_id: &PeerId, stream_id: u64) {
+        let mut state = self.state.lock().unwrap();
+        if state
+            .peers
+            .get(peer_id)
+            .is_some_and(|peer| peer.sender.stream_id() == stream_id)
+        {
+            state.peers.remove(peer_id);
+        }
+    }
+
+    fn handle_message<'a>(
+        &'a self,
+        from: &'a PeerId,
+        message: Message,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+        Bitswap::handle_message(self, from, message).boxed()
+    }
+}
+
+fn presence(cid: &Cid, type_pb: BlockPresenceType) -> BlockPresence {
+    BlockPresence {
+        cid: cid.to_bytes(),
+        type_pb,
+    }
+}
+
+fn wantlist(entries: Vec<Entry>, full: bool) -> Message {
+    Message {
+        wantlist: Some(Wantlist { entries, full }),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cid::CODEC_RAW,
+        identity::PrivateKey,
+        io,
+        net::{test_support::spawn_node, Manager},
+        payload::bitswap::mod_Message::{Block, BlockPresence},
+    };
+    use async_io::Async;
+    use std::net::{SocketAddr, TcpListener};
+
+    // a peer answering wants from its blocks
+    #[derive(Clone, Default)]
+    struct Responder {
+        blocks: HashMap<Cid, Vec<u8>>,
+        corrupt: bool,
+        received: Arc<Mutex<Vec<Entry>>>,
+    }
+
+    impl Responder {
+        async fn serve(
+            self,
+            connection: Connection,
+            mut stream: io::BoxedStream,
+        ) -> Result<(), Error> {
+            let mut reply = connection.open_stream(PROTOCOL_BITSWAP).await?;
+            loop {
+                let message: Message =
+                    io::read_protobuf_prefixed(&mut stream, DEFAULT_MAX_MESSAGE_SIZE).await?;
+                let mut response = Message::default();
+                for entry in message.wantlist.map(|w| w.entries).unwrap_or_default() {
+                    self.received.lock().unwrap().push(entry.clone());
+                    if entry.cancel {
+                        continue;
+                    }
+                    let cid = Cid::from_bytes(&entry.block)?;
+                    let presence = |type_pb| BlockPresence {
+                        cid: entry.block.clone(),
+                        type_pb,
+                    };
+                    match (self.blocks.get(&cid), entry.wantType) {
+                        (Some(data), WantType::Block) => response.payload.push(Block {
+                            prefix: cid.prefix(),
+                            data: if self.corrupt {
+                                b"corrupt".to_vec()
+                            } else {
+                                data.clone()
+                            },
+                        }),
+                        (Some(_), WantType::Have) => response
+                            .blockPresences
+                            .push(presence(BlockPresenceType::Have)),
+                        (None, _) => response
+                            .blockPresences
+                            .push(presence(BlockPresenceType::DontHave)),
+                    }
+                }
+                if response != Message::default() {
+                    io::write_protobuf_prefixed(&mut reply, &response).await?;
+                }
+            }
+        }
+    }
+
+    fn spawn_responder(responder: Responder) -> Result<(PeerId, SocketAddr), Error> {
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let socket_addr = listener.get_ref().local_addr()?;
+        let manager = Manager::new(PrivateKey::generate_ed25519(), socket_addr)
+            .with_protocol_handler(PROTOCOL_BITSWAP, move |connection, stream| {
+                responder.clone().serve(connection, stream)
+            });
+        let peer_id = manager.peer_id()?;
+        async_std::task::spawn(async move { manager.listen(listener).await });
+        Ok((peer_id, socket_addr))
+    }
+
+    fn raw_block(data: &[u8]) -> (Cid, Vec<u8>) {
+        (
+            Cid::new_v1(CODEC_RAW, Multihash::sha2_256(data)),
+            data.to_vec(),
+        )
+    }
+
+    #[async_std::test]
+    async fn test_bitswap_session() -> Result<(), Error> {
+        let (cid_1, data_1) = raw_block(b"first block");
+        let (cid_2, data_2) = raw_block(b"second block");
+        let (_, addr_empty) = spawn_responder(Responder::default())?;
+        let full = Responder {
+            blocks: HashMap::from([
+                (cid_1.clone(), data_1.clone()),
+                (cid_2.clone(), data_2.clone()),
+            ]),
+            ..Default::default()
+        };
+        let received = full.received.clone();
+        let (peer_full, addr_full) = spawn_responder(full)?;
+
+        let private_key = PrivateKey::generate_ed25519();
+        let bitswap = Bitswap::new();
+        let manager = Manager::new(private_key, "127.0.0.1:0".parse().unwrap())
+            .with_protocol(bitswap.clone());
+        bitswap
+            .add_peer(&manager.connect(addr_empty).await?)
+            .await?;
+        bitswap.add_peer(&manager.connect(addr_full).await?).await?;
+
+        // found through HAVE, whichever peer is asked for the block first
+        let session = bitswap.session();
+        let block = io::timeout(Duration::from_secs(5), session.get(&cid_1)).await?;
+        assert_eq!(block, data_1);
+        assert_eq!(session.peers()[0], peer_full);
+        assert!(bitswap.wantlist().is_empty());
+
+        // the block is asked straight from the peer which had the first one
+        received.lock().unwrap().clear();
+        let block = io::timeout(Duration::from_secs(5), session.get(&cid_2)).await?;
+        assert_eq!(block, data_2);
+        let received = received.lock().unwrap();
+        let first = received.iter().find(|entry| !entry.cancel).unwrap();
+        assert_eq!(first.block, cid_2.to_bytes());
+        assert_eq!(first.wantType, WantType::Block);
+        assert!(first.sendDontHave);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_bitswap_block_verification() -> Result<(), Error> {
+        let (cid, data) = raw_block(b"some block");
+        let corrupt = Responder {
+            blocks: HashMap::from([(cid.clone(), data)]),
+            corrupt: true,
+            ..Default::default()
+        };
+        let (_, addr) = spawn_responder(corrupt)?;
+
+        let bitswap = Bitswap::new().with_timeout(Duration::from_millis(500));
+        let manager = Manager::new(
+            PrivateKey::generate_ed25519(),
+            "127.0.0.1:0".parse().unwrap(),
+        )
+        .with_protocol(bitswap.clone());
+        bitswap.add_peer(&manager.connect(addr).await?).await?;
+
+        // data not matching the cid never completes the get, which times out
+        let get = bitswap.get(&cid).await;
+        assert_eq!(get.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+        assert!(bitswap.wantlist().is_empty());
+
+        // as does a get without any peer
+        let bitswap = Bitswap::new().with_timeout(Duration::from_millis(100));
+        let get = bitswap.get(&cid).await;
+        assert_eq!(get.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_bitswap_server() -> Result<(), Error> {
+        let blocks: Vec<(Cid, Vec<u8>)> = (0..5u8).map(|n| raw_block(&[n; 100])).collect();
+        let server = Bitswap::new()
+            .with_blockstore(MemoryBlockstore::new())
+            .with_max_queued_blocks(1);
+        for (cid, data) in &blocks {
+            server.put_block(cid, data)?;
+        }
+        let cache = Bitswap::new().with_blockstore(MemoryBlockstore::new());
+        let client = Bitswap::new();
+        let (manager_server, _, addr_server) = spawn_node(|_| Ok(server.clone()))?;
+        let (manager_cache, _, addr_cache) = spawn_node(|_| Ok(cache.clone()))?;
+        let (manager_client, _, _) = spawn_node(|_| Ok(client.clone()))?;
+        let peer_server = manager_server.peer_id()?;
+        let peer_cache = manager_cache.peer_id()?;
+        let peer_client = manager_client.peer_id()?;
+
+        // all the blocks through a queue of one
+        cache
+            .add_peer(&manager_cache.connect(addr_server).await?)
+            .await?;
+        let session = cache.session();
+        let fetched = io::timeout(Duration::from_secs(5), async {
+            let gets = blocks.iter().map(|(cid, _)| session.get(cid));
+            futures::future::try_join_all(gets).await
+        })
+        .await?;
+        assert!(fetched
+            .iter()
+            .zip(&blocks)
+            .all(|(data, (_, block))| data == block));
+        let ledger = server.ledger(&peer_cache).unwrap();
+        assert_eq!((ledger.blocks_sent, ledger.bytes_sent), (5, 500));
+        assert!(ledger.wantlist().is_empty());
+        assert_eq!(cache.ledger(&peer_server).unwrap().blocks_received, 5);
+
+        // the blocks fetched are served on
+        client
+            .add_peer(&manager_client.connect(addr_cache).await?)
+            .await?;
+        let block = io::timeout(Duration::from_secs(5), client.get(&blocks[0].0)).await?;
+        assert_eq!(block, blocks[0].1);
+
+        // a block missing is sent once it is put
+        let (cid, data) = raw_block(b"later");
+        let get = {
+            let (client, cid) = (client.clone(), cid.clone());
+            async_std::task::spawn(async move { client.get(&cid).await })
+        };
+        io::timeout(Duration::from_secs(5), async {
+            while !cache
+                .ledger(&peer_client)
+                .is_some_and(|ledger| ledger.wantlist().contains(&cid))
+            {
+                async_io::Timer::after(Duration::from_millis(10)).await;
+            }
+            Ok(())
+        })
+        .await?;
+        assert!(cache.put_block(&cid, b"not the data").is_err());
+        cache.put_block(&cid, &data)?;
+        assert_eq!(io::timeout(Duration::from_secs(5), get).await?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bitswap_block_gone() {
+        let bitswap = Bitswap::new().with_blockstore(MemoryBlockstore::new());
+        let peer_id: PeerId = PrivateKey::generate_ed25519().public().try_into().unwrap();
+        let (sender, _receiver) = PeerSender::channel(MAX_QUEUED_MESSAGES);
+        let stream_id = sender.stream_id();
+        assert!(bitswap.insert_peer(peer_id.clone(), sender, mpsc::unbounded().0));
+
+        // queued while at hand, deleted before it is sent
+        let (cid, _) = raw_block(b"gone");
+        let (kept, _) = raw_block(b"kept");
+        {
+            let mut state = bitswap.state.lock().unwrap();
+            let ledger = &mut state.peers.get_mut(&peer_id).unwrap().ledger;
+            let entries = [(&cid, true), (&kept, false)].map(|(cid, send_dont_have)| Entry {
+                block: cid.to_bytes(),
+                sendDontHave: send_dont_have,
+                ..Default::default()
+            });
+            ledger.update(
+                Wantlist {
+                    entries: entries.to_vec(),
+                    full: true,
+                },
+                8,
+            );
+            assert!(ledger.have_block(&cid, 8));
+            assert!(ledger.have_block(&kept, 8));
+        }
+        let message = bitswap.next_block(&peer_id, stream_id).unwrap();
+        assert_eq!(
+            message.blockPresences,
+            vec![presence(&cid, BlockPresenceType::DontHave)]
+        );
+        // no DONT_HAVE unless asked
+        assert!(bitswap.next_block(&peer_id, stream_id).is_none());
+    }
+}
@@ -0,0 +1,220 @@
+use crate::{cid::Cid, error::Error};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+// Blockstore
+//
+// Blocks by cid, the data is taken as matching the cid
+pub trait Blockstore: Send + Sync {
+    fn get(&self, cid: &Cid) -> Result<Option<Vec<u8>>, Error>;
+
+    fn has(&self, cid: &Cid) -> Result<bool, Error> {
+        Ok(self.get(cid)?.is_some())
+    }
+
+    fn put(&self, cid: &Cid, data: &[u8]) -> Result<(), Error>;
+
+    fn delete(&self, cid: &Cid) -> Result<(), Error>;
+
+    fn cids(&self) -> Result<Vec<Cid>, Error>;
+}
+
+// MemoryBlockstore
+#[derive(Clone, Default)]
+pub struct MemoryBlockstore {
+    blocks: Arc<Mutex<HashMap<Cid, Vec<u8>>>>,
+}
+
+impl MemoryBlockstore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Blockstore for MemoryBlockstore {
+    fn get(&self, cid: &Cid) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.blocks.lock().unwrap().get(cid).cloned())
+    }
+
+    fn has(&self, cid: &Cid) -> Result<bool, Error> {
+        Ok(self.blocks.lock().unwrap().contains_key(cid))
+    }
+
+    fn put(&self, cid: &Cid, data: &[u8]) -> Result<(), Error> {
+        self.blocks
+            .lock()
+            .unwrap()
+            .insert(cid.clone(), data.to_vec());
+        Ok(())
+    }
+
+    fn delete(&self, cid: &Cid) -> Result<(), Error> {
+        self.blocks.lock().unwrap().remove(cid);
+        Ok(())
+    }
+
+    fn cids(&self) -> Result<Vec<Cid>, Error> {
+        Ok(self.blocks.lock().unwrap().keys().cloned().collect())
+    }
+}
+
+// FsBlockstore
+//
+// A file per block named by its cid, sharded in directories by the next to
+// last two characters of the name
+#[derive(Clone)]
+pub struct FsBlockstore {
+    root: PathBuf,
+}
+
+impl FsBlockstore {
+    pub fn new(root: impl AsRef<Path>) -> Result<Self, Error> {
+        fs::create_dir_all(root.as_ref())?;
+        Ok(Self {
+            root: root.as_ref().to_path_buf(),
+        })
+    }
+
+    fn path(&self, cid: &Cid) -> PathBuf {
+        let name = cid.to_string();
+        let shard = &name[name.len() - 3..name.len() - 1];
+        self.root.join(shard).join(name)
+    }
+}
+
+impl Blockstore for FsBlockstore {
+    fn get(&self, cid: &Cid) -> Result<Option<Vec<u8>>, Error> {
+        match fs::read(self.path(cid)) {
+            Ok(data) => Ok(Some(data)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn has(&self, cid: &Cid) -> Result<bool, Error> {
+        Ok(self.path(cid).is_file())
+    }
+
+    // written aside first, so that a block is never read partially. Each
+    // writer has a file of its own, the same block may be put concurrently.
+    fn put(&self, cid: &Cid, data: &[u8]) -> Result<(), Error> {
+        let path = self.path(cid);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temp = path.with_extension(format!("{:016x}.tmp", rand::random::<u64>()));
+        let res = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp)
+            .and_then(|mut file| file.write_all(data))
+            .and_then(|_| fs::rename(&temp, &path));
+        match res {
+            Ok(()) => Ok(()),
+            // written by another writer meanwhile
+            Err(err) if err.kind() == ErrorKind::AlreadyExists && path.is_file() => {
+                let _ = fs::remove_file(&temp);
+                Ok(())
+            }
+            Err(err) => {
+                let _ = fs::remove_file(&temp);
+                Err(err)
+            }
+        }
+    }
+
+    fn delete(&self, cid: &Cid) -> Result<(), Error> {
+        match fs::remove_file(self.path(cid)) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    fn cids(&self) -> Result<Vec<Cid>, Error> {
+        let mut cids = Vec::new();
+        for shard in fs::read_dir(&self.root)? {
+            let shard = shard?;
+            if !shard.file_type()?.is_dir() {
+                continue;
+            }
+            for file in fs::read_dir(shard.path())? {
+                if let Some(cid) = file?
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| name.parse().ok())
+                {
+                    cids.push(cid);
+                }
+            }
+        }
+        Ok(cids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cid::{Multihash, CODEC_RAW};
+
+    fn check_blockstore(blockstore: &dyn Blockstore) -> Result<(), Error> {
+        let cid = Cid::new_v1(CODEC_RAW, Multihash::sha2_256(b"data"));
+        let v0 = Cid::new_v0(Multihash::sha2_256(b"node"))?;
+        assert!(!blockstore.has(&cid)?);
+        assert_eq!(blockstore.get(&cid)?, None);
+        blockstore.put(&cid, b"data")?;
+        blockstore.put(&v0, b"node")?;
+        assert!(blockstore.has(&cid)?);
+        assert_eq!(blockstore.get(&v0)?, Some(b"node".to_vec()));
+        let mut cids = blockstore.cids()?;
+        cids.sort_by_key(|cid| cid.to_string());
+        assert_eq!(cids, vec![v0.clone(), cid.clone()]);
+        blockstore.delete(&cid)?;
+        blockstore.delete(&cid)?;
+        assert_eq!(blockstore.cids()?, vec![v0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fs_blockstore_concurrent_puts() -> Result<(), Error> {
+        let root = std::env::temp_dir().join(format!("blockstore-{}", rand::random::<u64>()));
+        let blockstore = FsBlockstore::new(&root)?;
+        let data = vec![7u8; 1024 * 1024];
+        let cid = Cid::new_v1(CODEC_RAW, Multihash::sha2_256(&data));
+        let writers: Vec<_> = (0..4)
+            .map(|_| {
+                let (blockstore, cid, data) = (blockstore.clone(), cid.clone(), data.clone());
+                std::thread::spawn(move || blockstore.put(&cid, &data))
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap()?;
+        }
+        let res = blockstore.get(&cid).map(|stored| {
+            assert_eq!(stored, Some(data));
+            // no temporary file is left behind
+            assert_eq!(blockstore.cids().unwrap(), vec![cid.clone()]);
+            assert_eq!(
+                fs::read_dir(blockstore.path(&cid).parent().unwrap())
+                    .unwrap()
+                    .count(),
+                1
+            );
+        });
+        fs::remove_dir_all(root)?;
+        res
+    }
+
+    #[test]
+    fn test_blockstores() -> Result<(), Error> {
+        check_blockstore(&MemoryBlockstore::new())?;
+        let root = std::env::temp_dir().join(format!("blockstore-{}", rand::random::<u64>()));
+        let res = check_blockstore(&FsBlockstore::new(&root)?);
+        fs::remove_dir_all(root)?;
+        res
+    }
+}
@@ -0,0 +1,234 @@
+use crate::{
+    cid::Cid,
+    payload::bitswap::mod_Message::{mod_Wantlist::WantType, Wantlist},
+};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone)]
+struct LedgerEntry {
+    priority: i32,
+    want_type: WantType,
+    send_dont_have: bool,
+    // the block is at hand, and queued to be sent
+    have: bool,
+    queued: bool,
+}
+
+// Ledger
+//
+// What a peer wants from us and the blocks exchanged with it
+#[derive(Debug, Clone, Default)]
+pub struct Ledger {
+    pub blocks_sent: u64,
+    pub bytes_sent: u64,
+    pub blocks_received: u64,
+    pub bytes_received: u64,
+    wantlist: HashMap<Cid, LedgerEntry>,
+    // the blocks to send, bounded, the others at hand wait in the wantlist
+    queue: Vec<Cid>,
+}
+
+impl Ledger {
+    pub fn wantlist(&self) -> Vec<Cid> {
+        self.wantlist.keys().cloned().collect()
+    }
+
+    pub fn queued_blocks(&self) -> usize {
+        self.queue.len()
+    }
+
+    // apply the wantlist of the peer, returns the cids wanted anew; new
+    // wants beyond the bound are dropped
+    pub(super) fn update(&mut self, wantlist: Wantlist, max_entries: usize) -> Vec<Cid> {
+        let previous = if wantlist.full {
+            std::mem::take(&mut self.wantlist)
+        } else {
+            HashMap::new()
+        };
+        let mut wanted = Vec::new();
+        for entry in wantlist.entries {
+            let Ok(cid) = Cid::from_bytes(&entry.block) else {
+                continue;
+            };
+            if entry.cancel {
+                self.wantlist.remove(&cid);
+                continue;
+            }
+            if !self.wantlist.contains_key(&cid) && self.wantlist.len() >= max_entries {
+                continue;
+            }
+            let queued = previous
+                .get(&cid)
+                .or_else(|| self.wantlist.get(&cid))
+                .is_some_and(|entry| entry.queued);
+            let entry = LedgerEntry {
+                priority: entry.priority,
+                want_type: entry.wantType,
+                send_dont_have: entry.sendDontHave,
+                have: queued,
+                queued,
+            };
+            self.wantlist.insert(cid.clone(), entry);
+            wanted.push(cid);
+        }
+        let wantlist = &self.wantlist;
+        self.queue.retain(|cid| wantlist.contains_key(cid));
+        wanted
+    }
+
+    // the want type and whether DONT_HAVE is expected
+    pub(super) fn entry(&self, cid: &Cid) -> Option<(WantType, bool)> {
+        self.wantlist
+            .get(cid)
+            .map(|entry| (entry.want_type, entry.send_dont_have))
+    }
+
+    // the block wanted is at hand, false unless it is queued now
+    pub(super) fn have_block(&mut self, cid: &Cid, max_queued: usize) -> bool {
+        let full = self.queue.len() >= max_queued;
+        let Some(entry) = self.wantlist.get_mut(cid) else {
+            return false;
+        };
+        entry.have = true;
+        if entry.queued || full || entry.want_type != WantType::Block {
+            return false;
+        }
+        entry.queued = true;
+        self.queue.push(cid.clone());
+        true
+    }
+
+    // take the queued block of the highest priority out of the wantlist, the
+    // slot freed goes to the next block at hand; with whether DONT_HAVE is
+    // expected, should the block be gone meanwhile
+    pub(super) fn pop(&mut self, max_queued: usize) -> Option<(Cid, bool)> {
+        let wantlist = &self.wantlist;
+        let (index, _) =
+            self.queue.iter().enumerate().max_by_key(|(_, cid)| {
+                wantlist.get(*cid).map_or(i32::MIN, |entry| entry.priority)
+            })?;
+        let cid = self.queue.swap_remove(index);
+        let send_dont_have = self
+            .wantlist
+            .remove(&cid)
+            .is_some_and(|entry| entry.send_dont_have);
+        let deferred = self
+            .wantlist
+            .iter()
+            .filter(|(_, entry)| entry.have && !entry.queued && entry.want_type == WantType::Block)
+            .max_by_key(|(_, entry)| entry.priority)
+            .map(|(cid, _)| cid.clone());
+        if let Some(deferred) = deferred {
+            self.have_block(&deferred, max_queued);
+        }
+        Some((cid, send_dont_have))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cid::{Multihash, CODEC_RAW},
+        payload::bitswap::mod_Message::mod_Wantlist::Entry,
+    };
+
+    fn cid(n: u8) -> Cid {
+        Cid::new_v1(CODEC_RAW, Multihash::sha2_256(&[n]))
+    }
+
+    fn entry(n: u8, priority: i32) -> Entry {
+        Entry {
+            block: cid(n).to_bytes(),
+            priority,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_ledger_queue() {
+        let mut ledger = Ledger::default();
+        let wanted = ledger.update(
+            Wantlist {
+                entries: vec![entry(1, 1), entry(2, 3), entry(3, 2)],
+                full: true,
+            },
+            8,
+        );
+        assert_eq!(wanted.len(), 3);
+
+        // the queue holds two blocks, the third waits
+        assert!(ledger.have_block(&cid(1), 2));
+        assert!(ledger.have_block(&cid(2), 2));
+        assert!(!ledger.have_block(&cid(3), 2));
+        assert_eq!(ledger.queued_blocks(), 2);
+        assert_eq!(ledger.pop(2), Some((cid(2), false)));
+        assert_eq!(ledger.queued_blocks(), 2);
+        assert_eq!(ledger.pop(2), Some((cid(3), false)));
+        assert_eq!(ledger.wantlist(), vec![cid(1)]);
+
+        // cancelled and replaced by a full wantlist
+        ledger.update(
+            Wantlist {
+                entries: vec![Entry {
+                    cancel: true,
+                    ..entry(1, 0)
+                }],
+                full: false,
+            },
+            8,
+        );
+        assert_eq!(ledger.queued_blocks(), 0);
+        assert_eq!(ledger.pop(2), None);
+        ledger.update(
+            Wantlist {
+                entries: vec![entry(4, 1)],
+                full: true,
+            },
+            8,
+        );
+        assert_eq!(ledger.wantlist(), vec![cid(4)]);
+        assert_eq!(ledger.entry(&cid(4)), Some((WantType::Block, false)));
+    }
+
+    #[test]
+    fn test_ledger_max_entries() {
+        let mut ledger = Ledger::default();
+        let wanted = ledger.update(
+            Wantlist {
+                entries: vec![entry(1, 1), entry(2, 1), entry(3, 1)],
+                full: false,
+            },
+            2,
+        );
+        assert_eq!(wanted, vec![cid(1), cid(2)]);
+
+        // wants already in the wantlist are updated, new ones wait for room
+        let wanted = ledger.update(
+            Wantlist {
+                entries: vec![entry(2, 5), entry(3, 1)],
+                full: false,
+            },
+            2,
+        );
+        assert_eq!(wanted, vec![cid(2)]);
+        ledger.update(
+            Wantlist {
+                entries: vec![
+                    Entry {
+                        cancel: true,
+                        ..entry(1, 0)
+                    },
+                    entry(3, 1),
+                ],
+                full: false,
+            },
+            2,
+        );
+        let mut wantlist = ledger.wantlist();
+        wantlist.sort_by_key(|cid| cid.to_bytes());
+        let mut expected = vec![cid(2), cid(3)];
+        expected.sort_by_key(|cid| cid.to_bytes());
+        assert_eq!(wantlist, expected);
+    }
+}
@@ -0,0 +1,190 @@
+use super::{Bitswap, Want, WantEvent};
+use crate::{
+    cid::Cid,
+    error::{self, Error},
+    identity::PeerId,
+    io,
+    payload::bitswap::mod_Message::mod_Wantlist::WantType,
+};
+use async_io::Timer;
+use futures::{
+    channel::mpsc,
+    future::{self, Either},
+    StreamExt,
+};
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+#[derive(Default, Clone, Copy)]
+struct SessionPeer {
+    blocks: u64,
+    dont_haves: u64,
+}
+
+// the want of a get, withdrawn when the get ends or is dropped
+struct WantGuard<'a> {
+    bitswap: &'a Bitswap,
+    cid: &'a Cid,
+    waiter_id: u64,
+}
+
+impl Drop for WantGuard<'_> {
+    fn drop(&mut self) {
+        let mut state = self.bitswap.state.lock().unwrap();
+        let Some(want) = state.wants.get_mut(self.cid) else {
+            return;
+        };
+        want.waiters.remove(&self.waiter_id);
+        if want.waiters.is_empty() {
+            state.cancel(self.cid);
+        }
+    }
+}
+
+// Session
+//
+// Fetches related blocks, the block itself is asked from the peer which sent
+// the most blocks of the session and the other peers are asked whether they
+// have it
+#[derive(Clone)]
+pub struct Session {
+    bitswap: Bitswap,
+    peers: Arc<Mutex<HashMap<PeerId, SessionPeer>>>,
+}
+
+impl Session {
+    pub(super) fn new(bitswap: Bitswap) -> Self {
+        Self {
+            bitswap,
+            peers: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // the peers which had blocks of the session, best first
+    pub fn peers(&self) -> Vec<PeerId> {
+        let peers: Vec<PeerId> = self.peers.lock().unwrap().keys().cloned().collect();
+        self.rank(peers)
+    }
+
+    // fetch the block, fails once it did not arrive within the timeout of
+    // bitswap
+    pub async fn get(&self, cid: &Cid) -> Result<Vec<u8>, Error> {
+        let local = async_std::task::spawn_blocking({
+            let (bitswap, cid) = (self.bitswap.clone(), cid.clone());
+            move || bitswap.local_block(&cid)
+        })
+        .await;
+        if let Some(data) = local {
+            return Ok(data);
+        }
+        io::timeout(self.bitswap.timeout, self.fetch(cid)).await
+    }
+
+    async fn fetch(&self, cid: &Cid) -> Result<Vec<u8>, Error> {
+        let (_guard, mut events) = self.want(cid);
+        // the peer asked for the block, and the peers to ask next
+        let mut block_peer = None;
+        let mut haves = VecDeque::new();
+        let mut next_broadcast = Instant::now();
+        loop {
+            if next_broadcast <= Instant::now() {
+                haves.clear();
+                block_peer = self.broadcast(cid);
+                next_broadcast = Instant::now() + self.bitswap.rebroadcast_interval;
+            }
+            let event = match future::select(events.next(), Timer::at(next_broadcast)).await {
+                Either::Left((Some(event), _)) => event,
+                Either::Left((None, _)) => return Err(error::other("want cancelled")),
+                Either::Right(_) => continue,
+            };
+            match event {
+                WantEvent::Block(peer_id, data) => {
+                    self.update(&peer_id, |peer| peer.blocks += 1);
+                    return Ok(data);
+                }
+                WantEvent::Have(peer_id) => {
+                    self.update(&peer_id, |_| ());
+                    if block_peer.is_none() {
+                        self.send_want(cid, &peer_id, WantType::Block);
+                        block_peer = Some(peer_id);
+                    } else if block_peer.as_ref() != Some(&peer_id) {
+                        haves.push_back(peer_id);
+                    }
+                }
+                WantEvent::DontHave(peer_id) => {
+                    self.update(&peer_id, |peer| peer.dont_haves += 1);
+                    haves.retain(|peer| peer != &peer_id);
+                    if block_peer.as_ref() == Some(&peer_id) {
+                        block_peer = haves.pop_front();
+                        if let Some(peer_id) = &block_peer {
+                            self.send_want(cid, peer_id, WantType::Block);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn want<'a>(&'a self, cid: &'a Cid) -> (WantGuard<'a>, mpsc::UnboundedReceiver<WantEvent>) {
+        let (sender, receiver) = mpsc::unbounded();
+        let mut state = self.bitswap.state.lock().unwrap();
+        state.next_waiter_id += 1;
+        let waiter_id = state.next_waiter_id;
+        let priority = state.next_priority;
+        let want = state.wants.entry(cid.clone()).or_insert_with(|| Want {
+            priority,
+            waiters: HashMap::new(),
+            asked: HashSet::new(),
+        });
+        want.waiters.insert(waiter_id, sender);
+        state.next_priority = priority.saturating_sub(1).max(1);
+        let guard = WantGuard {
+            bitswap: &self.bitswap,
+            cid,
+            waiter_id,
+        };
+        (guard, receiver)
+    }
+
+    // ask the best peer for the block and the others whether they have it,
+    // returns the peer asked for the block
+    fn broadcast(&self, cid: &Cid) -> Option<PeerId> {
+        let mut peers = self.rank(self.bitswap.peers()).into_iter();
+        let block_peer = peers.next()?;
+        let mut state = self.bitswap.state.lock().unwrap();
+        state.send_want(cid, &block_peer, WantType::Block);
+        for peer_id in peers {
+            state.send_want(cid, &peer_id, WantType::Have);
+        }
+        Some(block_peer)
+    }
+
+    fn send_want(&self, cid: &Cid, peer_id: &PeerId, want_type: WantType) {
+        let mut state = self.bitswap.state.lock().unwrap();
+        state.send_want(cid, peer_id, want_type);
+    }
+
+    fn update(&self, peer_id: &PeerId, f: impl FnOnce(&mut SessionPeer)) {
+        f(self
+            .peers
+            .lock()
+            .unwrap()
+            .entry(peer_id.clone())
+            .or_default());
+    }
+
+    // peers with more blocks and fewer misses first, then the peers new to
+    // the session
+    fn rank(&self, mut peers: Vec<PeerId>) -> Vec<PeerId> {
+        let session = self.peers.lock().unwrap();
+        peers.sort_by_cached_key(|peer_id| match session.get(peer_id) {
+            Some(peer) => (false, Reverse(peer.blocks), peer.dont_haves),
+            None => (true, Reverse(0), 0),
+        });
+        peers
+    }
+}
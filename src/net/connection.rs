@@ -1,20 +1,438 @@
-use super::{multiaddr_to_tcpaddr, Multistream, Noise, ProtocolId, UpgradeOutbound};
+use super::{
+    multiaddr_to_tcpaddr, AutoNat, Mplex, Multistream, MuxerProtocol, MuxerUpgrader, NatStatus,
+    Ping, Pnet, PreSharedKey, Protocol, ProtocolRegistry, SecurityProtocol, SecurityUpgrader,
+    StreamMuxer, UpgradeInbound, UpgradeOutbound, Yamux,
+};
 use crate::{
     error::{self, Error},
     identity::{PeerId, PrivateKey},
+    io,
 };
 use async_io::Async;
+use async_std::task::JoinHandle;
+use futures::{
+    channel::{mpsc, oneshot},
+    future::{self, Either, FutureExt, Shared},
+    lock::Mutex as AsyncMutex,
+    stream::{self, FuturesUnordered},
+    Future, Stream, StreamExt,
+};
+use multiaddr::Multiaddr;
 use std::{
-    net::{SocketAddr, TcpStream},
+    collections::HashMap,
+    net::{SocketAddr, TcpListener, TcpStream},
     path::Path,
+    pin::Pin,
+    sync::{Arc, Mutex, Weak},
+    task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
+// inbound connections not secured and muxed in time are dropped
+const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(20);
+// connections kept by the manager, the least recently used is dropped first
+const DEFAULT_MAX_CONNECTIONS: usize = 128;
+const MAX_NEGOTIATING_STREAMS: usize = 16;
+// unhandled substreams waiting for Connection::incoming_streams
+const MAX_UNHANDLED_STREAMS: usize = 16;
+
+// ConnectionInfo of an authenticated connection
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionInfo {
+    // the address of the relay for relayed connections
+    pub remote_addr: SocketAddr,
+    pub peer_id: PeerId,
+    pub security_protocol: SecurityProtocol,
+    // the relay which the connection is tunneled through
+    pub relay: Option<PeerId>,
+}
+
+impl ConnectionInfo {
+    // the address the remote is seen at, unknown over a relay
+    pub fn observed_addr(&self) -> Option<SocketAddr> {
+        match self.relay {
+            Some(_) => None,
+            None => Some(self.remote_addr),
+        }
+    }
+}
+
+// Connection
+//
+// A handle of a muxed connection, each substream agrees on its application
+// protocol by multistream select. Inbound substreams are dispatched to the
+// handlers of the registry, the muxer is closed when the last handle drops.
+#[derive(Clone)]
+pub struct Connection {
+    info: ConnectionInfo,
+    muxer: Arc<MuxerGuard>,
+    muxer_protocol: MuxerProtocol,
+    protocols: Arc<Vec<Vec<u8>>>,
+    unhandled: Arc<AsyncMutex<mpsc::Receiver<(String, io::BoxedStream)>>>,
+    // resolves once the dispatcher is done with the closed muxer
+    closed: Shared<oneshot::Receiver<()>>,
+}
+
+// closes the muxer once no handle of the connection is left
+struct MuxerGuard(Arc<dyn StreamMuxer>);
+
+impl Drop for MuxerGuard {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+// WeakConnection held by the dispatcher, not to keep the connection open
+struct WeakConnection {
+    info: ConnectionInfo,
+    muxer: Weak<MuxerGuard>,
+    muxer_protocol: MuxerProtocol,
+    protocols: Arc<Vec<Vec<u8>>>,
+    unhandled: Weak<AsyncMutex<mpsc::Receiver<(String, io::BoxedStream)>>>,
+    closed: Shared<oneshot::Receiver<()>>,
+}
+
+impl WeakConnection {
+    fn upgrade(&self) -> Option<Connection> {
+        Some(Connection {
+            info: self.info.clone(),
+            muxer: self.muxer.upgrade()?,
+            muxer_protocol: self.muxer_protocol,
+            protocols: self.protocols.clone(),
+            unhandled: self.unhandled.upgrade()?,
+            closed: self.closed.clone(),
+        })
+    }
+}
+
+impl Connection {
+    // inbound substreams are accepted only for the protocols of the registry
+    pub fn new(
+        info: ConnectionInfo,
+        muxer: Arc<dyn StreamMuxer>,
+        muxer_protocol: MuxerProtocol,
+        registry: ProtocolRegistry,
+    ) -> Self {
+        let (unhandled_tx, unhandled_rx) = mpsc::channel(MAX_UNHANDLED_STREAMS);
+        let (closed_tx, closed_rx) = oneshot::channel();
+        let connection = Self {
+            info,
+            muxer: Arc::new(MuxerGuard(muxer.clone())),
+            muxer_protocol,
+            protocols: Arc::new(
+                registry
+                    .protocols()
+                    .into_iter()
+                    .map(String::into_bytes)
+                    .collect(),
+            ),
+            unhandled: Arc::new(AsyncMutex::new(unhandled_rx)),
+            closed: closed_rx.shared(),
+        };
+        async_std::task::spawn(dispatch(
+            connection.downgrade(),
+            muxer,
+            registry,
+            unhandled_tx,
+            closed_tx,
+        ));
+        connection
+    }
+
+    fn downgrade(&self) -> WeakConnection {
+        WeakConnection {
+            info: self.info.clone(),
+            muxer: Arc::downgrade(&self.muxer),
+            muxer_protocol: self.muxer_protocol,
+            protocols: self.protocols.clone(),
+            unhandled: Arc::downgrade(&self.unhandled),
+            closed: self.closed.clone(),
+        }
+    }
+
+    pub fn info(&self) -> &ConnectionInfo {
+        &self.info
+    }
+
+    pub fn muxer_protocol(&self) -> MuxerProtocol {
+        self.muxer_protocol
+    }
+
+    // protocols accepted on inbound substreams
+    pub fn protocols(&self) -> Vec<String> {
+        self.protocols
+            .iter()
+            .filter_map(|protocol| String::from_utf8(protocol.clone()).ok())
+            .collect()
+    }
+
+    pub async fn open_stream(&self, protocol: &str) -> Result<io::BoxedStream, Error> {
+        let stream = self.muxer.0.open_stream().await?;
+        let (stream, _agreed) = Multistream::new(vec![protocol.as_bytes().to_vec()])
+            .upgrade_outbound(stream)
+            .await?;
+        Ok(stream)
+    }
+
+    // inbound substreams of the protocols accepted without a handler, with
+    // their agreed protocol. Ends when the connection is closed, and does not
+    // keep it open.
+    pub fn incoming_streams(
+        &self,
+    ) -> Pin<Box<dyn Stream<Item = (String, io::BoxedStream)> + Send>> {
+        stream::unfold(self.unhandled.clone(), |unhandled| async move {
+            let next = unhandled.lock().await.next().await?;
+            Some((next, unhandled))
+        })
+        .boxed()
+    }
+
+    pub fn close(&self) {
+        self.muxer.0.close();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.muxer.0.is_closed()
+    }
+
+    // resolves once the connection is closed, without keeping it open
+    pub fn closed(&self) -> impl Future<Output = ()> + Send + 'static {
+        self.closed.clone().map(|_| ())
+    }
+}
+
+// the only reader of the inbound substreams, runs their handler or passes
+// them on as unhandled until the connection is closed, dropping the closed
+// sender then
+async fn dispatch(
+    connection: WeakConnection,
+    muxer: Arc<dyn StreamMuxer>,
+    registry: ProtocolRegistry,
+    mut unhandled: mpsc::Sender<(String, io::BoxedStream)>,
+    _closed: oneshot::Sender<()>,
+) {
+    let protocols = connection.protocols.clone();
+    let mut incoming = stream::unfold(muxer, |muxer| async move {
+        let stream = muxer.accept_stream().await.ok()?;
+        Some((stream, muxer))
+    })
+    .map(move |stream| Multistream::new(protocols.to_vec()).upgrade_inbound(stream))
+    // a slow substream does not hold back the others
+    .buffer_unordered(MAX_NEGOTIATING_STREAMS)
+    .boxed();
+
+    while let Some(res) = incoming.next().await {
+        let (stream, protocol) = match res {
+            Ok((stream, agreed)) => match String::from_utf8(agreed) {
+                Ok(protocol) => (stream, protocol),
+                Err(_) => continue,
+            },
+            Err(err) => {
+                log::debug!("substream negotiation failed, {:?}", err);
+                continue;
+            }
+        };
+        let Some(handle) = connection.upgrade() else {
+            break;
+        };
+        match registry.handler(&protocol) {
+            Some(handler) => {
+                async_std::task::spawn(async move {
+                    if let Err(err) = handler(handle, stream).await {
+                        log::debug!("{} handler failed, {:?}", protocol, err);
+                    }
+                });
+            }
+            None => {
+                if unhandled.try_send((protocol, stream)).is_err() {
+                    log::debug!("unhandled substream dropped");
+                }
+            }
+        }
+    }
+}
+
+// Incoming
+//
+// The connections accepted on a listener, their handshakes run concurrently
+// and carry on between reads, those failing are skipped. Ends after an error
+// of the listener.
+pub struct Incoming(Pin<Box<dyn Stream<Item = Result<Connection, Error>> + Send>>);
+
+impl Incoming {
+    // the next connection to finish its handshake
+    pub async fn accept(&mut self) -> Result<Connection, Error> {
+        self.next()
+            .await
+            .unwrap_or_else(|| Err(error::other("listener closed")))
+    }
+}
+
+impl Stream for Incoming {
+    type Item = Result<Connection, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0.as_mut().poll_next(cx)
+    }
+}
+
+// PeerMap
+//
+// What is known of the peers over their connections, the entry of a peer is
+// dropped once the connection which last set it closes
+pub(crate) struct PeerMap<V> {
+    entries: Arc<Mutex<PeerEntries<V>>>,
+}
+
+// the entries with the muxer of the connection which set them
+type PeerEntries<V> = HashMap<PeerId, (Weak<MuxerGuard>, V)>;
+
+impl<V> Clone for PeerMap<V> {
+    fn clone(&self) -> Self {
+        Self {
+            entries: self.entries.clone(),
+        }
+    }
+}
+
+impl<V: Send + 'static> PeerMap<V> {
+    pub fn new() -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn get(&self, peer_id: &PeerId) -> Option<V>
+    where
+        V: Clone,
+    {
+        let entries = self.entries.lock().unwrap();
+        entries.get(peer_id).map(|(_, value)| value.clone())
+    }
+
+    pub fn insert(&self, connection: &Connection, value: V) {
+        self.set(connection, |entry| *entry = Some(value));
+    }
+
+    pub fn update(&self, connection: &Connection, f: impl FnOnce(&mut V))
+    where
+        V: Default,
+    {
+        self.set(connection, |entry| f(entry.get_or_insert_with(V::default)));
+    }
+
+    fn set(&self, connection: &Connection, f: impl FnOnce(&mut Option<V>)) {
+        let peer_id = connection.info().peer_id.clone();
+        let muxer = Arc::downgrade(&connection.muxer);
+        let mut entries = self.entries.lock().unwrap();
+        let mut entry = entries.remove(&peer_id);
+        let watched = entry
+            .as_ref()
+            .is_some_and(|(set_by, _)| set_by.ptr_eq(&muxer));
+        let mut value = entry.take().map(|(_, value)| value);
+        f(&mut value);
+        let Some(value) = value else {
+            return;
+        };
+        entries.insert(peer_id.clone(), (muxer.clone(), value));
+        if watched {
+            return;
+        }
+
+        let weak_entries = Arc::downgrade(&self.entries);
+        let closed = connection.closed();
+        async_std::task::spawn(async move {
+            closed.await;
+            let Some(entries) = weak_entries.upgrade() else {
+                return;
+            };
+            let mut entries = entries.lock().unwrap();
+            if entries
+                .get(&peer_id)
+                .is_some_and(|(set_by, _)| set_by.ptr_eq(&muxer))
+            {
+                entries.remove(&peer_id);
+            }
+        });
+    }
+}
+
+// InboundRedirects
+//
+// Hands inbound tcp streams from expected addresses over to their waiter
+// instead of upgrading them as new connections, such as when hole punching
+#[derive(Clone, Default)]
+pub struct InboundRedirects {
+    waiters: Arc<Mutex<HashMap<SocketAddr, oneshot::Sender<Async<TcpStream>>>>>,
+}
+
+impl InboundRedirects {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // the next inbound stream from the address, replaces a previous waiter
+    pub fn expect(&self, remote_addr: SocketAddr) -> oneshot::Receiver<Async<TcpStream>> {
+        let (tx, rx) = oneshot::channel();
+        self.waiters.lock().unwrap().insert(remote_addr, tx);
+        rx
+    }
+
+    pub fn cancel(&self, remote_addr: &SocketAddr) {
+        self.waiters.lock().unwrap().remove(remote_addr);
+    }
+
+    // the stream is returned if nobody is waiting for it
+    pub fn redirect(
+        &self,
+        stream: Async<TcpStream>,
+        remote_addr: &SocketAddr,
+    ) -> Option<Async<TcpStream>> {
+        let waiter = self.waiters.lock().unwrap().remove(remote_addr);
+        match waiter {
+            Some(waiter) => waiter.send(stream).err(),
+            None => Some(stream),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Manager {
     socket_addr: SocketAddr,
     private_key: PrivateKey,
+    pre_shared_key: Option<PreSharedKey>,
+    security_protocols: Vec<SecurityProtocol>,
+    muxer_protocols: Vec<MuxerProtocol>,
+    yamux: Yamux,
+    mplex: Mplex,
+    registry: ProtocolRegistry,
+    redirects: InboundRedirects,
+    autonat: Option<AutoNat>,
+    handshake_timeout: Duration,
+    max_connections: usize,
+    // with the instant each one was last used
+    connections: Arc<Mutex<HashMap<PeerId, (Connection, Instant)>>>,
 }
 
 impl Manager {
+    pub fn new(private_key: PrivateKey, socket_addr: SocketAddr) -> Self {
+        Self {
+            socket_addr,
+            private_key,
+            pre_shared_key: None,
+            security_protocols: SecurityProtocol::defaults(),
+            muxer_protocols: MuxerProtocol::defaults(),
+            yamux: Yamux::new(),
+            mplex: Mplex::new(),
+            registry: ProtocolRegistry::new(),
+            redirects: InboundRedirects::new(),
+            autonat: None,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            connections: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
     pub fn from_key_path_and_addr(
         key_path: impl AsRef<Path>,
         target_addr: &str,
@@ -22,10 +440,98 @@ impl Manager {
         let socket_addr =
             multiaddr_to_tcpaddr(&target_addr.parse().map_err(|_| error::parse_error())?)?;
         let private_key = PrivateKey::from_ed25519_pem_file(key_path)?;
-        Ok(Self {
-            socket_addr,
-            private_key,
-        })
+        Ok(Self::new(private_key, socket_addr))
+    }
+
+    // join the private network protected by the pre-shared key
+    pub fn with_pre_shared_key(mut self, pre_shared_key: PreSharedKey) -> Self {
+        self.pre_shared_key = Some(pre_shared_key);
+        self
+    }
+
+    // security protocols offered as outbound and accepted as inbound, in preference order
+    pub fn with_security_protocols(mut self, security_protocols: Vec<SecurityProtocol>) -> Self {
+        self.security_protocols = security_protocols;
+        self
+    }
+
+    // muxers offered as outbound and accepted as inbound, in preference order
+    pub fn with_muxer_protocols(mut self, muxer_protocols: Vec<MuxerProtocol>) -> Self {
+        self.muxer_protocols = muxer_protocols;
+        self
+    }
+
+    // settings of the yamux connections, such as their stream limit
+    pub fn with_yamux(mut self, yamux: Yamux) -> Self {
+        self.yamux = yamux;
+        self
+    }
+
+    // settings of the mplex connections, such as their buffer limit
+    pub fn with_mplex(mut self, mplex: Mplex) -> Self {
+        self.mplex = mplex;
+        self
+    }
+
+    // serve inbound substreams of the protocol on every connection
+    pub fn with_protocol_handler<F, Fut>(mut self, protocol: &str, handler: F) -> Self
+    where
+        F: Fn(Connection, io::BoxedStream) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        self.registry.register(protocol, handler);
+        self
+    }
+
+    // accept inbound substreams of the protocol on every connection, they
+    // are returned by Connection::incoming_streams
+    pub fn with_inbound_protocol(mut self, protocol: &str) -> Self {
+        self.registry.accept(protocol);
+        self
+    }
+
+    // serve the protocols on every connection
+    pub fn with_protocol(mut self, protocol: impl Protocol) -> Self {
+        protocol.register(&mut self.registry);
+        self
+    }
+
+    // inbound streams expected by the redirects are not accepted as connections
+    pub fn with_inbound_redirects(mut self, redirects: InboundRedirects) -> Self {
+        self.redirects = redirects;
+        self
+    }
+
+    // reachability of the local peer, probed by AutoNat::run over the
+    // connections of the manager
+    pub fn with_autonat(mut self, autonat: AutoNat) -> Self {
+        self.autonat = Some(autonat);
+        self
+    }
+
+    // deadline of the pnet, security and muxer upgrades of an inbound connection
+    pub fn with_handshake_timeout(mut self, handshake_timeout: Duration) -> Self {
+        self.handshake_timeout = handshake_timeout;
+        self
+    }
+
+    // connections kept open by the manager, the least recently used one is
+    // dropped from the pool to make room and closes unless held elsewhere
+    pub fn with_max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    pub fn autonat(&self) -> Option<&AutoNat> {
+        self.autonat.as_ref()
+    }
+
+    // unknown without autonat
+    pub fn nat_status(&self) -> NatStatus {
+        self.autonat
+            .as_ref()
+            .map(AutoNat::status)
+            .unwrap_or_default()
     }
 
     pub fn peer_id(&self) -> Result<PeerId, Error> {
@@ -33,35 +539,628 @@ impl Manager {
     }
 
     pub async fn tcp_connect(&self) -> Result<(), Error> {
-        let stream = Async::<TcpStream>::connect(self.socket_addr)
+        let connection = self.connect(self.socket_addr).await?;
+        log::info!(
+            "connected to {:?} over {:?} and {:?}",
+            connection.info().peer_id,
+            connection.info().security_protocol,
+            connection.muxer_protocol()
+        );
+
+        // hold the connection as long as the remote answers pings
+        Ping::new().run(&connection).await
+    }
+
+    // dial, secure and mux an outbound connection, inbound substreams are
+    // served by the registered handlers
+    pub async fn connect(&self, socket_addr: SocketAddr) -> Result<Connection, Error> {
+        let (stream, info) = self.tcp_dial(socket_addr).await?;
+        self.mux_outbound(stream, info).await
+    }
+
+    // secure and mux a dialed tcp stream as the initiator
+    pub async fn connect_stream(
+        &self,
+        stream: Async<TcpStream>,
+        remote_addr: SocketAddr,
+    ) -> Result<Connection, Error> {
+        let (stream, info) = self.tcp_secure_outbound(stream, remote_addr).await?;
+        self.mux_outbound(stream, info).await
+    }
+
+    // secure and mux an outbound connection over a circuit of the relay
+    pub async fn connect_relayed(
+        &self,
+        stream: io::BoxedStream,
+        relay: &Connection,
+    ) -> Result<Connection, Error> {
+        let (stream, mut info) = self
+            .secure_outbound(stream, relay.info().remote_addr)
+            .await?;
+        info.relay = Some(relay.info().peer_id.clone());
+        self.mux_outbound(stream, info).await
+    }
+
+    // secure and mux an inbound connection over a circuit of the relay
+    pub async fn accept_relayed(
+        &self,
+        stream: io::BoxedStream,
+        relay: &Connection,
+    ) -> Result<Connection, Error> {
+        let (stream, mut info) = self
+            .secure_inbound(stream, relay.info().remote_addr)
+            .await?;
+        info.relay = Some(relay.info().peer_id.clone());
+        self.mux_inbound(stream, info).await
+    }
+
+    // the inbound connections of the listener, secured and muxed
+    pub fn incoming(&self, listener: Async<TcpListener>) -> Incoming {
+        let handshakes: FuturesUnordered<JoinHandle<Result<Connection, Error>>> =
+            FuturesUnordered::new();
+        let state = (self.clone(), listener, handshakes);
+        let connections = stream::unfold(Some(state), |state| async move {
+            let (manager, listener, mut handshakes) = state?;
+            loop {
+                let next = {
+                    let accepted = std::pin::pin!(listener.accept());
+                    let handshake = std::pin::pin!(async {
+                        match handshakes.next().await {
+                            Some(res) => res,
+                            None => future::pending().await,
+                        }
+                    });
+                    match future::select(accepted, handshake).await {
+                        Either::Left((accepted, _)) => Either::Left(accepted),
+                        Either::Right((res, _)) => Either::Right(res),
+                    }
+                };
+                match next {
+                    Either::Left(Ok((stream, remote_addr))) => {
+                        let Some(stream) = manager.redirects.redirect(stream, &remote_addr) else {
+                            continue;
+                        };
+                        // a stalled handshake does not hold back the next ones
+                        let accepting = manager.clone();
+                        handshakes.push(async_std::task::spawn(async move {
+                            accepting.accept_stream(stream, remote_addr).await
+                        }));
+                    }
+                    // the listener failed, which ends the connections
+                    Either::Left(Err(err)) => return Some((Err(err), None)),
+                    Either::Right(Ok(connection)) => {
+                        return Some((Ok(connection), Some((manager, listener, handshakes))))
+                    }
+                    Either::Right(Err(err)) => {
+                        log::debug!("inbound connection failed, {:?}", err)
+                    }
+                }
+            }
+        });
+        Incoming(connections.boxed())
+    }
+
+    // accept inbound connections until the listener fails
+    pub async fn listen(&self, listener: Async<TcpListener>) -> Result<(), Error> {
+        let mut incoming = self.incoming(listener);
+        loop {
+            let connection = incoming.accept().await?;
+            log::debug!("accepted {:?}", connection.info().peer_id);
+        }
+    }
+
+    // secure and mux an accepted tcp stream as the responder, within the
+    // handshake timeout
+    pub async fn accept_stream(
+        &self,
+        stream: Async<TcpStream>,
+        remote_addr: SocketAddr,
+    ) -> Result<Connection, Error> {
+        io::timeout(self.handshake_timeout, async {
+            let (stream, info) = self.tcp_secure_inbound(stream, remote_addr).await?;
+            self.mux_inbound(stream, info).await
+        })
+        .await
+    }
+
+    async fn mux_outbound(
+        &self,
+        stream: io::SecuredStream<io::BoxedStream>,
+        info: ConnectionInfo,
+    ) -> Result<Connection, Error> {
+        let (muxer, muxer_protocol) = MuxerUpgrader::new(self.muxer_protocols.clone())
+            .with_yamux(self.yamux.clone())
+            .with_mplex(self.mplex.clone())
+            .upgrade_outbound(stream)
+            .await?;
+        Ok(self.serve(Connection::new(
+            info,
+            muxer,
+            muxer_protocol,
+            self.registry.clone(),
+        )))
+    }
+
+    async fn mux_inbound(
+        &self,
+        stream: io::SecuredStream<io::BoxedStream>,
+        info: ConnectionInfo,
+    ) -> Result<Connection, Error> {
+        let (muxer, muxer_protocol) = MuxerUpgrader::new(self.muxer_protocols.clone())
+            .with_yamux(self.yamux.clone())
+            .with_mplex(self.mplex.clone())
+            .upgrade_inbound(stream)
+            .await?;
+        Ok(self.serve(Connection::new(
+            info,
+            muxer,
+            muxer_protocol,
+            self.registry.clone(),
+        )))
+    }
+
+    // an open connection to the peer, dialed or accepted before
+    pub fn connection(&self, peer_id: &PeerId) -> Option<Connection> {
+        let mut connections = self.connections.lock().unwrap();
+        connections.retain(|_, (connection, _)| !connection.is_closed());
+        let (connection, last_used) = connections.get_mut(peer_id)?;
+        *last_used = Instant::now();
+        Some(connection.clone())
+    }
+
+    // open connections, dialed or accepted before
+    pub fn connections(&self) -> Vec<Connection> {
+        let mut connections = self.connections.lock().unwrap();
+        connections.retain(|_, (connection, _)| !connection.is_closed());
+        connections
+            .values()
+            .map(|(connection, _)| connection.clone())
+            .collect()
+    }
+
+    // reuse an open connection to the peer, or dial its addresses in order
+    pub async fn dial_peer(
+        &self,
+        peer_id: &PeerId,
+        addrs: &[Multiaddr],
+    ) -> Result<Connection, Error> {
+        if let Some(connection) = self.connection(peer_id) {
+            return Ok(connection);
+        }
+
+        let mut last_err = error::unsupported("no dialable address");
+        for addr in addrs {
+            let socket_addr = match multiaddr_to_tcpaddr(addr) {
+                Ok(socket_addr) => socket_addr,
+                Err(err) => {
+                    last_err = err;
+                    continue;
+                }
+            };
+            match self.connect(socket_addr).await {
+                Ok(connection) if &connection.info().peer_id == peer_id => return Ok(connection),
+                Ok(connection) => {
+                    log::debug!("{:?} is not {:?}", connection.info().peer_id, peer_id);
+                    connection.close();
+                    last_err = error::verification_failed();
+                }
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+
+    // the manager keeps the connection open until it is closed, evicted to
+    // make room for another one, or the manager is dropped
+    fn serve(&self, connection: Connection) -> Connection {
+        let peer_id = connection.info().peer_id.clone();
+        {
+            let mut connections = self.connections.lock().unwrap();
+            connections.retain(|_, (connection, _)| !connection.is_closed());
+            while !connections.contains_key(&peer_id)
+                && !connections.is_empty()
+                && connections.len() >= self.max_connections
+            {
+                let evicted = connections
+                    .iter()
+                    .min_by_key(|(_, (_, last_used))| *last_used)
+                    .map(|(peer_id, _)| peer_id.clone())
+                    .unwrap();
+                log::debug!("connection to {:?} evicted", evicted);
+                connections.remove(&evicted);
+            }
+            connections.insert(peer_id.clone(), (connection.clone(), Instant::now()));
+        }
+
+        // the entry is dropped as soon as the connection closes
+        let connections = Arc::downgrade(&self.connections);
+        let closed = connection.closed();
+        let served = Arc::downgrade(&connection.muxer);
+        async_std::task::spawn(async move {
+            closed.await;
+            let Some(connections) = connections.upgrade() else {
+                return;
+            };
+            // unless replaced by a newer connection to the peer
+            let mut connections = connections.lock().unwrap();
+            let current = connections
+                .get(&peer_id)
+                .is_some_and(|(connection, _)| Arc::downgrade(&connection.muxer).ptr_eq(&served));
+            if current {
+                connections.remove(&peer_id);
+            }
+        });
+        connection
+    }
+
+    // dial and secure an outbound connection
+    pub async fn tcp_dial(
+        &self,
+        socket_addr: SocketAddr,
+    ) -> Result<(io::SecuredStream<io::BoxedStream>, ConnectionInfo), Error> {
+        let stream = Async::<TcpStream>::connect(socket_addr)
             .await
             .map_err(|_| error::other("async stream"))?;
+        self.tcp_secure_outbound(stream, socket_addr).await
+    }
+
+    async fn tcp_secure_outbound(
+        &self,
+        stream: Async<TcpStream>,
+        remote_addr: SocketAddr,
+    ) -> Result<(io::SecuredStream<io::BoxedStream>, ConnectionInfo), Error> {
+        // private network
+        let stream: io::BoxedStream = match &self.pre_shared_key {
+            Some(psk) => Box::new(Pnet::new(psk.clone()).upgrade_outbound(stream).await?),
+            None => Box::new(stream),
+        };
+
+        self.secure_outbound(stream, remote_addr).await
+    }
+
+    // accept and secure an inbound connection with any enabled security protocol
+    pub async fn tcp_accept(
+        &self,
+        listener: &Async<TcpListener>,
+    ) -> Result<(io::SecuredStream<io::BoxedStream>, ConnectionInfo), Error> {
+        let (stream, remote_addr) = listener.accept().await?;
+        self.tcp_secure_inbound(stream, remote_addr).await
+    }
+
+    async fn tcp_secure_inbound(
+        &self,
+        stream: Async<TcpStream>,
+        remote_addr: SocketAddr,
+    ) -> Result<(io::SecuredStream<io::BoxedStream>, ConnectionInfo), Error> {
+        // private network
+        let stream: io::BoxedStream = match &self.pre_shared_key {
+            Some(psk) => Box::new(Pnet::new(psk.clone()).upgrade_inbound(stream).await?),
+            None => Box::new(stream),
+        };
+
+        self.secure_inbound(stream, remote_addr).await
+    }
+
+    async fn secure_outbound(
+        &self,
+        stream: io::BoxedStream,
+        remote_addr: SocketAddr,
+    ) -> Result<(io::SecuredStream<io::BoxedStream>, ConnectionInfo), Error> {
+        let security_upgrader =
+            SecurityUpgrader::new(self.private_key.clone(), self.security_protocols.clone());
+        let (stream, peer_id, security_protocol) =
+            security_upgrader.upgrade_outbound(stream).await?;
+        Ok((
+            stream,
+            ConnectionInfo {
+                remote_addr,
+                peer_id,
+                security_protocol,
+                relay: None,
+            },
+        ))
+    }
+
+    async fn secure_inbound(
+        &self,
+        stream: io::BoxedStream,
+        remote_addr: SocketAddr,
+    ) -> Result<(io::SecuredStream<io::BoxedStream>, ConnectionInfo), Error> {
+        let security_upgrader =
+            SecurityUpgrader::new(self.private_key.clone(), self.security_protocols.clone());
+        let (stream, peer_id, security_protocol) =
+            security_upgrader.upgrade_inbound(stream).await?;
+        Ok((
+            stream,
+            ConnectionInfo {
+                remote_addr,
+                peer_id,
+                security_protocol,
+                relay: None,
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn test_manager_connection_info() -> Result<(), Error> {
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let addr = listener.get_ref().local_addr()?;
+        let psk = PreSharedKey::new([1u8; 32]);
+        let server = Manager::new(PrivateKey::generate_ed25519(), addr)
+            .with_pre_shared_key(psk.clone())
+            .with_security_protocols(vec![SecurityProtocol::Tls]);
+        let client = Manager::new(PrivateKey::generate_ed25519(), addr)
+            .with_pre_shared_key(psk)
+            .with_security_protocols(vec![SecurityProtocol::Noise, SecurityProtocol::Tls]);
+
+        let (server_res, client_res) =
+            futures::join!(server.tcp_accept(&listener), client.tcp_dial(addr));
+        let (_, server_info) = server_res?;
+        let (_, client_info) = client_res?;
+
+        assert_eq!(server_info.peer_id, client.peer_id()?);
+        assert_eq!(server_info.security_protocol, SecurityProtocol::Tls);
+        assert_eq!(client_info.peer_id, server.peer_id()?);
+        assert_eq!(client_info.security_protocol, SecurityProtocol::Tls);
+        assert_eq!(client_info.remote_addr, addr);
+        assert_eq!(client_info.observed_addr(), Some(addr));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_manager_yamux_over_noise() -> Result<(), Error> {
+        use futures::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let addr = listener.get_ref().local_addr()?;
+        let server = Manager::new(PrivateKey::generate_ed25519(), addr);
+        let client = Manager::new(PrivateKey::generate_ed25519(), addr);
+
+        let (server_res, client_res) =
+            futures::join!(server.tcp_accept(&listener), client.tcp_dial(addr));
+        let server_conn = Yamux::new().upgrade_inbound(server_res?.0).await?;
+        let client_conn = Yamux::new().upgrade_outbound(client_res?.0).await?;
+
+        // larger than a noise message
+        let payload = vec![7u8; 200 * 1024];
+        let mut stream = client_conn.open_stream().await?;
+        stream.write_all(&payload).await?;
+        stream.close().await?;
+
+        let mut buf = Vec::new();
+        server_conn
+            .accept_stream()
+            .await?
+            .read_to_end(&mut buf)
+            .await?;
+        assert_eq!(buf, payload);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_manager_protocol_handlers() -> Result<(), Error> {
+        use futures::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let addr = listener.get_ref().local_addr()?;
+        let server = Manager::new(PrivateKey::generate_ed25519(), addr)
+            .with_muxer_protocols(vec![MuxerProtocol::Mplex])
+            .with_protocol_handler("/echo/1.0.0", |_connection, mut stream| async move {
+                let mut buf = Vec::new();
+                stream.read_to_end(&mut buf).await?;
+                stream.write_all(&buf).await?;
+                stream.close().await
+            });
+        let client = Manager::new(PrivateKey::generate_ed25519(), addr);
+
+        let mut incoming = server.incoming(listener);
+        let (server_res, client_res) = futures::join!(incoming.accept(), client.connect(addr));
+        let (server_conn, client_conn) = (server_res?, client_res?);
+
+        // yamux is preferred by the client, but only mplex is supported by the server
+        assert_eq!(client_conn.muxer_protocol(), MuxerProtocol::Mplex);
+        assert_eq!(server_conn.info().peer_id, client.peer_id()?);
 
-        // upgrader
-        let noise_upgrader = Noise::new(self.private_key.clone());
-        let security_upgrader = Multistream::new(vec![Noise::protocol_id().as_bytes().to_vec()]);
+        let mut stream = client_conn.open_stream("/echo/1.0.0").await?;
+        stream.write_all(b"echo").await?;
+        stream.close().await?;
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await?;
+        assert_eq!(buf, b"echo");
 
-        // multistream select
-        let (stream, _agreed) = security_upgrader.upgrade_outbound(stream).await?;
+        // no handler for the protocol
+        assert!(client_conn.open_stream("/unknown/1.0.0").await.is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_connection_incoming_streams() -> Result<(), Error> {
+        use futures::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let addr = listener.get_ref().local_addr()?;
+        let server = Manager::new(PrivateKey::generate_ed25519(), addr)
+            .with_inbound_protocol("/user/1.0.0")
+            .with_protocol_handler("/echo/1.0.0", |_connection, mut stream| async move {
+                let mut buf = Vec::new();
+                stream.read_to_end(&mut buf).await?;
+                stream.write_all(&buf).await?;
+                stream.close().await
+            });
+        let client = Manager::new(PrivateKey::generate_ed25519(), addr);
+
+        let mut incoming = server.incoming(listener);
+        let (server_res, client_res) = futures::join!(incoming.accept(), client.connect(addr));
+        let (server_conn, client_conn) = (server_res?, client_res?);
+        let mut incoming = server_conn.incoming_streams();
+
+        // handled substreams never reach the incoming streams
+        for _ in 0..4 {
+            let mut stream = client_conn.open_stream("/echo/1.0.0").await?;
+            stream.write_all(b"echo").await?;
+            stream.close().await?;
+            let mut buf = Vec::new();
+            stream.read_to_end(&mut buf).await?;
+            assert_eq!(buf, b"echo");
+        }
+        let mut stream = client_conn.open_stream("/user/1.0.0").await?;
+        stream.write_all(b"user").await?;
+        let (protocol, mut inbound) = incoming.next().await.unwrap();
+        assert_eq!(protocol, "/user/1.0.0");
+        let mut buf = [0u8; 4];
+        inbound.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"user");
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_connection_closed_with_last_handle() -> Result<(), Error> {
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let addr = listener.get_ref().local_addr()?;
+        let server = Manager::new(PrivateKey::generate_ed25519(), addr)
+            .with_protocol_handler("/idle/1.0.0", |_connection, _stream| async { Ok(()) });
+        let client = Manager::new(PrivateKey::generate_ed25519(), addr);
+
+        let mut incoming = server.incoming(listener);
+        let (server_res, client_res) = futures::join!(incoming.accept(), client.connect(addr));
+        let (server_conn, client_conn) = (server_res?, client_res?);
+
+        // the handlers being served do not keep it open
+        drop(incoming);
+        drop(server);
+        drop(server_conn);
+        io::timeout(std::time::Duration::from_secs(5), async {
+            while !client_conn.is_closed() {
+                async_io::Timer::after(std::time::Duration::from_millis(10)).await;
+            }
+            Ok(())
+        })
+        .await
+    }
 
-        // noise handshake
-        let (upgraded_stream, _peer_id) = noise_upgrader.upgrade_outbound(stream).await?;
+    #[async_std::test]
+    async fn test_manager_mplex_buffer_limit() -> Result<(), Error> {
+        use futures::{AsyncReadExt, AsyncWriteExt};
 
-        // ===
-        //
-        // Note:
-        // Handshake is done here
-        // The following is to complete the connection upgrade so that it is easier to check on logs
-        //
-        // ===
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let addr = listener.get_ref().local_addr()?;
+        let server = Manager::new(PrivateKey::generate_ed25519(), addr)
+            .with_muxer_protocols(vec![MuxerProtocol::Mplex])
+            .with_mplex(Mplex::new().with_max_buffer_size(1024))
+            .with_protocol_handler("/sink/1.0.0", |_connection, stream| async move {
+                // hold the stream without reading it
+                async_io::Timer::after(std::time::Duration::from_secs(5)).await;
+                drop(stream);
+                Ok(())
+            });
+        let client = Manager::new(PrivateKey::generate_ed25519(), addr);
 
-        // multistream select over noise transport
-        let muxer_upgrader = Multistream::new(vec!["/yamux/1.0.0".as_bytes().to_vec()]);
-        let (_upgraded_stream, _agreed) = muxer_upgrader.upgrade_outbound(upgraded_stream).await?;
+        let mut incoming = server.incoming(listener);
+        let (server_res, client_res) = futures::join!(incoming.accept(), client.connect(addr));
+        let (_server_conn, client_conn) = (server_res?, client_res?);
 
-        log::info!("sleep for 60s for holding the connection");
-        std::thread::sleep(std::time::Duration::from_secs(60));
+        // over the limit of the server, the stream is reset
+        let mut stream = client_conn.open_stream("/sink/1.0.0").await?;
+        stream.write_all(&[0u8; 2048]).await?;
+        let mut buf = [0u8; 1];
+        assert!(stream.read(&mut buf).await.is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_manager_stalled_handshake() -> Result<(), Error> {
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let addr = listener.get_ref().local_addr()?;
+        let server = Arc::new(
+            Manager::new(PrivateKey::generate_ed25519(), addr)
+                .with_handshake_timeout(Duration::from_millis(500)),
+        );
+        let listening = server.clone();
+        async_std::task::spawn(async move { listening.listen(listener).await });
+
+        // a client which never says anything does not hold back the others
+        let mut silent = Async::<TcpStream>::connect(addr).await?;
+        let client = Manager::new(PrivateKey::generate_ed25519(), addr);
+        io::timeout(Duration::from_secs(5), client.connect(addr)).await?;
+
+        // and is dropped after the handshake timeout
+        let mut buf = [0u8; 64];
+        io::timeout(Duration::from_secs(5), async {
+            loop {
+                match futures::AsyncReadExt::read(&mut silent, &mut buf).await {
+                    Ok(0) | Err(_) => return Ok(()),
+                    Ok(_) => continue,
+                }
+            }
+        })
+        .await
+    }
 
+    #[async_std::test]
+    async fn test_manager_incoming_skips_failures() -> Result<(), Error> {
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let addr = listener.get_ref().local_addr()?;
+        let server = Manager::new(PrivateKey::generate_ed25519(), addr)
+            .with_handshake_timeout(Duration::from_millis(200));
+        let mut incoming = server.incoming(listener);
+
+        // a client failing its handshake does not end the connections, and
+        // handshakes in flight carry on to the next read
+        let failing = async {
+            let mut stream = Async::<TcpStream>::connect(addr).await?;
+            futures::AsyncWriteExt::write_all(&mut stream, b"garbage\n").await?;
+            Ok::<_, Error>(stream)
+        };
+        let client = Manager::new(PrivateKey::generate_ed25519(), addr);
+        let other = Manager::new(PrivateKey::generate_ed25519(), addr);
+        let (_failing, first, second, accepted) = futures::join!(
+            failing,
+            client.connect(addr),
+            other.connect(addr),
+            io::timeout(Duration::from_secs(5), incoming.accept())
+        );
+        let (_first, _second, accepted) = (first?, second?, accepted?);
+        let next = io::timeout(Duration::from_secs(5), incoming.accept()).await?;
+        let accepted = [accepted.info().peer_id.clone(), next.info().peer_id.clone()];
+        assert!(accepted.contains(&client.peer_id()?));
+        assert!(accepted.contains(&other.peer_id()?));
         Ok(())
     }
+
+    #[async_std::test]
+    async fn test_manager_connection_pool() -> Result<(), Error> {
+        let mut servers = Vec::new();
+        for _ in 0..2 {
+            let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+            let addr = listener.get_ref().local_addr()?;
+            let server = Arc::new(Manager::new(PrivateKey::generate_ed25519(), addr));
+            let listening = server.clone();
+            async_std::task::spawn(async move { listening.listen(listener).await });
+            servers.push((server.peer_id()?, addr));
+        }
+        let client =
+            Manager::new(PrivateKey::generate_ed25519(), servers[0].1).with_max_connections(1);
+
+        // the least recently used connection makes room
+        let first = client.connect(servers[0].1).await?;
+        let second = client.connect(servers[1].1).await?;
+        assert!(client.connection(&servers[0].0).is_none());
+        assert!(client.connection(&servers[1].0).is_some());
+        drop(first);
+
+        // the entry goes along with the connection
+        second.close();
+        io::timeout(Duration::from_secs(5), async {
+            while !client.connections.lock().unwrap().is_empty() {
+                async_io::Timer::after(Duration::from_millis(10)).await;
+            }
+            Ok(())
+        })
+        .await
+    }
 }
@@ -0,0 +1,425 @@
+use super::{
+    multiaddr_to_tcpaddr, tcp_connect_reuse_port, Connection, InboundRedirects, Manager, Protocol,
+    ProtocolRegistry,
+};
+use crate::{
+    error::{self, Error},
+    io,
+    payload::holepunch::{mod_HolePunch::Type as HolePunchType, HolePunch},
+};
+use async_io::{Async, Timer};
+use futures::{
+    channel::{mpsc, oneshot},
+    future,
+    lock::Mutex as AsyncMutex,
+    AsyncWriteExt, FutureExt, StreamExt,
+};
+use multiaddr::{Multiaddr, Protocol as AddrProtocol};
+use std::{
+    net::{SocketAddr, TcpStream},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+pub const PROTOCOL_DCUTR: &str = "/libp2p/dcutr";
+const MAX_DCUTR_MESSAGE_SIZE: usize = 4096;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_MAX_ATTEMPTS: usize = 3;
+const MAX_PENDING_PUNCHES: usize = 16;
+
+type Redirected = (SocketAddr, oneshot::Receiver<Async<TcpStream>>);
+
+// a hole punched stream, waiting to be upgraded by the responder
+struct Punched {
+    stream: Async<TcpStream>,
+    remote_addr: SocketAddr,
+    relayed: Connection,
+}
+
+// inbound streams expected while hole punching, no longer once dropped
+struct Expected {
+    redirects: InboundRedirects,
+    addrs: Vec<SocketAddr>,
+}
+
+impl Drop for Expected {
+    fn drop(&mut self) {
+        for addr in &self.addrs {
+            self.redirects.cancel(addr);
+        }
+    }
+}
+
+// Dcutr
+//
+// Direct connection upgrade through relay, the peers of a relayed connection
+// exchange their observed addresses and dial each other at once, so that the
+// dial of either one passes the NAT opened by the other. The peer which
+// accepted the relayed connection initiates and is the initiator of the
+// upgrade of the punched stream
+#[derive(Clone)]
+pub struct Dcutr {
+    local_addr: SocketAddr,
+    observed_addrs: Arc<Mutex<Vec<Multiaddr>>>,
+    timeout: Duration,
+    max_attempts: usize,
+    redirects: InboundRedirects,
+    punched_tx: mpsc::Sender<Punched>,
+    punched_rx: Arc<AsyncMutex<mpsc::Receiver<Punched>>>,
+}
+
+impl Dcutr {
+    // dials leave from the local address, that of a listener sharing its port
+    pub fn new(local_addr: SocketAddr) -> Self {
+        let (punched_tx, punched_rx) = mpsc::channel(MAX_PENDING_PUNCHES);
+        Self {
+            local_addr,
+            observed_addrs: Arc::new(Mutex::new(Vec::new())),
+            timeout: DEFAULT_TIMEOUT,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            redirects: InboundRedirects::new(),
+            punched_tx,
+            punched_rx: Arc::new(AsyncMutex::new(punched_rx)),
+        }
+    }
+
+    // timeout of an attempt, from the exchange to the punched stream
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    // addresses of the local peer as observed by others, such as through identify
+    pub fn with_observed_addrs(self, observed_addrs: Vec<Multiaddr>) -> Self {
+        *self.observed_addrs.lock().unwrap() = observed_addrs;
+        self
+    }
+
+    pub fn add_observed_addr(&self, addr: Multiaddr) {
+        let mut observed_addrs = self.observed_addrs.lock().unwrap();
+        if !observed_addrs.contains(&addr) {
+            observed_addrs.push(addr);
+        }
+    }
+
+    pub fn observed_addrs(&self) -> Vec<Multiaddr> {
+        self.observed_addrs.lock().unwrap().clone()
+    }
+
+    // to be given to the manager, so that punched streams are not accepted
+    // as new connections
+    pub fn redirects(&self) -> InboundRedirects {
+        self.redirects.clone()
+    }
+
+    // upgrade the relayed connection accepted by the local peer to a direct one
+    pub async fn upgrade(
+        &self,
+        manager: &Manager,
+        relayed: &Connection,
+    ) -> Result<Connection, Error> {
+        if relayed.info().relay.is_none() {
+            return Err(error::invalid_input("connection is not relayed"));
+        }
+        let mut last_err = error::timed_out();
+        for attempt in 1..=self.max_attempts {
+            match self.attempt(manager, relayed).await {
+                Ok(connection) => return Ok(connection),
+                Err(err) => {
+                    log::debug!(
+                        "hole punch {} to {:?} failed, {:?}",
+                        attempt,
+                        relayed.info().peer_id,
+                        err
+                    );
+                    last_err = err;
+                }
+            }
+        }
+        Err(last_err)
+    }
+
+    async fn attempt(&self, manager: &Manager, relayed: &Connection) -> Result<Connection, Error> {
+        let (stream, remote_addr) = io::timeout(self.timeout, async {
+            let mut stream = relayed.open_stream(PROTOCOL_DCUTR).await?;
+            write_message(&mut stream, &self.connect_message()).await?;
+            let sent = Instant::now();
+            let response: HolePunch = read_message(&mut stream).await?;
+            let rtt = sent.elapsed();
+            if response.type_pb != HolePunchType::CONNECT {
+                return Err(error::message_malformed());
+            }
+            let addrs = tcp_addrs(&response.ObsAddrs)?;
+            let (expected, redirected) = self.expect(&addrs);
+            write_message(&mut stream, &sync_message()).await?;
+            stream.close().await?;
+
+            // the sync reaches the remote in half of the round trip
+            Timer::after(rtt / 2).await;
+            let punched = self.punch(&addrs, redirected).await;
+            drop(expected);
+            punched
+        })
+        .await?;
+        let connection = manager.connect_stream(stream, remote_addr).await?;
+        verify_peer(connection, relayed)
+    }
+
+    // the next direct connection punched by a remote initiator
+    pub async fn accept(&self, manager: &Manager) -> Result<Connection, Error> {
+        let punched = self
+            .punched_rx
+            .lock()
+            .await
+            .next()
+            .await
+            .ok_or(error::other("dcutr dropped"))?;
+        let connection = manager
+            .accept_stream(punched.stream, punched.remote_addr)
+            .await?;
+        verify_peer(connection, &punched.relayed)
+    }
+
+    async fn respond(
+        self,
+        connection: Connection,
+        mut stream: io::BoxedStream,
+    ) -> Result<(), Error> {
+        if connection.info().relay.is_none() {
+            return Err(error::unsupported("hole punch over a direct connection"));
+        }
+        let (stream, remote_addr) = io::timeout(self.timeout, async {
+            let request: HolePunch = read_message(&mut stream).await?;
+            if request.type_pb != HolePunchType::CONNECT {
+                return Err(error::message_malformed());
+            }
+            let addrs = tcp_addrs(&request.ObsAddrs)?;
+            let (expected, redirected) = self.expect(&addrs);
+            write_message(&mut stream, &self.connect_message()).await?;
+            let sync: HolePunch = read_message(&mut stream).await?;
+            if sync.type_pb != HolePunchType::SYNC {
+                return Err(error::message_malformed());
+            }
+            let punched = self.punch(&addrs, redirected).await;
+            drop(expected);
+            punched
+        })
+        .await?;
+        self.punched_tx
+            .clone()
+            .try_send(Punched {
+                stream,
+                remote_addr,
+                relayed: connection,
+            })
+            .map_err(|_| error::other("too many pending hole punches"))
+    }
+
+    fn connect_message(&self) -> HolePunch {
+        HolePunch {
+            type_pb: HolePunchType::CONNECT,
+            ObsAddrs: self
+                .observed_addrs()
+                .iter()
+                .map(|addr| addr.to_vec())
+                .collect(),
+        }
+    }
+
+    fn expect(&self, addrs: &[SocketAddr]) -> (Expected, Vec<Redirected>) {
+        let redirected = addrs
+            .iter()
+            .map(|addr| (*addr, self.redirects.expect(*addr)))
+            .collect();
+        let expected = Expected {
+            redirects: self.redirects.clone(),
+            addrs: addrs.to_vec(),
+        };
+        (expected, redirected)
+    }
+
+    // dial the addresses while waiting for their dials, the first stream wins
+    async fn punch(
+        &self,
+        addrs: &[SocketAddr],
+        redirected: Vec<Redirected>,
+    ) -> Result<(Async<TcpStream>, SocketAddr), Error> {
+        let dials = addrs.iter().map(|addr| {
+            let addr = *addr;
+            async move {
+                let stream = tcp_connect_reuse_port(self.local_addr, addr).await?;
+                Ok::<_, Error>((stream, addr))
+            }
+            .boxed()
+        });
+        let inbound = redirected.into_iter().map(|(addr, redirected)| {
+            async move {
+                let stream = redirected
+                    .await
+                    .map_err(|_| error::other("hole punch cancelled"))?;
+                Ok::<_, Error>((stream, addr))
+            }
+            .boxed()
+        });
+        let (punched, _) = future::select_ok(dials.chain(inbound)).await?;
+        log::debug!("hole punched to {:?}", punched.1);
+        Ok(punched)
+    }
+}
+
+impl Protocol for Dcutr {
+    fn register(self, registry: &mut ProtocolRegistry) {
+        registry.register(PROTOCOL_DCUTR, move |connection, stream| {
+            self.clone().respond(connection, stream)
+        });
+    }
+}
+
+fn sync_message() -> HolePunch {
+    HolePunch {
+        type_pb: HolePunchType::SYNC,
+        ObsAddrs: Vec::new(),
+    }
+}
+
+// direct tcp addresses among the observed ones
+fn tcp_addrs(observed_addrs: &[Vec<u8>]) -> Result<Vec<SocketAddr>, Error> {
+    let mut addrs: Vec<SocketAddr> = Vec::new();
+    for addr in observed_addrs {
+        let Ok(addr) = Multiaddr::try_from(addr.clone()) else {
+            continue;
+        };
+        if addr
+            .iter()
+            .any(|protocol| protocol == AddrProtocol::P2pCircuit)
+        {
+            continue;
+        }
+        if let Ok(addr) = multiaddr_to_tcpaddr(&addr) {
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+    }
+    match addrs.is_empty() {
+        true => Err(error::not_found("no observed tcp address")),
+        false => Ok(addrs),
+    }
+}
+
+// the punched connection must be to the peer of the relayed one
+fn verify_peer(connection: Connection, relayed: &Connection) -> Result<Connection, Error> {
+    if connection.info().peer_id != relayed.info().peer_id {
+        log::debug!(
+            "{:?} is not {:?}",
+            connection.info().peer_id,
+            relayed.info().peer_id
+        );
+        connection.close();
+        return Err(error::verification_failed());
+    }
+    Ok(connection)
+}
+
+async fn write_message(stream: &mut io::BoxedStream, message: &HolePunch) -> Result<(), Error> {
+    io::write_protobuf_prefixed(stream, message).await
+}
+
+async fn read_message(stream: &mut io::BoxedStream) -> Result<HolePunch, Error> {
+    io::read_protobuf_prefixed(stream, MAX_DCUTR_MESSAGE_SIZE).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        identity::PrivateKey,
+        net::{
+            p2p_protocol, relay::tests::spawn_relay, tcp_listen_reuse_port, tcpaddr_to_multiaddr,
+            Ping, RelayClient, RelayResources,
+        },
+    };
+
+    struct NatedPeer {
+        manager: Arc<Manager>,
+        dcutr: Dcutr,
+        relay_client: RelayClient,
+        addr: SocketAddr,
+    }
+
+    // a peer behind an in-process NAT, which drops inbound streams unless
+    // a hole punch of the peer expects them
+    fn spawn_nated_peer() -> Result<NatedPeer, Error> {
+        let listener = tcp_listen_reuse_port(([127, 0, 0, 1], 0).into())?;
+        let addr = listener.get_ref().local_addr()?;
+        let dcutr = Dcutr::new(addr).with_observed_addrs(vec![tcpaddr_to_multiaddr(&addr)]);
+        let relay_client = RelayClient::new();
+        let manager = Manager::new(PrivateKey::generate_ed25519(), addr)
+            .with_protocol(relay_client.clone())
+            .with_protocol(dcutr.clone())
+            .with_protocol(Ping::new())
+            .with_inbound_redirects(dcutr.redirects());
+
+        let redirects = dcutr.redirects();
+        async_std::task::spawn(async move {
+            while let Ok((stream, remote_addr)) = listener.accept().await {
+                if let Some(unsolicited) = redirects.redirect(stream, &remote_addr) {
+                    log::debug!("nat dropped {:?}", remote_addr);
+                    drop(unsolicited);
+                }
+            }
+        });
+        Ok(NatedPeer {
+            manager: Arc::new(manager),
+            dcutr,
+            relay_client,
+            addr,
+        })
+    }
+
+    #[async_std::test]
+    async fn test_dcutr_hole_punch() -> Result<(), Error> {
+        let (relay, relay_addr, _) = spawn_relay(RelayResources::default())?;
+        let dialer = spawn_nated_peer()?;
+        let listener = spawn_nated_peer()?;
+        let (dialer_id, listener_id) = (dialer.manager.peer_id()?, listener.manager.peer_id()?);
+
+        // unsolicited dials are dropped by the NAT
+        assert!(dialer.manager.connect(listener.addr).await.is_err());
+
+        let reservation = listener
+            .relay_client
+            .reserve(&listener.manager, &relay, &[relay_addr])
+            .await?;
+        let circuit_addr = reservation.circuit_addrs()?[0]
+            .clone()
+            .with(p2p_protocol(&listener_id)?);
+        let (relayed_inbound, relayed_outbound) = futures::join!(
+            listener.relay_client.accept(&listener.manager),
+            dialer.relay_client.dial(&dialer.manager, &circuit_addr)
+        );
+        let (relayed_inbound, _relayed_outbound) = (relayed_inbound?, relayed_outbound?);
+
+        // the listener initiates over the relayed connection it accepted
+        let (direct_outbound, direct_inbound) = futures::join!(
+            listener.dcutr.upgrade(&listener.manager, &relayed_inbound),
+            dialer.dcutr.accept(&dialer.manager)
+        );
+        let (direct_outbound, direct_inbound) = (direct_outbound?, direct_inbound?);
+        assert_eq!(direct_outbound.info().peer_id, dialer_id);
+        assert_eq!(direct_outbound.info().relay, None);
+        assert_eq!(direct_inbound.info().peer_id, listener_id);
+        assert_eq!(direct_inbound.info().relay, None);
+
+        // the direct connection replaces the relayed one
+        Ping::new().ping(&direct_inbound).await?;
+        let pooled = dialer.manager.connection(&listener_id).unwrap();
+        assert_eq!(pooled.info().relay, None);
+        Ok(())
+    }
+}
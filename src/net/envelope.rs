@@ -0,0 +1,74 @@
+use crate::{
+    error::{self, Error},
+    identity::{PrivateKey, PublicKey},
+    io::{self, protobuf_decode, protobuf_encode},
+    payload::{
+        envelope::Envelope,
+        keys::{KeyType, PublicKey as PublicKeyPayload},
+    },
+};
+
+// sign the payload into an envelope, the domain separates the payloads of
+// different purposes signed by the same key
+pub fn seal_envelope(
+    private_key: &PrivateKey,
+    domain: &str,
+    payload_type: &[u8],
+    payload: Vec<u8>,
+) -> Result<Vec<u8>, Error> {
+    let signature = private_key.sign(&signing_content(domain, payload_type, &payload));
+    protobuf_encode(&Envelope {
+        public_key: Some(PublicKeyPayload {
+            Type: KeyType::Ed25519,
+            Data: private_key.public().to_bytes(),
+        }),
+        payload_type: payload_type.to_vec(),
+        payload,
+        signature,
+    })
+}
+
+// verify the envelope and return its signer and payload
+pub fn open_envelope(
+    buf: &[u8],
+    domain: &str,
+    payload_type: &[u8],
+) -> Result<(PublicKey, Vec<u8>), Error> {
+    let envelope: Envelope = protobuf_decode(buf)?;
+    if envelope.payload_type != payload_type {
+        return Err(error::unsupported("envelope payload type"));
+    }
+    let public_key = PublicKey::try_from(envelope.public_key.ok_or(error::missing_key())?)?;
+    public_key.verify(
+        &signing_content(domain, payload_type, &envelope.payload),
+        &envelope.signature,
+    )?;
+    Ok((public_key, envelope.payload))
+}
+
+// domain, payload type and payload, each prefixed by its uvarint length
+fn signing_content(domain: &str, payload_type: &[u8], payload: &[u8]) -> Vec<u8> {
+    [domain.as_bytes(), payload_type, payload]
+        .iter()
+        .flat_map(|field| [io::uvarint_encode(field.len() as u64), field.to_vec()].concat())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope() -> Result<(), Error> {
+        let private_key = PrivateKey::generate_ed25519();
+        let envelope = seal_envelope(&private_key, "domain", &[0x01], b"payload".to_vec())?;
+        let (public_key, payload) = open_envelope(&envelope, "domain", &[0x01])?;
+        assert_eq!(public_key, private_key.public());
+        assert_eq!(payload, b"payload".to_vec());
+
+        // the domain and the payload type are signed
+        assert!(open_envelope(&envelope, "other", &[0x01]).is_err());
+        assert!(open_envelope(&envelope, "domain", &[0x02]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,312 @@
+mod record;
+
+use super::{tcpaddr_to_multiaddr, Connection, PeerMap, Protocol, ProtocolRegistry};
+use crate::{
+    error::{self, Error},
+    identity::{PeerId, PrivateKey, PublicKey},
+    io::{self, protobuf_decode, protobuf_encode},
+    payload::{identify::Identify as IdentifyPayload, keys::PublicKey as PublicKeyPayload},
+};
+use futures::AsyncWriteExt;
+use multiaddr::Multiaddr;
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+
+pub use record::PeerRecord;
+
+pub const PROTOCOL_IDENTIFY: &str = "/ipfs/id/1.0.0";
+pub const PROTOCOL_IDENTIFY_PUSH: &str = "/ipfs/id/push/1.0.0";
+const DEFAULT_PROTOCOL_VERSION: &str = "ipfs/0.1.0";
+const DEFAULT_AGENT_VERSION: &str = concat!("libp2p-handshake/", env!("CARGO_PKG_VERSION"));
+const MAX_IDENTIFY_SIZE: usize = 8 * 1024;
+
+// IdentifyInfo of a remote peer
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdentifyInfo {
+    pub public_key: PublicKey,
+    pub protocol_version: Option<String>,
+    pub agent_version: Option<String>,
+    pub listen_addrs: Vec<Multiaddr>,
+    pub protocols: Vec<String>,
+    // our address as observed by the remote
+    pub observed_addr: Option<Multiaddr>,
+    pub peer_record: Option<PeerRecord>,
+}
+
+impl IdentifyInfo {
+    fn new(public_key: PublicKey) -> Self {
+        Self {
+            public_key,
+            protocol_version: None,
+            agent_version: None,
+            listen_addrs: Vec::new(),
+            protocols: Vec::new(),
+            observed_addr: None,
+            peer_record: None,
+        }
+    }
+
+    // fields missing in a push keep the values known before
+    fn update(&mut self, payload: IdentifyPayload) -> Result<(), Error> {
+        if payload.protocolVersion.is_some() {
+            self.protocol_version = payload.protocolVersion;
+        }
+        if payload.agentVersion.is_some() {
+            self.agent_version = payload.agentVersion;
+        }
+        if !payload.listenAddrs.is_empty() {
+            self.listen_addrs = payload
+                .listenAddrs
+                .into_iter()
+                .filter_map(|addr| Multiaddr::try_from(addr).ok())
+                .collect();
+        }
+        if !payload.protocols.is_empty() {
+            self.protocols = payload.protocols;
+        }
+        if let Some(addr) = payload.observedAddr {
+            self.observed_addr = Multiaddr::try_from(addr).ok();
+        }
+        if let Some(envelope) = payload.signedPeerRecord {
+            let record = PeerRecord::from_signed_envelope(&envelope)?;
+            let peer_id: PeerId = self.public_key.clone().try_into()?;
+            if record.peer_id != peer_id {
+                return Err(error::verification_failed());
+            }
+            self.peer_record = Some(record);
+        }
+        Ok(())
+    }
+}
+
+// Identify
+//
+// Answers identify requests and pushes, and keeps what the remote peers told
+// about themselves while connected
+#[derive(Clone)]
+pub struct Identify {
+    private_key: PrivateKey,
+    protocol_version: String,
+    agent_version: String,
+    listen_addrs: Vec<Multiaddr>,
+    // served but not advertised, such as kad in client mode
+    hidden_protocols: Arc<Mutex<HashSet<String>>>,
+    peers: PeerMap<IdentifyInfo>,
+}
+
+impl Identify {
+    pub fn new(private_key: PrivateKey) -> Self {
+        Self {
+            private_key,
+            protocol_version: DEFAULT_PROTOCOL_VERSION.to_string(),
+            agent_version: DEFAULT_AGENT_VERSION.to_string(),
+            listen_addrs: Vec::new(),
+            hidden_protocols: Arc::new(Mutex::new(HashSet::new())),
+            peers: PeerMap::new(),
+        }
+    }
+
+    pub fn with_agent_version(mut self, agent_version: &str) -> Self {
+        self.agent_version = agent_version.to_string();
+        self
+    }
+
+    // addresses advertised in the signed peer record as well
+    pub fn with_listen_addrs(mut self, listen_addrs: Vec<Multiaddr>) -> Self {
+        self.listen_addrs = listen_addrs;
+        self
+    }
+
+    // whether the protocol is among those advertised, returns true if that
+    // changed and the connected peers should be pushed to
+    pub fn set_advertised(&self, protocol: &str, advertised: bool) -> bool {
+        let mut hidden_protocols = self.hidden_protocols.lock().unwrap();
+        if advertised {
+            hidden_protocols.remove(protocol)
+        } else {
+            hidden_protocols.insert(protocol.to_string())
+        }
+    }
+
+    pub fn peer_info(&self, peer_id: &PeerId) -> Option<IdentifyInfo> {
+        self.peers.get(peer_id)
+    }
+
+    // ask the remote to identify itself
+    pub async fn identify(&self, connection: &Connection) -> Result<IdentifyInfo, Error> {
+        let mut stream = connection.open_stream(PROTOCOL_IDENTIFY).await?;
+        let buf = io::read_uvarint_prefixed(&mut stream, MAX_IDENTIFY_SIZE).await?;
+        stream.close().await?;
+
+        let payload: IdentifyPayload = protobuf_decode(&buf)?;
+        let mut info = IdentifyInfo::new(Self::remote_public_key(connection, &payload)?);
+        info.update(payload)?;
+        log::info!(
+            "identified {:?} as {:?}, observed us at {:?}",
+            connection.info().peer_id,
+            info.agent_version,
+            info.observed_addr
+        );
+
+        self.peers.insert(connection, info.clone());
+        Ok(info)
+    }
+
+    // tell the remote our current information
+    pub async fn push(&self, connection: &Connection) -> Result<(), Error> {
+        let mut stream = connection.open_stream(PROTOCOL_IDENTIFY_PUSH).await?;
+        let payload = self.payload(connection)?;
+        io::write_uvarint_prefixed(&mut stream, &protobuf_encode(&payload)?).await?;
+        stream.close().await
+    }
+
+    fn payload(&self, connection: &Connection) -> Result<IdentifyPayload, Error> {
+        let public_key = self.private_key.public();
+        let record = PeerRecord::new(public_key.clone().try_into()?, self.listen_addrs.clone());
+        Ok(IdentifyPayload {
+            protocolVersion: Some(self.protocol_version.clone()),
+            agentVersion: Some(self.agent_version.clone()),
+            publicKey: Some(public_key.to_protobuf_bytes()?),
+            listenAddrs: self.listen_addrs.iter().map(|addr| addr.to_vec()).collect(),
+            observedAddr: connection
+                .info()
+                .observed_addr()
+                .map(|addr| tcpaddr_to_multiaddr(&addr).to_vec()),
+            protocols: {
+                let hidden_protocols = self.hidden_protocols.lock().unwrap();
+                connection
+                    .protocols()
+                    .into_iter()
+                    .filter(|protocol| !hidden_protocols.contains(protocol))
+                    .collect()
+            },
+            signedPeerRecord: Some(record.to_signed_envelope(&self.private_key)?),
+        })
+    }
+
+    // the public key must be the one authenticated by the security handshake
+    fn remote_public_key(
+        connection: &Connection,
+        payload: &IdentifyPayload,
+    ) -> Result<PublicKey, Error> {
+        let public_key = match &payload.publicKey {
+            Some(buf) => PublicKey::try_from(protobuf_decode::<PublicKeyPayload>(buf)?)?,
+            None => return PublicKey::try_from(connection.info().peer_id.clone()),
+        };
+        let peer_id: PeerId = public_key.clone().try_into()?;
+        if peer_id != connection.info().peer_id {
+            log::debug!("identify public key mismatch");
+            return Err(error::verification_failed());
+        }
+        Ok(public_key)
+    }
+
+    async fn respond(
+        self,
+        connection: Connection,
+        mut stream: io::BoxedStream,
+    ) -> Result<(), Error> {
+        let payload = self.payload(&connection)?;
+        io::write_uvarint_prefixed(&mut stream, &protobuf_encode(&payload)?).await?;
+        stream.close().await
+    }
+
+    async fn on_push(
+        self,
+        connection: Connection,
+        mut stream: io::BoxedStream,
+    ) -> Result<(), Error> {
+        let buf = io::read_uvarint_prefixed(&mut stream, MAX_IDENTIFY_SIZE).await?;
+        let payload: IdentifyPayload = protobuf_decode(&buf)?;
+        let public_key = Self::remote_public_key(&connection, &payload)?;
+        let peer_id = connection.info().peer_id.clone();
+
+        let mut info = self
+            .peer_info(&peer_id)
+            .unwrap_or_else(|| IdentifyInfo::new(public_key));
+        info.update(payload)?;
+        log::debug!("identify push from {:?}", peer_id);
+        self.peers.insert(&connection, info);
+        Ok(())
+    }
+}
+
+impl Protocol for Identify {
+    fn register(self, registry: &mut ProtocolRegistry) {
+        let push = self.clone();
+        registry.register(PROTOCOL_IDENTIFY, move |connection, stream| {
+            self.clone().respond(connection, stream)
+        });
+        registry.register(PROTOCOL_IDENTIFY_PUSH, move |connection, stream| {
+            push.clone().on_push(connection, stream)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::Manager;
+    use async_io::{Async, Timer};
+    use std::{net::TcpListener, time::Duration};
+
+    #[async_std::test]
+    async fn test_identify_and_push() -> Result<(), Error> {
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let addr = listener.get_ref().local_addr()?;
+        let listen_addr = tcpaddr_to_multiaddr(&addr);
+
+        let server_key = PrivateKey::generate_ed25519();
+        let server_identify =
+            Identify::new(server_key.clone()).with_listen_addrs(vec![listen_addr.clone()]);
+        let server = Manager::new(server_key, addr).with_protocol(server_identify.clone());
+        let client_key = PrivateKey::generate_ed25519();
+        let client_identify = Identify::new(client_key.clone()).with_agent_version("client");
+        let client = Manager::new(client_key, addr).with_protocol(client_identify.clone());
+
+        let mut incoming = server.incoming(listener);
+        let (server_res, client_res) = futures::join!(incoming.accept(), client.connect(addr));
+        let (server_conn, client_conn) = (server_res?, client_res?);
+
+        let info = client_identify.identify(&client_conn).await?;
+        assert_eq!(info.public_key, server.peer_id()?.try_into()?);
+        assert_eq!(info.listen_addrs, vec![listen_addr.clone()]);
+        assert!(info.protocols.contains(&PROTOCOL_IDENTIFY_PUSH.to_string()));
+        assert_eq!(
+            info.observed_addr,
+            Some(tcpaddr_to_multiaddr(&server_conn.info().remote_addr))
+        );
+        assert_eq!(
+            info.peer_record.as_ref().map(|record| record.addrs.clone()),
+            Some(vec![listen_addr])
+        );
+        assert_eq!(client_identify.peer_info(&server.peer_id()?), Some(info));
+
+        // the server learns about the client only by the push
+        let client_peer_id = client.peer_id()?;
+        assert_eq!(server_identify.peer_info(&client_peer_id), None);
+        client_identify.push(&client_conn).await?;
+        for _ in 0..50 {
+            if server_identify.peer_info(&client_peer_id).is_some() {
+                break;
+            }
+            Timer::after(Duration::from_millis(10)).await;
+        }
+        let pushed = server_identify.peer_info(&client_peer_id).unwrap();
+        assert_eq!(pushed.agent_version, Some("client".to_string()));
+
+        // forgotten once the connection closes
+        client_conn.close();
+        for _ in 0..50 {
+            if server_identify.peer_info(&client_peer_id).is_none() {
+                break;
+            }
+            Timer::after(Duration::from_millis(10)).await;
+        }
+        assert_eq!(server_identify.peer_info(&client_peer_id), None);
+        assert_eq!(client_identify.peer_info(&server.peer_id()?), None);
+        Ok(())
+    }
+}
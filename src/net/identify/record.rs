@@ -0,0 +1,109 @@
+use crate::{
+    error::{self, Error},
+    identity::{PeerId, PrivateKey},
+    io::{protobuf_decode, protobuf_encode},
+    net::{open_envelope, seal_envelope},
+    payload::peer_record::{mod_PeerRecord::AddressInfo, PeerRecord as PeerRecordPayload},
+};
+use multiaddr::Multiaddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const PEER_RECORD_DOMAIN: &str = "libp2p-routing-state";
+// multicodec libp2p-peer-record
+const PEER_RECORD_PAYLOAD_TYPE: [u8; 2] = [0x03, 0x01];
+
+// PeerRecord of the addresses a peer is reachable at, exchanged in a signed envelope
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerRecord {
+    pub peer_id: PeerId,
+    pub seq: u64,
+    pub addrs: Vec<Multiaddr>,
+}
+
+impl PeerRecord {
+    // seq is taken from the clock so that a newer record supersedes the older
+    pub fn new(peer_id: PeerId, addrs: Vec<Multiaddr>) -> Self {
+        let seq = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_nanos() as u64);
+        Self {
+            peer_id,
+            seq,
+            addrs,
+        }
+    }
+
+    pub fn to_signed_envelope(&self, private_key: &PrivateKey) -> Result<Vec<u8>, Error> {
+        let public_key = private_key.public();
+        let peer_id: PeerId = public_key.clone().try_into()?;
+        if peer_id != self.peer_id {
+            return Err(error::invalid_input("peer record of another peer"));
+        }
+
+        let payload = protobuf_encode(&PeerRecordPayload {
+            peer_id: self.peer_id.to_bytes()?,
+            seq: self.seq,
+            addresses: self
+                .addrs
+                .iter()
+                .map(|addr| AddressInfo {
+                    multiaddr: addr.to_vec(),
+                })
+                .collect(),
+        })?;
+        seal_envelope(
+            private_key,
+            PEER_RECORD_DOMAIN,
+            &PEER_RECORD_PAYLOAD_TYPE,
+            payload,
+        )
+    }
+
+    // verify the envelope is signed by the peer of the record
+    pub fn from_signed_envelope(buf: &[u8]) -> Result<Self, Error> {
+        let (public_key, payload) =
+            open_envelope(buf, PEER_RECORD_DOMAIN, &PEER_RECORD_PAYLOAD_TYPE)?;
+        let record: PeerRecordPayload = protobuf_decode(&payload)?;
+        let peer_id = PeerId::from_bytes(&record.peer_id)?;
+        let signer: PeerId = public_key.try_into()?;
+        if peer_id != signer {
+            log::debug!("peer record signed by {:?} for {:?}", signer, peer_id);
+            return Err(error::verification_failed());
+        }
+        let addrs = record
+            .addresses
+            .into_iter()
+            .map(|info| Multiaddr::try_from(info.multiaddr).map_err(|_| error::parse_error()))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            peer_id,
+            seq: record.seq,
+            addrs,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peer_record_envelope() -> Result<(), Error> {
+        let private_key = PrivateKey::generate_ed25519();
+        let peer_id: PeerId = private_key.public().try_into()?;
+        let record = PeerRecord::new(peer_id, vec!["/ip4/127.0.0.1/tcp/4001".parse().unwrap()]);
+
+        let envelope = record.to_signed_envelope(&private_key)?;
+        assert_eq!(PeerRecord::from_signed_envelope(&envelope)?, record);
+
+        // signed by another key
+        assert!(record
+            .to_signed_envelope(&PrivateKey::generate_ed25519())
+            .is_err());
+        let mut tampered = envelope.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(PeerRecord::from_signed_envelope(&tampered).is_err());
+        Ok(())
+    }
+}
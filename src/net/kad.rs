@@ -0,0 +1,1099 @@
+mod key;
+mod lookup;
+mod routing;
+mod store;
+mod validator;
+
+use super::{
+    AutoNat, Connection, Identify, Manager, NatStatus, PeerRecord, Protocol, ProtocolRegistry,
+};
+use crate::{
+    error::{self, Error},
+    identity::{PeerId, PrivateKey},
+    io::{self, protobuf_decode, protobuf_encode},
+    ipns::{IpnsValidator, IPNS_NAMESPACE},
+    payload::kad::{
+        mod_Message::{MessageType, Peer as PeerPayload},
+        Message, Record as RecordPayload,
+    },
+};
+use async_io::Timer;
+use futures::{
+    future::{self, Either},
+    stream::FuturesUnordered,
+    AsyncWriteExt, StreamExt,
+};
+use lookup::Lookup;
+use multiaddr::Multiaddr;
+use routing::RoutingTable;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+pub use key::{Distance, Key};
+pub use routing::InsertResult;
+pub use store::{KadRecord, MemoryStore, ProviderStore, RecordStore};
+pub use validator::RecordValidator;
+
+pub const PROTOCOL_KAD: &str = "/ipfs/kad/1.0.0";
+pub const MAX_KAD_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+const DEFAULT_K: usize = 20;
+const DEFAULT_ALPHA: usize = 3;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(10 * 60);
+// well within the ttl of provider records
+const DEFAULT_REPROVIDE_INTERVAL: Duration = Duration::from_secs(22 * 60 * 60);
+const MIN_RUN_INTERVAL: Duration = Duration::from_secs(1);
+// valid values to collect before a lookup of a validated key ends
+const DEFAULT_QUORUM: usize = 16;
+
+// KadPeer with the addresses it is reachable at
+#[derive(Debug, Clone, PartialEq)]
+pub struct KadPeer {
+    pub peer_id: PeerId,
+    pub addrs: Vec<Multiaddr>,
+    // signed peer record of the addresses, announced with provider records
+    pub signed_record: Option<Vec<u8>>,
+}
+
+impl KadPeer {
+    pub fn to_payload(&self) -> Result<PeerPayload, Error> {
+        Ok(PeerPayload {
+            id: self.peer_id.to_bytes()?,
+            addrs: self.addrs.iter().map(|addr| addr.to_vec()).collect(),
+            signedRecord: self.signed_record.clone().unwrap_or_default(),
+            ..Default::default()
+        })
+    }
+}
+
+impl TryFrom<PeerPayload> for KadPeer {
+    type Error = Error;
+
+    // addresses which can not be parsed are dropped, the addresses of a
+    // signed record replace the unsigned ones once it is verified
+    fn try_from(payload: PeerPayload) -> Result<Self, Self::Error> {
+        let peer_id = PeerId::from_bytes(&payload.id)?;
+        if !payload.signedRecord.is_empty() {
+            let record = PeerRecord::from_signed_envelope(&payload.signedRecord)?;
+            if record.peer_id != peer_id {
+                return Err(error::verification_failed());
+            }
+            return Ok(Self {
+                peer_id,
+                addrs: record.addrs,
+                signed_record: Some(payload.signedRecord),
+            });
+        }
+        Ok(Self {
+            peer_id,
+            addrs: payload
+                .addrs
+                .into_iter()
+                .filter_map(|addr| Multiaddr::try_from(addr).ok())
+                .collect(),
+            signed_record: None,
+        })
+    }
+}
+
+// KadMode of the local peer, clients query the DHT but do not answer queries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KadMode {
+    Client,
+    Server,
+}
+
+// Kademlia
+//
+// Client and server of the DHT, lookups start from the closest peers of the
+// routing table and walk towards the target with alpha queries in flight
+#[derive(Clone)]
+pub struct Kademlia {
+    local_peer_id: PeerId,
+    k: usize,
+    alpha: usize,
+    timeout: Duration,
+    refresh_interval: Duration,
+    reprovide_interval: Duration,
+    listen_addrs: Vec<Multiaddr>,
+    private_key: Option<PrivateKey>,
+    autonat: Option<AutoNat>,
+    identify: Option<Identify>,
+    provided: Arc<Mutex<HashMap<Vec<u8>, Instant>>>,
+    routing_table: Arc<Mutex<RoutingTable>>,
+    records: Arc<dyn RecordStore>,
+    providers: Arc<dyn ProviderStore>,
+    validators: Arc<HashMap<String, Arc<dyn RecordValidator>>>,
+}
+
+impl Kademlia {
+    pub fn new(local_peer_id: PeerId) -> Result<Self, Error> {
+        let store = Arc::new(MemoryStore::new());
+        Ok(Self {
+            routing_table: Arc::new(Mutex::new(RoutingTable::new(
+                Key::from_peer_id(&local_peer_id)?,
+                DEFAULT_K,
+            ))),
+            local_peer_id,
+            k: DEFAULT_K,
+            alpha: DEFAULT_ALPHA,
+            timeout: DEFAULT_TIMEOUT,
+            refresh_interval: DEFAULT_REFRESH_INTERVAL,
+            reprovide_interval: DEFAULT_REPROVIDE_INTERVAL,
+            listen_addrs: Vec::new(),
+            private_key: None,
+            autonat: None,
+            identify: None,
+            provided: Arc::new(Mutex::new(HashMap::new())),
+            records: store.clone(),
+            providers: store,
+            validators: Arc::new(HashMap::from([(
+                IPNS_NAMESPACE.to_string(),
+                Arc::new(IpnsValidator) as Arc<dyn RecordValidator>,
+            )])),
+        })
+    }
+
+    // size of the result set and of the buckets
+    pub fn with_k(mut self, k: usize) -> Result<Self, Error> {
+        self.k = k;
+        self.routing_table = Arc::new(Mutex::new(RoutingTable::new(
+            Key::from_peer_id(&self.local_peer_id)?,
+            k,
+        )));
+        Ok(self)
+    }
+
+    // concurrent queries of a lookup
+    pub fn with_alpha(mut self, alpha: usize) -> Self {
+        self.alpha = alpha;
+        self
+    }
+
+    // timeout of a single query
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // buckets not looked up within the interval are refreshed
+    pub fn with_refresh_interval(mut self, refresh_interval: Duration) -> Self {
+        self.refresh_interval = refresh_interval;
+        self
+    }
+
+    // provided keys are announced again after the interval
+    pub fn with_reprovide_interval(mut self, reprovide_interval: Duration) -> Self {
+        self.reprovide_interval = reprovide_interval;
+        self
+    }
+
+    // addresses announced along with provider records
+    pub fn with_listen_addrs(mut self, listen_addrs: Vec<Multiaddr>) -> Self {
+        self.listen_addrs = listen_addrs;
+        self
+    }
+
+    // key of the local peer, which signs the addresses of provider records
+    pub fn with_private_key(mut self, private_key: PrivateKey) -> Self {
+        self.private_key = Some(private_key);
+        self
+    }
+
+    // the mode follows the reachability, a server only while public
+    pub fn with_autonat(mut self, autonat: AutoNat) -> Self {
+        self.autonat = Some(autonat);
+        self
+    }
+
+    // the peers querying us are added to the routing table at the listen
+    // addresses they identify with, if they serve the DHT themselves
+    pub fn with_identify(mut self, identify: Identify) -> Self {
+        self.identify = Some(identify);
+        self
+    }
+
+    // a server without autonat, or once autonat found the local peer public,
+    // a client while its status is still unknown
+    pub fn mode(&self) -> KadMode {
+        match self.autonat.as_ref().map(AutoNat::status) {
+            None | Some(NatStatus::Public) => KadMode::Server,
+            Some(_) => KadMode::Client,
+        }
+    }
+
+    pub fn with_record_store(mut self, records: Arc<dyn RecordStore>) -> Self {
+        self.records = records;
+        self
+    }
+
+    pub fn with_provider_store(mut self, providers: Arc<dyn ProviderStore>) -> Self {
+        self.providers = providers;
+        self
+    }
+
+    // values under /<namespace>/ are checked by the validator, the ipns
+    // namespace is validated by default
+    pub fn with_validator(
+        mut self,
+        namespace: &str,
+        validator: impl RecordValidator + 'static,
+    ) -> Self {
+        Arc::make_mut(&mut self.validators).insert(namespace.to_string(), Arc::new(validator));
+        self
+    }
+
+    // a peer to start lookups from, such as a bootstrap node
+    pub fn add_address(&self, peer_id: PeerId, addr: Multiaddr) -> InsertResult {
+        self.add_peer(KadPeer {
+            peer_id,
+            addrs: vec![addr],
+            signed_record: None,
+        })
+    }
+
+    fn add_peer(&self, peer: KadPeer) -> InsertResult {
+        if peer.peer_id == self.local_peer_id || peer.addrs.is_empty() {
+            return InsertResult::Updated;
+        }
+        self.routing_table.lock().unwrap().insert(peer)
+    }
+
+    // the entry of the peer in the routing table
+    pub fn routing_peer(&self, peer_id: &PeerId) -> Option<KadPeer> {
+        self.routing_table.lock().unwrap().peer(peer_id)
+    }
+
+    pub fn routing_table_len(&self) -> usize {
+        self.routing_table.lock().unwrap().len()
+    }
+
+    // the peers of the routing table closest to the key
+    pub fn closest_peers(&self, key: &Key) -> Vec<KadPeer> {
+        self.routing_table.lock().unwrap().closest(key, self.k)
+    }
+
+    // fill the routing table by looking up the local peer
+    pub async fn bootstrap(&self, manager: &Manager) -> Result<Vec<KadPeer>, Error> {
+        self.find_node(manager, &self.local_peer_id.clone()).await
+    }
+
+    // check the liveness of full buckets and refresh the stale ones
+    pub async fn refresh(&self, manager: &Manager) -> Result<(), Error> {
+        let checks = self.routing_table.lock().unwrap().liveness_checks();
+        for peer in checks {
+            let request = Self::request(MessageType::FIND_NODE, &self.local_peer_id.to_bytes()?);
+            let alive = self.query(manager, &peer, &request).await.is_ok();
+            log::debug!("kad liveness of {:?} {}", peer.peer_id, alive);
+            self.routing_table
+                .lock()
+                .unwrap()
+                .on_liveness_checked(&peer.peer_id, alive);
+        }
+
+        let keys = self
+            .routing_table
+            .lock()
+            .unwrap()
+            .refresh_keys(self.refresh_interval);
+        for key in keys {
+            let request = Self::request(MessageType::FIND_NODE, key.preimage());
+            if let Err(err) = self.lookup(manager, key, request, |_, _| false).await {
+                log::debug!("kad bucket refresh failed, {:?}", err);
+            }
+        }
+        Ok(())
+    }
+
+    // refresh, reprovide and drop the expired entries of the stores
+    // periodically, and advertise the mode as it changes; meant to be
+    // spawned along with the node
+    pub async fn run(&self, manager: &Manager) {
+        // wakes up often enough for both the refresh and the reprovide
+        let tick = self
+            .refresh_interval
+            .min(self.reprovide_interval)
+            .max(MIN_RUN_INTERVAL);
+        let mut ticks = Timer::interval(tick);
+        let mut statuses = match &self.autonat {
+            Some(autonat) => autonat.subscribe().boxed(),
+            None => futures::stream::pending().boxed(),
+        };
+        self.advertise_mode(manager).await;
+        let mut refreshed = Instant::now();
+        loop {
+            match future::select(ticks.next(), statuses.next()).await {
+                Either::Left(_) => (),
+                Either::Right((Some(_), _)) => {
+                    self.advertise_mode(manager).await;
+                    continue;
+                }
+                Either::Right((None, _)) => {
+                    statuses = futures::stream::pending().boxed();
+                    continue;
+                }
+            }
+            if refreshed.elapsed() >= self.refresh_interval {
+                refreshed = Instant::now();
+                if let Err(err) = self.refresh(manager).await {
+                    log::debug!("kad refresh failed, {:?}", err);
+                }
+            }
+            self.reprovide(manager).await;
+            self.records.remove_expired();
+            self.providers.remove_expired();
+        }
+    }
+
+    // kad is advertised by identify only in server mode, so that remote
+    // peers do not add a client to their routing tables; the connected
+    // peers are pushed to once it changes
+    async fn advertise_mode(&self, manager: &Manager) {
+        let Some(identify) = &self.identify else {
+            return;
+        };
+        if !identify.set_advertised(PROTOCOL_KAD, self.mode() == KadMode::Server) {
+            return;
+        }
+        log::debug!("kad {:?} mode advertised", self.mode());
+        let connections = manager.connections();
+        let pushes = connections
+            .iter()
+            .map(|connection| identify.push(connection));
+        for res in future::join_all(pushes).await {
+            if let Err(err) = res {
+                log::debug!("kad mode push failed, {:?}", err);
+            }
+        }
+    }
+
+    // the k closest peers to the peer id
+    pub async fn find_node(
+        &self,
+        manager: &Manager,
+        peer_id: &PeerId,
+    ) -> Result<Vec<KadPeer>, Error> {
+        let key = Key::from_peer_id(peer_id)?;
+        let request = Self::request(MessageType::FIND_NODE, key.preimage());
+        self.lookup(manager, key, request, |_, _| false).await
+    }
+
+    // peers providing the content of the key
+    pub async fn get_providers(
+        &self,
+        manager: &Manager,
+        key: &[u8],
+    ) -> Result<Vec<KadPeer>, Error> {
+        let mut providers: Vec<KadPeer> = Vec::new();
+        let request = Self::request(MessageType::GET_PROVIDERS, key);
+        self.lookup(manager, Key::new(key), request, |_, response| {
+            for payload in &response.providerPeers {
+                match KadPeer::try_from(payload.clone()) {
+                    Ok(provider) if !providers.iter().any(|p| p.peer_id == provider.peer_id) => {
+                        providers.push(provider)
+                    }
+                    _ => {}
+                }
+            }
+            providers.len() >= self.k
+        })
+        .await?;
+        Ok(providers)
+    }
+
+    // the first value stored under the key, or the best of the valid values
+    // found when the key has a validator
+    pub async fn get_value(&self, manager: &Manager, key: &[u8]) -> Result<Vec<u8>, Error> {
+        let validator = self.validator(key);
+        let mut values: Vec<Vec<u8>> = Vec::new();
+        let mut found = 0;
+        let request = Self::request(MessageType::GET_VALUE, key);
+        self.lookup(
+            manager,
+            Key::new(key),
+            request,
+            |peer, response| match &response.record {
+                Some(record) if record.key == key && !record.value.is_empty() => {
+                    let Some(validator) = &validator else {
+                        log::debug!("value found at {:?}", peer.peer_id);
+                        values.push(record.value.clone());
+                        return true;
+                    };
+                    match validator.validate(key, &record.value) {
+                        Ok(()) => {
+                            found += 1;
+                            if !values.contains(&record.value) {
+                                values.push(record.value.clone());
+                            }
+                        }
+                        Err(err) => log::debug!("invalid value at {:?}, {:?}", peer.peer_id, err),
+                    }
+                    found >= DEFAULT_QUORUM
+                }
+                _ => false,
+            },
+        )
+        .await?;
+        let index = match &validator {
+            _ if values.is_empty() => return Err(error::not_found("dht value")),
+            Some(validator) => validator.select(key, &values)?,
+            None => 0,
+        };
+        Ok(values.swap_remove(index))
+    }
+
+    // store the value at the k closest peers of the key, which must be of a
+    // namespace with a validator; returns the number of peers which stored it
+    pub async fn put_value(
+        &self,
+        manager: &Manager,
+        key: &[u8],
+        value: Vec<u8>,
+    ) -> Result<usize, Error> {
+        // peers store only the keys they can validate
+        let validator = self
+            .validator(key)
+            .ok_or_else(|| error::unsupported("kad key without a validator"))?;
+        validator.validate(key, &value)?;
+        let closest = self.closest_to(manager, key).await?;
+        let mut request = Self::request(MessageType::PUT_VALUE, key);
+        request.record = Some(RecordPayload {
+            key: key.to_vec(),
+            value,
+            ..Default::default()
+        });
+        let stored = futures::future::join_all(
+            closest
+                .iter()
+                .map(|peer| self.query(manager, peer, &request)),
+        )
+        .await
+        .into_iter()
+        .filter(|res| res.is_ok())
+        .count();
+        Ok(stored)
+    }
+
+    // announce the local peer as a provider of the key to the k closest
+    // peers, with its addresses signed by the private key. Returns the
+    // number of peers the announcement was sent to.
+    //
+    // The key is announced again every reprovide interval until
+    // stop_providing, only while run is running.
+    pub async fn provide(&self, manager: &Manager, key: &[u8]) -> Result<usize, Error> {
+        if self.listen_addrs.is_empty() {
+            return Err(error::invalid_input("no listen addresses to announce"));
+        }
+        let private_key = self
+            .private_key
+            .as_ref()
+            .ok_or_else(|| error::invalid_input("no private key to sign the addresses"))?;
+        let record = PeerRecord::new(self.local_peer_id.clone(), self.listen_addrs.clone());
+        let local_peer = KadPeer {
+            peer_id: self.local_peer_id.clone(),
+            addrs: self.listen_addrs.clone(),
+            signed_record: Some(record.to_signed_envelope(private_key)?),
+        };
+        self.providers.add_provider(key, local_peer.clone())?;
+        self.provided
+            .lock()
+            .unwrap()
+            .insert(key.to_vec(), Instant::now());
+
+        let closest = self.closest_to(manager, key).await?;
+        let mut request = Self::request(MessageType::ADD_PROVIDER, key);
+        request.providerPeers = vec![local_peer.to_payload()?];
+        let announced = futures::future::join_all(
+            closest
+                .iter()
+                .map(|peer| self.notify(manager, peer, &request)),
+        )
+        .await
+        .into_iter()
+        .filter(|res| res.is_ok())
+        .count();
+        log::debug!(
+            "provider of {:?} announced to {} peers",
+            Key::new(key),
+            announced
+        );
+        Ok(announced)
+    }
+
+    pub fn stop_providing(&self, key: &[u8]) {
+        self.provided.lock().unwrap().remove(key);
+    }
+
+    // announce again the keys provided longer than the reprovide interval
+    // ago, returns the number of keys announced
+    pub async fn reprovide(&self, manager: &Manager) -> usize {
+        let keys: Vec<Vec<u8>> = self
+            .provided
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, provided)| provided.elapsed() >= self.reprovide_interval)
+            .map(|(key, _)| key.clone())
+            .collect();
+        let mut reprovided = 0;
+        for key in keys {
+            match self.provide(manager, &key).await {
+                Ok(_) => reprovided += 1,
+                Err(err) => log::debug!("reprovide of {:?} failed, {:?}", Key::new(&key), err),
+            }
+        }
+        reprovided
+    }
+
+    // the k closest peers to the key which answered
+    async fn closest_to(&self, manager: &Manager, key: &[u8]) -> Result<Vec<KadPeer>, Error> {
+        let request = Self::request(MessageType::FIND_NODE, key);
+        self.lookup(manager, Key::new(key), request, |_, _| false)
+            .await
+    }
+
+    fn validator(&self, key: &[u8]) -> Option<Arc<dyn RecordValidator>> {
+        self.validators.get(validator::namespace(key)?).cloned()
+    }
+
+    fn request(message_type: MessageType, key: &[u8]) -> Message {
+        Message {
+            type_pb: message_type,
+            key: key.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    // iterative lookup, on_response returns true to end the lookup early
+    async fn lookup<F>(
+        &self,
+        manager: &Manager,
+        target: Key,
+        request: Message,
+        mut on_response: F,
+    ) -> Result<Vec<KadPeer>, Error>
+    where
+        F: FnMut(&KadPeer, &Message) -> bool,
+    {
+        let seeds = self.closest_peers(&target);
+        if seeds.is_empty() {
+            return Err(error::not_found("no known peers"));
+        }
+        let mut lookup = Lookup::new(target, self.k, self.alpha);
+        for peer in seeds {
+            lookup.add(peer);
+        }
+
+        let mut queries = FuturesUnordered::new();
+        // queries still in flight beyond the k closest are not waited for
+        while !lookup.is_finished() {
+            while let Some(peer) = lookup.next() {
+                let request = request.clone();
+                queries.push(async move {
+                    let res = self.query(manager, &peer, &request).await;
+                    (peer, res)
+                });
+            }
+            let (peer, res) = match queries.next().await {
+                Some(query) => query,
+                None => break,
+            };
+
+            let response = match res {
+                Ok(response) => response,
+                Err(err) => {
+                    log::debug!("kad query to {:?} failed, {:?}", peer.peer_id, err);
+                    lookup.on_failure(&peer);
+                    self.routing_table.lock().unwrap().remove(&peer.peer_id);
+                    continue;
+                }
+            };
+            lookup.on_success(&peer);
+            self.add_peer(peer.clone());
+            for payload in &response.closerPeers {
+                if let Ok(closer) = KadPeer::try_from(payload.clone()) {
+                    if closer.peer_id != self.local_peer_id && !closer.addrs.is_empty() {
+                        lookup.add(closer);
+                    }
+                }
+            }
+            if on_response(&peer, &response) {
+                break;
+            }
+        }
+        Ok(lookup.closest())
+    }
+
+    // send a request to the peer over a new stream and read its response
+    async fn query(
+        &self,
+        manager: &Manager,
+        peer: &KadPeer,
+        request: &Message,
+    ) -> Result<Message, Error> {
+        io::timeout(self.timeout, async {
+            let connection = manager.dial_peer(&peer.peer_id, &peer.addrs).await?;
+            let mut stream = connection.open_stream(PROTOCOL_KAD).await?;
+            io::write_uvarint_prefixed(&mut stream, &protobuf_encode(request)?).await?;
+            let buf = io::read_uvarint_prefixed(&mut stream, MAX_KAD_MESSAGE_SIZE).await?;
+            stream.close().await?;
+            let response: Message = protobuf_decode(&buf)?;
+            if response.type_pb != request.type_pb {
+                return Err(error::message_malformed());
+            }
+            Ok(response)
+        })
+        .await
+    }
+
+    // send a message which has no response
+    async fn notify(
+        &self,
+        manager: &Manager,
+        peer: &KadPeer,
+        message: &Message,
+    ) -> Result<(), Error> {
+        io::timeout(self.timeout, async {
+            let connection = manager.dial_peer(&peer.peer_id, &peer.addrs).await?;
+            let mut stream = connection.open_stream(PROTOCOL_KAD).await?;
+            io::write_uvarint_prefixed(&mut stream, &protobuf_encode(message)?).await?;
+            stream.close().await
+        })
+        .await
+    }
+
+    // answer requests of the remote until it closes the stream
+    async fn respond(
+        self,
+        connection: Connection,
+        mut stream: io::BoxedStream,
+    ) -> Result<(), Error> {
+        if self.mode() == KadMode::Client {
+            log::debug!("kad request refused in client mode");
+            return Err(error::unsupported("kad client mode"));
+        }
+        if connection.info().relay.is_none() {
+            let kad = self.clone();
+            let connection = connection.clone();
+            async_std::task::spawn(async move {
+                if let Err(err) = kad.add_remote(&connection).await {
+                    log::debug!("kad remote not added, {:?}", err);
+                }
+            });
+        }
+        loop {
+            let buf = match io::read_uvarint_prefixed(&mut stream, MAX_KAD_MESSAGE_SIZE).await {
+                Ok(buf) => buf,
+                Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err),
+            };
+            let request: Message = protobuf_decode(&buf)?;
+            if let Some(response) = self.handle_request(&connection, request)? {
+                io::write_uvarint_prefixed(&mut stream, &protobuf_encode(&response)?).await?;
+            }
+        }
+        stream.close().await
+    }
+
+    // the address the remote connected from is not the one it listens at,
+    // nor does a client mode peer answer queries
+    async fn add_remote(&self, connection: &Connection) -> Result<(), Error> {
+        let Some(identify) = &self.identify else {
+            return Ok(());
+        };
+        let peer_id = &connection.info().peer_id;
+        let info = match identify.peer_info(peer_id) {
+            Some(info) => info,
+            None => identify.identify(connection).await?,
+        };
+        if !info
+            .protocols
+            .iter()
+            .any(|protocol| protocol == PROTOCOL_KAD)
+        {
+            return Err(error::unsupported("remote does not serve kad"));
+        }
+        // the signed addresses over the unsigned ones
+        let addrs = match info.peer_record {
+            Some(record) if !record.addrs.is_empty() => record.addrs,
+            _ => info.listen_addrs,
+        };
+        self.add_peer(KadPeer {
+            peer_id: peer_id.clone(),
+            addrs,
+            signed_record: None,
+        });
+        Ok(())
+    }
+
+    fn handle_request(
+        &self,
+        connection: &Connection,
+        request: Message,
+    ) -> Result<Option<Message>, Error> {
+        let remote_peer_id = &connection.info().peer_id;
+        let mut response = Self::request(request.type_pb, &request.key);
+        match request.type_pb {
+            MessageType::FIND_NODE => {}
+            MessageType::GET_PROVIDERS => {
+                response.providerPeers = self
+                    .providers
+                    .providers(&request.key)
+                    .iter()
+                    .map(KadPeer::to_payload)
+                    .collect::<Result<_, _>>()?;
+            }
+            MessageType::ADD_PROVIDER => {
+                // only the remote itself can be announced as a provider,
+                // invalid entries are skipped
+                for payload in request.providerPeers {
+                    let Ok(provider) = KadPeer::try_from(payload) else {
+                        continue;
+                    };
+                    if &provider.peer_id != remote_peer_id {
+                        continue;
+                    }
+                    if let Err(err) = self.providers.add_provider(&request.key, provider) {
+                        log::debug!("kad provider of {:?} dropped, {:?}", remote_peer_id, err);
+                    }
+                }
+                return Ok(None);
+            }
+            MessageType::GET_VALUE => {
+                response.record = self.records.get(&request.key).map(|record| RecordPayload {
+                    key: record.key,
+                    value: record.value,
+                    ..Default::default()
+                });
+            }
+            MessageType::PUT_VALUE => {
+                let record = request.record.ok_or(error::message_malformed())?;
+                if record.key != request.key {
+                    return Err(error::message_malformed());
+                }
+                // only keys of a namespace with a validator are stored, and a
+                // valid value stored is only replaced by a better one
+                let validator = self
+                    .validator(&record.key)
+                    .ok_or_else(|| error::unsupported("kad key without a validator"))?;
+                validator.validate(&record.key, &record.value)?;
+                let stored = self
+                    .records
+                    .get(&record.key)
+                    .filter(|stored| validator.validate(&stored.key, &stored.value).is_ok());
+                if let Some(stored) = stored {
+                    let values = [record.value.clone(), stored.value];
+                    if validator.select(&record.key, &values)? != 0 {
+                        return Err(error::invalid_input("value worse than the one stored"));
+                    }
+                }
+                self.records.put(KadRecord {
+                    key: record.key.clone(),
+                    value: record.value.clone(),
+                })?;
+                response.record = Some(record);
+                return Ok(Some(response));
+            }
+            MessageType::PING => return Ok(Some(response)),
+        }
+
+        response.closerPeers = self
+            .closest_peers(&Key::new(&request.key))
+            .iter()
+            .filter(|peer| &peer.peer_id != remote_peer_id)
+            .map(KadPeer::to_payload)
+            .collect::<Result<_, _>>()?;
+        Ok(Some(response))
+    }
+}
+
+impl Protocol for Kademlia {
+    fn register(self, registry: &mut ProtocolRegistry) {
+        if let Some(identify) = &self.identify {
+            identify.set_advertised(PROTOCOL_KAD, self.mode() == KadMode::Server);
+        }
+        registry.register(PROTOCOL_KAD, move |connection, stream| {
+            self.clone().respond(connection, stream)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        identity::PrivateKey,
+        net::{tcpaddr_to_multiaddr, test_support::spawn_kad_servers},
+        payload::kad::Record,
+    };
+    use async_io::Async;
+    use std::{net::TcpListener, sync::Arc};
+
+    const NODES: usize = 10;
+
+    // nodes on a ring, each one knows only its two successors
+    fn spawn_network() -> Result<Vec<KadPeer>, Error> {
+        let mut nodes = Vec::new();
+        for _ in 0..NODES {
+            let private_key = PrivateKey::generate_ed25519();
+            let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+            let peer = KadPeer {
+                peer_id: private_key.public().try_into()?,
+                addrs: vec![tcpaddr_to_multiaddr(&listener.get_ref().local_addr()?)],
+                signed_record: None,
+            };
+            nodes.push((private_key, listener, peer));
+        }
+        let peers: Vec<KadPeer> = nodes.iter().map(|(_, _, peer)| peer.clone()).collect();
+
+        for (i, (private_key, listener, _)) in nodes.into_iter().enumerate() {
+            let neighbours = vec![
+                peers[(i + 1) % NODES].to_payload()?,
+                peers[(i + 2) % NODES].to_payload()?,
+            ];
+            let provider = peers[0].to_payload()?;
+            let addr = listener.get_ref().local_addr()?;
+            let manager = Manager::new(private_key, addr).with_protocol_handler(
+                PROTOCOL_KAD,
+                move |_connection, mut stream| {
+                    let neighbours = neighbours.clone();
+                    let provider = provider.clone();
+                    async move {
+                        let buf =
+                            io::read_uvarint_prefixed(&mut stream, MAX_KAD_MESSAGE_SIZE).await?;
+                        let request: Message = protobuf_decode(&buf)?;
+                        let mut response = Message {
+                            type_pb: request.type_pb,
+                            key: request.key.clone(),
+                            closerPeers: neighbours,
+                            ..Default::default()
+                        };
+                        match request.type_pb {
+                            MessageType::GET_PROVIDERS if i == 5 => {
+                                response.providerPeers = vec![provider]
+                            }
+                            MessageType::GET_VALUE if i == 8 => {
+                                response.record = Some(Record {
+                                    key: request.key,
+                                    value: b"value".to_vec(),
+                                    ..Default::default()
+                                })
+                            }
+                            _ => {}
+                        }
+                        io::write_uvarint_prefixed(&mut stream, &protobuf_encode(&response)?)
+                            .await?;
+                        stream.close().await
+                    }
+                },
+            );
+            async_std::task::spawn(async move { manager.listen(listener).await });
+        }
+        Ok(peers)
+    }
+
+    #[async_std::test]
+    async fn test_kademlia_lookups() -> Result<(), Error> {
+        let peers = spawn_network()?;
+        let private_key = PrivateKey::generate_ed25519();
+        let manager = Manager::new(private_key.clone(), "127.0.0.1:0".parse().unwrap());
+        let kad = Kademlia::new(private_key.public().try_into()?)?;
+        kad.add_address(peers[0].peer_id.clone(), peers[0].addrs[0].clone());
+
+        // every node is reached by walking the ring from the bootstrap node
+        let closest = kad.find_node(&manager, &peers[7].peer_id).await?;
+        assert_eq!(closest.len(), NODES);
+        assert_eq!(closest[0], peers[7]);
+
+        let providers = kad.get_providers(&manager, b"content").await?;
+        assert_eq!(providers, vec![peers[0].clone()]);
+
+        assert_eq!(kad.get_value(&manager, b"key").await?, b"value");
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_kademlia_server() -> Result<(), Error> {
+        let nodes = spawn_kad_servers(6).await?;
+
+        let (manager, kad, _) = &nodes[1];
+        assert!(
+            kad.put_value(manager, b"/test/key", b"value".to_vec())
+                .await?
+                > 0
+        );
+        let (manager, kad, _) = &nodes[2];
+        assert_eq!(kad.get_value(manager, b"/test/key").await?, b"value");
+
+        // keys without a validator are refused, by the remote as well
+        assert!(kad
+            .put_value(manager, b"key", b"value".to_vec())
+            .await
+            .is_err());
+        let mut request = Kademlia::request(MessageType::PUT_VALUE, b"key");
+        request.record = Some(RecordPayload {
+            key: b"key".to_vec(),
+            value: b"value".to_vec(),
+            ..Default::default()
+        });
+        assert!(kad.query(manager, &nodes[3].2, &request).await.is_err());
+        assert!(nodes[3].1.records.get(b"key").is_none());
+
+        // a provider can only announce itself
+        let (manager, _, provider) = &nodes[3];
+        let mut request = Kademlia::request(MessageType::ADD_PROVIDER, b"content");
+        // an invalid entry does not drop the others
+        let invalid = PeerPayload {
+            id: vec![0, 9],
+            ..Default::default()
+        };
+        request.providerPeers = vec![invalid, provider.to_payload()?, nodes[1].2.to_payload()?];
+        let connection = manager
+            .dial_peer(&nodes[4].2.peer_id, &nodes[4].2.addrs)
+            .await?;
+        let mut stream = connection.open_stream(PROTOCOL_KAD).await?;
+        io::write_uvarint_prefixed(&mut stream, &protobuf_encode(&request)?).await?;
+        stream.close().await?;
+
+        let (manager, kad, _) = &nodes[5];
+        let mut providers = Vec::new();
+        for _ in 0..50 {
+            providers = kad.get_providers(manager, b"content").await?;
+            if !providers.is_empty() {
+                break;
+            }
+            Timer::after(Duration::from_millis(10)).await;
+        }
+        assert_eq!(providers, vec![provider.clone()]);
+
+        // the peers queried add the querying peer at its listen addresses
+        // once identified as a DHT server, and not a client
+        let client_key = PrivateKey::generate_ed25519();
+        let client_id: PeerId = client_key.public().try_into()?;
+        let client_manager = Manager::new(client_key.clone(), "127.0.0.1:0".parse().unwrap())
+            .with_protocol(Identify::new(client_key));
+        let client = Kademlia::new(client_id.clone())?;
+        let (_, queried, queried_peer) = &nodes[5];
+        client.add_address(queried_peer.peer_id.clone(), queried_peer.addrs[0].clone());
+        client.find_node(&client_manager, &client_id).await?;
+
+        let server = spawn_kad_servers(1).await?.remove(0);
+        let (server_manager, server_kad, server_peer) = &server;
+        server_kad.add_address(queried_peer.peer_id.clone(), queried_peer.addrs[0].clone());
+        server_kad.bootstrap(server_manager).await?;
+        io::timeout(Duration::from_secs(5), async {
+            while queried.routing_peer(&server_peer.peer_id).is_none() {
+                Timer::after(Duration::from_millis(10)).await;
+            }
+            Ok(())
+        })
+        .await?;
+        assert_eq!(
+            queried.routing_peer(&server_peer.peer_id).unwrap().addrs,
+            server_peer.addrs
+        );
+        assert!(queried.routing_peer(&client_id).is_none());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_kademlia_provide() -> Result<(), Error> {
+        let nodes = spawn_kad_servers(6).await?;
+
+        let (manager, kad, provider) = &nodes[1];
+        let kad = kad.clone().with_reprovide_interval(Duration::ZERO);
+        assert!(kad.provide(manager, b"content").await? > 0);
+        let (other_manager, other_kad, _) = &nodes[4];
+        let providers = other_kad.get_providers(other_manager, b"content").await?;
+        assert_eq!(providers.len(), 1);
+        assert_eq!(providers[0].peer_id, provider.peer_id);
+        assert_eq!(providers[0].addrs, provider.addrs);
+
+        // the addresses are signed by the provider
+        let mut payload = providers[0].to_payload()?;
+        let record = PeerRecord::from_signed_envelope(&payload.signedRecord)?;
+        assert_eq!(record.addrs, provider.addrs);
+        let last = payload.signedRecord.len() - 1;
+        payload.signedRecord[last] ^= 1;
+        assert!(KadPeer::try_from(payload).is_err());
+
+        // announced again until stopped
+        assert_eq!(kad.reprovide(manager).await, 1);
+        kad.stop_providing(b"content");
+        assert_eq!(kad.reprovide(manager).await, 0);
+
+        // nothing to announce without listen addresses
+        let (manager, kad, _) = &nodes[2];
+        let kad = kad.clone().with_listen_addrs(Vec::new());
+        assert!(kad.provide(manager, b"content").await.is_err());
+        // nor without a key to sign them
+        let kad =
+            Kademlia::new(nodes[3].2.peer_id.clone())?.with_listen_addrs(nodes[3].2.addrs.clone());
+        assert!(kad.provide(manager, b"content").await.is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_kademlia_mode() -> Result<(), Error> {
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let addr = listener.get_ref().local_addr()?;
+        let private_key = PrivateKey::generate_ed25519();
+        let peer = KadPeer {
+            peer_id: private_key.public().try_into()?,
+            addrs: vec![tcpaddr_to_multiaddr(&addr)],
+            signed_record: None,
+        };
+        let autonat = AutoNat::new(peer.peer_id.clone());
+        let identify = Identify::new(private_key.clone());
+        let server_kad = Kademlia::new(peer.peer_id.clone())?
+            .with_autonat(autonat.clone())
+            .with_identify(identify.clone());
+        let server = Arc::new(
+            Manager::new(private_key, addr)
+                .with_protocol(server_kad.clone())
+                .with_protocol(identify),
+        );
+        let listening = server.clone();
+        async_std::task::spawn(async move { listening.listen(listener).await });
+
+        let client_key = PrivateKey::generate_ed25519();
+        let client_kad = Kademlia::new(client_key.public().try_into()?)?;
+        let client_identify = Identify::new(client_key.clone());
+        let client = Manager::new(client_key, addr).with_protocol(client_identify.clone());
+        let request = Kademlia::request(MessageType::PING, &[]);
+
+        // a client until the local peer is known to be public, and kad is
+        // not advertised meanwhile
+        assert_eq!(server_kad.mode(), KadMode::Client);
+        assert!(client_kad.query(&client, &peer, &request).await.is_err());
+        assert_eq!(server_kad.routing_table_len(), 0);
+        let connection = client.dial_peer(&peer.peer_id, &peer.addrs).await?;
+        let info = client_identify.identify(&connection).await?;
+        assert!(!info.protocols.contains(&PROTOCOL_KAD.to_string()));
+
+        // advertised again by a push once a server
+        let running = (server.clone(), server_kad.clone());
+        async_std::task::spawn(async move { running.1.run(&running.0).await });
+        autonat.record(NatStatus::Public, None);
+        assert_eq!(server_kad.mode(), KadMode::Server);
+        client_kad.query(&client, &peer, &request).await?;
+        io::timeout(Duration::from_secs(5), async {
+            loop {
+                let info = client_identify.peer_info(&peer.peer_id).unwrap();
+                if info.protocols.contains(&PROTOCOL_KAD.to_string()) {
+                    return Ok(());
+                }
+                Timer::after(Duration::from_millis(10)).await;
+            }
+        })
+        .await?;
+
+        autonat.record(NatStatus::Private, None);
+        assert_eq!(server_kad.mode(), KadMode::Client);
+        assert_eq!(client_kad.mode(), KadMode::Server);
+        Ok(())
+    }
+}
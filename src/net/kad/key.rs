@@ -0,0 +1,85 @@
+use crate::{error::Error, identity::PeerId};
+use ring::digest;
+use std::fmt;
+
+// Key in the keyspace of the DHT, distances are measured between sha256 hashes
+#[derive(Clone, PartialEq, Eq)]
+pub struct Key {
+    preimage: Vec<u8>,
+    hash: [u8; 32],
+}
+
+impl Key {
+    pub fn new(preimage: &[u8]) -> Self {
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(digest::digest(&digest::SHA256, preimage).as_ref());
+        Self {
+            preimage: preimage.to_vec(),
+            hash,
+        }
+    }
+
+    // key of the peer id multihash
+    pub fn from_peer_id(peer_id: &PeerId) -> Result<Self, Error> {
+        Ok(Self::new(&peer_id.to_bytes()?))
+    }
+
+    pub fn preimage(&self) -> &[u8] {
+        &self.preimage
+    }
+
+    pub fn distance(&self, other: &Key) -> Distance {
+        let mut distance = [0u8; 32];
+        for (i, byte) in distance.iter_mut().enumerate() {
+            *byte = self.hash[i] ^ other.hash[i];
+        }
+        Distance(distance)
+    }
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Key({})",
+            multibase::Base::Base58Btc.encode(&self.preimage)
+        )
+    }
+}
+
+// Distance is the XOR of two hashes, compared as a big-endian integer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Distance([u8; 32]);
+
+impl Distance {
+    pub fn leading_zeros(&self) -> u32 {
+        let mut zeros = 0;
+        for byte in self.0 {
+            zeros += byte.leading_zeros();
+            if byte != 0 {
+                break;
+            }
+        }
+        zeros
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_distance() {
+        let a = Key::new(b"a");
+        let b = Key::new(b"b");
+        assert_eq!(a.distance(&a).leading_zeros(), 256);
+        assert_eq!(a.distance(&b), b.distance(&a));
+        assert!(a.distance(&a) < a.distance(&b));
+
+        let mut bytes = [0u8; 32];
+        bytes[1] = 0x01;
+        assert_eq!(Distance(bytes).leading_zeros(), 15);
+        bytes[0] = 0x80;
+        assert_eq!(Distance(bytes).leading_zeros(), 0);
+    }
+}
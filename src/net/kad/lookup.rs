@@ -0,0 +1,139 @@
+use super::{Distance, KadPeer, Key};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    NotContacted,
+    Waiting,
+    Succeeded,
+    Failed,
+}
+
+// Lookup
+//
+// State of an iterative lookup, candidates are kept sorted by their distance
+// to the target. The lookup ends once the k closest peers which have not
+// failed have all answered.
+pub struct Lookup {
+    target: Key,
+    k: usize,
+    alpha: usize,
+    candidates: BTreeMap<Distance, (KadPeer, State)>,
+}
+
+impl Lookup {
+    pub fn new(target: Key, k: usize, alpha: usize) -> Self {
+        Self {
+            target,
+            k,
+            alpha,
+            candidates: BTreeMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, peer: KadPeer) {
+        let distance = match Key::from_peer_id(&peer.peer_id) {
+            Ok(key) => key.distance(&self.target),
+            Err(_) => return,
+        };
+        self.candidates
+            .entry(distance)
+            .or_insert((peer, State::NotContacted));
+    }
+
+    // the next peer to query, when less than alpha queries are in flight
+    pub fn next(&mut self) -> Option<KadPeer> {
+        let waiting = self
+            .candidates
+            .values()
+            .filter(|(_, state)| *state == State::Waiting)
+            .count();
+        if waiting >= self.alpha {
+            return None;
+        }
+        let (peer, state) = self
+            .candidates
+            .values_mut()
+            .filter(|(_, state)| *state != State::Failed)
+            .take(self.k)
+            .find(|(_, state)| *state == State::NotContacted)?;
+        *state = State::Waiting;
+        Some(peer.clone())
+    }
+
+    pub fn on_success(&mut self, peer: &KadPeer) {
+        self.set_state(peer, State::Succeeded);
+    }
+
+    pub fn on_failure(&mut self, peer: &KadPeer) {
+        self.set_state(peer, State::Failed);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.candidates
+            .values()
+            .filter(|(_, state)| *state != State::Failed)
+            .take(self.k)
+            .all(|(_, state)| *state == State::Succeeded)
+    }
+
+    // the k closest peers which have answered
+    pub fn closest(&self) -> Vec<KadPeer> {
+        self.candidates
+            .values()
+            .filter(|(_, state)| *state == State::Succeeded)
+            .take(self.k)
+            .map(|(peer, _)| peer.clone())
+            .collect()
+    }
+
+    fn set_state(&mut self, peer: &KadPeer, new_state: State) {
+        if let Some((_, state)) = self
+            .candidates
+            .values_mut()
+            .find(|(candidate, _)| candidate.peer_id == peer.peer_id)
+        {
+            *state = new_state;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::{PeerId, PrivateKey};
+
+    fn peer() -> KadPeer {
+        let peer_id: PeerId = PrivateKey::generate_ed25519().public().try_into().unwrap();
+        KadPeer {
+            peer_id,
+            addrs: Vec::new(),
+            signed_record: None,
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        let mut lookup = Lookup::new(Key::new(b"target"), 2, 1);
+        let peers: Vec<KadPeer> = (0..3).map(|_| peer()).collect();
+        for peer in &peers {
+            lookup.add(peer.clone());
+        }
+
+        // one query at a time
+        let first = lookup.next().unwrap();
+        assert!(lookup.next().is_none());
+        lookup.on_failure(&first);
+
+        let second = lookup.next().unwrap();
+        lookup.on_success(&second);
+        let third = lookup.next().unwrap();
+        assert!(!lookup.is_finished());
+        lookup.on_success(&third);
+
+        // the failed peer is replaced by the third
+        assert!(lookup.next().is_none());
+        assert!(lookup.is_finished());
+        assert_eq!(lookup.closest(), vec![second, third]);
+    }
+}
@@ -0,0 +1,297 @@
+use super::{KadPeer, Key};
+use crate::identity::PeerId;
+use multiaddr::Multiaddr;
+use rand::RngCore;
+use std::time::{Duration, Instant};
+
+const BUCKET_COUNT: usize = 256;
+// random keys are only generated for buckets up to this common prefix length
+const MAX_REFRESH_CPL: usize = 15;
+// addresses kept per peer, the oldest are evicted first
+const MAX_ADDRS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InsertResult {
+    Inserted,
+    Updated,
+    // the bucket is full, the peer waits for the liveness check of the least
+    // recently seen entry
+    Pending,
+}
+
+struct Entry {
+    peer: KadPeer,
+    last_seen: Instant,
+}
+
+struct KBucket {
+    // least recently seen first
+    entries: Vec<Entry>,
+    pending: Option<KadPeer>,
+    last_refreshed: Instant,
+}
+
+// RoutingTable
+//
+// k-buckets indexed by the common prefix length of the peer key with the
+// local key
+pub struct RoutingTable {
+    local_key: Key,
+    k: usize,
+    buckets: Vec<KBucket>,
+}
+
+impl RoutingTable {
+    pub fn new(local_key: Key, k: usize) -> Self {
+        let now = Instant::now();
+        Self {
+            local_key,
+            k,
+            buckets: (0..BUCKET_COUNT)
+                .map(|_| KBucket {
+                    entries: Vec::new(),
+                    pending: None,
+                    last_refreshed: now,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(|bucket| bucket.entries.len()).sum()
+    }
+
+    pub fn insert(&mut self, mut peer: KadPeer) -> InsertResult {
+        let k = self.k;
+        let bucket = match self.bucket_mut(&peer.peer_id) {
+            Some(bucket) => bucket,
+            // the local peer is never added
+            None => return InsertResult::Updated,
+        };
+        if let Some(i) = bucket
+            .entries
+            .iter()
+            .position(|entry| entry.peer.peer_id == peer.peer_id)
+        {
+            let mut entry = bucket.entries.remove(i);
+            merge_addrs(&mut entry.peer.addrs, peer.addrs);
+            entry.last_seen = Instant::now();
+            bucket.entries.push(entry);
+            return InsertResult::Updated;
+        }
+        let addrs = std::mem::take(&mut peer.addrs);
+        merge_addrs(&mut peer.addrs, addrs);
+        if bucket.entries.len() < k {
+            bucket.entries.push(Entry {
+                peer,
+                last_seen: Instant::now(),
+            });
+            return InsertResult::Inserted;
+        }
+        bucket.pending = Some(peer);
+        InsertResult::Pending
+    }
+
+    // the pending peer of the bucket takes the place of the removed one
+    pub fn remove(&mut self, peer_id: &PeerId) -> Option<KadPeer> {
+        let bucket = self.bucket_mut(peer_id)?;
+        let i = bucket
+            .entries
+            .iter()
+            .position(|entry| &entry.peer.peer_id == peer_id)?;
+        let removed = bucket.entries.remove(i).peer;
+        if let Some(pending) = bucket.pending.take() {
+            bucket.entries.push(Entry {
+                peer: pending,
+                last_seen: Instant::now(),
+            });
+        }
+        Some(removed)
+    }
+
+    pub fn peer(&self, peer_id: &PeerId) -> Option<KadPeer> {
+        let index = self.bucket_index(&Key::from_peer_id(peer_id).ok()?)?;
+        self.buckets[index]
+            .entries
+            .iter()
+            .find(|entry| &entry.peer.peer_id == peer_id)
+            .map(|entry| entry.peer.clone())
+    }
+
+    pub fn closest(&self, key: &Key, count: usize) -> Vec<KadPeer> {
+        let mut peers: Vec<_> = self
+            .buckets
+            .iter()
+            .flat_map(|bucket| bucket.entries.iter())
+            .filter_map(|entry| {
+                let distance = Key::from_peer_id(&entry.peer.peer_id).ok()?.distance(key);
+                Some((distance, entry.peer.clone()))
+            })
+            .collect();
+        peers.sort_by_key(|(distance, _)| *distance);
+        peers
+            .into_iter()
+            .take(count)
+            .map(|(_, peer)| peer)
+            .collect()
+    }
+
+    // least recently seen entries of full buckets which have a pending peer
+    pub fn liveness_checks(&self) -> Vec<KadPeer> {
+        self.buckets
+            .iter()
+            .filter(|bucket| bucket.pending.is_some())
+            .filter_map(|bucket| bucket.entries.first().map(|entry| entry.peer.clone()))
+            .collect()
+    }
+
+    // an alive entry stays and the pending peer is dropped, otherwise the
+    // pending peer replaces it
+    pub fn on_liveness_checked(&mut self, peer_id: &PeerId, alive: bool) {
+        if !alive {
+            self.remove(peer_id);
+            return;
+        }
+        if let Some(bucket) = self.bucket_mut(peer_id) {
+            bucket.pending = None;
+            if let Some(i) = bucket
+                .entries
+                .iter()
+                .position(|entry| &entry.peer.peer_id == peer_id)
+            {
+                let mut entry = bucket.entries.remove(i);
+                entry.last_seen = Instant::now();
+                bucket.entries.push(entry);
+            }
+        }
+    }
+
+    // random keys to look up for the non-empty buckets not refreshed within
+    // the interval
+    pub fn refresh_keys(&mut self, interval: Duration) -> Vec<Key> {
+        let mut keys = Vec::new();
+        for cpl in 0..=MAX_REFRESH_CPL {
+            let bucket = &self.buckets[cpl];
+            if bucket.entries.is_empty() || bucket.last_refreshed.elapsed() < interval {
+                continue;
+            }
+            keys.push(self.random_key(cpl));
+            self.buckets[cpl].last_refreshed = Instant::now();
+        }
+        keys
+    }
+
+    fn random_key(&self, cpl: usize) -> Key {
+        let mut preimage = [0u8; 32];
+        loop {
+            rand::thread_rng().fill_bytes(&mut preimage);
+            let key = Key::new(&preimage);
+            if self.bucket_index(&key) == Some(cpl) {
+                return key;
+            }
+        }
+    }
+
+    // common prefix length with the local key, none for the local key itself
+    fn bucket_index(&self, key: &Key) -> Option<usize> {
+        let cpl = key.distance(&self.local_key).leading_zeros() as usize;
+        (cpl < BUCKET_COUNT).then_some(cpl)
+    }
+
+    fn bucket_mut(&mut self, peer_id: &PeerId) -> Option<&mut KBucket> {
+        let index = self.bucket_index(&Key::from_peer_id(peer_id).ok()?)?;
+        Some(&mut self.buckets[index])
+    }
+}
+
+// the addresses seen again move to the end, as the newest
+fn merge_addrs(addrs: &mut Vec<Multiaddr>, new: Vec<Multiaddr>) {
+    for addr in new {
+        addrs.retain(|known| known != &addr);
+        addrs.push(addr);
+    }
+    if addrs.len() > MAX_ADDRS {
+        addrs.drain(..addrs.len() - MAX_ADDRS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::PrivateKey;
+
+    fn peer() -> KadPeer {
+        KadPeer {
+            peer_id: PrivateKey::generate_ed25519().public().try_into().unwrap(),
+            addrs: vec!["/ip4/127.0.0.1/tcp/4001".parse().unwrap()],
+            signed_record: None,
+        }
+    }
+
+    #[test]
+    fn test_routing_table_buckets() {
+        let mut table = RoutingTable::new(Key::new(b"local"), 1);
+
+        // a bucket overflows as soon as two peers share it
+        let mut pending = None;
+        let mut inserted = Vec::new();
+        while pending.is_none() {
+            let peer = peer();
+            match table.insert(peer.clone()) {
+                InsertResult::Inserted => inserted.push(peer),
+                InsertResult::Pending => pending = Some(peer),
+                InsertResult::Updated => unreachable!(),
+            }
+        }
+        let pending = pending.unwrap();
+        assert_eq!(table.insert(inserted[0].clone()), InsertResult::Updated);
+        assert_eq!(table.len(), inserted.len());
+
+        // the least recently seen entry does not answer, so it is replaced
+        let checks = table.liveness_checks();
+        assert_eq!(checks.len(), 1);
+        table.on_liveness_checked(&checks[0].peer_id, false);
+        assert_eq!(table.peer(&checks[0].peer_id), None);
+        assert_eq!(table.peer(&pending.peer_id), Some(pending.clone()));
+        assert!(table.liveness_checks().is_empty());
+
+        let key = Key::from_peer_id(&pending.peer_id).unwrap();
+        assert_eq!(table.closest(&key, 1), vec![pending]);
+    }
+
+    #[test]
+    fn test_routing_table_addrs() {
+        let mut table = RoutingTable::new(Key::new(b"local"), 20);
+        let addr =
+            |port: usize| -> Multiaddr { format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap() };
+        let mut peer = peer();
+        peer.addrs = vec![addr(1), addr(1)];
+        table.insert(peer.clone());
+        assert_eq!(table.peer(&peer.peer_id).unwrap().addrs, vec![addr(1)]);
+
+        // the oldest addresses are evicted beyond the limit
+        peer.addrs = (2..=MAX_ADDRS + 1).map(addr).collect();
+        table.insert(peer.clone());
+        peer.addrs = vec![addr(2)];
+        table.insert(peer.clone());
+        let addrs = table.peer(&peer.peer_id).unwrap().addrs;
+        assert_eq!(addrs.len(), MAX_ADDRS);
+        assert!(!addrs.contains(&addr(1)));
+        assert_eq!(addrs.last(), Some(&addr(2)));
+    }
+
+    #[test]
+    fn test_routing_table_refresh() {
+        let mut table = RoutingTable::new(Key::new(b"local"), 20);
+        for _ in 0..10 {
+            table.insert(peer());
+        }
+        let keys = table.refresh_keys(Duration::ZERO);
+        assert!(!keys.is_empty());
+        for key in keys {
+            let index = table.bucket_index(&key).unwrap();
+            assert!(!table.buckets[index].entries.is_empty());
+        }
+        assert!(table.refresh_keys(Duration::from_secs(60)).is_empty());
+    }
+}
@@ -0,0 +1,283 @@
+use super::KadPeer;
+use crate::error::{self, Error};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+const DEFAULT_RECORD_TTL: Duration = Duration::from_secs(36 * 60 * 60);
+const DEFAULT_PROVIDER_TTL: Duration = Duration::from_secs(48 * 60 * 60);
+const DEFAULT_MAX_RECORDS: usize = 1024;
+const DEFAULT_MAX_VALUE_SIZE: usize = 64 * 1024;
+const DEFAULT_MAX_PROVIDER_KEYS: usize = 1024;
+const DEFAULT_MAX_PROVIDERS_PER_KEY: usize = 64;
+
+// entry with the instant it expires at
+type Expiring<T> = (T, Instant);
+
+// KadRecord of a value stored in the DHT
+#[derive(Debug, Clone, PartialEq)]
+pub struct KadRecord {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+}
+
+// RecordStore keeps the values put by remote peers
+pub trait RecordStore: Send + Sync {
+    // fails once the store is full, or the value too large
+    fn put(&self, record: KadRecord) -> Result<(), Error>;
+
+    fn get(&self, key: &[u8]) -> Option<KadRecord>;
+
+    fn remove(&self, key: &[u8]);
+
+    // drop the expired records, called periodically by Kademlia::run
+    fn remove_expired(&self) {}
+}
+
+// ProviderStore keeps the providers announced by remote peers
+pub trait ProviderStore: Send + Sync {
+    // fails once the store, or the providers of the key, are full
+    fn add_provider(&self, key: &[u8], provider: KadPeer) -> Result<(), Error>;
+
+    fn providers(&self, key: &[u8]) -> Vec<KadPeer>;
+
+    // drop the expired providers, called periodically by Kademlia::run
+    fn remove_expired(&self) {}
+}
+
+// MemoryStore
+//
+// Records and providers in memory, entries expire after their ttl and are
+// dropped when they are read. The entries are bounded, replacing a record
+// or announcing a provider again is still allowed once full.
+pub struct MemoryStore {
+    record_ttl: Duration,
+    provider_ttl: Duration,
+    max_records: usize,
+    max_value_size: usize,
+    max_provider_keys: usize,
+    max_providers_per_key: usize,
+    records: Mutex<HashMap<Vec<u8>, Expiring<KadRecord>>>,
+    providers: Mutex<HashMap<Vec<u8>, Vec<Expiring<KadPeer>>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self {
+            record_ttl: DEFAULT_RECORD_TTL,
+            provider_ttl: DEFAULT_PROVIDER_TTL,
+            max_records: DEFAULT_MAX_RECORDS,
+            max_value_size: DEFAULT_MAX_VALUE_SIZE,
+            max_provider_keys: DEFAULT_MAX_PROVIDER_KEYS,
+            max_providers_per_key: DEFAULT_MAX_PROVIDERS_PER_KEY,
+            records: Mutex::new(HashMap::new()),
+            providers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_record_ttl(mut self, record_ttl: Duration) -> Self {
+        self.record_ttl = record_ttl;
+        self
+    }
+
+    pub fn with_provider_ttl(mut self, provider_ttl: Duration) -> Self {
+        self.provider_ttl = provider_ttl;
+        self
+    }
+
+    pub fn with_max_records(mut self, max_records: usize) -> Self {
+        self.max_records = max_records;
+        self
+    }
+
+    pub fn with_max_value_size(mut self, max_value_size: usize) -> Self {
+        self.max_value_size = max_value_size;
+        self
+    }
+
+    // keys with providers
+    pub fn with_max_provider_keys(mut self, max_provider_keys: usize) -> Self {
+        self.max_provider_keys = max_provider_keys;
+        self
+    }
+
+    pub fn with_max_providers_per_key(mut self, max_providers_per_key: usize) -> Self {
+        self.max_providers_per_key = max_providers_per_key;
+        self
+    }
+
+    // drop every expired entry
+    pub fn remove_expired(&self) {
+        RecordStore::remove_expired(self);
+        ProviderStore::remove_expired(self);
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RecordStore for MemoryStore {
+    fn put(&self, record: KadRecord) -> Result<(), Error> {
+        if record.value.len() > self.max_value_size {
+            return Err(error::invalid_data("record value too large"));
+        }
+        let now = Instant::now();
+        let mut records = self.records.lock().unwrap();
+        if !records.contains_key(&record.key) && records.len() >= self.max_records {
+            records.retain(|_, (_, expires)| *expires > now);
+            if records.len() >= self.max_records {
+                return Err(error::other("too many records"));
+            }
+        }
+        records.insert(record.key.clone(), (record, now + self.record_ttl));
+        Ok(())
+    }
+
+    fn get(&self, key: &[u8]) -> Option<KadRecord> {
+        let mut records = self.records.lock().unwrap();
+        match records.get(key) {
+            Some((record, expires)) if *expires > Instant::now() => Some(record.clone()),
+            Some(_) => {
+                records.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn remove(&self, key: &[u8]) {
+        self.records.lock().unwrap().remove(key);
+    }
+
+    fn remove_expired(&self) {
+        let now = Instant::now();
+        self.records
+            .lock()
+            .unwrap()
+            .retain(|_, (_, expires)| *expires > now);
+    }
+}
+
+impl ProviderStore for MemoryStore {
+    // announcing again extends the ttl of the provider
+    fn add_provider(&self, key: &[u8], provider: KadPeer) -> Result<(), Error> {
+        let now = Instant::now();
+        let mut providers = self.providers.lock().unwrap();
+        if !providers.contains_key(key) && providers.len() >= self.max_provider_keys {
+            for entries in providers.values_mut() {
+                entries.retain(|(_, expires)| *expires > now);
+            }
+            providers.retain(|_, entries| !entries.is_empty());
+            if providers.len() >= self.max_provider_keys {
+                return Err(error::other("too many provider keys"));
+            }
+        }
+        let entries = providers.entry(key.to_vec()).or_default();
+        entries.retain(|(entry, expires)| entry.peer_id != provider.peer_id && *expires > now);
+        if entries.len() >= self.max_providers_per_key {
+            return Err(error::other("too many providers"));
+        }
+        entries.push((provider, now + self.provider_ttl));
+        Ok(())
+    }
+
+    fn providers(&self, key: &[u8]) -> Vec<KadPeer> {
+        let now = Instant::now();
+        let mut providers = self.providers.lock().unwrap();
+        match providers.get_mut(key) {
+            Some(entries) => {
+                entries.retain(|(_, expires)| *expires > now);
+                entries
+                    .iter()
+                    .map(|(provider, _)| provider.clone())
+                    .collect()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    fn remove_expired(&self) {
+        let now = Instant::now();
+        let mut providers = self.providers.lock().unwrap();
+        for entries in providers.values_mut() {
+            entries.retain(|(_, expires)| *expires > now);
+        }
+        providers.retain(|_, entries| !entries.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::PrivateKey;
+
+    #[test]
+    fn test_memory_store_expiry() {
+        let store = MemoryStore::new()
+            .with_record_ttl(Duration::ZERO)
+            .with_provider_ttl(Duration::from_secs(60));
+        let record = KadRecord {
+            key: b"key".to_vec(),
+            value: b"value".to_vec(),
+        };
+        store.put(record).unwrap();
+        assert_eq!(store.get(b"key"), None);
+
+        let provider = KadPeer {
+            peer_id: PrivateKey::generate_ed25519().public().try_into().unwrap(),
+            addrs: Vec::new(),
+            signed_record: None,
+        };
+        store.add_provider(b"key", provider.clone()).unwrap();
+        store.add_provider(b"key", provider.clone()).unwrap();
+        assert_eq!(store.providers(b"key"), vec![provider]);
+
+        let store = store.with_provider_ttl(Duration::ZERO);
+        store
+            .add_provider(b"other", store.providers(b"key")[0].clone())
+            .unwrap();
+        store.remove_expired();
+        assert!(store.providers(b"other").is_empty());
+        assert_eq!(store.providers(b"key").len(), 1);
+    }
+
+    #[test]
+    fn test_memory_store_bounds() {
+        let store = MemoryStore::new()
+            .with_max_records(1)
+            .with_max_value_size(8)
+            .with_max_provider_keys(1)
+            .with_max_providers_per_key(1);
+        let record = |key: &[u8], value: &[u8]| KadRecord {
+            key: key.to_vec(),
+            value: value.to_vec(),
+        };
+        assert!(store.put(record(b"key", b"too large")).is_err());
+        store.put(record(b"key", b"value")).unwrap();
+        assert!(store.put(record(b"other", b"value")).is_err());
+        // replaced while full
+        store.put(record(b"key", b"new")).unwrap();
+        assert_eq!(store.get(b"key"), Some(record(b"key", b"new")));
+
+        let provider = || KadPeer {
+            peer_id: PrivateKey::generate_ed25519().public().try_into().unwrap(),
+            addrs: Vec::new(),
+            signed_record: None,
+        };
+        let first = provider();
+        store.add_provider(b"key", first.clone()).unwrap();
+        store.add_provider(b"key", first.clone()).unwrap();
+        assert!(store.add_provider(b"key", provider()).is_err());
+        assert!(store.add_provider(b"other", first).is_err());
+
+        // expired entries make room
+        let store = store.with_record_ttl(Duration::ZERO);
+        store.put(record(b"key", b"value")).unwrap();
+        store.put(record(b"other", b"value")).unwrap();
+    }
+}
@@ -0,0 +1,32 @@
+use crate::error::Error;
+
+// RecordValidator
+//
+// Checks the values put under a namespace of keys, /<namespace>/..., and
+// picks the best one when peers return different values
+pub trait RecordValidator: Send + Sync {
+    fn validate(&self, key: &[u8], value: &[u8]) -> Result<(), Error>;
+
+    // the index of the best value, the first one of equally good values
+    fn select(&self, key: &[u8], values: &[Vec<u8>]) -> Result<usize, Error>;
+}
+
+// the namespace of the key, ipns for /ipns/...
+pub(super) fn namespace(key: &[u8]) -> Option<&str> {
+    let rest = key.strip_prefix(b"/")?;
+    let end = rest.iter().position(|b| *b == b'/')?;
+    std::str::from_utf8(&rest[..end]).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_namespace() {
+        assert_eq!(namespace(b"/ipns/\x00\x24"), Some("ipns"));
+        assert_eq!(namespace(b"/pk/"), Some("pk"));
+        assert_eq!(namespace(b"/ipns"), None);
+        assert_eq!(namespace(b"key"), None);
+    }
+}
@@ -0,0 +1,257 @@
+mod connection;
+mod stream;
+
+use super::upgrade::{ProtocolId, UpgradeInbound, UpgradeOutbound};
+use crate::error::Error;
+use futures::{
+    future::{self, Ready},
+    AsyncRead, AsyncWrite,
+};
+
+pub use connection::MplexConnection;
+pub use stream::MplexStream;
+
+const PROTOCOL_MPLEX: &str = "/mplex/6.7.0";
+const DEFAULT_MAX_BUFFER_SIZE: usize = 4 * 1024 * 1024;
+const DEFAULT_MAX_STREAMS: usize = 512;
+
+// Mplex
+#[derive(Debug, Clone)]
+pub struct Mplex {
+    max_buffer_size: usize,
+    max_streams: usize,
+}
+
+impl Mplex {
+    pub fn new() -> Self {
+        Self {
+            max_buffer_size: DEFAULT_MAX_BUFFER_SIZE,
+            max_streams: DEFAULT_MAX_STREAMS,
+        }
+    }
+
+    // unread bytes buffered per stream before it is reset
+    pub fn with_max_buffer_size(mut self, max_buffer_size: usize) -> Self {
+        self.max_buffer_size = max_buffer_size;
+        self
+    }
+
+    // streams open at once, either side opening more is refused
+    pub fn with_max_streams(mut self, max_streams: usize) -> Self {
+        self.max_streams = max_streams;
+        self
+    }
+
+    fn connection<T>(self, stream: T) -> MplexConnection
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        MplexConnection::new(stream, self.max_buffer_size, self.max_streams)
+    }
+}
+
+impl Default for Mplex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProtocolId for Mplex {
+    fn protocol_id() -> &'static str {
+        PROTOCOL_MPLEX
+    }
+}
+
+impl<'a, T> UpgradeOutbound<'a, T> for Mplex
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Output = MplexConnection;
+    type Error = Error;
+    type Future = Ready<Result<Self::Output, Self::Error>>;
+
+    fn upgrade_outbound(self, stream: T) -> Self::Future {
+        future::ready(Ok(self.connection(stream)))
+    }
+}
+
+impl<'a, T> UpgradeInbound<'a, T> for Mplex
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Output = MplexConnection;
+    type Error = Error;
+    type Future = Ready<Result<Self::Output, Self::Error>>;
+
+    fn upgrade_inbound(self, stream: T) -> Self::Future {
+        future::ready(Ok(self.connection(stream)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        io::{MplexCodec, MplexFlag, MplexFrame},
+        net::test_support::Stalled,
+    };
+    use asynchronous_codec::Encoder;
+    use bytes::BytesMut;
+    use futures::{channel::mpsc, AsyncReadExt, AsyncWriteExt, TryStreamExt};
+    use std::{
+        net::{TcpListener, TcpStream},
+        time::Duration,
+    };
+    use stream::MAX_MESSAGE_SIZE;
+
+    async fn connection_pair(mplex: Mplex) -> Result<(MplexConnection, MplexConnection), Error> {
+        let listener = async_io::Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let addr = listener.get_ref().local_addr()?;
+        let (server, client) = futures::join!(
+            listener.accept(),
+            async_io::Async::<TcpStream>::connect(addr)
+        );
+        Ok((
+            mplex.upgrade_inbound(server?.0).await?,
+            Mplex::new().upgrade_outbound(client?).await?,
+        ))
+    }
+
+    #[async_std::test]
+    async fn test_mplex_streams() -> Result<(), Error> {
+        let (server, client) = connection_pair(Mplex::new()).await?;
+
+        // both sides use the same ids for the streams they open
+        let mut outbound = client.open_stream().await?;
+        let mut reverse = server.open_stream().await?;
+        assert_eq!((outbound.id(), reverse.id()), (0, 0));
+
+        let mut inbound = server.accept_stream().await?;
+        outbound.write_all(b"ping").await?;
+        outbound.close().await?;
+        let mut buf = Vec::new();
+        inbound.read_to_end(&mut buf).await?;
+        assert_eq!(buf, b"ping");
+
+        // half-closed stream is still writable by the receiver
+        inbound.write_all(b"pong").await?;
+        inbound.close().await?;
+        let mut buf = Vec::new();
+        outbound.read_to_end(&mut buf).await?;
+        assert_eq!(buf, b"pong");
+
+        reverse.write_all(b"reverse").await?;
+        let mut buf = [0u8; 7];
+        client.accept_stream().await?.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"reverse");
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_mplex_buffer_limit() -> Result<(), Error> {
+        let (server, client) = connection_pair(Mplex::new().with_max_buffer_size(1024)).await?;
+
+        let mut outbound = client.open_stream().await?;
+        let mut inbound = server.accept_stream().await?;
+        outbound.write_all(&[0u8; 2048]).await?;
+
+        // the receiver never read, so the stream is reset
+        let mut buf = [0u8; 1];
+        assert!(outbound.read(&mut buf).await.is_err());
+        assert!(inbound.read(&mut buf).await.is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_mplex_send_backpressure() -> Result<(), Error> {
+        let listener = async_io::Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let addr = listener.get_ref().local_addr()?;
+        let (server, client) = futures::join!(
+            listener.accept(),
+            async_io::Async::<TcpStream>::connect(addr)
+        );
+        // the remote never reads the connection
+        let _server = server?;
+        let client = Mplex::new().upgrade_outbound(client?).await?;
+
+        // writes wait for the driver instead of queueing without limit
+        let mut stream = client.open_stream().await?;
+        let payload = vec![0u8; 64 * 1024 * 1024];
+        let res = crate::io::timeout(
+            std::time::Duration::from_millis(500),
+            stream.write_all(&payload),
+        )
+        .await;
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_mplex_stream_limit() -> Result<(), Error> {
+        let (server, client) = connection_pair(Mplex::new().with_max_streams(1)).await?;
+
+        let mut first = client.open_stream().await?;
+        let _inbound = server.accept_stream().await?;
+        // over the limit of the server, the stream is reset
+        let mut second = client.open_stream().await?;
+        let mut buf = [0u8; 1];
+        assert!(second.read(&mut buf).await.is_err());
+        first.write_all(b"first").await?;
+
+        // and the server opens no more than its limit
+        assert!(server.open_stream().await.is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_mplex_dropped_half_closed_stream() -> Result<(), Error> {
+        let (server, client) = connection_pair(Mplex::new().with_max_streams(1)).await?;
+
+        let mut first = client.open_stream().await?;
+        first.write_all(b"first").await?;
+        let mut inbound = server.accept_stream().await?;
+        inbound.write_all(b"reply").await?;
+        inbound.close().await?;
+        drop(inbound);
+        // the remote reads to the end, not reset
+        let mut buf = Vec::new();
+        first.read_to_end(&mut buf).await?;
+        assert_eq!(buf, b"reply");
+
+        // the slot is freed before the client closes the stream
+        let mut second = client.open_stream().await?;
+        second.write_all(b"second").await?;
+        let mut inbound = server.accept_stream().await?;
+        let mut buf = [0u8; 6];
+        inbound.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"second");
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_mplex_reads_while_writes_block() -> Result<(), Error> {
+        let (frames, received) = mpsc::unbounded();
+        let connection = Mplex::new().connection(Stalled(received.into_async_read()));
+
+        // the stream opened locally is never written, the stream of the remote
+        // is still read
+        crate::io::timeout(Duration::from_secs(5), async {
+            let _outbound = connection.open_stream().await?;
+            let mut codec = MplexCodec::new(MAX_MESSAGE_SIZE);
+            for frame in [
+                MplexFrame::new(0, MplexFlag::NewStream, b"0".to_vec()),
+                MplexFrame::new(0, MplexFlag::MessageInitiator, b"hello".to_vec()),
+            ] {
+                let mut buf = BytesMut::new();
+                codec.encode(&frame, &mut buf)?;
+                let _ = frames.unbounded_send(Ok(buf.to_vec()));
+            }
+            let mut inbound = connection.accept_stream().await?;
+            let mut buf = [0u8; 5];
+            inbound.read_exact(&mut buf).await?;
+            assert_eq!(&buf, b"hello");
+            Ok(())
+        })
+        .await
+    }
+}
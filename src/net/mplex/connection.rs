@@ -0,0 +1,341 @@
+use super::stream::{MplexStream, Shared, StreamKey, MAX_MESSAGE_SIZE};
+use crate::{
+    error::{self, Error},
+    io::{MplexCodec, MplexFlag, MplexFrame},
+};
+use asynchronous_codec::{Encoder, FramedRead};
+use bytes::BytesMut;
+use futures::{
+    channel::{mpsc, oneshot},
+    io::{ReadHalf, WriteHalf},
+    lock::Mutex as AsyncMutex,
+    stream, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, StreamExt,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+const MAX_INCOMING_BACKLOG: usize = 32;
+
+pub(super) enum Command {
+    Open(oneshot::Sender<Result<MplexStream, Error>>),
+    // the frame with the stream which queued it
+    Send(MplexFrame, Arc<Mutex<Shared>>),
+    // the handle of the stream is dropped
+    Dropped(StreamKey),
+    Close,
+}
+
+enum Event {
+    Frame(Result<MplexFrame, Error>),
+    ReadClosed,
+    Command(Command),
+}
+
+// MplexConnection
+//
+// A handle of the connection, the frames are read by a driver task spawned
+// along with it, which queues the frames it sends to a writer task
+#[derive(Clone)]
+pub struct MplexConnection {
+    commands: mpsc::UnboundedSender<Command>,
+    incoming: Arc<AsyncMutex<mpsc::Receiver<MplexStream>>>,
+}
+
+impl MplexConnection {
+    // streams buffering more than max_buffer_size unread bytes are reset, and
+    // no more than max_streams are open at once
+    pub fn new<T>(io: T, max_buffer_size: usize, max_streams: usize) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (commands_tx, commands_rx) = mpsc::unbounded();
+        let (incoming_tx, incoming_rx) = mpsc::channel(MAX_INCOMING_BACKLOG);
+        let (reader, writer) = io.split();
+        let (frames_tx, frames_rx) = mpsc::unbounded();
+        async_std::task::spawn(write_frames(writer, frames_rx, commands_tx.clone()));
+
+        let driver = Driver {
+            frames: frames_tx,
+            next_id: 0,
+            max_buffer_size,
+            max_streams,
+            streams: HashMap::new(),
+            commands: commands_tx.clone(),
+            incoming: incoming_tx,
+        };
+        async_std::task::spawn(driver.run(reader, commands_rx));
+
+        Self {
+            commands: commands_tx,
+            incoming: Arc::new(AsyncMutex::new(incoming_rx)),
+        }
+    }
+
+    pub async fn open_stream(&self) -> Result<MplexStream, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.commands
+            .unbounded_send(Command::Open(tx))
+            .map_err(|_| error::other("mplex connection closed"))?;
+        rx.await
+            .map_err(|_| error::other("mplex connection closed"))?
+    }
+
+    pub async fn accept_stream(&self) -> Result<MplexStream, Error> {
+        self.incoming
+            .lock()
+            .await
+            .next()
+            .await
+            .ok_or(error::other("mplex connection closed"))
+    }
+
+    // close the underlying io
+    pub fn close(&self) {
+        let _ = self.commands.unbounded_send(Command::Close);
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.commands.is_closed()
+    }
+}
+
+// a frame to write, with the stream whose queued bytes it accounts for
+type Outgoing = (MplexFrame, Option<Arc<Mutex<Shared>>>);
+
+// write the frames queued by the driver, so that it keeps reading while the
+// remote is slow to read, and end the connection once a write fails
+async fn write_frames<T>(
+    mut writer: WriteHalf<T>,
+    mut frames: mpsc::UnboundedReceiver<Outgoing>,
+    commands: mpsc::UnboundedSender<Command>,
+) where
+    T: AsyncWrite + Unpin,
+{
+    let mut codec = MplexCodec::new(MAX_MESSAGE_SIZE);
+    while let Some((frame, shared)) = frames.next().await {
+        let mut buf = BytesMut::new();
+        let res = match codec.encode(&frame, &mut buf) {
+            Ok(()) => match writer.write_all(&buf).await {
+                Ok(()) => writer.flush().await,
+                Err(err) => Err(err),
+            },
+            Err(err) => Err(err),
+        };
+        if let Some(shared) = shared {
+            shared.lock().unwrap().sent(frame.data.len());
+        }
+        if let Err(err) = res {
+            log::debug!("mplex write failed, {:?}", err);
+            let _ = commands.unbounded_send(Command::Close);
+            break;
+        }
+    }
+    let _ = writer.close().await;
+}
+
+// Driver
+struct Driver {
+    frames: mpsc::UnboundedSender<Outgoing>,
+    next_id: u64,
+    max_buffer_size: usize,
+    max_streams: usize,
+    streams: HashMap<StreamKey, Arc<Mutex<Shared>>>,
+    commands: mpsc::UnboundedSender<Command>,
+    incoming: mpsc::Sender<MplexStream>,
+}
+
+impl Driver {
+    async fn run<T>(mut self, reader: ReadHalf<T>, commands: mpsc::UnboundedReceiver<Command>)
+    where
+        T: AsyncRead + Unpin,
+    {
+        let frames = FramedRead::new(reader, MplexCodec::new(MAX_MESSAGE_SIZE))
+            .map(Event::Frame)
+            .chain(stream::iter([Event::ReadClosed]));
+        let mut events = stream::select(frames, commands.map(Event::Command));
+
+        while let Some(event) = events.next().await {
+            let res = match event {
+                Event::Frame(Ok(frame)) => self.on_frame(frame),
+                Event::Frame(Err(err)) => Err(err),
+                Event::ReadClosed | Event::Command(Command::Close) => {
+                    Err(error::other("mplex connection closed"))
+                }
+                Event::Command(command) => self.on_command(command),
+            };
+            if let Err(err) = res {
+                log::debug!("mplex connection end, {:?}", err);
+                break;
+            }
+        }
+
+        // fail everything still waiting on the connection, the writer closes
+        // the io once the frames queued so far are written
+        self.frames.close_channel();
+        self.commands.close_channel();
+        for shared in self.streams.values() {
+            let mut shared = shared.lock().unwrap();
+            if !shared.remote_closed {
+                shared.reset = true;
+            }
+            // the queued frames are never written
+            shared.send_queued = 0;
+            shared.wake();
+        }
+    }
+
+    fn write(
+        &mut self,
+        frame: MplexFrame,
+        shared: Option<Arc<Mutex<Shared>>>,
+    ) -> Result<(), Error> {
+        self.frames
+            .unbounded_send((frame, shared))
+            .map_err(|_| error::other("mplex connection closed"))
+    }
+
+    fn new_stream(&mut self, key: StreamKey) -> MplexStream {
+        let shared = Arc::new(Mutex::new(Shared::new()));
+        self.streams.insert(key, shared.clone());
+        MplexStream::new(key, shared, self.commands.clone())
+    }
+
+    // remove the stream once both sides are closed, it is reset or its handle
+    // is dropped
+    fn gc_stream(&mut self, key: StreamKey) {
+        let closed = match self.streams.get(&key) {
+            Some(shared) => shared.lock().unwrap().is_closed(),
+            None => false,
+        };
+        if closed {
+            self.streams.remove(&key);
+        }
+    }
+
+    fn on_command(&mut self, command: Command) -> Result<(), Error> {
+        match command {
+            Command::Open(reply) => {
+                if self.streams.len() >= self.max_streams {
+                    let _ = reply.send(Err(error::other("too many mplex streams")));
+                    return Ok(());
+                }
+                let id = self.next_id;
+                self.next_id += 1;
+                let stream = self.new_stream(StreamKey { id, local: true });
+                self.write(
+                    MplexFrame::new(id, MplexFlag::NewStream, id.to_string().into_bytes()),
+                    None,
+                )?;
+                log::debug!("mplex stream {} opened", id);
+                let _ = reply.send(Ok(stream));
+            }
+            Command::Send(frame, shared) => {
+                let key = StreamKey {
+                    id: frame.stream_id,
+                    local: frame.flag.is_initiator(),
+                };
+                // frames of streams reset by the remote are dropped, while
+                // frames queued before the stream is removed are still sent
+                let reset = match self.streams.get(&key) {
+                    Some(shared) => {
+                        shared.lock().unwrap().reset
+                            && !matches!(
+                                frame.flag,
+                                MplexFlag::ResetInitiator | MplexFlag::ResetReceiver
+                            )
+                    }
+                    None => false,
+                };
+                if reset {
+                    shared.lock().unwrap().sent(frame.data.len());
+                } else {
+                    self.write(frame, Some(shared))?;
+                }
+                self.gc_stream(key);
+            }
+            Command::Dropped(key) => self.gc_stream(key),
+            Command::Close => unreachable!(),
+        }
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: MplexFrame) -> Result<(), Error> {
+        // a frame sent by the initiator refers to a stream opened by the remote
+        let key = StreamKey {
+            id: frame.stream_id,
+            local: !frame.flag.is_initiator(),
+        };
+
+        if frame.flag == MplexFlag::NewStream {
+            if self.streams.contains_key(&key) {
+                return Err(error::message_malformed());
+            }
+            if self.streams.len() >= self.max_streams {
+                log::debug!("mplex stream {} refused, too many streams", key.id);
+                self.write(
+                    MplexFrame::new(key.id, MplexFlag::ResetReceiver, Vec::new()),
+                    None,
+                )?;
+                return Ok(());
+            }
+            let stream = self.new_stream(key);
+            if let Err(err) = self.incoming.try_send(stream) {
+                // backlog is full or nobody accepts streams
+                log::debug!("mplex stream {} refused", key.id);
+                drop(err.into_inner());
+            } else {
+                log::debug!("mplex stream {} accepted", key.id);
+            }
+            return Ok(());
+        }
+
+        let shared = match self.streams.get(&key) {
+            Some(shared) => shared.clone(),
+            None => {
+                // late frames of removed streams are ignored
+                log::debug!("mplex stream {} unknown", key.id);
+                return Ok(());
+            }
+        };
+
+        let exceeded = {
+            let mut shared = shared.lock().unwrap();
+            let exceeded = match frame.flag {
+                MplexFlag::MessageInitiator | MplexFlag::MessageReceiver => {
+                    if shared.recv_buffer.len() + frame.data.len() > self.max_buffer_size {
+                        shared.reset = true;
+                        true
+                    } else {
+                        shared.recv_buffer.extend_from_slice(&frame.data);
+                        false
+                    }
+                }
+                MplexFlag::CloseInitiator | MplexFlag::CloseReceiver => {
+                    shared.remote_closed = true;
+                    false
+                }
+                MplexFlag::ResetInitiator | MplexFlag::ResetReceiver => {
+                    shared.reset = true;
+                    false
+                }
+                MplexFlag::NewStream => false,
+            };
+            shared.wake();
+            exceeded
+        };
+        if exceeded {
+            // the reader is too slow, reset instead of buffering without limit
+            log::debug!("mplex stream {} exceeded buffer limit", key.id);
+            let flag = if key.local {
+                MplexFlag::ResetInitiator
+            } else {
+                MplexFlag::ResetReceiver
+            };
+            self.write(MplexFrame::new(key.id, flag, Vec::new()), None)?;
+        }
+        self.gc_stream(key);
+        Ok(())
+    }
+}
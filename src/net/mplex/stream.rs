@@ -0,0 +1,232 @@
+use super::connection::Command;
+use crate::io::{MplexFlag, MplexFrame};
+use bytes::BytesMut;
+use futures::{
+    channel::mpsc,
+    task::{Context, Poll, Waker},
+    AsyncRead, AsyncWrite,
+};
+use std::{
+    cmp::min,
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+
+pub const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+const MAX_WRITE_SIZE: usize = 64 * 1024;
+// bytes written by the handle but not yet by the connection driver
+const MAX_SEND_QUEUED: usize = 256 * 1024;
+
+// StreamKey identifies a stream by its id and the side which opened it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StreamKey {
+    pub id: u64,
+    pub local: bool,
+}
+
+// Shared state of a stream between its handle and the connection driver
+pub struct Shared {
+    pub recv_buffer: BytesMut,
+    pub send_queued: usize,
+    pub local_closed: bool,
+    pub remote_closed: bool,
+    pub reset: bool,
+    // the handle is gone, the stream is removed without waiting for the
+    // remote to close it
+    pub dropped: bool,
+    pub reader: Option<Waker>,
+    pub writer: Option<Waker>,
+}
+
+impl Shared {
+    pub fn new() -> Self {
+        Self {
+            recv_buffer: BytesMut::new(),
+            send_queued: 0,
+            local_closed: false,
+            remote_closed: false,
+            reset: false,
+            dropped: false,
+            reader: None,
+            writer: None,
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.reset || self.dropped || (self.local_closed && self.remote_closed)
+    }
+
+    pub fn wake(&mut self) {
+        if let Some(waker) = self.reader.take() {
+            waker.wake();
+        }
+        if let Some(waker) = self.writer.take() {
+            waker.wake();
+        }
+    }
+
+    // the driver has written, or dropped, bytes queued by the handle
+    pub fn sent(&mut self, n: usize) {
+        self.send_queued = self.send_queued.saturating_sub(n);
+        if let Some(waker) = self.writer.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Default for Shared {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// MplexStream
+pub struct MplexStream {
+    key: StreamKey,
+    shared: Arc<Mutex<Shared>>,
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+impl MplexStream {
+    pub(super) fn new(
+        key: StreamKey,
+        shared: Arc<Mutex<Shared>>,
+        commands: mpsc::UnboundedSender<Command>,
+    ) -> Self {
+        Self {
+            key,
+            shared,
+            commands,
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.key.id
+    }
+
+    // flags are chosen by the side which opened the stream
+    fn flag(&self, initiator: MplexFlag, receiver: MplexFlag) -> MplexFlag {
+        if self.key.local {
+            initiator
+        } else {
+            receiver
+        }
+    }
+
+    fn send(&self, flag: MplexFlag, data: Vec<u8>) -> Result<(), futures::io::Error> {
+        let frame = MplexFrame::new(self.key.id, flag, data);
+        self.commands
+            .unbounded_send(Command::Send(frame, self.shared.clone()))
+            .map_err(|_| futures::io::ErrorKind::BrokenPipe.into())
+    }
+}
+
+impl AsyncRead for MplexStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, futures::io::Error>> {
+        let mut shared = self.shared.lock().unwrap();
+        if !shared.recv_buffer.is_empty() {
+            let n = min(buf.len(), shared.recv_buffer.len());
+            buf[..n].copy_from_slice(&shared.recv_buffer.split_to(n));
+            return Poll::Ready(Ok(n));
+        }
+        if shared.reset {
+            return Poll::Ready(Err(futures::io::ErrorKind::ConnectionReset.into()));
+        }
+        if shared.remote_closed {
+            return Poll::Ready(Ok(0));
+        }
+        shared.reader = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl AsyncWrite for MplexStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, futures::io::Error>> {
+        {
+            let mut shared = self.shared.lock().unwrap();
+            if shared.reset {
+                return Poll::Ready(Err(futures::io::ErrorKind::ConnectionReset.into()));
+            }
+            if shared.local_closed {
+                return Poll::Ready(Err(futures::io::ErrorKind::BrokenPipe.into()));
+            }
+            // wait for the driver to catch up with the queued frames
+            if shared.send_queued >= MAX_SEND_QUEUED {
+                shared.writer = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+        }
+        let n = min(buf.len(), MAX_WRITE_SIZE);
+        // counted before the driver can see the frame
+        self.shared.lock().unwrap().send_queued += n;
+        let flag = self.flag(MplexFlag::MessageInitiator, MplexFlag::MessageReceiver);
+        self.send(flag, buf[..n].to_vec())?;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), futures::io::Error>> {
+        // frames are written out by the connection driver
+        let mut shared = self.shared.lock().unwrap();
+        if shared.send_queued == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        if shared.reset {
+            return Poll::Ready(Err(futures::io::ErrorKind::ConnectionReset.into()));
+        }
+        shared.writer = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), futures::io::Error>> {
+        {
+            let shared = self.shared.lock().unwrap();
+            if shared.local_closed || shared.reset {
+                return Poll::Ready(Ok(()));
+            }
+        }
+        if self.as_mut().poll_flush(cx)?.is_pending() {
+            return Poll::Pending;
+        }
+        self.shared.lock().unwrap().local_closed = true;
+        // half-close, the remote can still send until it closes as well
+        let flag = self.flag(MplexFlag::CloseInitiator, MplexFlag::CloseReceiver);
+        self.send(flag, Vec::new())?;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for MplexStream {
+    fn drop(&mut self) {
+        {
+            let mut shared = self.shared.lock().unwrap();
+            if shared.is_closed() {
+                return;
+            }
+            // a half-closed stream is not reset, so that the remote reads to
+            // the end, but its slot is freed without waiting for the remote
+            if shared.local_closed {
+                shared.dropped = true;
+                drop(shared);
+                let _ = self.commands.unbounded_send(Command::Dropped(self.key));
+                return;
+            }
+            shared.reset = true;
+        }
+        let flag = self.flag(MplexFlag::ResetInitiator, MplexFlag::ResetReceiver);
+        let _ = self.send(flag, Vec::new());
+    }
+}
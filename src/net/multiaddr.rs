@@ -1,4 +1,7 @@
-use crate::error::{self, Error};
+use crate::{
+    error::{self, Error},
+    identity::PeerId,
+};
 use multiaddr::{Multiaddr, Protocol};
 use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
 
@@ -11,8 +14,10 @@ pub fn multiaddr_to_tcpaddr(addr: &Multiaddr) -> Result<SocketAddr, Error> {
         match proto {
             Protocol::Dns4(dns) => hostname = Some(dns.to_string()),
             Protocol::Ip4(ipv4) => ip_addr = Some(IpAddr::V4(ipv4)),
+            Protocol::Ip6(ipv6) => ip_addr = Some(IpAddr::V6(ipv6)),
             Protocol::Tcp(tcp_port) => port = Some(tcp_port),
-            _ => todo!(),
+            Protocol::P2p(_) => {}
+            _ => return Err(error::unsupported("multiaddr protocol")),
         }
     }
 
@@ -27,12 +32,36 @@ pub fn multiaddr_to_tcpaddr(addr: &Multiaddr) -> Result<SocketAddr, Error> {
     }
 }
 
+pub fn tcpaddr_to_multiaddr(addr: &SocketAddr) -> Multiaddr {
+    let ip = match addr.ip() {
+        IpAddr::V4(ipv4) => Protocol::Ip4(ipv4),
+        IpAddr::V6(ipv6) => Protocol::Ip6(ipv6),
+    };
+    Multiaddr::empty().with(ip).with(Protocol::Tcp(addr.port()))
+}
+
+// the /p2p component of the peer id
+pub fn p2p_protocol(peer_id: &PeerId) -> Result<Protocol<'static>, Error> {
+    let peer_id =
+        ::multiaddr::PeerId::from_bytes(&peer_id.to_bytes()?).map_err(|_| error::parse_error())?;
+    Ok(Protocol::P2p(peer_id))
+}
+
+// the peer id of a /p2p component
+pub fn p2p_peer_id(protocol: &Protocol) -> Result<PeerId, Error> {
+    match protocol {
+        Protocol::P2p(peer_id) => PeerId::from_bytes(&peer_id.to_bytes()),
+        _ => Err(error::parse_error()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::net::Ipv4Addr;
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_multiaddr_to_tcpaddr() {
         assert_eq!(
             multiaddr_to_tcpaddr(&"/dns4/localhost/tcp/4001".parse::<Multiaddr>().unwrap())
@@ -69,5 +98,34 @@ mod tests {
             multiaddr_to_tcpaddr(&"/tcp/4001".parse::<Multiaddr>().unwrap()).is_err(),
             true
         );
+
+        assert!(multiaddr_to_tcpaddr(
+            &"/ip4/127.0.0.1/udp/4001/quic-v1"
+                .parse::<Multiaddr>()
+                .unwrap()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_p2p_protocol() {
+        let peer_id: PeerId = crate::identity::PrivateKey::generate_ed25519()
+            .public()
+            .try_into()
+            .unwrap();
+        let protocol = p2p_protocol(&peer_id).unwrap();
+        assert_eq!(p2p_peer_id(&protocol).unwrap(), peer_id);
+        assert!(p2p_peer_id(&Protocol::Tcp(4001)).is_err());
+    }
+
+    #[test]
+    fn test_tcpaddr_to_multiaddr() {
+        assert_eq!(
+            tcpaddr_to_multiaddr(&SocketAddr::new(
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                4001
+            )),
+            "/ip4/127.0.0.1/tcp/4001".parse::<Multiaddr>().unwrap()
+        );
     }
 }
@@ -1,13 +1,15 @@
-use super::{ProtocolId, UpgradeOutbound, PROTOCOL_UNSUPPORTED};
+use super::{ProtocolId, UpgradeInbound, UpgradeOutbound, PROTOCOL_UNSUPPORTED};
 use crate::{
     error::{self, Error},
     io,
 };
-use asynchronous_codec::Framed;
-use futures::{AsyncRead, AsyncWrite, Future, FutureExt, SinkExt, StreamExt};
+use futures::{AsyncRead, AsyncWrite, AsyncWriteExt, Future, FutureExt};
 use std::{boxed::Box, pin::Pin};
 
 const PROTOCOL_MULTISTREAM: &str = "/multistream/1.0.0";
+const MAX_MESSAGE_SIZE: usize = 1024;
+// protocols the initiator may propose before the negotiation fails
+const MAX_PROPOSALS: usize = 16;
 
 // Multistream
 pub struct Multistream {
@@ -15,6 +17,7 @@ pub struct Multistream {
 }
 
 impl Multistream {
+    // protocols are proposed as outbound or accepted as inbound in the given order
     pub fn new(initial_protocols: Vec<Vec<u8>>) -> Self {
         Self { initial_protocols }
     }
@@ -26,58 +29,173 @@ impl ProtocolId for Multistream {
     }
 }
 
+// messages are read one by one, so that nothing after the agreement is consumed
+async fn read_message<T>(stream: &mut T) -> Result<Vec<u8>, Error>
+where
+    T: AsyncRead + Unpin,
+{
+    let mut msg = io::read_uvarint_prefixed(stream, MAX_MESSAGE_SIZE).await?;
+    if msg.pop() != Some(b'\n') {
+        return Err(error::message_malformed());
+    }
+    Ok(msg)
+}
+
+async fn write_message<T>(stream: &mut T, msg: &[u8]) -> Result<(), Error>
+where
+    T: AsyncWrite + Unpin,
+{
+    io::write_uvarint_prefixed(stream, &[msg, b"\n"].concat()).await
+}
+
 impl<'a, T> UpgradeOutbound<'a, T> for Multistream
 where
     T: AsyncRead + AsyncWrite + Unpin + Send + 'a,
 {
     type Output = (T, Vec<u8>);
     type Error = Error;
-    type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + 'a>>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send + 'a>>;
 
-    fn upgrade_outbound(self, stream: T) -> Self::Future {
+    fn upgrade_outbound(self, mut stream: T) -> Self::Future {
         async move {
             let protocol_id = Self::protocol_id().as_bytes();
             let Self { initial_protocols } = self;
-            let mut framed = Framed::new(stream, io::U8LengthLineCodec::new());
+
+            write_message(&mut stream, protocol_id).await?;
+            log::debug!(
+                "initiator protocol send {:?}",
+                std::str::from_utf8(protocol_id)
+            );
 
             // respond protocols
-            let msg = match framed.next().await {
-                Some(Ok(msg)) => msg,
-                _ => return Err(error::other("connection error")),
-            };
+            let msg = read_message(&mut stream)
+                .await
+                .map_err(|_| error::other("connection error"))?;
             log::info!("responder protocol recv {:?}", std::str::from_utf8(&msg));
-            if msg != protocol_id.to_vec() {
-                framed.send(PROTOCOL_UNSUPPORTED.as_bytes()).await?;
+            if msg != protocol_id {
                 log::info!("responder protocol na");
                 return Err(error::unsupported("multistream"));
             }
-            framed.send(protocol_id).await?;
-            log::info!(
-                "responder protocol send {:?}",
-                std::str::from_utf8(protocol_id)
-            );
             log::info!("multistream agreed");
 
             // initiate protocols
             for protocol in initial_protocols {
-                framed.send(&protocol).await?;
+                write_message(&mut stream, &protocol).await?;
                 log::info!(
                     "initiator protocol send {:?}",
                     std::str::from_utf8(&protocol)
                 );
-                let res = framed.next().await;
+                let res = read_message(&mut stream).await;
                 log::debug!("initiator protocol recv {:?}", res);
-                if let Some(Ok(msg)) = res {
-                    log::info!("initiator protocol recv {:?}", std::str::from_utf8(&msg));
-                    if msg != PROTOCOL_UNSUPPORTED.as_bytes().to_vec() {
-                        log::info!("{:?} agreed", std::str::from_utf8(&msg));
-                        return Ok((framed.into_inner(), msg));
-                    }
+                let msg = res?;
+                log::info!("initiator protocol recv {:?}", std::str::from_utf8(&msg));
+                if msg == protocol {
+                    log::info!("{:?} agreed", std::str::from_utf8(&msg));
+                    return Ok((stream, msg));
+                }
+                if msg != PROTOCOL_UNSUPPORTED.as_bytes() {
+                    return Err(error::message_malformed());
                 }
             }
             log::info!("initiator protocol na");
+            stream.close().await?;
+            Err(error::unsupported("upgrade protocol"))
+        }
+        .boxed()
+    }
+}
+
+impl<'a, T> UpgradeInbound<'a, T> for Multistream
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'a,
+{
+    type Output = (T, Vec<u8>);
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send + 'a>>;
+
+    fn upgrade_inbound(self, mut stream: T) -> Self::Future {
+        async move {
+            let protocol_id = Self::protocol_id().as_bytes();
+            let Self { initial_protocols } = self;
+
+            write_message(&mut stream, protocol_id).await?;
+            let msg = read_message(&mut stream).await?;
+            log::debug!("initiator protocol recv {:?}", std::str::from_utf8(&msg));
+            if msg != protocol_id {
+                return Err(error::unsupported("multistream"));
+            }
+
+            // accept the first supported protocol proposed by the initiator
+            for _ in 0..MAX_PROPOSALS {
+                let msg = read_message(&mut stream).await?;
+                log::debug!("initiator protocol recv {:?}", std::str::from_utf8(&msg));
+                if initial_protocols.contains(&msg) {
+                    write_message(&mut stream, &msg).await?;
+                    log::debug!("{:?} agreed", std::str::from_utf8(&msg));
+                    return Ok((stream, msg));
+                }
+                write_message(&mut stream, PROTOCOL_UNSUPPORTED.as_bytes()).await?;
+                log::debug!("responder protocol na");
+            }
+            log::debug!("too many protocols proposed");
             Err(error::unsupported("upgrade protocol"))
         }
         .boxed()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_io::Async;
+    use std::net::{TcpListener, TcpStream};
+
+    #[async_std::test]
+    async fn test_multistream_select() -> Result<(), Error> {
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let addr = listener.get_ref().local_addr()?;
+
+        let server = async {
+            let (stream, _) = listener.accept().await?;
+            Multistream::new(vec![b"/b".to_vec(), b"/c".to_vec()])
+                .upgrade_inbound(stream)
+                .await
+        };
+        let client = async {
+            let stream = Async::<TcpStream>::connect(addr).await?;
+            Multistream::new(vec![b"/a".to_vec(), b"/c".to_vec(), b"/b".to_vec()])
+                .upgrade_outbound(stream)
+                .await
+        };
+        let (server, client) = futures::join!(server, client);
+        assert_eq!(server?.1, b"/c".to_vec());
+        assert_eq!(client?.1, b"/c".to_vec());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_multistream_proposal_limit() -> Result<(), Error> {
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let addr = listener.get_ref().local_addr()?;
+
+        // the supported protocol comes after too many unsupported ones
+        let server = async {
+            let (stream, _) = listener.accept().await?;
+            Multistream::new(vec![b"/b".to_vec()])
+                .upgrade_inbound(stream)
+                .await
+        };
+        let client = async {
+            let stream = Async::<TcpStream>::connect(addr).await?;
+            let mut protocols: Vec<Vec<u8>> = (0..MAX_PROPOSALS)
+                .map(|n| format!("/a/{}", n).into_bytes())
+                .collect();
+            protocols.push(b"/b".to_vec());
+            Multistream::new(protocols).upgrade_outbound(stream).await
+        };
+        let (server, client) = futures::join!(server, client);
+        assert!(server.is_err());
+        assert!(client.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+use super::{
+    Mplex, MplexConnection, Multistream, ProtocolId, UpgradeInbound, UpgradeOutbound, Yamux,
+    YamuxConnection,
+};
+use crate::{
+    error::{self, Error},
+    io,
+};
+use futures::{AsyncRead, AsyncWrite, Future, FutureExt};
+use std::{boxed::Box, pin::Pin, sync::Arc};
+
+// MuxerProtocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MuxerProtocol {
+    Yamux,
+    Mplex,
+}
+
+impl MuxerProtocol {
+    // protocols enabled by default, in preference order
+    pub fn defaults() -> Vec<Self> {
+        vec![Self::Yamux, Self::Mplex]
+    }
+
+    pub fn protocol_id(&self) -> &'static str {
+        match self {
+            Self::Yamux => Yamux::protocol_id(),
+            Self::Mplex => Mplex::protocol_id(),
+        }
+    }
+
+    pub fn from_protocol_id(protocol_id: &[u8]) -> Option<Self> {
+        [Self::Yamux, Self::Mplex]
+            .into_iter()
+            .find(|protocol| protocol.protocol_id().as_bytes() == protocol_id)
+    }
+}
+
+// StreamMuxer
+//
+// Opens and accepts substreams of a connection whichever muxer is in use
+pub trait StreamMuxer: Send + Sync {
+    fn open_stream(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = Result<io::BoxedStream, Error>> + Send + '_>>;
+
+    fn accept_stream(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = Result<io::BoxedStream, Error>> + Send + '_>>;
+
+    fn close(&self);
+
+    fn is_closed(&self) -> bool;
+}
+
+impl StreamMuxer for YamuxConnection {
+    fn open_stream(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = Result<io::BoxedStream, Error>> + Send + '_>> {
+        async move { Ok(Box::new(YamuxConnection::open_stream(self).await?) as io::BoxedStream) }
+            .boxed()
+    }
+
+    fn accept_stream(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = Result<io::BoxedStream, Error>> + Send + '_>> {
+        async move { Ok(Box::new(YamuxConnection::accept_stream(self).await?) as io::BoxedStream) }
+            .boxed()
+    }
+
+    fn close(&self) {
+        YamuxConnection::close(self)
+    }
+
+    fn is_closed(&self) -> bool {
+        YamuxConnection::is_closed(self)
+    }
+}
+
+impl StreamMuxer for MplexConnection {
+    fn open_stream(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = Result<io::BoxedStream, Error>> + Send + '_>> {
+        async move { Ok(Box::new(MplexConnection::open_stream(self).await?) as io::BoxedStream) }
+            .boxed()
+    }
+
+    fn accept_stream(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = Result<io::BoxedStream, Error>> + Send + '_>> {
+        async move { Ok(Box::new(MplexConnection::accept_stream(self).await?) as io::BoxedStream) }
+            .boxed()
+    }
+
+    fn close(&self) {
+        MplexConnection::close(self)
+    }
+
+    fn is_closed(&self) -> bool {
+        MplexConnection::is_closed(self)
+    }
+}
+
+// MuxerUpgrader
+//
+// Negotiates one of the enabled muxers by multistream select over the
+// secured stream
+pub struct MuxerUpgrader {
+    protocols: Vec<MuxerProtocol>,
+    yamux: Yamux,
+    mplex: Mplex,
+}
+
+impl MuxerUpgrader {
+    pub fn new(protocols: Vec<MuxerProtocol>) -> Self {
+        Self {
+            protocols,
+            yamux: Yamux::new(),
+            mplex: Mplex::new(),
+        }
+    }
+
+    // settings of yamux when it is agreed, such as its stream limit
+    pub fn with_yamux(mut self, yamux: Yamux) -> Self {
+        self.yamux = yamux;
+        self
+    }
+
+    // settings of mplex when it is agreed, such as its buffer limit
+    pub fn with_mplex(mut self, mplex: Mplex) -> Self {
+        self.mplex = mplex;
+        self
+    }
+
+    fn multistream(&self) -> Multistream {
+        Multistream::new(
+            self.protocols
+                .iter()
+                .map(|protocol| protocol.protocol_id().as_bytes().to_vec())
+                .collect(),
+        )
+    }
+
+    fn agreed_protocol(agreed: &[u8]) -> Result<MuxerProtocol, Error> {
+        MuxerProtocol::from_protocol_id(agreed).ok_or(error::unsupported("muxer protocol"))
+    }
+}
+
+impl<'a, T> UpgradeOutbound<'a, T> for MuxerUpgrader
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Output = (Arc<dyn StreamMuxer>, MuxerProtocol);
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send + 'a>>;
+
+    fn upgrade_outbound(self, stream: T) -> Self::Future {
+        async move {
+            let (stream, agreed) = self.multistream().upgrade_outbound(stream).await?;
+            let protocol = Self::agreed_protocol(&agreed)?;
+            let muxer: Arc<dyn StreamMuxer> = match protocol {
+                MuxerProtocol::Yamux => Arc::new(self.yamux.upgrade_outbound(stream).await?),
+                MuxerProtocol::Mplex => Arc::new(self.mplex.upgrade_outbound(stream).await?),
+            };
+            Ok((muxer, protocol))
+        }
+        .boxed()
+    }
+}
+
+impl<'a, T> UpgradeInbound<'a, T> for MuxerUpgrader
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Output = (Arc<dyn StreamMuxer>, MuxerProtocol);
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send + 'a>>;
+
+    fn upgrade_inbound(self, stream: T) -> Self::Future {
+        async move {
+            let (stream, agreed) = self.multistream().upgrade_inbound(stream).await?;
+            let protocol = Self::agreed_protocol(&agreed)?;
+            let muxer: Arc<dyn StreamMuxer> = match protocol {
+                MuxerProtocol::Yamux => Arc::new(self.yamux.upgrade_inbound(stream).await?),
+                MuxerProtocol::Mplex => Arc::new(self.mplex.upgrade_inbound(stream).await?),
+            };
+            Ok((muxer, protocol))
+        }
+        .boxed()
+    }
+}
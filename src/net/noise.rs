@@ -1,4 +1,4 @@
-use super::upgrade::{ProtocolId, UpgradeOutbound};
+use super::upgrade::{ProtocolId, UpgradeInbound, UpgradeOutbound};
 use crate::{
     error::{self, Error},
     identity::{PeerId, PrivateKey, PublicKey},
@@ -10,6 +10,7 @@ use crate::{
 };
 use asynchronous_codec::{Framed, FramedParts};
 use futures::{AsyncRead, AsyncWrite, Future, FutureExt, SinkExt, StreamExt};
+use snow::HandshakeState;
 use std::{boxed::Box, pin::Pin};
 
 const PROTOCOL_NOISE: &str = "/noise";
@@ -29,6 +30,111 @@ impl Noise {
             static_key: PrivateKey::generate_ed25519(),
         }
     }
+
+    fn builder(&self) -> Result<snow::Builder<'_>, Error> {
+        Ok(snow::Builder::new(
+            NOISE_PROTOCOL_NAME
+                .parse()
+                .map_err(|_| error::other("noise protocol parse failed"))?,
+        )
+        .local_private_key(self.static_key.as_bytes()))
+    }
+
+    // payload proving the static key belongs to the identity key
+    fn my_payload(&self) -> Result<NoiseHandshakePayload, Error> {
+        let my_sig = self.private_key.sign(
+            &[
+                STATIC_KEY_PREFIX.as_bytes(),
+                &self.static_key.into_x25519_encoded(),
+            ]
+            .concat(),
+        );
+        log::debug!("my signature get");
+
+        Ok(NoiseHandshakePayload {
+            identity_key: Some(io::protobuf_encode(&PublicKeyPayload {
+                Type: KeyType::Ed25519,
+                Data: self.private_key.public().to_bytes(),
+            })?),
+            identity_sig: Some(my_sig),
+            extensions: None,
+        })
+    }
+
+    // verify the remote payload against the remote static key
+    fn verify_remote<T>(
+        framed: &Framed<T, io::NoiseCodec<HandshakeState>>,
+        remote_payload: NoiseHandshakePayload,
+    ) -> Result<PublicKey, Error>
+    where
+        T: AsyncRead + AsyncWrite,
+    {
+        let remote_pub = if let Some(rawtext) = remote_payload.identity_key {
+            // other key types are refused as unsupported
+            PublicKey::try_from(io::protobuf_decode::<PublicKeyPayload>(&rawtext)?)?
+        } else {
+            log::debug!("remote public key missing");
+            return Err(error::verification_failed());
+        };
+        log::debug!("remote public key get");
+
+        let remote_sig = if let Some(buf) = remote_payload.identity_sig {
+            // only ED25519 can reach here
+            buf
+        } else {
+            log::debug!("remote signature missing");
+            return Err(error::verification_failed());
+        };
+        log::debug!("remote signature get");
+
+        let remote_static = match framed.codec().state().get_remote_static() {
+            Some(key) => key,
+            _ => return Err(error::verification_failed()),
+        };
+        log::debug!("remote static key get");
+
+        remote_pub
+            .verify(
+                &[STATIC_KEY_PREFIX.as_bytes(), remote_static].concat(),
+                &remote_sig,
+            )
+            .map_err(|_| {
+                log::debug!("remote signature invalid");
+                error::verification_failed()
+            })?;
+        log::debug!("remote static key verified");
+
+        Ok(remote_pub)
+    }
+
+    fn into_output<T>(
+        self,
+        framed: Framed<T, io::NoiseCodec<HandshakeState>>,
+        remote_pub: PublicKey,
+    ) -> Result<(io::NoiseUpgradedStream<T>, PeerId), Error>
+    where
+        T: AsyncRead + AsyncWrite,
+    {
+        // keep the bytes read ahead of the handshake end
+        let FramedParts {
+            io,
+            codec,
+            read_buffer,
+            ..
+        } = framed.into_parts();
+        let noise_transport = codec.into_transport()?;
+        let upgraded =
+            io::NoiseUpgradedStream::new(io, noise_transport).with_read_buffer(read_buffer);
+
+        let peer_id: PeerId = remote_pub.try_into()?;
+        log::info!(
+            "handshake complete my peer_id: {:?} and remote peer_id: {:?}",
+            TryInto::<PeerId>::try_into(self.private_key.public())?,
+            peer_id
+        );
+
+        Ok((upgraded, peer_id))
+    }
 }
 
 impl ProtocolId for Noise {
@@ -43,22 +149,14 @@ where
 {
     type Output = (io::NoiseUpgradedStream<T>, PeerId);
     type Error = Error;
-    type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + 'a>>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send + 'a>>;
 
     fn upgrade_outbound(self, stream: T) -> Self::Future {
         async move {
-            let Self {
-                static_key,
-                private_key,
-            } = self;
-            let noise_state = snow::Builder::new(
-                NOISE_PROTOCOL_NAME
-                    .parse()
-                    .map_err(|_| error::other("noise protocol parse failed"))?,
-            )
-            .local_private_key(static_key.as_bytes())
-            .build_initiator()
-            .map_err(|_| error::other("noise builder failed"))?;
+            let noise_state = self
+                .builder()?
+                .build_initiator()
+                .map_err(|_| error::other("noise builder failed"))?;
 
             let mut framed = Framed::new(stream, io::NoiseCodec::new(noise_state));
 
@@ -75,82 +173,107 @@ where
                 }
             };
             log::debug!("remote payload recv");
+            let remote_pub = Self::verify_remote(&framed, remote_payload)?;
+            log::info!("noise handshake stage 2 complete");
+
+            // stage 3
+            framed.send(&self.my_payload()?).await?;
+            log::debug!("my signature send");
+            log::info!("noise handshake stage 3 complete");
+
+            self.into_output(framed, remote_pub)
+        }
+        .boxed()
+    }
+}
+
+impl<'a, T> UpgradeInbound<'a, T> for Noise
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'a,
+{
+    type Output = (io::NoiseUpgradedStream<T>, PeerId);
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send + 'a>>;
+
+    fn upgrade_inbound(self, stream: T) -> Self::Future {
+        async move {
+            let noise_state = self
+                .builder()?
+                .build_responder()
+                .map_err(|_| error::other("noise builder failed"))?;
 
-            let remote_pub = if let Some(rawtext) = remote_payload.identity_key {
-                let payload: PublicKeyPayload = io::protobuf_decode(&rawtext)?;
-                match payload.Type {
-                    KeyType::Ed25519 => PublicKey::from_ed25519_bytes(&payload.Data)?,
-                    _ => todo!(),
+            let mut framed = Framed::new(stream, io::NoiseCodec::new(noise_state));
+
+            // stage 1
+            match framed.next().await {
+                Some(Ok(_)) => {}
+                _ => {
+                    log::debug!("remote ephemeral key invalid");
+                    return Err(error::verification_failed());
                 }
-            } else {
-                log::debug!("remote public key missing");
-                return Err(error::verification_failed());
-            };
-            log::debug!("remote public key get");
-
-            let remote_sig = if let Some(buf) = remote_payload.identity_sig {
-                // only ED25519 can reach here
-                buf
-            } else {
-                log::debug!("remote signature missing");
-                return Err(error::verification_failed());
             };
-            log::debug!("remote signature get");
+            log::info!("noise handshake stage 1 complete");
 
-            let remote_static = match framed.codec().state().get_remote_static() {
-                Some(key) => key,
-                _ => return Err(error::verification_failed()),
-            };
-            log::debug!("remote static key get");
-
-            remote_pub
-                .verify(
-                    &[STATIC_KEY_PREFIX.as_bytes(), remote_static].concat(),
-                    &remote_sig,
-                )
-                .map_err(|_| {
-                    log::debug!("remote signature invalid");
-                    error::verification_failed()
-                })?;
-            log::debug!("remote static key verified");
+            // stage 2
+            framed.send(&self.my_payload()?).await?;
+            log::debug!("my signature send");
             log::info!("noise handshake stage 2 complete");
 
             // stage 3
-            let my_sig = private_key.sign(
-                &[
-                    STATIC_KEY_PREFIX.as_bytes(),
-                    &static_key.into_x25519_encoded(),
-                ]
-                .concat(),
-            );
-            log::debug!("my signature get");
-
-            let my_payload = NoiseHandshakePayload {
-                identity_key: Some(io::protobuf_encode(&PublicKeyPayload {
-                    Type: KeyType::Ed25519,
-                    Data: private_key.public().to_bytes(),
-                })?),
-                identity_sig: Some(my_sig),
-                extensions: None,
+            let remote_payload = match framed.next().await {
+                Some(Ok(payload)) => payload,
+                _ => {
+                    log::debug!("remote payload invalid");
+                    return Err(error::verification_failed());
+                }
             };
-            framed.send(&my_payload).await?;
-            log::debug!("my signature send");
+            log::debug!("remote payload recv");
+            let remote_pub = Self::verify_remote(&framed, remote_payload)?;
             log::info!("noise handshake stage 3 complete");
 
-            // prepare output
-            let FramedParts { io, codec, .. } = framed.into_parts();
-            let noise_transport = codec.into_transport()?;
-            let upgraded = io::NoiseUpgradedStream::new(io, noise_transport);
-
-            let peer_id: PeerId = remote_pub.try_into()?;
-            log::info!(
-                "handshake complete my peer_id: {:?} and remote peer_id: {:?}",
-                TryInto::<PeerId>::try_into(private_key.public()).unwrap(),
-                peer_id
-            );
-
-            Ok((upgraded, peer_id))
+            self.into_output(framed, remote_pub)
         }
         .boxed()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_io::Async;
+    use futures::{AsyncReadExt, AsyncWriteExt};
+    use std::net::{TcpListener, TcpStream};
+
+    #[async_std::test]
+    async fn test_noise_upgrade() -> Result<(), Error> {
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let addr = listener.get_ref().local_addr()?;
+        let server_key = PrivateKey::generate_ed25519();
+        let client_key = PrivateKey::generate_ed25519();
+
+        let server = async {
+            let (stream, _) = listener.accept().await?;
+            Noise::new(server_key.clone()).upgrade_inbound(stream).await
+        };
+        let client = async {
+            let stream = Async::<TcpStream>::connect(addr).await?;
+            let (mut stream, peer_id) = Noise::new(client_key.clone())
+                .upgrade_outbound(stream)
+                .await?;
+            // sent right after the handshake, before the responder reads
+            stream.write_all(b"ping").await?;
+            Ok::<_, Error>((stream, peer_id))
+        };
+        let (server, client) = futures::join!(server, client);
+        let (mut server_stream, client_peer_id) = server?;
+        let (_client_stream, server_peer_id) = client?;
+
+        assert_eq!(server_peer_id, server_key.public().try_into()?);
+        assert_eq!(client_peer_id, client_key.public().try_into()?);
+
+        let mut buf = [0u8; 4];
+        server_stream.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"ping");
+        Ok(())
+    }
+}
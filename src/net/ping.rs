@@ -0,0 +1,253 @@
+use super::{Connection, PeerMap, Protocol, ProtocolRegistry};
+use crate::{
+    error::{self, Error},
+    identity::PeerId,
+    io,
+};
+use async_io::Timer;
+use futures::{AsyncReadExt, AsyncWriteExt};
+use rand::RngCore;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+pub const PROTOCOL_PING: &str = "/ipfs/ping/1.0.0";
+const PING_SIZE: usize = 32;
+const MAX_RTT_SAMPLES: usize = 16;
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(15);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(20);
+const DEFAULT_MAX_FAILURES: usize = 3;
+
+// Ping
+//
+// Measures round-trip times of connections and keeps the latest samples of
+// each peer while connected
+#[derive(Clone)]
+pub struct Ping {
+    interval: Duration,
+    timeout: Duration,
+    max_failures: usize,
+    rtts: PeerMap<VecDeque<Duration>>,
+}
+
+impl Ping {
+    pub fn new() -> Self {
+        Self {
+            interval: DEFAULT_INTERVAL,
+            timeout: DEFAULT_TIMEOUT,
+            max_failures: DEFAULT_MAX_FAILURES,
+            rtts: PeerMap::new(),
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // consecutive failed pings before the connection is closed
+    pub fn with_max_failures(mut self, max_failures: usize) -> Self {
+        self.max_failures = max_failures;
+        self
+    }
+
+    // latest round-trip time samples of the peer, the oldest first
+    pub fn rtts(&self, peer_id: &PeerId) -> Vec<Duration> {
+        self.rtts
+            .get(peer_id)
+            .map(|samples| samples.into_iter().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn rtt(&self, peer_id: &PeerId) -> Option<Duration> {
+        self.rtts(peer_id).last().copied()
+    }
+
+    // ping once over a new stream
+    pub async fn ping(&self, connection: &Connection) -> Result<Duration, Error> {
+        let mut stream = connection.open_stream(PROTOCOL_PING).await?;
+        let rtt = self.ping_stream(connection, &mut stream).await?;
+        stream.close().await?;
+        Ok(rtt)
+    }
+
+    // ping at every interval until the connection is closed, or is closed
+    // after too many consecutive failures
+    pub async fn run(&self, connection: &Connection) -> Result<(), Error> {
+        let mut stream: Option<io::BoxedStream> = None;
+        let mut failures = 0;
+        while !connection.is_closed() {
+            let res = io::timeout(self.timeout, async {
+                let stream = match &mut stream {
+                    Some(stream) => stream,
+                    None => stream.insert(connection.open_stream(PROTOCOL_PING).await?),
+                };
+                self.ping_stream(connection, stream).await
+            })
+            .await;
+
+            match res {
+                Ok(rtt) => {
+                    log::info!("ping {:?} rtt {:?}", connection.info().peer_id, rtt);
+                    failures = 0;
+                }
+                Err(err) => {
+                    // the stream is opened again by the next ping
+                    stream = None;
+                    failures += 1;
+                    log::debug!("ping failed {}/{}, {:?}", failures, self.max_failures, err);
+                    if failures >= self.max_failures {
+                        log::info!("ping {:?} unresponsive", connection.info().peer_id);
+                        connection.close();
+                        return Err(error::timed_out());
+                    }
+                }
+            }
+            Timer::after(self.interval).await;
+        }
+        Ok(())
+    }
+
+    async fn ping_stream(
+        &self,
+        connection: &Connection,
+        stream: &mut io::BoxedStream,
+    ) -> Result<Duration, Error> {
+        let mut payload = [0u8; PING_SIZE];
+        rand::thread_rng().fill_bytes(&mut payload);
+
+        let start = Instant::now();
+        stream.write_all(&payload).await?;
+        stream.flush().await?;
+        let mut buf = [0u8; PING_SIZE];
+        stream.read_exact(&mut buf).await?;
+        let rtt = start.elapsed();
+        if buf != payload {
+            return Err(error::message_malformed());
+        }
+
+        self.rtts.update(connection, |samples| {
+            if samples.len() == MAX_RTT_SAMPLES {
+                samples.pop_front();
+            }
+            samples.push_back(rtt);
+        });
+        Ok(rtt)
+    }
+
+    // echo every payload until the remote closes the stream
+    async fn respond(mut stream: io::BoxedStream) -> Result<(), Error> {
+        let mut buf = [0u8; PING_SIZE];
+        loop {
+            match stream.read_exact(&mut buf).await {
+                Ok(()) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err),
+            }
+            stream.write_all(&buf).await?;
+            stream.flush().await?;
+        }
+        stream.close().await
+    }
+}
+
+impl Default for Ping {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Protocol for Ping {
+    fn register(self, registry: &mut ProtocolRegistry) {
+        registry.register(PROTOCOL_PING, |_connection, stream| Self::respond(stream));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{identity::PrivateKey, net::Manager};
+    use async_io::Async;
+    use std::net::TcpListener;
+
+    #[async_std::test]
+    async fn test_ping_rtt_and_failures() -> Result<(), Error> {
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let addr = listener.get_ref().local_addr()?;
+        let server = Manager::new(PrivateKey::generate_ed25519(), addr).with_protocol(Ping::new());
+        let client = Manager::new(PrivateKey::generate_ed25519(), addr);
+
+        let mut incoming = server.incoming(listener);
+        let (server_res, client_res) = futures::join!(incoming.accept(), client.connect(addr));
+        let (_server_conn, client_conn) = (server_res?, client_res?);
+
+        let ping = Ping::new();
+        let rtt = ping.ping(&client_conn).await?;
+        assert_eq!(ping.rtt(&server.peer_id()?), Some(rtt));
+
+        // the samples are dropped along with the connection
+        client_conn.close();
+        io::timeout(Duration::from_secs(5), async {
+            while ping.rtt(&server.peer_id()?).is_some() {
+                Timer::after(Duration::from_millis(10)).await;
+            }
+            Ok(())
+        })
+        .await?;
+
+        // the client does not answer pings, so the server gives up on it
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let addr = listener.get_ref().local_addr()?;
+        let mut incoming = client.incoming(listener);
+        let (server_res, client_res) = futures::join!(incoming.accept(), server.connect(addr));
+        let (_client_conn, server_conn) = (server_res?, client_res?);
+
+        let ping = Ping::new()
+            .with_interval(Duration::from_millis(10))
+            .with_max_failures(2);
+        assert!(ping.run(&server_conn).await.is_err());
+        assert!(server_conn.open_stream(PROTOCOL_PING).await.is_err());
+        assert!(ping.rtts(&client.peer_id()?).is_empty());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_ping_timeout_threshold() -> Result<(), Error> {
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let addr = listener.get_ref().local_addr()?;
+        // the remote reads the pings but never echoes them
+        let server = Manager::new(PrivateKey::generate_ed25519(), addr).with_protocol_handler(
+            PROTOCOL_PING,
+            |_connection, mut stream| async move {
+                let mut buf = [0u8; PING_SIZE];
+                loop {
+                    stream.read_exact(&mut buf).await?;
+                }
+            },
+        );
+        let client = Manager::new(PrivateKey::generate_ed25519(), addr);
+
+        let mut incoming = server.incoming(listener);
+        let (server_res, client_res) = futures::join!(incoming.accept(), client.connect(addr));
+        let (_server_conn, client_conn) = (server_res?, client_res?);
+
+        let ping = Ping::new()
+            .with_interval(Duration::from_millis(10))
+            .with_timeout(Duration::from_millis(100))
+            .with_max_failures(3);
+        let start = Instant::now();
+        let err = ping.run(&client_conn).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        // each of the pings waited for the whole timeout
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        assert!(client_conn.open_stream(PROTOCOL_PING).await.is_err());
+        assert!(ping.rtts(&server.peer_id()?).is_empty());
+        Ok(())
+    }
+}
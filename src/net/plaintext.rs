@@ -0,0 +1,143 @@
+use super::upgrade::{ProtocolId, UpgradeInbound, UpgradeOutbound};
+use crate::{
+    error::{self, Error},
+    identity::{PeerId, PrivateKey, PublicKey},
+    io,
+    payload::{
+        keys::{KeyType, PublicKey as PublicKeyPayload},
+        plaintext::Exchange,
+    },
+};
+use futures::{AsyncRead, AsyncWrite, Future, FutureExt};
+use std::{boxed::Box, pin::Pin};
+
+const PROTOCOL_PLAINTEXT: &str = "/plaintext/2.0.0";
+const MAX_EXCHANGE_SIZE: usize = 4096;
+
+// Plaintext
+//
+// Exchanges public keys without encrypting the stream, only for tests,
+// debugging and links that are already encrypted by other means
+pub struct Plaintext {
+    private_key: PrivateKey,
+}
+
+impl Plaintext {
+    pub fn new(private_key: PrivateKey) -> Self {
+        Self { private_key }
+    }
+
+    async fn exchange<T>(self, mut stream: T) -> Result<(T, PeerId), Error>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let public_key = self.private_key.public();
+        let my_peer_id: PeerId = public_key.clone().try_into()?;
+        let my_exchange = Exchange {
+            id: Some(my_peer_id.to_bytes()?),
+            pubkey: Some(PublicKeyPayload {
+                Type: KeyType::Ed25519,
+                Data: public_key.to_bytes(),
+            }),
+        };
+        io::write_uvarint_prefixed(&mut stream, &io::protobuf_encode(&my_exchange)?).await?;
+        log::debug!("plaintext exchange send");
+
+        let buf = io::read_uvarint_prefixed(&mut stream, MAX_EXCHANGE_SIZE).await?;
+        let remote_exchange: Exchange = io::protobuf_decode(&buf)?;
+        log::debug!("plaintext exchange recv");
+
+        let remote_pub = match remote_exchange.pubkey {
+            Some(payload) => PublicKey::try_from(payload)?,
+            None => {
+                log::debug!("remote public key missing");
+                return Err(error::verification_failed());
+            }
+        };
+        let peer_id: PeerId = remote_pub.try_into()?;
+        if remote_exchange.id != Some(peer_id.to_bytes()?) {
+            log::debug!("remote peer id mismatch");
+            return Err(error::verification_failed());
+        }
+        log::info!(
+            "handshake complete my peer_id: {:?} and remote peer_id: {:?}",
+            my_peer_id,
+            peer_id
+        );
+
+        Ok((stream, peer_id))
+    }
+}
+
+impl ProtocolId for Plaintext {
+    fn protocol_id() -> &'static str {
+        PROTOCOL_PLAINTEXT
+    }
+}
+
+impl<'a, T> UpgradeOutbound<'a, T> for Plaintext
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'a,
+{
+    type Output = (T, PeerId);
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send + 'a>>;
+
+    fn upgrade_outbound(self, stream: T) -> Self::Future {
+        self.exchange(stream).boxed()
+    }
+}
+
+impl<'a, T> UpgradeInbound<'a, T> for Plaintext
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'a,
+{
+    type Output = (T, PeerId);
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send + 'a>>;
+
+    fn upgrade_inbound(self, stream: T) -> Self::Future {
+        self.exchange(stream).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_io::Async;
+    use futures::{AsyncReadExt, AsyncWriteExt};
+    use std::net::{TcpListener, TcpStream};
+
+    #[async_std::test]
+    async fn test_plaintext_upgrade() -> Result<(), Error> {
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let addr = listener.get_ref().local_addr()?;
+        let server_key = PrivateKey::generate_ed25519();
+        let client_key = PrivateKey::generate_ed25519();
+
+        let server = async {
+            let (stream, _) = listener.accept().await?;
+            Plaintext::new(server_key.clone())
+                .upgrade_inbound(stream)
+                .await
+        };
+        let client = async {
+            let stream = Async::<TcpStream>::connect(addr).await?;
+            Plaintext::new(client_key.clone())
+                .upgrade_outbound(stream)
+                .await
+        };
+        let (server, client) = futures::join!(server, client);
+        let (mut server_stream, client_peer_id) = server?;
+        let (mut client_stream, server_peer_id) = client?;
+
+        assert_eq!(server_peer_id, server_key.public().try_into()?);
+        assert_eq!(client_peer_id, client_key.public().try_into()?);
+
+        client_stream.write_all(b"ping").await?;
+        let mut buf = [0u8; 4];
+        server_stream.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"ping");
+        Ok(())
+    }
+}
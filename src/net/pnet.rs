@@ -0,0 +1,218 @@
+use super::upgrade::{UpgradeInbound, UpgradeOutbound};
+use crate::{
+    error::{self, Error},
+    io,
+};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Future, FutureExt};
+use rand::RngCore;
+use std::{boxed::Box, path::Path, pin::Pin};
+
+const PSK_HEADER: &str = "/key/swarm/psk/1.0.0/";
+const PSK_LENGTH: usize = 32;
+const NONCE_LENGTH: usize = 24;
+
+// PreSharedKey of a private network
+#[derive(Clone, PartialEq)]
+pub struct PreSharedKey([u8; PSK_LENGTH]);
+
+impl PreSharedKey {
+    pub fn new(key: [u8; PSK_LENGTH]) -> Self {
+        Self(key)
+    }
+
+    // parse the `swarm.key` format used by kubo, a /bin/ key is the raw
+    // bytes after the header lines
+    pub fn from_swarm_key(content: &(impl AsRef<[u8]> + ?Sized)) -> Result<Self, Error> {
+        let (header, content) = split_line(content.as_ref());
+        if header != PSK_HEADER.as_bytes() {
+            log::debug!("swarm key header invalid");
+            return Err(error::parse_error());
+        }
+        let (encoding, content) = split_line(content);
+        let key = match encoding {
+            b"/base16/" | b"/base64/" => {
+                let encoded: String = std::str::from_utf8(content)
+                    .map_err(|_| error::parse_error())?
+                    .split_whitespace()
+                    .collect();
+                if encoding == b"/base16/" {
+                    multibase::Base::Base16Lower.decode(encoded.to_lowercase())
+                } else {
+                    multibase::Base::Base64Pad
+                        .decode(&encoded)
+                        .or_else(|_| multibase::Base::Base64.decode(&encoded))
+                }
+                .map_err(|_| error::parse_error())?
+            }
+            b"/bin/" => content
+                .get(..PSK_LENGTH)
+                .ok_or(error::parse_error())?
+                .to_vec(),
+            _ => {
+                log::debug!(
+                    "swarm key encoding {:?} unsupported",
+                    String::from_utf8_lossy(encoding)
+                );
+                return Err(error::unsupported("swarm key encoding"));
+            }
+        };
+        let key: [u8; PSK_LENGTH] = key.try_into().map_err(|_| error::parse_error())?;
+        Ok(Self(key))
+    }
+
+    pub fn from_swarm_key_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::from_swarm_key(&std::fs::read(path)?)
+    }
+
+    pub fn to_swarm_key(&self) -> String {
+        format!(
+            "{}\n/base16/\n{}\n",
+            PSK_HEADER,
+            multibase::Base::Base16Lower.encode(self.0)
+        )
+    }
+
+    pub fn as_bytes(&self) -> &[u8; PSK_LENGTH] {
+        &self.0
+    }
+}
+
+// the first line trimmed and the content after it
+fn split_line(content: &[u8]) -> (&[u8], &[u8]) {
+    match content.iter().position(|byte| *byte == b'\n') {
+        Some(index) => (content[..index].trim_ascii(), &content[index + 1..]),
+        None => (content.trim_ascii(), &[]),
+    }
+}
+
+impl std::fmt::Debug for PreSharedKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // never print the key itself
+        f.write_str("PreSharedKey(..)")
+    }
+}
+
+// Pnet
+//
+// Wraps the raw transport stream with XSalsa20 ahead of multistream select,
+// both directions behave the same
+pub struct Pnet {
+    psk: PreSharedKey,
+}
+
+impl Pnet {
+    pub fn new(psk: PreSharedKey) -> Self {
+        Self { psk }
+    }
+
+    async fn handshake<T>(self, mut stream: T) -> Result<io::PnetStream<T>, Error>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let mut local_nonce = [0u8; NONCE_LENGTH];
+        rand::rngs::OsRng.fill_bytes(&mut local_nonce);
+        stream.write_all(&local_nonce).await?;
+        stream.flush().await?;
+        log::debug!("pnet nonce send");
+
+        let mut remote_nonce = [0u8; NONCE_LENGTH];
+        stream.read_exact(&mut remote_nonce).await?;
+        log::debug!("pnet nonce recv");
+
+        Ok(io::PnetStream::new(
+            stream,
+            self.psk.as_bytes(),
+            &local_nonce,
+            &remote_nonce,
+        ))
+    }
+}
+
+impl<'a, T> UpgradeOutbound<'a, T> for Pnet
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'a,
+{
+    type Output = io::PnetStream<T>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send + 'a>>;
+
+    fn upgrade_outbound(self, stream: T) -> Self::Future {
+        self.handshake(stream).boxed()
+    }
+}
+
+impl<'a, T> UpgradeInbound<'a, T> for Pnet
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'a,
+{
+    type Output = io::PnetStream<T>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send + 'a>>;
+
+    fn upgrade_inbound(self, stream: T) -> Self::Future {
+        self.handshake(stream).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_io::Async;
+    use std::net::{TcpListener, TcpStream};
+
+    const TEST_SWARM_KEY: &str = "/key/swarm/psk/1.0.0/\n/base16/\n\
+        0123456789abcdef0123456789ABCDEF0123456789abcdef0123456789abcdef\n";
+
+    #[test]
+    fn test_pre_shared_key_from_swarm_key() -> Result<(), Error> {
+        let psk = PreSharedKey::from_swarm_key(TEST_SWARM_KEY)?;
+        assert_eq!(psk.as_bytes()[..4], [0x01, 0x23, 0x45, 0x67]);
+        assert_eq!(PreSharedKey::from_swarm_key(&psk.to_swarm_key())?, psk);
+
+        assert!(PreSharedKey::from_swarm_key("/key/swarm/psk/1.0.0/\n/base16/\n0123\n").is_err());
+        assert!(PreSharedKey::from_swarm_key("/base16/\n0123\n").is_err());
+
+        // binary keys are taken as they are, whitespace and newlines included
+        let key: [u8; PSK_LENGTH] = std::array::from_fn(|i| [b'\n', b' ', 0xff, i as u8][i % 4]);
+        let content = [b"/key/swarm/psk/1.0.0/\n/bin/\n".as_slice(), &key].concat();
+        assert_eq!(PreSharedKey::from_swarm_key(&content)?.as_bytes(), &key);
+        assert!(PreSharedKey::from_swarm_key(&content[..content.len() - 1]).is_err());
+        Ok(())
+    }
+
+    async fn exchange(
+        server_psk: PreSharedKey,
+        client_psk: PreSharedKey,
+    ) -> Result<[u8; 4], Error> {
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let addr = listener.get_ref().local_addr()?;
+
+        let server = async {
+            let (stream, _) = listener.accept().await?;
+            Pnet::new(server_psk).upgrade_inbound(stream).await
+        };
+        let client = async {
+            let stream = Async::<TcpStream>::connect(addr).await?;
+            Pnet::new(client_psk).upgrade_outbound(stream).await
+        };
+        let (server, client) = futures::join!(server, client);
+        let (mut server_stream, mut client_stream) = (server?, client?);
+
+        client_stream.write_all(b"ping").await?;
+        client_stream.flush().await?;
+        let mut buf = [0u8; 4];
+        server_stream.read_exact(&mut buf).await?;
+        Ok(buf)
+    }
+
+    #[async_std::test]
+    async fn test_pnet_upgrade() -> Result<(), Error> {
+        let psk = PreSharedKey::from_swarm_key(TEST_SWARM_KEY)?;
+        assert_eq!(&exchange(psk.clone(), psk).await?, b"ping");
+
+        let other = PreSharedKey::new([7u8; PSK_LENGTH]);
+        let psk = PreSharedKey::from_swarm_key(TEST_SWARM_KEY)?;
+        assert_ne!(&exchange(psk, other).await?, b"ping");
+        Ok(())
+    }
+}
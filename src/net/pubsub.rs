@@ -0,0 +1,255 @@
+mod floodsub;
+mod gossipsub;
+mod message;
+
+use super::{Connection, Protocol, ProtocolRegistry};
+use crate::{
+    error::Error,
+    identity::{PeerId, PrivateKey},
+};
+use futures::{channel::mpsc, Future};
+use std::{
+    pin::Pin,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub use floodsub::{Floodsub, PROTOCOL_FLOODSUB};
+pub use gossipsub::{
+    Gossipsub, GossipsubConfig, PeerScoreParams, PeerScoreThresholds, TopicScoreParams,
+    ValidationResult, PROTOCOL_GOSSIPSUB,
+};
+pub use message::{sign_message, verify_message, MessageId, PubSubMessage};
+
+// messages waiting to be read by a subscriber, more are dropped
+const MAX_QUEUED_MESSAGES: usize = 1024;
+
+// PubSub
+//
+// Topic subscriptions and publishing whichever router is in use, messages
+// are exchanged as the same signed rpcs
+pub trait PubSub: Send + Sync {
+    // open the stream to the peer over the connection
+    fn add_peer<'a>(
+        &'a self,
+        connection: &'a Connection,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>>;
+
+    // the messages of the topic, a subscriber falling behind misses messages
+    fn subscribe(&self, topic: &str) -> mpsc::Receiver<PubSubMessage>;
+
+    fn unsubscribe(&self, topic: &str);
+
+    fn publish(&self, topic: &str, data: Vec<u8>) -> Result<MessageId, Error>;
+
+    fn topics(&self) -> Vec<String>;
+
+    // peers subscribed to the topic
+    fn topic_peers(&self, topic: &str) -> Vec<PeerId>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PubSubProtocol {
+    Floodsub,
+    Gossipsub,
+}
+
+impl PubSubProtocol {
+    pub fn protocol_id(&self) -> &'static str {
+        match self {
+            Self::Floodsub => PROTOCOL_FLOODSUB,
+            Self::Gossipsub => PROTOCOL_GOSSIPSUB,
+        }
+    }
+}
+
+// PubSubRouter of the protocol chosen by configuration, with the default
+// settings of the router
+#[derive(Clone)]
+pub enum PubSubRouter {
+    Floodsub(Floodsub),
+    Gossipsub(Gossipsub),
+}
+
+impl PubSubRouter {
+    pub fn new(protocol: PubSubProtocol, private_key: PrivateKey) -> Result<Self, Error> {
+        Ok(match protocol {
+            PubSubProtocol::Floodsub => Self::Floodsub(Floodsub::new(private_key)?),
+            PubSubProtocol::Gossipsub => Self::Gossipsub(Gossipsub::new(private_key)?),
+        })
+    }
+
+    pub fn protocol(&self) -> PubSubProtocol {
+        match self {
+            Self::Floodsub(_) => PubSubProtocol::Floodsub,
+            Self::Gossipsub(_) => PubSubProtocol::Gossipsub,
+        }
+    }
+
+    // the router as a pubsub, such as to be shared behind an Arc
+    pub fn as_pubsub(&self) -> &dyn PubSub {
+        match self {
+            Self::Floodsub(floodsub) => floodsub,
+            Self::Gossipsub(gossipsub) => gossipsub,
+        }
+    }
+
+    // maintain the router, meant to be spawned along with the node; floodsub
+    // has nothing to maintain
+    pub async fn run(&self) {
+        match self {
+            Self::Floodsub(_) => futures::future::pending().await,
+            Self::Gossipsub(gossipsub) => gossipsub.run().await,
+        }
+    }
+}
+
+impl PubSub for PubSubRouter {
+    fn add_peer<'a>(
+        &'a self,
+        connection: &'a Connection,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+        self.as_pubsub().add_peer(connection)
+    }
+
+    fn subscribe(&self, topic: &str) -> mpsc::Receiver<PubSubMessage> {
+        self.as_pubsub().subscribe(topic)
+    }
+
+    fn unsubscribe(&self, topic: &str) {
+        self.as_pubsub().unsubscribe(topic)
+    }
+
+    fn publish(&self, topic: &str, data: Vec<u8>) -> Result<MessageId, Error> {
+        self.as_pubsub().publish(topic, data)
+    }
+
+    fn topics(&self) -> Vec<String> {
+        self.as_pubsub().topics()
+    }
+
+    fn topic_peers(&self, topic: &str) -> Vec<PeerId> {
+        self.as_pubsub().topic_peers(topic)
+    }
+}
+
+impl Protocol for PubSubRouter {
+    fn register(self, registry: &mut ProtocolRegistry) {
+        match self {
+            Self::Floodsub(floodsub) => floodsub.register(registry),
+            Self::Gossipsub(gossipsub) => gossipsub.register(registry),
+        }
+    }
+}
+
+pub(crate) fn subscriber() -> (mpsc::Sender<PubSubMessage>, mpsc::Receiver<PubSubMessage>) {
+    mpsc::channel(MAX_QUEUED_MESSAGES)
+}
+
+// hand the message to the subscribers, those dropped are removed and those
+// with a full queue miss it
+pub(crate) fn deliver(subscribers: &mut Vec<mpsc::Sender<PubSubMessage>>, message: &PubSubMessage) {
+    subscribers.retain_mut(|subscriber| match subscriber.try_send(message.clone()) {
+        Ok(()) => true,
+        Err(err) if err.is_full() => {
+            log::debug!(
+                "subscriber of {:?} behind, {:?} dropped",
+                message.topic,
+                message.id
+            );
+            true
+        }
+        Err(_) => false,
+    });
+}
+
+// sequence numbers start from the clock to stay unique across restarts
+pub(crate) fn initial_seqno() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_nanos() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{io, net::test_support::spawn_node};
+    use async_io::Timer;
+    use futures::StreamExt;
+    use std::time::Duration;
+
+    // the same application code over either router
+    async fn broadcast(protocol: PubSubProtocol) -> Result<(), Error> {
+        let router = |private_key| PubSubRouter::new(protocol, private_key);
+        let (manager_a, a, _) = spawn_node(router)?;
+        let (manager_b, b, addr_b) = spawn_node(router)?;
+        let (_, c, addr_c) = spawn_node(router)?;
+        for router in [&a, &b, &c] {
+            let running = router.clone();
+            async_std::task::spawn(async move { running.run().await });
+        }
+        assert_eq!(a.protocol(), protocol);
+        let _messages_b = b.subscribe("news");
+        let mut messages_c = c.subscribe("news");
+        a.add_peer(&manager_a.connect(addr_b).await?).await?;
+        b.add_peer(&manager_b.connect(addr_c).await?).await?;
+
+        // published until the mesh, if any, is formed
+        let message = io::timeout(Duration::from_secs(10), async {
+            loop {
+                if a.topic_peers("news").is_empty() {
+                    Timer::after(Duration::from_millis(10)).await;
+                    continue;
+                }
+                a.publish("news", b"hello".to_vec())?;
+                let next = messages_c.next();
+                let timer = Timer::after(Duration::from_millis(200));
+                if let futures::future::Either::Left((message, _)) =
+                    futures::future::select(next, timer).await
+                {
+                    return Ok(message);
+                }
+            }
+        })
+        .await?
+        .unwrap();
+        assert_eq!(message.data, b"hello");
+        assert_eq!(message.topic, "news");
+        assert_eq!(c.topics(), vec!["news".to_string()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pubsub_deliver_bounded() -> Result<(), Error> {
+        let peer_id: PeerId = PrivateKey::generate_ed25519().public().try_into()?;
+        let message = |seqno: u64| {
+            let message = crate::payload::pubsub::Message {
+                seqno: Some(seqno.to_be_bytes().to_vec()),
+                topic: "news".to_string(),
+                ..Default::default()
+            };
+            PubSubMessage::new(MessageId::from_message(&message), &message, peer_id.clone())
+        };
+        let (slow, mut slow_messages) = subscriber();
+        let (gone, _) = subscriber();
+        let mut subscribers = vec![slow, gone];
+        for seqno in 0..MAX_QUEUED_MESSAGES as u64 + 10 {
+            deliver(&mut subscribers, &message(seqno));
+        }
+        // the slow subscriber is kept and misses the messages beyond its queue
+        assert_eq!(subscribers.len(), 1);
+        let mut received = 0;
+        while let Ok(Some(_)) = slow_messages.try_next() {
+            received += 1;
+        }
+        assert!(received <= MAX_QUEUED_MESSAGES + 1);
+        deliver(&mut subscribers, &message(0));
+        assert!(slow_messages.try_next().is_ok());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_pubsub_router_switch() -> Result<(), Error> {
+        broadcast(PubSubProtocol::Floodsub).await?;
+        broadcast(PubSubProtocol::Gossipsub).await
+    }
+}
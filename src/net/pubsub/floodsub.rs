@@ -0,0 +1,506 @@
+use super::{
+    deliver, initial_seqno, sign_message, subscriber, verify_message, MessageId, PubSub,
+    PubSubMessage,
+};
+use crate::{
+    error::{self, Error},
+    identity::{PeerId, PrivateKey},
+    io::protobuf_encode,
+    net::{
+        serve_peer, spawn_writer, Connection, PeerProtocol, PeerSender, Protocol, ProtocolRegistry,
+    },
+    payload::pubsub::{mod_RPC::SubOpts, Message, RPC},
+};
+use futures::{channel::mpsc, future, AsyncWriteExt, Future, FutureExt};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+pub const PROTOCOL_FLOODSUB: &str = "/floodsub/1.0.0";
+const DEFAULT_SEEN_TTL: Duration = Duration::from_secs(2 * 60);
+const DEFAULT_MAX_TRANSMIT_SIZE: usize = 1024 * 1024;
+const DEFAULT_MAX_QUEUED_RPCS: usize = 128;
+
+struct PeerState {
+    sender: PeerSender<RPC>,
+    topics: HashSet<String>,
+}
+
+struct State {
+    peers: HashMap<PeerId, PeerState>,
+    subscriptions: HashMap<String, Vec<mpsc::Sender<PubSubMessage>>>,
+    seen: HashSet<MessageId>,
+    // the seen ids in the order they expire
+    seen_order: VecDeque<(Instant, MessageId)>,
+    seqno: u64,
+}
+
+impl State {
+    // false if seen already
+    fn insert_seen(&mut self, id: MessageId, seen_ttl: Duration) -> bool {
+        while self
+            .seen_order
+            .front()
+            .is_some_and(|(seen, _)| seen.elapsed() > seen_ttl)
+        {
+            if let Some((_, expired)) = self.seen_order.pop_front() {
+                self.seen.remove(&expired);
+            }
+        }
+        if !self.seen.insert(id.clone()) {
+            return false;
+        }
+        self.seen_order.push_back((Instant::now(), id));
+        true
+    }
+
+    // send the message to the peers of its topic but the excluded ones
+    fn forward(&mut self, message: &Message, exclude: &[&PeerId]) {
+        let rpc = RPC {
+            publish: vec![message.clone()],
+            ..Default::default()
+        };
+        for (peer_id, peer) in &mut self.peers {
+            if peer.topics.contains(&message.topic) && !exclude.contains(&peer_id) {
+                peer.sender.send(rpc.clone());
+            }
+        }
+    }
+}
+
+// Floodsub
+//
+// Every message is forwarded to all the peers subscribed to its topic, the
+// seen cache stops it from looping
+#[derive(Clone)]
+pub struct Floodsub {
+    private_key: PrivateKey,
+    local_peer_id: PeerId,
+    seen_ttl: Duration,
+    max_transmit_size: usize,
+    max_queued_rpcs: usize,
+    state: Arc<Mutex<State>>,
+}
+
+impl Floodsub {
+    pub fn new(private_key: PrivateKey) -> Result<Self, Error> {
+        Ok(Self {
+            local_peer_id: private_key.public().try_into()?,
+            private_key,
+            seen_ttl: DEFAULT_SEEN_TTL,
+            max_transmit_size: DEFAULT_MAX_TRANSMIT_SIZE,
+            max_queued_rpcs: DEFAULT_MAX_QUEUED_RPCS,
+            state: Arc::new(Mutex::new(State {
+                peers: HashMap::new(),
+                subscriptions: HashMap::new(),
+                seen: HashSet::new(),
+                seen_order: VecDeque::new(),
+                seqno: initial_seqno(),
+            })),
+        })
+    }
+
+    // copies of a message within the ttl are dropped
+    pub fn with_seen_ttl(mut self, seen_ttl: Duration) -> Self {
+        self.seen_ttl = seen_ttl;
+        self
+    }
+
+    pub fn with_max_transmit_size(mut self, max_transmit_size: usize) -> Self {
+        self.max_transmit_size = max_transmit_size;
+        self
+    }
+
+    // rpcs waiting to be written to a peer, more are dropped
+    pub fn with_max_queued_rpcs(mut self, max_queued_rpcs: usize) -> Self {
+        self.max_queued_rpcs = max_queued_rpcs;
+        self
+    }
+
+    // open the stream to the peer over the connection, inbound streams of
+    // peers not added yet add them
+    pub async fn add_peer(&self, connection: &Connection) -> Result<(), Error> {
+        let peer_id = connection.info().peer_id.clone();
+        if self.state.lock().unwrap().peers.contains_key(&peer_id) {
+            return Ok(());
+        }
+        let mut stream = connection.open_stream(PROTOCOL_FLOODSUB).await?;
+        let (sender, receiver) = PeerSender::channel(self.max_queued_rpcs);
+        let stream_id = sender.stream_id();
+        if !self.insert_peer(peer_id.clone(), sender) {
+            return stream.close().await;
+        }
+
+        spawn_writer(self.clone(), peer_id, stream_id, stream, receiver);
+        Ok(())
+    }
+
+    pub fn peers(&self) -> Vec<PeerId> {
+        self.state.lock().unwrap().peers.keys().cloned().collect()
+    }
+
+    pub fn topic_peers(&self, topic: &str) -> Vec<PeerId> {
+        self.state
+            .lock()
+            .unwrap()
+            .peers
+            .iter()
+            .filter(|(_, peer)| peer.topics.contains(topic))
+            .map(|(peer_id, _)| peer_id.clone())
+            .collect()
+    }
+
+    pub fn topics(&self) -> Vec<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .subscriptions
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    pub fn subscribe(&self, topic: &str) -> mpsc::Receiver<PubSubMessage> {
+        let (sender, receiver) = subscriber();
+        let mut state = self.state.lock().unwrap();
+        let subscribers = state.subscriptions.entry(topic.to_string()).or_default();
+        subscribers.push(sender);
+        if subscribers.len() == 1 {
+            announce(&mut state, topic, true);
+        }
+        receiver
+    }
+
+    // all the subscribers of the topic are dropped
+    pub fn unsubscribe(&self, topic: &str) {
+        let mut state = self.state.lock().unwrap();
+        if state.subscriptions.remove(topic).is_some() {
+            announce(&mut state, topic, false);
+        }
+    }
+
+    // sign and send the data to all the peers of the topic
+    pub fn publish(&self, topic: &str, data: Vec<u8>) -> Result<MessageId, Error> {
+        let mut state = self.state.lock().unwrap();
+        state.seqno += 1;
+        let mut message = Message {
+            from: Some(self.local_peer_id.to_bytes()?),
+            data: Some(data),
+            seqno: Some(state.seqno.to_be_bytes().to_vec()),
+            topic: topic.to_string(),
+            ..Default::default()
+        };
+        sign_message(&self.private_key, &mut message)?;
+        if protobuf_encode(&message)?.len() > self.max_transmit_size {
+            return Err(error::invalid_input("message too large"));
+        }
+        let id = MessageId::from_message(&message);
+        state.insert_seen(id.clone(), self.seen_ttl);
+        state.forward(&message, &[]);
+        Ok(id)
+    }
+
+    // false if the peer has a stream already, the local subscriptions are
+    // sent first
+    fn insert_peer(&self, peer_id: PeerId, mut sender: PeerSender<RPC>) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.peers.contains_key(&peer_id) {
+            return false;
+        }
+        let hello = RPC {
+            subscriptions: state
+                .subscriptions
+                .keys()
+                .map(|topic| SubOpts {
+                    subscribe: Some(true),
+                    topicid: Some(topic.clone()),
+                })
+                .collect(),
+            ..Default::default()
+        };
+        if !hello.subscriptions.is_empty() {
+            sender.send(hello);
+        }
+        state.peers.insert(
+            peer_id,
+            PeerState {
+                sender,
+                topics: HashSet::new(),
+            },
+        );
+        true
+    }
+
+    fn handle_rpc(&self, from: &PeerId, rpc: RPC) {
+        // the signatures are checked ahead of the lock
+        let messages: Vec<(Message, Result<(), Error>)> = rpc
+            .publish
+            .into_iter()
+            .map(|message| {
+                let verified = verify_message(&message);
+                (message, verified)
+            })
+            .collect();
+        let mut state = self.state.lock().unwrap();
+        let Some(peer) = state.peers.get_mut(from) else {
+            return;
+        };
+        for subscription in rpc.subscriptions {
+            let Some(topic) = subscription.topicid else {
+                continue;
+            };
+            if subscription.subscribe.unwrap_or(false) {
+                peer.topics.insert(topic);
+            } else {
+                peer.topics.remove(&topic);
+            }
+        }
+
+        for (message, verified) in messages {
+            let id = MessageId::from_message(&message);
+            if state.seen.contains(&id) {
+                continue;
+            }
+            // marked seen once valid only, so that a forged copy can not
+            // shadow it
+            if let Err(err) = verified {
+                log::debug!("floodsub {:?} of {:?} rejected, {:?}", id, from, err);
+                continue;
+            }
+            state.insert_seen(id.clone(), self.seen_ttl);
+            let message_received = PubSubMessage::new(id, &message, from.clone());
+            let source = message_received.source.clone();
+            let exclude: Vec<&PeerId> = [Some(from), source.as_ref()]
+                .into_iter()
+                .flatten()
+                .collect();
+            state.forward(&message, &exclude);
+            if let Some(subscribers) = state.subscriptions.get_mut(&message.topic) {
+                deliver(subscribers, &message_received);
+            }
+        }
+    }
+}
+
+impl Protocol for Floodsub {
+    fn register(self, registry: &mut ProtocolRegistry) {
+        registry.register(PROTOCOL_FLOODSUB, move |connection, stream| {
+            serve_peer(self.clone(), connection, stream)
+        });
+    }
+}
+
+impl PeerProtocol for Floodsub {
+    type Message = RPC;
+
+    fn name(&self) -> &'static str {
+        PROTOCOL_FLOODSUB
+    }
+
+    fn max_message_size(&self) -> usize {
+        self.max_transmit_size
+    }
+
+    fn add_peer<'a>(
+        &'a self,
+        connection: &'a Connection,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+        Floodsub::add_peer(self, connection).boxed()
+    }
+
+    fn stream_id(&self, peer_id: &PeerId) -> Option<u64> {
+        let state = self.state.lock().unwrap();
+        state.peers.get(peer_id).map(|peer| peer.sender.stream_id())
+    }
+
+    fn remove_peer(&self, peer_id: &PeerId, stream_id: u64) {
+        let mut state = self.state.lock().unwrap();
+        if state
+            .peers
+            .get(peer_id)
+            .is_some_and(|peer| peer.sender.stream_id() == stream_id)
+        {
+            state.peers.remove(peer_id);
+        }
+    }
+
+    fn handle_message<'a>(
+        &'a self,
+        from: &'a PeerId,
+        rpc: RPC,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+        self.handle_rpc(from, rpc);
+        future::ready(()).boxed()
+    }
+}
+
+impl PubSub for Floodsub {
+    fn add_peer<'a>(
+        &'a self,
+        connection: &'a Connection,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+        Floodsub::add_peer(self, connection).boxed()
+    }
+
+    fn subscribe(&self, topic: &str) -> mpsc::Receiver<PubSubMessage> {
+        Floodsub::subscribe(self, topic)
+    }
+
+    fn unsubscribe(&self, topic: &str) {
+        Floodsub::unsubscribe(self, topic)
+    }
+
+    fn publish(&self, topic: &str, data: Vec<u8>) -> Result<MessageId, Error> {
+        Floodsub::publish(self, topic, data)
+    }
+
+    fn topics(&self) -> Vec<String> {
+        Floodsub::topics(self)
+    }
+
+    fn topic_peers(&self, topic: &str) -> Vec<PeerId> {
+        Floodsub::topic_peers(self, topic)
+    }
+}
+
+fn announce(state: &mut State, topic: &str, subscribe: bool) {
+    let rpc = RPC {
+        subscriptions: vec![SubOpts {
+            subscribe: Some(subscribe),
+            topicid: Some(topic.to_string()),
+        }],
+        ..Default::default()
+    };
+    for peer in state.peers.values_mut() {
+        peer.sender.send(rpc.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        io::{self, write_protobuf_prefixed},
+        net::test_support::spawn_node,
+    };
+    use async_io::{Async, Timer};
+    use futures::StreamExt;
+    use std::net::{TcpListener, TcpStream};
+
+    #[async_std::test]
+    async fn test_floodsub_dedup() -> Result<(), Error> {
+        let (manager_a, a, _) = spawn_node(Floodsub::new)?;
+        let (manager_b, b, addr_b) = spawn_node(Floodsub::new)?;
+        let (_, c, addr_c) = spawn_node(Floodsub::new)?;
+        let _messages_b = b.subscribe("news");
+        let mut messages_c = c.subscribe("news");
+
+        // a triangle, c receives from a and through b
+        a.add_peer(&manager_a.connect(addr_b).await?).await?;
+        a.add_peer(&manager_a.connect(addr_c).await?).await?;
+        b.add_peer(&manager_b.connect(addr_c).await?).await?;
+        io::timeout(Duration::from_secs(5), async {
+            while a.topic_peers("news").len() < 2 || b.topic_peers("news").is_empty() {
+                Timer::after(Duration::from_millis(10)).await;
+            }
+            Ok(())
+        })
+        .await?;
+
+        let id = a.publish("news", b"hello".to_vec())?;
+        let message = io::timeout(Duration::from_secs(5), async {
+            Ok(messages_c.next().await)
+        })
+        .await?
+        .unwrap();
+        assert_eq!(message.id, id);
+        assert_eq!(message.source, Some(a.local_peer_id.clone()));
+        Timer::after(Duration::from_millis(200)).await;
+        assert!(messages_c.try_next().is_err());
+
+        // not sent to peers which left the topic
+        c.unsubscribe("news");
+        io::timeout(Duration::from_secs(5), async {
+            while a.topic_peers("news").len() > 1 {
+                Timer::after(Duration::from_millis(10)).await;
+            }
+            Ok(())
+        })
+        .await?;
+        assert_eq!(a.topic_peers("news"), vec![b.local_peer_id.clone()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_floodsub_crafted_source() -> Result<(), Error> {
+        let floodsub = Floodsub::new(PrivateKey::generate_ed25519())?;
+        let mut messages = floodsub.subscribe("news");
+        let peer_id: PeerId = PrivateKey::generate_ed25519().public().try_into()?;
+        let (sender, _receiver) = PeerSender::channel(DEFAULT_MAX_QUEUED_RPCS);
+        assert!(floodsub.insert_peer(peer_id.clone(), sender));
+
+        // the inlined key of the source is not a valid ed25519 key
+        let message = Message {
+            from: Some(vec![0x00, 0x04, 0x08, 0x01, 0x12, 0x00]),
+            data: Some(b"hello".to_vec()),
+            seqno: Some(1u64.to_be_bytes().to_vec()),
+            topic: "news".to_string(),
+            signature: Some(vec![0; 64]),
+            ..Default::default()
+        };
+        let rpc = RPC {
+            publish: vec![message],
+            ..Default::default()
+        };
+        floodsub.handle_rpc(&peer_id, rpc);
+        assert!(messages.try_next().is_err());
+        assert_eq!(floodsub.peers(), vec![peer_id]);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_floodsub_old_stream_keeps_peer() -> Result<(), Error> {
+        let (_, a, addr_a) = spawn_node(Floodsub::new)?;
+        let (manager_b, b, _) = spawn_node(Floodsub::new)?;
+        let connection = manager_b.connect(addr_a).await?;
+
+        // an inbound stream of a served by b
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let addr = listener.get_ref().local_addr()?;
+        let (accepted, mut inbound) =
+            futures::join!(listener.accept(), Async::<TcpStream>::connect(addr));
+        let (accepted, _) = accepted?;
+        let serving = async_std::task::spawn(serve_peer(
+            b.clone(),
+            connection.clone(),
+            Box::new(accepted),
+        ));
+        let rpc = RPC {
+            subscriptions: vec![SubOpts {
+                subscribe: Some(true),
+                topicid: Some("news".to_string()),
+            }],
+            ..Default::default()
+        };
+        write_protobuf_prefixed(inbound.as_mut().unwrap(), &rpc).await?;
+        io::timeout(Duration::from_secs(5), async {
+            while b.topic_peers("news").is_empty() {
+                Timer::after(Duration::from_millis(10)).await;
+            }
+            Ok(())
+        })
+        .await?;
+
+        // the sender is replaced, the old inbound stream ending keeps it
+        let stream_id = PeerProtocol::stream_id(&b, &a.local_peer_id).unwrap();
+        PeerProtocol::remove_peer(&b, &a.local_peer_id, stream_id);
+        b.add_peer(&connection).await?;
+        drop(inbound);
+        serving.await?;
+        assert_eq!(b.peers(), vec![a.local_peer_id.clone()]);
+        Ok(())
+    }
+}
@@ -0,0 +1,1520 @@
+mod mcache;
+mod score;
+
+use super::{
+    deliver, initial_seqno, sign_message, subscriber, verify_message, MessageId, PubSub,
+    PubSubMessage,
+};
+use crate::{
+    error::{self, Error},
+    identity::{PeerId, PrivateKey},
+    io::protobuf_encode,
+    net::{
+        serve_peer, spawn_writer, Connection, PeerProtocol, PeerSender, Protocol, ProtocolRegistry,
+    },
+    payload::pubsub::{
+        mod_RPC::SubOpts, ControlGraft, ControlIHave, ControlIWant, ControlMessage, ControlPrune,
+        Message, RPC,
+    },
+};
+use async_io::Timer;
+use futures::{channel::mpsc, AsyncWriteExt, Future, FutureExt};
+use mcache::MessageCache;
+use quick_protobuf::{sizeofs::sizeof_len, MessageWrite};
+use rand::seq::SliceRandom;
+use score::PeerScore;
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+pub use score::{PeerScoreParams, PeerScoreThresholds, TopicScoreParams};
+
+pub const PROTOCOL_GOSSIPSUB: &str = "/meshsub/1.1.0";
+// backoffs, such as those asked for by the remote, are cut down to it
+const MAX_PRUNE_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+// ValidationResult of a message by the application validator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationResult {
+    // delivered and forwarded
+    Accept,
+    // dropped, and counted against the peer which forwarded it
+    Reject,
+    // dropped only, such as a message too old to be of use
+    Ignore,
+}
+
+type Validator = Arc<
+    dyn Fn(PubSubMessage) -> Pin<Box<dyn Future<Output = ValidationResult> + Send>> + Send + Sync,
+>;
+
+// GossipsubConfig of the mesh and of the gossip, the defaults are the ones
+// of the spec
+#[derive(Debug, Clone)]
+pub struct GossipsubConfig {
+    // target degree of the mesh, kept between the low and high watermarks
+    pub d: usize,
+    pub d_lo: usize,
+    pub d_hi: usize,
+    // least peers to gossip to at each heartbeat, more with the gossip factor
+    pub d_lazy: usize,
+    pub gossip_factor: f64,
+    pub heartbeat_interval: Duration,
+    // fanout of topics published to without a subscription
+    pub fanout_ttl: Duration,
+    // heartbeats messages are cached for, and advertised for
+    pub history_length: usize,
+    pub history_gossip: usize,
+    pub seen_ttl: Duration,
+    pub prune_backoff: Duration,
+    // publish to all the peers of the topic rather than to the mesh
+    pub flood_publish: bool,
+    // message ids asked for by IWANT of a peer per heartbeat, and IHAVEs of
+    // a peer heeded per heartbeat
+    pub max_ihave_length: usize,
+    pub max_ihave_messages: usize,
+    // times a peer is sent a message it asks for
+    pub gossip_retransmission: usize,
+    // time for a message asked for by IWANT to arrive, the peer which
+    // advertised it is penalized after
+    pub iwant_followup_time: Duration,
+    pub max_transmit_size: usize,
+    // rpcs waiting to be written to a peer, more are dropped
+    pub max_queued_rpcs: usize,
+}
+
+impl Default for GossipsubConfig {
+    fn default() -> Self {
+        Self {
+            d: 6,
+            d_lo: 4,
+            d_hi: 12,
+            d_lazy: 6,
+            gossip_factor: 0.25,
+            heartbeat_interval: Duration::from_secs(1),
+            fanout_ttl: Duration::from_secs(60),
+            history_length: 5,
+            history_gossip: 3,
+            seen_ttl: Duration::from_secs(2 * 60),
+            prune_backoff: Duration::from_secs(60),
+            flood_publish: true,
+            max_ihave_length: 5000,
+            max_ihave_messages: 10,
+            gossip_retransmission: 3,
+            iwant_followup_time: Duration::from_secs(3),
+            max_transmit_size: 1024 * 1024,
+            max_queued_rpcs: 128,
+        }
+    }
+}
+
+struct PeerState {
+    sender: PeerSender<RPC>,
+    topics: HashSet<String>,
+    // IHAVEs heeded and message ids asked for since the heartbeat
+    ihaves: usize,
+    asked: usize,
+}
+
+struct State {
+    peers: HashMap<PeerId, PeerState>,
+    subscriptions: HashMap<String, Vec<mpsc::Sender<PubSubMessage>>>,
+    mesh: HashMap<String, HashSet<PeerId>>,
+    fanout: HashMap<String, HashSet<PeerId>>,
+    fanout_published: HashMap<String, Instant>,
+    // peers not to graft until the instant
+    backoff: HashMap<(String, PeerId), Instant>,
+    seen: HashMap<MessageId, Instant>,
+    // messages asked for by IWANT, with the peers which promised them and
+    // until when
+    promises: HashMap<MessageId, HashMap<PeerId, Instant>>,
+    mcache: MessageCache,
+    // None while scoring is disabled, every peer scores zero
+    score: Option<PeerScore>,
+    seqno: u64,
+    max_transmit_size: usize,
+}
+
+impl State {
+    fn new(config: &GossipsubConfig) -> Self {
+        Self {
+            peers: HashMap::new(),
+            subscriptions: HashMap::new(),
+            mesh: HashMap::new(),
+            fanout: HashMap::new(),
+            fanout_published: HashMap::new(),
+            backoff: HashMap::new(),
+            seen: HashMap::new(),
+            promises: HashMap::new(),
+            mcache: MessageCache::new(config.history_length, config.history_gossip),
+            score: None,
+            seqno: initial_seqno(),
+            max_transmit_size: config.max_transmit_size,
+        }
+    }
+
+    fn peer_score(&self, peer_id: &PeerId) -> f64 {
+        self.score
+            .as_ref()
+            .map_or(0.0, |score| score.score(peer_id))
+    }
+
+    fn topic_peers(&self, topic: &str) -> Vec<PeerId> {
+        self.peers
+            .iter()
+            .filter(|(_, peer)| peer.topics.contains(topic))
+            .map(|(peer_id, _)| peer_id.clone())
+            .collect()
+    }
+
+    // random peers of the topic scoring at least the min score, except the
+    // excluded and the backed off ones
+    fn candidates(
+        &self,
+        topic: &str,
+        exclude: &HashSet<PeerId>,
+        n: usize,
+        min_score: f64,
+    ) -> Vec<PeerId> {
+        let mut candidates: Vec<PeerId> = self
+            .topic_peers(topic)
+            .into_iter()
+            .filter(|peer_id| {
+                !exclude.contains(peer_id)
+                    && !self
+                        .backoff
+                        .contains_key(&(topic.to_string(), peer_id.clone()))
+                    && self.peer_score(peer_id) >= min_score
+            })
+            .collect();
+        candidates.shuffle(&mut rand::thread_rng());
+        candidates.truncate(n);
+        candidates
+    }
+
+    fn join_mesh(&mut self, topic: &str, peer_id: &PeerId) {
+        self.mesh
+            .entry(topic.to_string())
+            .or_default()
+            .insert(peer_id.clone());
+        if let Some(score) = &mut self.score {
+            score.graft(peer_id, topic);
+        }
+    }
+
+    fn leave_mesh(&mut self, topic: &str, peer_id: &PeerId) {
+        let removed = self
+            .mesh
+            .get_mut(topic)
+            .is_some_and(|mesh| mesh.remove(peer_id));
+        if let (true, Some(score)) = (removed, &mut self.score) {
+            score.prune(peer_id, topic);
+        }
+    }
+
+    // remove the peer from the mesh and tell it not to graft within the backoff
+    fn prune(&mut self, outbox: &mut Outbox, peer_id: &PeerId, topic: &str, backoff: Duration) {
+        self.leave_mesh(topic, peer_id);
+        let backoff = backoff.min(MAX_PRUNE_BACKOFF);
+        self.set_backoff(topic.to_string(), peer_id.clone(), backoff);
+        outbox.control(peer_id).prune.push(ControlPrune {
+            topicID: Some(topic.to_string()),
+            peers: Vec::new(),
+            backoff: Some(backoff.as_secs()),
+        });
+    }
+
+    fn set_backoff(&mut self, topic: String, peer_id: PeerId, backoff: Duration) {
+        if let Some(until) = Instant::now().checked_add(backoff) {
+            self.backoff.insert((topic, peer_id), until);
+        }
+    }
+
+    fn deliver(&mut self, message: PubSubMessage) {
+        if let Some(subscribers) = self.subscriptions.get_mut(&message.topic) {
+            deliver(subscribers, &message);
+        }
+    }
+
+    fn send(&mut self, outbox: Outbox) {
+        for (peer_id, rpc) in outbox.0 {
+            if let Some(peer) = self.peers.get_mut(&peer_id) {
+                for rpc in split_rpc(rpc, self.max_transmit_size) {
+                    peer.sender.send(rpc);
+                }
+            }
+        }
+    }
+}
+
+// Outbox merging what is sent to each peer into a single rpc
+#[derive(Default)]
+struct Outbox(HashMap<PeerId, RPC>);
+
+impl Outbox {
+    fn rpc(&mut self, peer_id: &PeerId) -> &mut RPC {
+        self.0.entry(peer_id.clone()).or_default()
+    }
+
+    fn publish(&mut self, peer_id: &PeerId, message: Message) {
+        self.rpc(peer_id).publish.push(message);
+    }
+
+    fn control(&mut self, peer_id: &PeerId) -> &mut ControlMessage {
+        self.rpc(peer_id)
+            .control
+            .get_or_insert_with(Default::default)
+    }
+}
+
+// Gossipsub
+//
+// Messages are pushed along a mesh of d peers per topic, maintained by
+// GRAFT and PRUNE at every heartbeat, and the ids of recent ones are
+// gossiped to other peers of the topic which pull the missed ones by IWANT
+#[derive(Clone)]
+pub struct Gossipsub {
+    private_key: PrivateKey,
+    local_peer_id: PeerId,
+    config: GossipsubConfig,
+    thresholds: PeerScoreThresholds,
+    validator: Option<Validator>,
+    state: Arc<Mutex<State>>,
+}
+
+impl Gossipsub {
+    pub fn new(private_key: PrivateKey) -> Result<Self, Error> {
+        let config = GossipsubConfig::default();
+        Ok(Self {
+            local_peer_id: private_key.public().try_into()?,
+            private_key,
+            thresholds: PeerScoreThresholds::default(),
+            validator: None,
+            state: Arc::new(Mutex::new(State::new(&config))),
+            config,
+        })
+    }
+
+    pub fn with_config(mut self, config: GossipsubConfig) -> Self {
+        let mut state = State::new(&config);
+        state.score = self.state.lock().unwrap().score.take();
+        self.state = Arc::new(Mutex::new(state));
+        self.config = config;
+        self
+    }
+
+    // score peers by their behaviour, peers scoring below zero are kept out
+    // of the meshes and the lower ones below the thresholds
+    pub fn with_peer_score(self, params: PeerScoreParams, thresholds: PeerScoreThresholds) -> Self {
+        self.state.lock().unwrap().score = Some(PeerScore::new(params));
+        Self { thresholds, ..self }
+    }
+
+    // validate each message before it is delivered and forwarded, rejected
+    // ones count against the peer which forwarded them
+    pub fn with_validator<F, Fut>(mut self, validator: F) -> Self
+    where
+        F: Fn(PubSubMessage) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ValidationResult> + Send + 'static,
+    {
+        self.validator = Some(Arc::new(move |message| validator(message).boxed()));
+        self
+    }
+
+    // open the stream to the peer over the connection, inbound streams of
+    // peers not added yet add them
+    pub async fn add_peer(&self, connection: &Connection) -> Result<(), Error> {
+        let peer_id = connection.info().peer_id.clone();
+        if self.state.lock().unwrap().peers.contains_key(&peer_id) {
+            return Ok(());
+        }
+        let mut stream = connection.open_stream(PROTOCOL_GOSSIPSUB).await?;
+        let (sender, receiver) = PeerSender::channel(self.config.max_queued_rpcs);
+        let stream_id = sender.stream_id();
+        // peers behind a relay share its address, not colocated
+        let ip = connection.info().observed_addr().map(|addr| addr.ip());
+        if !self.insert_peer(peer_id.clone(), ip, sender) {
+            return stream.close().await;
+        }
+
+        spawn_writer(self.clone(), peer_id, stream_id, stream, receiver);
+        Ok(())
+    }
+
+    pub fn peers(&self) -> Vec<PeerId> {
+        self.state.lock().unwrap().peers.keys().cloned().collect()
+    }
+
+    // peers subscribed to the topic
+    pub fn topic_peers(&self, topic: &str) -> Vec<PeerId> {
+        self.state.lock().unwrap().topic_peers(topic)
+    }
+
+    pub fn mesh_peers(&self, topic: &str) -> Vec<PeerId> {
+        self.state
+            .lock()
+            .unwrap()
+            .mesh
+            .get(topic)
+            .map_or(Vec::new(), |mesh| mesh.iter().cloned().collect())
+    }
+
+    pub fn topics(&self) -> Vec<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .subscriptions
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    // zero while scoring is disabled
+    pub fn peer_score(&self, peer_id: &PeerId) -> f64 {
+        self.state.lock().unwrap().peer_score(peer_id)
+    }
+
+    // the P5 score of a known peer, such as from its behaviour in the
+    // application
+    pub fn set_application_score(&self, peer_id: &PeerId, score: f64) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .score
+            .as_mut()
+            .is_some_and(|peer_score| peer_score.set_application_score(peer_id, score))
+    }
+
+    // messages of the topic, the mesh of the topic is joined by the first
+    // subscription
+    pub fn subscribe(&self, topic: &str) -> mpsc::Receiver<PubSubMessage> {
+        let (sender, receiver) = subscriber();
+        let mut state = self.state.lock().unwrap();
+        let subscribers = state.subscriptions.entry(topic.to_string()).or_default();
+        subscribers.push(sender);
+        if subscribers.len() > 1 || state.mesh.contains_key(topic) {
+            return receiver;
+        }
+
+        let mut outbox = Outbox::default();
+        announce(&state, &mut outbox, topic, true);
+        // the fanout peers are kept in the mesh
+        let mut peers = state.fanout.remove(topic).unwrap_or_default();
+        state.fanout_published.remove(topic);
+        peers.retain(|peer_id| state.peer_score(peer_id) >= 0.0);
+        let more = state.candidates(
+            topic,
+            &peers,
+            self.config.d.saturating_sub(peers.len()),
+            0.0,
+        );
+        peers.extend(more);
+        state.mesh.insert(topic.to_string(), HashSet::new());
+        for peer_id in &peers {
+            state.join_mesh(topic, peer_id);
+            outbox.control(peer_id).graft.push(ControlGraft {
+                topicID: Some(topic.to_string()),
+            });
+        }
+        state.send(outbox);
+        receiver
+    }
+
+    // leave the mesh of the topic, all of its subscribers are dropped
+    pub fn unsubscribe(&self, topic: &str) {
+        let mut state = self.state.lock().unwrap();
+        if state.subscriptions.remove(topic).is_none() {
+            return;
+        }
+        let mut outbox = Outbox::default();
+        announce(&state, &mut outbox, topic, false);
+        for peer_id in state.mesh.get(topic).cloned().unwrap_or_default() {
+            state.prune(&mut outbox, &peer_id, topic, self.config.prune_backoff);
+        }
+        state.mesh.remove(topic);
+        state.send(outbox);
+    }
+
+    // sign and publish the data, to the mesh or the fanout of the topic
+    pub fn publish(&self, topic: &str, data: Vec<u8>) -> Result<MessageId, Error> {
+        let mut state = self.state.lock().unwrap();
+        state.seqno += 1;
+        let mut message = Message {
+            from: Some(self.local_peer_id.to_bytes()?),
+            data: Some(data),
+            seqno: Some(state.seqno.to_be_bytes().to_vec()),
+            topic: topic.to_string(),
+            ..Default::default()
+        };
+        sign_message(&self.private_key, &mut message)?;
+        if protobuf_encode(&message)?.len() > self.config.max_transmit_size {
+            return Err(error::invalid_input("message too large"));
+        }
+        let id = MessageId::from_message(&message);
+        state.seen.insert(id.clone(), Instant::now());
+        state.mcache.put(id.clone(), message.clone());
+
+        let publish_threshold = self.thresholds.publish_threshold;
+        let peers: Vec<PeerId> = if self.config.flood_publish {
+            state
+                .topic_peers(topic)
+                .into_iter()
+                .filter(|peer_id| state.peer_score(peer_id) >= publish_threshold)
+                .collect()
+        } else if let Some(mesh) = state.mesh.get(topic) {
+            mesh.iter().cloned().collect()
+        } else {
+            let mut fanout = state.fanout.remove(topic).unwrap_or_default();
+            if fanout.is_empty() {
+                let more = state.candidates(topic, &fanout, self.config.d, publish_threshold);
+                fanout.extend(more);
+            }
+            state
+                .fanout_published
+                .insert(topic.to_string(), Instant::now());
+            state.fanout.insert(topic.to_string(), fanout.clone());
+            fanout.into_iter().collect()
+        };
+        log::debug!("gossipsub {:?} published to {} peers", id, peers.len());
+
+        let mut outbox = Outbox::default();
+        for peer_id in &peers {
+            outbox.publish(peer_id, message.clone());
+        }
+        state.send(outbox);
+        Ok(id)
+    }
+
+    // maintain the meshes and gossip, meant to be spawned along with the node
+    pub async fn run(&self) {
+        loop {
+            Timer::after(self.config.heartbeat_interval).await;
+            self.heartbeat();
+        }
+    }
+
+    pub fn heartbeat(&self) {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let mut outbox = Outbox::default();
+        let now = Instant::now();
+        state.backoff.retain(|_, until| *until > now);
+        for peer in state.peers.values_mut() {
+            peer.ihaves = 0;
+            peer.asked = 0;
+        }
+        if let Some(score) = &mut state.score {
+            score.refresh();
+        }
+        // a promise broken is a misbehaviour
+        let mut broken: HashMap<PeerId, usize> = HashMap::new();
+        state.promises.retain(|_, peers| {
+            peers.retain(|peer_id, until| {
+                if *until > now {
+                    return true;
+                }
+                *broken.entry(peer_id.clone()).or_default() += 1;
+                false
+            });
+            !peers.is_empty()
+        });
+        if let Some(score) = &mut state.score {
+            for (peer_id, count) in broken {
+                log::debug!("gossipsub {:?} broke {} iwant promises", peer_id, count);
+                score.add_penalty(&peer_id, count);
+            }
+        }
+
+        let topics: Vec<String> = state.mesh.keys().cloned().collect();
+        for topic in &topics {
+            let mut mesh = state.mesh.get(topic).cloned().unwrap_or_default();
+            for peer_id in mesh.clone() {
+                if state.peer_score(&peer_id) < 0.0 {
+                    mesh.remove(&peer_id);
+                    state.prune(&mut outbox, &peer_id, topic, self.config.prune_backoff);
+                }
+            }
+
+            if mesh.len() < self.config.d_lo {
+                let grafted =
+                    state.candidates(topic, &mesh, self.config.d.saturating_sub(mesh.len()), 0.0);
+                for peer_id in grafted {
+                    state.join_mesh(topic, &peer_id);
+                    outbox.control(&peer_id).graft.push(ControlGraft {
+                        topicID: Some(topic.clone()),
+                    });
+                }
+            } else if mesh.len() > self.config.d_hi {
+                // the best scoring peers are kept, in random order among ties
+                let mut peers: Vec<PeerId> = mesh.into_iter().collect();
+                peers.shuffle(&mut rand::thread_rng());
+                peers.sort_by(|a, b| state.peer_score(b).total_cmp(&state.peer_score(a)));
+                for peer_id in peers.split_off(self.config.d.min(peers.len())) {
+                    state.prune(&mut outbox, &peer_id, topic, self.config.prune_backoff);
+                }
+            }
+        }
+
+        let fanout_ttl = self.config.fanout_ttl;
+        state
+            .fanout_published
+            .retain(|_, published| published.elapsed() < fanout_ttl);
+        let fanout_published = &state.fanout_published;
+        state
+            .fanout
+            .retain(|topic, _| fanout_published.contains_key(topic));
+        let publish_threshold = self.thresholds.publish_threshold;
+        let topics: Vec<String> = state.fanout.keys().cloned().collect();
+        for topic in &topics {
+            let topic_peers: HashSet<PeerId> = state.topic_peers(topic).into_iter().collect();
+            let mut fanout = state.fanout.remove(topic).unwrap_or_default();
+            fanout.retain(|peer_id| {
+                topic_peers.contains(peer_id) && state.peer_score(peer_id) >= publish_threshold
+            });
+            if fanout.len() < self.config.d {
+                let more = state.candidates(
+                    topic,
+                    &fanout,
+                    self.config.d.saturating_sub(fanout.len()),
+                    publish_threshold,
+                );
+                fanout.extend(more);
+            }
+            state.fanout.insert(topic.clone(), fanout);
+        }
+
+        self.emit_gossip(state, &mut outbox);
+        state.mcache.shift();
+        let seen_ttl = self.config.seen_ttl;
+        state.seen.retain(|_, seen| seen.elapsed() < seen_ttl);
+        state.send(outbox);
+    }
+
+    // advertise the recent messages to peers of the topic out of the mesh
+    fn emit_gossip(&self, state: &State, outbox: &mut Outbox) {
+        let topics: HashSet<&String> = state.mesh.keys().chain(state.fanout.keys()).collect();
+        for topic in topics {
+            let mut ids = state.mcache.gossip_ids(topic);
+            if ids.is_empty() {
+                continue;
+            }
+            ids.truncate(self.config.max_ihave_length);
+            let mut exclude = state.mesh.get(topic).cloned().unwrap_or_default();
+            exclude.extend(state.fanout.get(topic).cloned().unwrap_or_default());
+            let mut peers: Vec<PeerId> = state
+                .topic_peers(topic)
+                .into_iter()
+                .filter(|peer_id| {
+                    !exclude.contains(peer_id)
+                        && state.peer_score(peer_id) >= self.thresholds.gossip_threshold
+                })
+                .collect();
+            let n = self
+                .config
+                .d_lazy
+                .max((self.config.gossip_factor * peers.len() as f64) as usize);
+            peers.shuffle(&mut rand::thread_rng());
+            for peer_id in peers.iter().take(n) {
+                outbox.control(peer_id).ihave.push(ControlIHave {
+                    topicID: Some(topic.clone()),
+                    messageIDs: ids.iter().map(|id| id.0.clone()).collect(),
+                });
+            }
+        }
+    }
+
+    // false if the peer has a stream already, the local subscriptions are
+    // sent first
+    fn insert_peer(
+        &self,
+        peer_id: PeerId,
+        ip: Option<IpAddr>,
+        mut sender: PeerSender<RPC>,
+    ) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.peers.contains_key(&peer_id) {
+            return false;
+        }
+        let hello = RPC {
+            subscriptions: state
+                .subscriptions
+                .keys()
+                .map(|topic| SubOpts {
+                    subscribe: Some(true),
+                    topicid: Some(topic.clone()),
+                })
+                .collect(),
+            ..Default::default()
+        };
+        if !hello.subscriptions.is_empty() {
+            sender.send(hello);
+        }
+        if let Some(score) = &mut state.score {
+            score.add_peer(&peer_id, ip);
+        }
+        state.peers.insert(
+            peer_id,
+            PeerState {
+                sender,
+                topics: HashSet::new(),
+                ihaves: 0,
+                asked: 0,
+            },
+        );
+        true
+    }
+
+    fn forget_peer(&self, state: &mut State, peer_id: &PeerId) {
+        state.peers.remove(peer_id);
+        for mesh in state.mesh.values_mut() {
+            mesh.remove(peer_id);
+        }
+        for fanout in state.fanout.values_mut() {
+            fanout.remove(peer_id);
+        }
+        if let Some(score) = &mut state.score {
+            score.remove_peer(peer_id);
+        }
+    }
+
+    async fn handle_rpc(&self, from: &PeerId, rpc: RPC) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if !state.peers.contains_key(from) {
+                return;
+            }
+            if state.peer_score(from) < self.thresholds.graylist_threshold {
+                log::debug!("gossipsub rpc of graylisted {:?} ignored", from);
+                return;
+            }
+            for subscription in rpc.subscriptions {
+                let Some(topic) = subscription.topicid else {
+                    continue;
+                };
+                let Some(peer) = state.peers.get_mut(from) else {
+                    continue;
+                };
+                if subscription.subscribe.unwrap_or(false) {
+                    peer.topics.insert(topic);
+                } else {
+                    peer.topics.remove(&topic);
+                    state.leave_mesh(&topic, from);
+                    if let Some(fanout) = state.fanout.get_mut(&topic) {
+                        fanout.remove(from);
+                    }
+                }
+            }
+        }
+
+        for message in rpc.publish {
+            self.handle_message(from, message).await;
+        }
+
+        if let Some(control) = rpc.control {
+            let mut state = self.state.lock().unwrap();
+            let mut outbox = Outbox::default();
+            self.handle_control(&mut state, &mut outbox, from, control);
+            state.send(outbox);
+        }
+    }
+
+    // validate a new message, then deliver it and forward it to the mesh
+    async fn handle_message(&self, from: &PeerId, message: Message) {
+        let id = MessageId::from_message(&message);
+        // checked ahead of the lock, the signature check is the costly part
+        let verified = verify_message(&message);
+        {
+            let mut state = self.state.lock().unwrap();
+            let state = &mut *state;
+            // neither delivered nor cached and forwarded
+            if !state.subscriptions.contains_key(&message.topic) {
+                log::debug!("gossipsub {:?} of unsubscribed {:?}", id, message.topic);
+                return;
+            }
+            if let Some(first_seen) = state.seen.get(&id) {
+                if let Some(score) = &mut state.score {
+                    score.duplicate_message(from, &message.topic, *first_seen);
+                }
+                return;
+            }
+            // marked seen once signed only, so that a forged copy can not
+            // shadow it
+            if let Err(err) = verified {
+                log::debug!("gossipsub {:?} of {:?} rejected, {:?}", id, from, err);
+                if let Some(score) = &mut state.score {
+                    score.reject_message(from, &message.topic);
+                }
+                return;
+            }
+            state.seen.insert(id.clone(), Instant::now());
+            state.promises.remove(&id);
+        }
+
+        let pubsub_message = PubSubMessage::new(id.clone(), &message, from.clone());
+        let result = match &self.validator {
+            Some(validator) => validator(pubsub_message.clone()).await,
+            None => ValidationResult::Accept,
+        };
+        let mut state = self.state.lock().unwrap();
+        match result {
+            ValidationResult::Accept => {}
+            ValidationResult::Reject => {
+                log::debug!("gossipsub {:?} of {:?} rejected by the validator", id, from);
+                if let Some(score) = &mut state.score {
+                    score.reject_message(from, &message.topic);
+                }
+                return;
+            }
+            ValidationResult::Ignore => return,
+        }
+
+        if let Some(score) = &mut state.score {
+            score.deliver_message(from, &message.topic);
+        }
+        state.mcache.put(id, message.clone());
+        let mut outbox = Outbox::default();
+        if let Some(mesh) = state.mesh.get(&message.topic) {
+            for peer_id in mesh {
+                if peer_id != from && Some(peer_id) != pubsub_message.source.as_ref() {
+                    outbox.publish(peer_id, message.clone());
+                }
+            }
+        }
+        state.deliver(pubsub_message);
+        state.send(outbox);
+    }
+
+    fn handle_control(
+        &self,
+        state: &mut State,
+        outbox: &mut Outbox,
+        from: &PeerId,
+        control: ControlMessage,
+    ) {
+        // gossip of peers below the threshold is ignored
+        let gossip = state.peer_score(from) >= self.thresholds.gossip_threshold;
+        let mut wanted: HashSet<Vec<u8>> = HashSet::new();
+        for ihave in control.ihave.into_iter().filter(|_| gossip) {
+            let subscribed = ihave
+                .topicID
+                .as_ref()
+                .is_some_and(|topic| state.mesh.contains_key(topic));
+            if !subscribed {
+                continue;
+            }
+            let Some(peer) = state.peers.get_mut(from) else {
+                break;
+            };
+            peer.ihaves += 1;
+            if peer.ihaves > self.config.max_ihave_messages {
+                log::debug!("gossipsub ihave of {:?} beyond the limit ignored", from);
+                break;
+            }
+            for id in ihave.messageIDs {
+                if peer.asked >= self.config.max_ihave_length {
+                    break;
+                }
+                if !state.seen.contains_key(&MessageId(id.clone())) && wanted.insert(id) {
+                    peer.asked += 1;
+                }
+            }
+        }
+        if !wanted.is_empty() {
+            // one of the ids, at random, is followed up on
+            let wanted: Vec<Vec<u8>> = wanted.into_iter().collect();
+            if let (Some(id), Some(until)) = (
+                wanted.choose(&mut rand::thread_rng()),
+                Instant::now().checked_add(self.config.iwant_followup_time),
+            ) {
+                state
+                    .promises
+                    .entry(MessageId(id.clone()))
+                    .or_default()
+                    .entry(from.clone())
+                    .or_insert(until);
+            }
+            outbox
+                .control(from)
+                .iwant
+                .push(ControlIWant { messageIDs: wanted });
+        }
+
+        for iwant in control.iwant.into_iter().filter(|_| gossip) {
+            for id in iwant.messageIDs {
+                let retransmission = self.config.gossip_retransmission;
+                if let Some(message) = state
+                    .mcache
+                    .retransmit(&MessageId(id), from, retransmission)
+                {
+                    outbox.publish(from, message.clone());
+                }
+            }
+        }
+
+        for graft in control.graft {
+            let Some(topic) = graft.topicID else {
+                continue;
+            };
+            if state.backoff.contains_key(&(topic.clone(), from.clone())) {
+                // grafting within the backoff is a misbehaviour
+                if let Some(score) = &mut state.score {
+                    score.add_penalty(from, 1);
+                }
+                state.prune(outbox, from, &topic, self.config.prune_backoff);
+            } else if state.mesh.contains_key(&topic) && state.peer_score(from) >= 0.0 {
+                state.join_mesh(&topic, from);
+            } else {
+                state.prune(outbox, from, &topic, self.config.prune_backoff);
+            }
+        }
+
+        // peers exchanged in PRUNE are not connected to, they come unsigned
+        for prune in control.prune {
+            let Some(topic) = prune.topicID else {
+                continue;
+            };
+            state.leave_mesh(&topic, from);
+            let backoff = prune
+                .backoff
+                .map_or(self.config.prune_backoff, Duration::from_secs)
+                .min(MAX_PRUNE_BACKOFF);
+            state.set_backoff(topic, from.clone(), backoff);
+        }
+    }
+}
+
+impl Protocol for Gossipsub {
+    fn register(self, registry: &mut ProtocolRegistry) {
+        registry.register(PROTOCOL_GOSSIPSUB, move |connection, stream| {
+            serve_peer(self.clone(), connection, stream)
+        });
+    }
+}
+
+impl PeerProtocol for Gossipsub {
+    type Message = RPC;
+
+    fn name(&self) -> &'static str {
+        PROTOCOL_GOSSIPSUB
+    }
+
+    fn max_message_size(&self) -> usize {
+        self.config.max_transmit_size
+    }
+
+    fn add_peer<'a>(
+        &'a self,
+        connection: &'a Connection,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+        Gossipsub::add_peer(self, connection).boxed()
+    }
+
+    fn stream_id(&self, peer_id: &PeerId) -> Option<u64> {
+        let state = self.state.lock().unwrap();
+        state.peers.get(peer_id).map(|peer| peer.sender.stream_id())
+    }
+
+    fn remove_peer(&self, peer_id: &PeerId, stream_id: u64) {
+        let mut state = self.state.lock().unwrap();
+        if state
+            .peers
+            .get(peer_id)
+            .is_some_and(|peer| peer.sender.stream_id() == stream_id)
+        {
+            self.forget_peer(&mut state, peer_id);
+        }
+    }
+
+    fn handle_message<'a>(
+        &'a self,
+        from: &'a PeerId,
+        rpc: RPC,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+        self.handle_rpc(from, rpc).boxed()
+    }
+}
+
+impl PubSub for Gossipsub {
+    fn add_peer<'a>(
+        &'a self,
+        connection: &'a Connection,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+        Gossipsub::add_peer(self, connection).boxed()
+    }
+
+    fn subscribe(&self, topic: &str) -> mpsc::Receiver<PubSubMessage> {
+        Gossipsub::subscribe(self, topic)
+    }
+
+    fn unsubscribe(&self, topic: &str) {
+        Gossipsub::unsubscribe(self, topic)
+    }
+
+    fn publish(&self, topic: &str, data: Vec<u8>) -> Result<MessageId, Error> {
+        Gossipsub::publish(self, topic, data)
+    }
+
+    fn topics(&self) -> Vec<String> {
+        Gossipsub::topics(self)
+    }
+
+    fn topic_peers(&self, topic: &str) -> Vec<PeerId> {
+        Gossipsub::topic_peers(self, topic)
+    }
+}
+
+// the rpc as it is if within the max size, else its messages spread over as
+// many rpcs as needed for the peer to read them all
+fn split_rpc(mut rpc: RPC, max_size: usize) -> Vec<RPC> {
+    if rpc.get_size() <= max_size {
+        return vec![rpc];
+    }
+    let publish = std::mem::take(&mut rpc.publish);
+    let mut rpcs = Vec::new();
+    let mut size = rpc.get_size();
+    for message in publish {
+        // the field tag, the length and the message
+        let message_size = 1 + sizeof_len(message.get_size());
+        if size > 0 && size + message_size > max_size {
+            rpcs.push(std::mem::take(&mut rpc));
+            size = 0;
+        }
+        size += message_size;
+        rpc.publish.push(message);
+    }
+    rpcs.push(rpc);
+    rpcs
+}
+
+fn announce(state: &State, outbox: &mut Outbox, topic: &str, subscribe: bool) {
+    for peer_id in state.peers.keys() {
+        outbox.rpc(peer_id).subscriptions.push(SubOpts {
+            subscribe: Some(subscribe),
+            topicid: Some(topic.to_string()),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        io,
+        net::test_support::{spawn_node, wait_until},
+    };
+    use futures::StreamExt;
+
+    // a peer whose rpcs are read from the receiver
+    fn fake_peer(gossipsub: &Gossipsub) -> Result<(PeerId, mpsc::Receiver<RPC>), Error> {
+        let peer_id: PeerId = PrivateKey::generate_ed25519().public().try_into()?;
+        let (sender, receiver) = PeerSender::channel(gossipsub.config.max_queued_rpcs);
+        gossipsub.insert_peer(peer_id.clone(), None, sender);
+        Ok((peer_id, receiver))
+    }
+
+    // the rpcs sent so far merged into one
+    fn drain(receiver: &mut mpsc::Receiver<RPC>) -> RPC {
+        let mut merged = RPC::default();
+        while let Ok(Some(rpc)) = receiver.try_next() {
+            merged.subscriptions.extend(rpc.subscriptions);
+            merged.publish.extend(rpc.publish);
+            if let Some(control) = rpc.control {
+                let merged = merged.control.get_or_insert_with(Default::default);
+                merged.ihave.extend(control.ihave);
+                merged.iwant.extend(control.iwant);
+                merged.graft.extend(control.graft);
+                merged.prune.extend(control.prune);
+            }
+        }
+        merged
+    }
+
+    fn control(rpc: RPC) -> ControlMessage {
+        rpc.control.unwrap_or_default()
+    }
+
+    fn subscription(topic: &str) -> RPC {
+        RPC {
+            subscriptions: vec![SubOpts {
+                subscribe: Some(true),
+                topicid: Some(topic.to_string()),
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[async_std::test]
+    async fn test_gossipsub_mesh_delivery() -> Result<(), Error> {
+        let config = GossipsubConfig {
+            heartbeat_interval: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let gossipsub = |private_key| Ok(Gossipsub::new(private_key)?.with_config(config.clone()));
+        let (manager_a, a, _) = spawn_node(gossipsub)?;
+        let (manager_b, b, addr_b) = spawn_node(gossipsub)?;
+        let (_, c, addr_c) = spawn_node(gossipsub)?;
+        for gossipsub in [&a, &b, &c] {
+            let heartbeat = gossipsub.clone();
+            async_std::task::spawn(async move { heartbeat.run().await });
+        }
+        let mut messages_a = a.subscribe("news");
+        let mut messages_b = b.subscribe("news");
+        let mut messages_c = c.subscribe("news");
+
+        // a line of a, b and c
+        a.add_peer(&manager_a.connect(addr_b).await?).await?;
+        b.add_peer(&manager_b.connect(addr_c).await?).await?;
+        wait_until(|| {
+            b.mesh_peers("news").len() == 2
+                && a.mesh_peers("news").len() == 1
+                && c.mesh_peers("news").len() == 1
+        })
+        .await?;
+
+        let id = a.publish("news", b"hello".to_vec())?;
+        let message = io::timeout(Duration::from_secs(5), async {
+            Ok(messages_c.next().await)
+        })
+        .await?
+        .unwrap();
+        assert_eq!(message.id, id);
+        assert_eq!(message.data, b"hello");
+        assert_eq!(message.source, Some(a.local_peer_id.clone()));
+        assert_eq!(message.propagation_source, b.local_peer_id);
+        assert_eq!(messages_b.next().await.unwrap().id, id);
+
+        c.publish("news", b"bye".to_vec())?;
+        let message = io::timeout(Duration::from_secs(5), async {
+            Ok(messages_a.next().await)
+        })
+        .await?
+        .unwrap();
+        assert_eq!(message.source, Some(c.local_peer_id.clone()));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_gossipsub_control() -> Result<(), Error> {
+        let config = GossipsubConfig {
+            d: 1,
+            d_lo: 1,
+            d_hi: 1,
+            ..Default::default()
+        };
+        let gossipsub = Gossipsub::new(PrivateKey::generate_ed25519())?.with_config(config);
+        let mut messages = gossipsub.subscribe("topic");
+        let (p, mut p_rpcs) = fake_peer(&gossipsub)?;
+        let (q, mut q_rpcs) = fake_peer(&gossipsub)?;
+        assert_eq!(drain(&mut p_rpcs).subscriptions.len(), 1);
+
+        gossipsub.handle_rpc(&p, subscription("topic")).await;
+        gossipsub.heartbeat();
+        assert_eq!(gossipsub.mesh_peers("topic"), vec![p.clone()]);
+        assert_eq!(control(drain(&mut p_rpcs)).graft.len(), 1);
+        gossipsub.handle_rpc(&q, subscription("topic")).await;
+
+        // delivered once, with a valid signature only
+        let source = PrivateKey::generate_ed25519();
+        let source_id: PeerId = source.public().try_into()?;
+        let mut message = Message {
+            from: Some(source_id.to_bytes()?),
+            data: Some(b"data".to_vec()),
+            seqno: Some(1u64.to_be_bytes().to_vec()),
+            topic: "topic".to_string(),
+            ..Default::default()
+        };
+        sign_message(&source, &mut message)?;
+        let mut forged = message.clone();
+        forged.seqno = Some(2u64.to_be_bytes().to_vec());
+        let publish = RPC {
+            publish: vec![message.clone(), message.clone(), forged],
+            ..Default::default()
+        };
+        gossipsub.handle_rpc(&p, publish).await;
+        let delivered = messages.try_next().unwrap().unwrap();
+        assert_eq!(delivered.source, Some(source_id));
+        assert_eq!(delivered.propagation_source, p);
+        assert!(messages.try_next().is_err());
+
+        // gossiped to q out of the mesh, which pulls it
+        gossipsub.heartbeat();
+        let id = MessageId::from_message(&message);
+        let ihave = control(drain(&mut q_rpcs)).ihave;
+        assert_eq!(ihave[0].messageIDs, vec![id.0.clone()]);
+        assert!(control(drain(&mut p_rpcs)).ihave.is_empty());
+        let iwant = RPC {
+            control: Some(ControlMessage {
+                iwant: vec![ControlIWant {
+                    messageIDs: vec![id.0.clone()],
+                }],
+                // unknown ids are asked for
+                ihave: vec![ControlIHave {
+                    topicID: Some("topic".to_string()),
+                    messageIDs: vec![id.0.clone(), vec![9, 9]],
+                }],
+                // not subscribed to the topic
+                graft: vec![ControlGraft {
+                    topicID: Some("other".to_string()),
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        gossipsub.handle_rpc(&q, iwant).await;
+        let rpc = drain(&mut q_rpcs);
+        assert_eq!(rpc.publish, vec![message]);
+        let control = rpc.control.unwrap();
+        assert_eq!(control.iwant[0].messageIDs, vec![vec![9, 9]]);
+        assert_eq!(control.prune[0].topicID.as_deref(), Some("other"));
+
+        // p is not grafted again within the backoff
+        let prune = RPC {
+            control: Some(ControlMessage {
+                prune: vec![ControlPrune {
+                    topicID: Some("topic".to_string()),
+                    peers: Vec::new(),
+                    backoff: Some(60),
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        gossipsub.handle_rpc(&p, prune).await;
+        assert!(gossipsub.mesh_peers("topic").is_empty());
+        gossipsub.heartbeat();
+        assert_eq!(gossipsub.mesh_peers("topic"), vec![q]);
+        Ok(())
+    }
+
+    fn signed_message(source: &PrivateKey, seqno: u64, data: &[u8]) -> Result<Message, Error> {
+        let source_id: PeerId = source.public().try_into()?;
+        let mut message = Message {
+            from: Some(source_id.to_bytes()?),
+            data: Some(data.to_vec()),
+            seqno: Some(seqno.to_be_bytes().to_vec()),
+            topic: "topic".to_string(),
+            ..Default::default()
+        };
+        sign_message(source, &mut message)?;
+        Ok(message)
+    }
+
+    #[async_std::test]
+    async fn test_gossipsub_validation_and_scoring() -> Result<(), Error> {
+        // one invalid message graylists the peer
+        let params = PeerScoreParams {
+            topics: HashMap::from([(
+                "topic".to_string(),
+                TopicScoreParams {
+                    topic_weight: 1.0,
+                    time_in_mesh_weight: 0.0,
+                    first_message_deliveries_weight: 0.0,
+                    mesh_message_deliveries_weight: 0.0,
+                    invalid_message_deliveries_weight: -100.0,
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let gossipsub = Gossipsub::new(PrivateKey::generate_ed25519())?
+            .with_peer_score(params, PeerScoreThresholds::default())
+            .with_validator(|message: PubSubMessage| async move {
+                match message.data.as_slice() {
+                    b"reject" => ValidationResult::Reject,
+                    b"ignore" => ValidationResult::Ignore,
+                    _ => ValidationResult::Accept,
+                }
+            });
+        let mut messages = gossipsub.subscribe("topic");
+        let (p, _p_rpcs) = fake_peer(&gossipsub)?;
+        let (q, mut q_rpcs) = fake_peer(&gossipsub)?;
+        gossipsub.handle_rpc(&p, subscription("topic")).await;
+
+        let source = PrivateKey::generate_ed25519();
+        let publish = |message: Message| RPC {
+            publish: vec![message],
+            ..Default::default()
+        };
+        gossipsub
+            .handle_rpc(&p, publish(signed_message(&source, 1, b"ignore")?))
+            .await;
+        assert!(messages.try_next().is_err());
+        assert_eq!(gossipsub.peer_score(&p), 0.0);
+        gossipsub
+            .handle_rpc(&p, publish(signed_message(&source, 2, b"accept")?))
+            .await;
+        assert_eq!(messages.try_next().unwrap().unwrap().data, b"accept");
+
+        gossipsub
+            .handle_rpc(&p, publish(signed_message(&source, 3, b"reject")?))
+            .await;
+        assert!(messages.try_next().is_err());
+        assert_eq!(gossipsub.peer_score(&p), -100.0);
+        gossipsub
+            .handle_rpc(&p, publish(signed_message(&source, 4, b"accept")?))
+            .await;
+        assert!(messages.try_next().is_err());
+        gossipsub.heartbeat();
+        assert!(gossipsub.mesh_peers("topic").is_empty());
+
+        // grafting within the backoff is penalized
+        let control = |control: ControlMessage| RPC {
+            control: Some(control),
+            ..Default::default()
+        };
+        gossipsub
+            .handle_rpc(
+                &q,
+                control(ControlMessage {
+                    prune: vec![ControlPrune {
+                        topicID: Some("topic".to_string()),
+                        peers: Vec::new(),
+                        backoff: Some(60),
+                    }],
+                    ..Default::default()
+                }),
+            )
+            .await;
+        gossipsub
+            .handle_rpc(
+                &q,
+                control(ControlMessage {
+                    graft: vec![ControlGraft {
+                        topicID: Some("topic".to_string()),
+                    }],
+                    ..Default::default()
+                }),
+            )
+            .await;
+        assert_eq!(drain(&mut q_rpcs).control.unwrap().prune.len(), 1);
+        assert_eq!(gossipsub.peer_score(&q), -10.0);
+        assert!(gossipsub.set_application_score(&q, 2.0));
+        assert_eq!(gossipsub.peer_score(&q), 10.0);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_gossipsub_gossip_limits() -> Result<(), Error> {
+        let config = GossipsubConfig {
+            max_ihave_length: 3,
+            max_ihave_messages: 2,
+            gossip_retransmission: 2,
+            ..Default::default()
+        };
+        let gossipsub = Gossipsub::new(PrivateKey::generate_ed25519())?.with_config(config);
+        let _messages = gossipsub.subscribe("topic");
+        let (p, mut p_rpcs) = fake_peer(&gossipsub)?;
+        let (q, mut q_rpcs) = fake_peer(&gossipsub)?;
+        gossipsub.handle_rpc(&p, subscription("topic")).await;
+        drain(&mut p_rpcs);
+        drain(&mut q_rpcs);
+
+        // ids are asked for once, up to the limits of the heartbeat
+        let ihave = |ids: Vec<Vec<u8>>| RPC {
+            control: Some(ControlMessage {
+                ihave: vec![ControlIHave {
+                    topicID: Some("topic".to_string()),
+                    messageIDs: ids,
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        gossipsub
+            .handle_rpc(&p, ihave(vec![vec![1], vec![1], vec![2]]))
+            .await;
+        gossipsub
+            .handle_rpc(&p, ihave(vec![vec![3], vec![4]]))
+            .await;
+        gossipsub.handle_rpc(&p, ihave(vec![vec![5]])).await;
+        let mut asked: Vec<Vec<u8>> = control(drain(&mut p_rpcs))
+            .iwant
+            .into_iter()
+            .flat_map(|iwant| iwant.messageIDs)
+            .collect();
+        asked.sort();
+        assert_eq!(asked, vec![vec![1], vec![2], vec![3]]);
+        gossipsub.heartbeat();
+        drain(&mut p_rpcs);
+        gossipsub.handle_rpc(&p, ihave(vec![vec![5]])).await;
+        assert_eq!(
+            control(drain(&mut p_rpcs)).iwant[0].messageIDs,
+            vec![vec![5]]
+        );
+
+        // a message is sent to a peer asking for it a limited number of times
+        let message = signed_message(&PrivateKey::generate_ed25519(), 1, b"data")?;
+        let id = MessageId::from_message(&message);
+        let publish = RPC {
+            publish: vec![message],
+            ..Default::default()
+        };
+        gossipsub.handle_rpc(&p, publish).await;
+        let iwant = RPC {
+            control: Some(ControlMessage {
+                iwant: vec![ControlIWant {
+                    messageIDs: vec![id.0.clone()],
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        for _ in 0..3 {
+            gossipsub.handle_rpc(&q, iwant.clone()).await;
+        }
+        assert_eq!(drain(&mut q_rpcs).publish.len(), 2);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_gossipsub_iwant_within_max_size() -> Result<(), Error> {
+        let config = GossipsubConfig {
+            max_transmit_size: 1024,
+            ..Default::default()
+        };
+        let gossipsub = Gossipsub::new(PrivateKey::generate_ed25519())?.with_config(config);
+        let _messages = gossipsub.subscribe("topic");
+        let (p, _p_rpcs) = fake_peer(&gossipsub)?;
+        let (q, mut q_rpcs) = fake_peer(&gossipsub)?;
+        drain(&mut q_rpcs);
+
+        // messages each within the max size, not all together
+        let source = PrivateKey::generate_ed25519();
+        let mut ids = Vec::new();
+        for seqno in 0..8 {
+            let message = signed_message(&source, seqno, &[0; 400])?;
+            ids.push(MessageId::from_message(&message).0);
+            let publish = RPC {
+                publish: vec![message],
+                ..Default::default()
+            };
+            gossipsub.handle_rpc(&p, publish).await;
+        }
+        let iwant = RPC {
+            control: Some(ControlMessage {
+                iwant: vec![ControlIWant { messageIDs: ids }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        gossipsub.handle_rpc(&q, iwant).await;
+        let mut published = 0;
+        while let Ok(Some(rpc)) = q_rpcs.try_next() {
+            assert!(rpc.get_size() <= 1024);
+            published += rpc.publish.len();
+        }
+        assert_eq!(published, 8);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_gossipsub_broken_promises() -> Result<(), Error> {
+        let config = GossipsubConfig {
+            iwant_followup_time: Duration::ZERO,
+            ..Default::default()
+        };
+        let gossipsub = Gossipsub::new(PrivateKey::generate_ed25519())?
+            .with_config(config)
+            .with_peer_score(PeerScoreParams::default(), PeerScoreThresholds::default());
+        let _messages = gossipsub.subscribe("topic");
+        let (p, _p_rpcs) = fake_peer(&gossipsub)?;
+        let (q, _q_rpcs) = fake_peer(&gossipsub)?;
+
+        // both advertise a message only q delivers
+        let message = signed_message(&PrivateKey::generate_ed25519(), 1, b"data")?;
+        let id = MessageId::from_message(&message);
+        let ihave = RPC {
+            control: Some(ControlMessage {
+                ihave: vec![ControlIHave {
+                    topicID: Some("topic".to_string()),
+                    messageIDs: vec![id.0.clone()],
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        gossipsub.handle_rpc(&p, ihave.clone()).await;
+        gossipsub.handle_rpc(&q, ihave.clone()).await;
+        let publish = RPC {
+            publish: vec![message],
+            ..Default::default()
+        };
+        gossipsub.handle_rpc(&q, publish).await;
+        gossipsub.heartbeat();
+        assert_eq!(gossipsub.peer_score(&p), 0.0);
+        assert_eq!(gossipsub.peer_score(&q), 0.0);
+
+        // the promise of an id never delivered expires
+        let mut ihave = ihave;
+        ihave.control.as_mut().unwrap().ihave[0].messageIDs = vec![vec![9]];
+        gossipsub.handle_rpc(&p, ihave).await;
+        gossipsub.heartbeat();
+        assert_eq!(gossipsub.peer_score(&p), -10.0);
+        assert_eq!(gossipsub.peer_score(&q), 0.0);
+        assert!(gossipsub.state.lock().unwrap().promises.is_empty());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_gossipsub_bounds() -> Result<(), Error> {
+        // a target degree below the low watermark is tolerated
+        let config = GossipsubConfig {
+            d: 1,
+            d_lo: 3,
+            d_hi: 0,
+            max_queued_rpcs: 1,
+            ..Default::default()
+        };
+        let gossipsub = Gossipsub::new(PrivateKey::generate_ed25519())?.with_config(config);
+        let mut messages = gossipsub.subscribe("topic");
+        let (p, mut p_rpcs) = fake_peer(&gossipsub)?;
+        gossipsub.handle_rpc(&p, subscription("topic")).await;
+        gossipsub.heartbeat();
+        gossipsub.heartbeat();
+
+        // messages of topics not subscribed to are neither delivered nor cached
+        let source = PrivateKey::generate_ed25519();
+        let mut message = signed_message(&source, 1, b"data")?;
+        message.topic = "other".to_string();
+        sign_message(&source, &mut message)?;
+        let id = MessageId::from_message(&message);
+        let publish = RPC {
+            publish: vec![message],
+            ..Default::default()
+        };
+        gossipsub.handle_rpc(&p, publish).await;
+        assert!(messages.try_next().is_err());
+        assert!(gossipsub.state.lock().unwrap().mcache.get(&id).is_none());
+
+        // rpcs are dropped while the queue of the peer is full, the channel
+        // has a slot for its sender beyond the bound
+        drain(&mut p_rpcs);
+        for _ in 0..4 {
+            gossipsub.publish("topic", b"data".to_vec())?;
+        }
+        assert_eq!(drain(&mut p_rpcs).publish.len(), 2);
+
+        // a backoff beyond any instant is cut down, the state stays usable
+        let prune = RPC {
+            control: Some(ControlMessage {
+                prune: vec![ControlPrune {
+                    topicID: Some("topic".to_string()),
+                    peers: Vec::new(),
+                    backoff: Some(u64::MAX),
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        gossipsub.handle_rpc(&p, prune).await;
+        assert!(gossipsub.mesh_peers("topic").is_empty());
+        let until = gossipsub.state.lock().unwrap().backoff[&("topic".to_string(), p.clone())];
+        assert!(until <= Instant::now() + MAX_PRUNE_BACKOFF);
+        gossipsub.publish("topic", b"data".to_vec())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+use super::MessageId;
+use crate::{identity::PeerId, payload::pubsub::Message};
+use std::collections::{HashMap, VecDeque};
+
+// MessageCache
+//
+// Messages seen within the last history windows, to answer IWANT, of which
+// the ones of the last gossip windows are advertised by IHAVE. The times each
+// peer asked for a message are kept along with it
+pub struct MessageCache {
+    messages: HashMap<MessageId, (Message, HashMap<PeerId, usize>)>,
+    history: VecDeque<Vec<(MessageId, String)>>,
+    gossip: usize,
+}
+
+impl MessageCache {
+    pub fn new(history_length: usize, history_gossip: usize) -> Self {
+        let mut history = VecDeque::with_capacity(history_length);
+        history.resize(history_length.max(1), Vec::new());
+        Self {
+            messages: HashMap::new(),
+            history,
+            gossip: history_gossip,
+        }
+    }
+
+    pub fn put(&mut self, id: MessageId, message: Message) {
+        if self.messages.contains_key(&id) {
+            return;
+        }
+        self.history[0].push((id.clone(), message.topic.clone()));
+        self.messages.insert(id, (message, HashMap::new()));
+    }
+
+    #[cfg(test)]
+    pub fn get(&self, id: &MessageId) -> Option<&Message> {
+        self.messages.get(id).map(|(message, _)| message)
+    }
+
+    // the message for the peer asking for it, until it was sent the message
+    // the limit times
+    pub fn retransmit(
+        &mut self,
+        id: &MessageId,
+        peer_id: &PeerId,
+        limit: usize,
+    ) -> Option<&Message> {
+        let (message, transmissions) = self.messages.get_mut(id)?;
+        let count = transmissions.entry(peer_id.clone()).or_insert(0);
+        if *count >= limit {
+            return None;
+        }
+        *count += 1;
+        Some(message)
+    }
+
+    // ids of the recent messages of the topic
+    pub fn gossip_ids(&self, topic: &str) -> Vec<MessageId> {
+        self.history
+            .iter()
+            .take(self.gossip)
+            .flatten()
+            .filter(|(_, message_topic)| message_topic == topic)
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    // start a new window, dropping the messages of the oldest one
+    pub fn shift(&mut self) {
+        if let Some(oldest) = self.history.pop_back() {
+            for (id, _) in oldest {
+                self.messages.remove(&id);
+            }
+        }
+        self.history.push_front(Vec::new());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::Error, identity::PrivateKey};
+
+    fn message(topic: &str, seqno: u8) -> (MessageId, Message) {
+        (
+            MessageId(vec![seqno]),
+            Message {
+                seqno: Some(vec![seqno]),
+                topic: topic.to_string(),
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn test_message_cache_windows() {
+        let mut mcache = MessageCache::new(3, 2);
+        let (id, msg) = message("a", 1);
+        mcache.put(id.clone(), msg);
+        let (other, msg) = message("b", 2);
+        mcache.put(other, msg);
+        assert_eq!(mcache.gossip_ids("a"), vec![id.clone()]);
+
+        mcache.shift();
+        let (recent, msg) = message("a", 3);
+        mcache.put(recent.clone(), msg);
+        assert_eq!(mcache.gossip_ids("a"), vec![recent.clone(), id.clone()]);
+
+        // out of the gossip windows, but still served
+        mcache.shift();
+        assert_eq!(mcache.gossip_ids("a"), vec![recent.clone()]);
+        assert!(mcache.get(&id).is_some());
+
+        mcache.shift();
+        assert!(mcache.get(&id).is_none());
+        assert!(mcache.get(&recent).is_some());
+    }
+
+    #[test]
+    fn test_message_cache_retransmit() -> Result<(), Error> {
+        let mut mcache = MessageCache::new(3, 2);
+        let (id, msg) = message("a", 1);
+        mcache.put(id.clone(), msg);
+        let peer_id: PeerId = PrivateKey::generate_ed25519().public().try_into()?;
+        let other: PeerId = PrivateKey::generate_ed25519().public().try_into()?;
+        assert!(mcache.retransmit(&id, &peer_id, 2).is_some());
+        assert!(mcache.retransmit(&id, &peer_id, 2).is_some());
+        assert!(mcache.retransmit(&id, &peer_id, 2).is_none());
+        assert!(mcache.retransmit(&id, &other, 2).is_some());
+        assert!(mcache.retransmit(&MessageId(vec![2]), &other, 2).is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,510 @@
+use crate::identity::PeerId;
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+// TopicScoreParams of the P1 to P4 counters of a topic, the defaults are
+// the ones of rust-libp2p
+#[derive(Debug, Clone)]
+pub struct TopicScoreParams {
+    pub topic_weight: f64,
+    // P1 time in the mesh, in quantums up to the cap
+    pub time_in_mesh_weight: f64,
+    pub time_in_mesh_quantum: Duration,
+    pub time_in_mesh_cap: f64,
+    // P2 messages delivered first
+    pub first_message_deliveries_weight: f64,
+    pub first_message_deliveries_decay: f64,
+    pub first_message_deliveries_cap: f64,
+    // P3 deficit of messages delivered by a mesh peer, squared, counted once
+    // the peer is in the mesh for the activation
+    pub mesh_message_deliveries_weight: f64,
+    pub mesh_message_deliveries_decay: f64,
+    pub mesh_message_deliveries_threshold: f64,
+    pub mesh_message_deliveries_cap: f64,
+    // duplicates received within the window of the first are delivered too
+    pub mesh_message_deliveries_window: Duration,
+    pub mesh_message_deliveries_activation: Duration,
+    // P3b deficit kept when the peer leaves the mesh
+    pub mesh_failure_penalty_weight: f64,
+    pub mesh_failure_penalty_decay: f64,
+    // P4 invalid messages, squared
+    pub invalid_message_deliveries_weight: f64,
+    pub invalid_message_deliveries_decay: f64,
+}
+
+impl Default for TopicScoreParams {
+    fn default() -> Self {
+        Self {
+            topic_weight: 0.5,
+            time_in_mesh_weight: 1.0,
+            time_in_mesh_quantum: Duration::from_millis(1),
+            time_in_mesh_cap: 3600.0,
+            first_message_deliveries_weight: 1.0,
+            first_message_deliveries_decay: 0.5,
+            first_message_deliveries_cap: 2000.0,
+            mesh_message_deliveries_weight: -1.0,
+            mesh_message_deliveries_decay: 0.5,
+            mesh_message_deliveries_threshold: 20.0,
+            mesh_message_deliveries_cap: 100.0,
+            mesh_message_deliveries_window: Duration::from_millis(10),
+            mesh_message_deliveries_activation: Duration::from_secs(5),
+            mesh_failure_penalty_weight: -1.0,
+            mesh_failure_penalty_decay: 0.5,
+            invalid_message_deliveries_weight: -1.0,
+            invalid_message_deliveries_decay: 0.3,
+        }
+    }
+}
+
+// PeerScoreParams of the topics and of the P5 to P7 counters
+#[derive(Debug, Clone)]
+pub struct PeerScoreParams {
+    // topics without parameters do not count towards the score
+    pub topics: HashMap<String, TopicScoreParams>,
+    pub topic_score_cap: f64,
+    // P5 score set by the application
+    pub app_specific_weight: f64,
+    // P6 peers sharing an ip address beyond the threshold, squared
+    pub ip_colocation_factor_weight: f64,
+    pub ip_colocation_factor_threshold: f64,
+    // P7 misbehaviours beyond the threshold, squared
+    pub behaviour_penalty_weight: f64,
+    pub behaviour_penalty_threshold: f64,
+    pub behaviour_penalty_decay: f64,
+    pub decay_interval: Duration,
+    // counters decayed below it are reset to zero
+    pub decay_to_zero: f64,
+    // scores of disconnected peers are kept so that reconnecting does not
+    // reset them
+    pub retain_score: Duration,
+}
+
+impl Default for PeerScoreParams {
+    fn default() -> Self {
+        Self {
+            topics: HashMap::new(),
+            topic_score_cap: 3600.0,
+            app_specific_weight: 10.0,
+            ip_colocation_factor_weight: -5.0,
+            ip_colocation_factor_threshold: 10.0,
+            behaviour_penalty_weight: -10.0,
+            behaviour_penalty_threshold: 0.0,
+            behaviour_penalty_decay: 0.2,
+            decay_interval: Duration::from_secs(1),
+            decay_to_zero: 0.1,
+            retain_score: Duration::from_secs(3600),
+        }
+    }
+}
+
+// PeerScoreThresholds below which peers are left out
+#[derive(Debug, Clone)]
+pub struct PeerScoreThresholds {
+    // no gossip is emitted to or accepted from the peer
+    pub gossip_threshold: f64,
+    // messages are not published to the peer
+    pub publish_threshold: f64,
+    // rpcs of the peer are ignored altogether
+    pub graylist_threshold: f64,
+}
+
+impl Default for PeerScoreThresholds {
+    fn default() -> Self {
+        Self {
+            gossip_threshold: -10.0,
+            publish_threshold: -50.0,
+            graylist_threshold: -80.0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct TopicStats {
+    grafted: Option<Instant>,
+    first_message_deliveries: f64,
+    mesh_message_deliveries: f64,
+    mesh_failure_penalty: f64,
+    invalid_message_deliveries: f64,
+}
+
+impl TopicStats {
+    // the mesh deficit counts once the peer is in the mesh for the activation
+    fn mesh_deficit(&self, params: &TopicScoreParams) -> f64 {
+        match self.grafted {
+            Some(grafted) if grafted.elapsed() >= params.mesh_message_deliveries_activation => {
+                (params.mesh_message_deliveries_threshold - self.mesh_message_deliveries).max(0.0)
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct PeerStats {
+    // set once disconnected
+    expire: Option<Instant>,
+    ip: Option<IpAddr>,
+    topics: HashMap<String, TopicStats>,
+    application_score: f64,
+    behaviour_penalty: f64,
+}
+
+// PeerScore
+//
+// Counters of the behaviour of each peer, decayed at every interval and
+// weighted into a score
+pub struct PeerScore {
+    params: PeerScoreParams,
+    peers: HashMap<PeerId, PeerStats>,
+    ips: HashMap<IpAddr, HashSet<PeerId>>,
+    decayed: Instant,
+}
+
+impl PeerScore {
+    pub fn new(params: PeerScoreParams) -> Self {
+        Self {
+            params,
+            peers: HashMap::new(),
+            ips: HashMap::new(),
+            decayed: Instant::now(),
+        }
+    }
+
+    pub fn score(&self, peer_id: &PeerId) -> f64 {
+        let Some(stats) = self.peers.get(peer_id) else {
+            return 0.0;
+        };
+
+        let mut topic_score = 0.0;
+        for (topic, topic_stats) in &stats.topics {
+            let Some(params) = self.params.topics.get(topic) else {
+                continue;
+            };
+            let mut score = 0.0;
+            if let Some(grafted) = topic_stats.grafted {
+                let quantums =
+                    grafted.elapsed().as_secs_f64() / params.time_in_mesh_quantum.as_secs_f64();
+                score += quantums.min(params.time_in_mesh_cap) * params.time_in_mesh_weight;
+            }
+            score += topic_stats.first_message_deliveries * params.first_message_deliveries_weight;
+            score +=
+                topic_stats.mesh_deficit(params).powi(2) * params.mesh_message_deliveries_weight;
+            score += topic_stats.mesh_failure_penalty * params.mesh_failure_penalty_weight;
+            score += topic_stats.invalid_message_deliveries.powi(2)
+                * params.invalid_message_deliveries_weight;
+            topic_score += score * params.topic_weight;
+        }
+        if self.params.topic_score_cap > 0.0 {
+            topic_score = topic_score.min(self.params.topic_score_cap);
+        }
+
+        let mut score = topic_score + stats.application_score * self.params.app_specific_weight;
+        if let Some(ip) = &stats.ip {
+            let colocated = self.ips.get(ip).map_or(0, |peers| peers.len()) as f64;
+            if colocated > self.params.ip_colocation_factor_threshold {
+                score += (colocated - self.params.ip_colocation_factor_threshold).powi(2)
+                    * self.params.ip_colocation_factor_weight;
+            }
+        }
+        if stats.behaviour_penalty > self.params.behaviour_penalty_threshold {
+            score += (stats.behaviour_penalty - self.params.behaviour_penalty_threshold).powi(2)
+                * self.params.behaviour_penalty_weight;
+        }
+        score
+    }
+
+    pub fn add_peer(&mut self, peer_id: &PeerId, ip: Option<IpAddr>) {
+        let stats = self.peers.entry(peer_id.clone()).or_default();
+        stats.expire = None;
+        if let Some(old) = stats.ip.take() {
+            if let Some(peers) = self.ips.get_mut(&old) {
+                peers.remove(peer_id);
+            }
+        }
+        stats.ip = ip;
+        if let Some(ip) = ip {
+            self.ips.entry(ip).or_default().insert(peer_id.clone());
+        }
+    }
+
+    // the counters of a peer with a positive score are dropped, the others
+    // are retained
+    pub fn remove_peer(&mut self, peer_id: &PeerId) {
+        if self.score(peer_id) > 0.0 {
+            self.forget(peer_id);
+            return;
+        }
+        let retain_score = self.params.retain_score;
+        if let Some(stats) = self.peers.get_mut(peer_id) {
+            for topic_stats in stats.topics.values_mut() {
+                topic_stats.grafted = None;
+            }
+            stats.expire = Some(Instant::now() + retain_score);
+        }
+    }
+
+    pub fn graft(&mut self, peer_id: &PeerId, topic: &str) {
+        if let Some(topic_stats) = self.topic_stats(peer_id, topic) {
+            topic_stats.grafted = Some(Instant::now());
+            topic_stats.mesh_message_deliveries = 0.0;
+        }
+    }
+
+    // the mesh deficit left is kept as the mesh failure penalty
+    pub fn prune(&mut self, peer_id: &PeerId, topic: &str) {
+        let Some(params) = self.params.topics.get(topic).cloned() else {
+            return;
+        };
+        if let Some(topic_stats) = self.topic_stats(peer_id, topic) {
+            topic_stats.mesh_failure_penalty += topic_stats.mesh_deficit(&params).powi(2);
+            topic_stats.grafted = None;
+        }
+    }
+
+    // a valid message delivered first by the peer
+    pub fn deliver_message(&mut self, peer_id: &PeerId, topic: &str) {
+        let Some(params) = self.params.topics.get(topic).cloned() else {
+            return;
+        };
+        if let Some(topic_stats) = self.topic_stats(peer_id, topic) {
+            topic_stats.first_message_deliveries = (topic_stats.first_message_deliveries + 1.0)
+                .min(params.first_message_deliveries_cap);
+            if topic_stats.grafted.is_some() {
+                topic_stats.mesh_message_deliveries = (topic_stats.mesh_message_deliveries + 1.0)
+                    .min(params.mesh_message_deliveries_cap);
+            }
+        }
+    }
+
+    // a copy of a message first seen at the instant
+    pub fn duplicate_message(&mut self, peer_id: &PeerId, topic: &str, first_seen: Instant) {
+        let Some(params) = self.params.topics.get(topic).cloned() else {
+            return;
+        };
+        if first_seen.elapsed() > params.mesh_message_deliveries_window {
+            return;
+        }
+        if let Some(topic_stats) = self.topic_stats(peer_id, topic) {
+            if topic_stats.grafted.is_some() {
+                topic_stats.mesh_message_deliveries = (topic_stats.mesh_message_deliveries + 1.0)
+                    .min(params.mesh_message_deliveries_cap);
+            }
+        }
+    }
+
+    // a message with an invalid signature or rejected by the validator
+    pub fn reject_message(&mut self, peer_id: &PeerId, topic: &str) {
+        if let Some(topic_stats) = self.topic_stats(peer_id, topic) {
+            topic_stats.invalid_message_deliveries += 1.0;
+        }
+    }
+
+    pub fn add_penalty(&mut self, peer_id: &PeerId, count: usize) {
+        if let Some(stats) = self.peers.get_mut(peer_id) {
+            stats.behaviour_penalty += count as f64;
+        }
+    }
+
+    pub fn set_application_score(&mut self, peer_id: &PeerId, score: f64) -> bool {
+        match self.peers.get_mut(peer_id) {
+            Some(stats) => {
+                stats.application_score = score;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // decay the counters once the decay interval is over, and forget the
+    // peers disconnected for longer than the retention
+    pub fn refresh(&mut self) {
+        if self.decayed.elapsed() < self.params.decay_interval {
+            return;
+        }
+        self.decayed = Instant::now();
+
+        let expired: Vec<PeerId> = self
+            .peers
+            .iter()
+            .filter(|(_, stats)| stats.expire.is_some_and(|expire| expire <= Instant::now()))
+            .map(|(peer_id, _)| peer_id.clone())
+            .collect();
+        for peer_id in expired {
+            self.forget(&peer_id);
+        }
+
+        let decay_to_zero = self.params.decay_to_zero;
+        let decay = |value: &mut f64, factor: f64| {
+            *value *= factor;
+            if *value < decay_to_zero {
+                *value = 0.0;
+            }
+        };
+        for stats in self.peers.values_mut() {
+            for (topic, topic_stats) in stats.topics.iter_mut() {
+                let Some(params) = self.params.topics.get(topic) else {
+                    continue;
+                };
+                decay(
+                    &mut topic_stats.first_message_deliveries,
+                    params.first_message_deliveries_decay,
+                );
+                decay(
+                    &mut topic_stats.mesh_message_deliveries,
+                    params.mesh_message_deliveries_decay,
+                );
+                decay(
+                    &mut topic_stats.mesh_failure_penalty,
+                    params.mesh_failure_penalty_decay,
+                );
+                decay(
+                    &mut topic_stats.invalid_message_deliveries,
+                    params.invalid_message_deliveries_decay,
+                );
+            }
+            decay(
+                &mut stats.behaviour_penalty,
+                self.params.behaviour_penalty_decay,
+            );
+        }
+    }
+
+    fn topic_stats(&mut self, peer_id: &PeerId, topic: &str) -> Option<&mut TopicStats> {
+        if !self.params.topics.contains_key(topic) {
+            return None;
+        }
+        let stats = self.peers.get_mut(peer_id)?;
+        Some(stats.topics.entry(topic.to_string()).or_default())
+    }
+
+    fn forget(&mut self, peer_id: &PeerId) {
+        if let Some(ip) = self.peers.remove(peer_id).and_then(|stats| stats.ip) {
+            if let Some(peers) = self.ips.get_mut(&ip) {
+                peers.remove(peer_id);
+                if peers.is_empty() {
+                    self.ips.remove(&ip);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::Error, identity::PrivateKey};
+
+    fn peer_id() -> Result<PeerId, Error> {
+        PrivateKey::generate_ed25519().public().try_into()
+    }
+
+    // a topic of which only the counter under test weighs
+    fn params(topic: TopicScoreParams) -> PeerScoreParams {
+        PeerScoreParams {
+            topics: HashMap::from([("topic".to_string(), topic)]),
+            decay_interval: Duration::ZERO,
+            ..Default::default()
+        }
+    }
+
+    fn weightless() -> TopicScoreParams {
+        TopicScoreParams {
+            topic_weight: 1.0,
+            time_in_mesh_weight: 0.0,
+            first_message_deliveries_weight: 0.0,
+            mesh_message_deliveries_weight: 0.0,
+            mesh_failure_penalty_weight: 0.0,
+            invalid_message_deliveries_weight: 0.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_peer_score_counters() -> Result<(), Error> {
+        let peer = peer_id()?;
+
+        // P2 capped, and decayed
+        let mut score = PeerScore::new(params(TopicScoreParams {
+            first_message_deliveries_weight: 1.0,
+            first_message_deliveries_cap: 2.0,
+            ..weightless()
+        }));
+        score.add_peer(&peer, None);
+        for _ in 0..3 {
+            score.deliver_message(&peer, "topic");
+        }
+        score.deliver_message(&peer, "other");
+        assert_eq!(score.score(&peer), 2.0);
+        score.refresh();
+        assert_eq!(score.score(&peer), 1.0);
+
+        // P3 deficit squared once active, kept as P3b when pruned
+        let mut score = PeerScore::new(params(TopicScoreParams {
+            mesh_message_deliveries_weight: -1.0,
+            mesh_message_deliveries_threshold: 3.0,
+            mesh_message_deliveries_activation: Duration::ZERO,
+            mesh_failure_penalty_weight: -1.0,
+            ..weightless()
+        }));
+        score.add_peer(&peer, None);
+        score.graft(&peer, "topic");
+        score.deliver_message(&peer, "topic");
+        assert_eq!(score.score(&peer), -4.0);
+        score.duplicate_message(&peer, "topic", Instant::now());
+        assert_eq!(score.score(&peer), -1.0);
+        score.prune(&peer, "topic");
+        assert_eq!(score.score(&peer), -1.0);
+
+        // P4 squared
+        let mut score = PeerScore::new(params(TopicScoreParams {
+            invalid_message_deliveries_weight: -1.0,
+            ..weightless()
+        }));
+        score.add_peer(&peer, None);
+        score.reject_message(&peer, "topic");
+        score.reject_message(&peer, "topic");
+        assert_eq!(score.score(&peer), -4.0);
+
+        // P5, P6 and P7
+        let mut score = PeerScore::new(PeerScoreParams {
+            ip_colocation_factor_threshold: 1.0,
+            ..params(weightless())
+        });
+        let ip: IpAddr = [1, 2, 3, 4].into();
+        score.add_peer(&peer, Some(ip));
+        assert!(score.set_application_score(&peer, 1.0));
+        assert_eq!(score.score(&peer), 10.0);
+        let colocated = peer_id()?;
+        score.add_peer(&colocated, Some(ip));
+        assert_eq!(score.score(&peer), 5.0);
+        score.add_penalty(&peer, 2);
+        assert_eq!(score.score(&peer), -35.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_peer_score_retention() -> Result<(), Error> {
+        let peer = peer_id()?;
+        let mut score = PeerScore::new(PeerScoreParams {
+            retain_score: Duration::ZERO,
+            ..params(weightless())
+        });
+
+        // a penalty survives reconnecting
+        score.add_peer(&peer, None);
+        score.add_penalty(&peer, 1);
+        score.remove_peer(&peer);
+        score.add_peer(&peer, None);
+        assert_eq!(score.score(&peer), -10.0);
+
+        // and is forgotten after the retention
+        score.remove_peer(&peer);
+        score.refresh();
+        assert_eq!(score.score(&peer), 0.0);
+        assert!(!score.set_application_score(&peer, 1.0));
+        Ok(())
+    }
+}
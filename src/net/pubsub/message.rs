@@ -0,0 +1,135 @@
+use crate::{
+    error::{self, Error},
+    identity::{PeerId, PrivateKey, PublicKey},
+    io::{protobuf_decode, protobuf_encode},
+    payload::{keys::PublicKey as PublicKeyProto, pubsub::Message},
+};
+use std::fmt;
+
+const SIGNING_PREFIX: &[u8] = b"libp2p-pubsub:";
+
+// MessageId identifying a message across the network, the source and the
+// sequence number by default
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct MessageId(pub Vec<u8>);
+
+impl MessageId {
+    pub fn from_message(message: &Message) -> Self {
+        let mut id = message.from.clone().unwrap_or_default();
+        id.extend_from_slice(message.seqno.as_deref().unwrap_or_default());
+        Self(id)
+    }
+}
+
+impl fmt::Debug for MessageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hex: String = self.0.iter().map(|b| format!("{:02x}", b)).collect();
+        write!(f, "MessageId({})", hex)
+    }
+}
+
+// PubSubMessage delivered to the subscribers of its topic
+#[derive(Debug, Clone, PartialEq)]
+pub struct PubSubMessage {
+    pub id: MessageId,
+    pub source: Option<PeerId>,
+    pub topic: String,
+    pub data: Vec<u8>,
+    // the peer which forwarded the message
+    pub propagation_source: PeerId,
+}
+
+impl PubSubMessage {
+    pub(super) fn new(id: MessageId, message: &Message, propagation_source: PeerId) -> Self {
+        Self {
+            id,
+            source: message
+                .from
+                .as_ref()
+                .and_then(|from| PeerId::from_bytes(from).ok()),
+            topic: message.topic.clone(),
+            data: message.data.clone().unwrap_or_default(),
+            propagation_source,
+        }
+    }
+}
+
+// sign the message with the key of its source, ed25519 keys are inlined in
+// the peer id so that the key field is left out
+pub fn sign_message(private_key: &PrivateKey, message: &mut Message) -> Result<(), Error> {
+    message.signature = None;
+    message.key = None;
+    let signature = private_key.sign(&signing_bytes(message)?);
+    message.signature = Some(signature);
+    Ok(())
+}
+
+pub fn verify_message(message: &Message) -> Result<(), Error> {
+    let (Some(from), Some(signature), Some(_)) =
+        (&message.from, &message.signature, &message.seqno)
+    else {
+        return Err(error::verification_failed());
+    };
+    let source = PeerId::from_bytes(from)?;
+    let public_key = match &message.key {
+        Some(key) => {
+            let public_key = PublicKey::try_from(protobuf_decode::<PublicKeyProto>(key)?)?;
+            let signer: PeerId = public_key.clone().try_into()?;
+            if signer != source {
+                return Err(error::verification_failed());
+            }
+            public_key
+        }
+        None => PublicKey::try_from(source)?,
+    };
+
+    let unsigned = Message {
+        signature: None,
+        key: None,
+        ..message.clone()
+    };
+    public_key.verify(&signing_bytes(&unsigned)?, signature)
+}
+
+fn signing_bytes(message: &Message) -> Result<Vec<u8>, Error> {
+    Ok([SIGNING_PREFIX, &protobuf_encode(message)?].concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_signature() -> Result<(), Error> {
+        let private_key = PrivateKey::generate_ed25519();
+        let peer_id: PeerId = private_key.public().try_into()?;
+        let mut message = Message {
+            from: Some(peer_id.to_bytes()?),
+            data: Some(b"hello".to_vec()),
+            seqno: Some(1u64.to_be_bytes().to_vec()),
+            topic: "topic".to_string(),
+            ..Default::default()
+        };
+        assert!(verify_message(&message).is_err());
+        sign_message(&private_key, &mut message)?;
+        verify_message(&message)?;
+
+        let mut tampered = message.clone();
+        tampered.data = Some(b"bye".to_vec());
+        assert!(verify_message(&tampered).is_err());
+
+        // claimed by another source
+        let mut forged = message.clone();
+        let other: PeerId = PrivateKey::generate_ed25519().public().try_into()?;
+        forged.from = Some(other.to_bytes()?);
+        assert!(verify_message(&forged).is_err());
+
+        // an inlined key which is not an ed25519 key of 32 bytes
+        let mut crafted = message.clone();
+        crafted.from = Some(vec![0x00, 0x04, 0x08, 0x01, 0x12, 0x00]);
+        assert!(verify_message(&crafted).is_err());
+        crafted.from = Some(vec![0x00, 0x00]);
+        assert!(verify_message(&crafted).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use super::Connection;
+use crate::{error::Error, io};
+use futures::{Future, FutureExt};
+use std::{
+    boxed::Box,
+    collections::{HashMap, HashSet},
+    pin::Pin,
+    sync::Arc,
+};
+
+pub(super) type Handler = Arc<
+    dyn Fn(Connection, io::BoxedStream) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send>>
+        + Send
+        + Sync,
+>;
+
+// Protocol serving its protocol ids on every connection
+pub trait Protocol {
+    fn register(self, registry: &mut ProtocolRegistry);
+}
+
+// ProtocolRegistry
+//
+// Maps application protocol ids to the handlers of inbound substreams,
+// the substreams of unhandled protocols go to Connection::incoming_streams
+#[derive(Clone, Default)]
+pub struct ProtocolRegistry {
+    handlers: HashMap<String, Handler>,
+    unhandled: HashSet<String>,
+}
+
+impl ProtocolRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // a handler registered again for the same protocol replaces the previous one
+    pub fn register<F, Fut>(&mut self, protocol: &str, handler: F)
+    where
+        F: Fn(Connection, io::BoxedStream) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        self.handlers.insert(
+            protocol.to_string(),
+            Arc::new(move |connection, stream| handler(connection, stream).boxed()),
+        );
+    }
+
+    // accept inbound substreams of the protocol without a handler
+    pub fn accept(&mut self, protocol: &str) {
+        self.unhandled.insert(protocol.to_string());
+    }
+
+    pub fn protocols(&self) -> Vec<String> {
+        self.handlers
+            .keys()
+            .chain(self.unhandled.iter())
+            .cloned()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    pub(super) fn handler(&self, protocol: &str) -> Option<Handler> {
+        self.handlers.get(protocol).cloned()
+    }
+}
@@ -0,0 +1,246 @@
+mod client;
+mod server;
+
+use super::{open_envelope, p2p_peer_id, p2p_protocol, seal_envelope};
+use crate::{
+    error::{self, Error},
+    identity::{PeerId, PrivateKey},
+    io::{self, protobuf_decode, protobuf_encode},
+    payload::{
+        circuit::{Limit, Peer as PeerPayload, Status},
+        voucher::ReservationVoucher as VoucherPayload,
+    },
+};
+use multiaddr::{Multiaddr, Protocol};
+use quick_protobuf::{MessageRead, MessageWrite};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub use client::{RelayClient, Reservation};
+pub use server::{RelayResources, RelayServer};
+
+pub const PROTOCOL_RELAY_HOP: &str = "/libp2p/circuit/relay/0.2.0/hop";
+pub const PROTOCOL_RELAY_STOP: &str = "/libp2p/circuit/relay/0.2.0/stop";
+pub const MAX_RELAY_MESSAGE_SIZE: usize = 4096;
+const VOUCHER_DOMAIN: &str = "libp2p-relay-rsvp";
+// multicodec libp2p-relay-rsvp
+const VOUCHER_PAYLOAD_TYPE: [u8; 2] = [0x03, 0x02];
+
+// RelayLimit of a relayed connection, none is unlimited
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RelayLimit {
+    pub duration: Option<Duration>,
+    pub data: Option<u64>,
+}
+
+impl RelayLimit {
+    pub fn to_payload(&self) -> Limit {
+        Limit {
+            duration: self
+                .duration
+                .map(|duration| duration.as_secs().try_into().unwrap_or(u32::MAX)),
+            data: self.data,
+        }
+    }
+}
+
+impl From<Limit> for RelayLimit {
+    fn from(payload: Limit) -> Self {
+        Self {
+            duration: payload
+                .duration
+                .map(|secs| Duration::from_secs(secs.into())),
+            data: payload.data,
+        }
+    }
+}
+
+// CircuitAddr of a peer reachable through a relay,
+// /<relay addr>/p2p/<relay>/p2p-circuit/p2p/<target>
+#[derive(Debug, Clone, PartialEq)]
+pub struct CircuitAddr {
+    // the transport address of the relay, empty if only its peer id is known
+    pub relay_addr: Multiaddr,
+    pub relay: PeerId,
+    pub target: PeerId,
+}
+
+impl CircuitAddr {
+    pub fn to_multiaddr(&self) -> Result<Multiaddr, Error> {
+        Ok(self
+            .relay_addr
+            .clone()
+            .with(p2p_protocol(&self.relay)?)
+            .with(Protocol::P2pCircuit)
+            .with(p2p_protocol(&self.target)?))
+    }
+}
+
+impl TryFrom<&Multiaddr> for CircuitAddr {
+    type Error = Error;
+
+    fn try_from(addr: &Multiaddr) -> Result<Self, Self::Error> {
+        let protocols: Vec<Protocol> = addr.iter().collect();
+        let circuit = protocols
+            .iter()
+            .position(|protocol| *protocol == Protocol::P2pCircuit)
+            .ok_or(error::unsupported("not a circuit address"))?;
+        let (relay, target) = match (
+            circuit.checked_sub(1).map(|i| &protocols[i]),
+            &protocols[circuit + 1..],
+        ) {
+            (Some(relay), [target]) => (p2p_peer_id(relay)?, p2p_peer_id(target)?),
+            _ => return Err(error::parse_error()),
+        };
+        Ok(Self {
+            relay_addr: protocols[..circuit - 1].iter().cloned().collect(),
+            relay,
+            target,
+        })
+    }
+}
+
+// ReservationVoucher signed by the relay, proving the reservation of the peer
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReservationVoucher {
+    pub relay: PeerId,
+    pub peer: PeerId,
+    // with a precision of seconds
+    pub expire: SystemTime,
+}
+
+impl ReservationVoucher {
+    pub fn to_signed_envelope(&self, private_key: &PrivateKey) -> Result<Vec<u8>, Error> {
+        let relay: PeerId = private_key.public().try_into()?;
+        if relay != self.relay {
+            return Err(error::invalid_input("voucher of another relay"));
+        }
+
+        let payload = protobuf_encode(&VoucherPayload {
+            relay: self.relay.to_bytes()?,
+            peer: self.peer.to_bytes()?,
+            expiration: unix_secs(self.expire),
+        })?;
+        seal_envelope(private_key, VOUCHER_DOMAIN, &VOUCHER_PAYLOAD_TYPE, payload)
+    }
+
+    // verify the envelope is signed by the relay of the voucher
+    pub fn from_signed_envelope(buf: &[u8]) -> Result<Self, Error> {
+        let (public_key, payload) = open_envelope(buf, VOUCHER_DOMAIN, &VOUCHER_PAYLOAD_TYPE)?;
+        let voucher: VoucherPayload = protobuf_decode(&payload)?;
+        let relay = PeerId::from_bytes(&voucher.relay)?;
+        let signer: PeerId = public_key.try_into()?;
+        if relay != signer {
+            log::debug!("voucher signed by {:?} for {:?}", signer, relay);
+            return Err(error::verification_failed());
+        }
+        Ok(Self {
+            relay,
+            peer: PeerId::from_bytes(&voucher.peer)?,
+            expire: UNIX_EPOCH + Duration::from_secs(voucher.expiration),
+        })
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+fn peer_payload(peer_id: &PeerId) -> Result<PeerPayload, Error> {
+    Ok(PeerPayload {
+        id: peer_id.to_bytes()?,
+        addrs: Vec::new(),
+    })
+}
+
+fn status_error(status: Option<Status>) -> Error {
+    error::other(&format!("relay status {:?}", status.unwrap_or_default()))
+}
+
+async fn write_message<M>(stream: &mut io::BoxedStream, message: &M) -> Result<(), Error>
+where
+    M: MessageWrite,
+{
+    io::write_protobuf_prefixed(stream, message).await
+}
+
+async fn read_message<M>(stream: &mut io::BoxedStream) -> Result<M, Error>
+where
+    M: for<'a> MessageRead<'a>,
+{
+    io::read_protobuf_prefixed(stream, MAX_RELAY_MESSAGE_SIZE).await
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::net::{tcpaddr_to_multiaddr, Manager, Ping};
+    use async_io::Async;
+    use std::{net::TcpListener, sync::Arc};
+
+    // a relay listening on localhost
+    pub(crate) fn spawn_relay(
+        resources: RelayResources,
+    ) -> Result<(PeerId, Multiaddr, RelayServer), Error> {
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let socket_addr = listener.get_ref().local_addr()?;
+        let private_key = PrivateKey::generate_ed25519();
+        let server = RelayServer::new(private_key.clone())?
+            .with_listen_addrs(vec![tcpaddr_to_multiaddr(&socket_addr)])
+            .with_resources(resources);
+        let manager = Manager::new(private_key, socket_addr).with_protocol(server.clone());
+        let peer_id = manager.peer_id()?;
+        async_std::task::spawn(async move { manager.listen(listener).await });
+        Ok((peer_id, tcpaddr_to_multiaddr(&socket_addr), server))
+    }
+
+    // a peer reachable through relays, answering pings
+    pub(super) fn spawn_peer() -> (Arc<Manager>, RelayClient) {
+        let client = RelayClient::new();
+        let manager = Manager::new(PrivateKey::generate_ed25519(), ([127, 0, 0, 1], 0).into())
+            .with_protocol(client.clone())
+            .with_protocol(Ping::new());
+        (Arc::new(manager), client)
+    }
+
+    #[test]
+    fn test_circuit_addr() -> Result<(), Error> {
+        let relay: PeerId = PrivateKey::generate_ed25519().public().try_into()?;
+        let target: PeerId = PrivateKey::generate_ed25519().public().try_into()?;
+        let circuit = CircuitAddr {
+            relay_addr: "/ip4/127.0.0.1/tcp/4001".parse().unwrap(),
+            relay,
+            target,
+        };
+        let addr = circuit.to_multiaddr()?;
+        assert_eq!(CircuitAddr::try_from(&addr)?, circuit);
+
+        // the target is required
+        let addr: Multiaddr = addr.iter().take(4).collect();
+        assert!(CircuitAddr::try_from(&addr).is_err());
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+        assert!(CircuitAddr::try_from(&addr).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reservation_voucher() -> Result<(), Error> {
+        let private_key = PrivateKey::generate_ed25519();
+        let voucher = ReservationVoucher {
+            relay: private_key.public().try_into()?,
+            peer: PrivateKey::generate_ed25519().public().try_into()?,
+            expire: UNIX_EPOCH + Duration::from_secs(unix_secs(SystemTime::now())),
+        };
+        let envelope = voucher.to_signed_envelope(&private_key)?;
+        assert_eq!(
+            ReservationVoucher::from_signed_envelope(&envelope)?,
+            voucher
+        );
+
+        // signed by another key
+        assert!(voucher
+            .to_signed_envelope(&PrivateKey::generate_ed25519())
+            .is_err());
+        Ok(())
+    }
+}
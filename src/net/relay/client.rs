@@ -0,0 +1,515 @@
+use super::{
+    peer_payload, read_message, status_error, write_message, CircuitAddr, RelayLimit,
+    ReservationVoucher, PROTOCOL_RELAY_HOP, PROTOCOL_RELAY_STOP,
+};
+use crate::{
+    error::{self, Error},
+    identity::PeerId,
+    io,
+    net::{p2p_protocol, Connection, Manager, NatStatus, Protocol, ProtocolRegistry},
+    payload::circuit::{mod_HopMessage, mod_StopMessage, HopMessage, Status, StopMessage},
+};
+use async_io::Timer;
+use futures::{
+    channel::mpsc,
+    future::{self, Either},
+    lock::Mutex as AsyncMutex,
+    AsyncWriteExt, StreamExt,
+};
+use multiaddr::{Multiaddr, Protocol as AddrProtocol};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+// reservations are refreshed ahead of their expiry
+const REFRESH_MARGIN: Duration = Duration::from_secs(60);
+// however short the reservations of a relay are
+const DEFAULT_MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_PENDING_CIRCUITS: usize = 16;
+
+// Reservation of a slot on a relay, through which the local peer is reachable
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reservation {
+    pub relay: PeerId,
+    pub expire: SystemTime,
+    // public addresses of the relay
+    pub addrs: Vec<Multiaddr>,
+    pub voucher: Option<ReservationVoucher>,
+    pub limit: RelayLimit,
+}
+
+impl Reservation {
+    // addresses to advertise, dialed as /<relay addr>/p2p/<relay>/p2p-circuit/p2p/<local>
+    pub fn circuit_addrs(&self) -> Result<Vec<Multiaddr>, Error> {
+        let relay = p2p_protocol(&self.relay)?;
+        Ok(self
+            .addrs
+            .iter()
+            .map(|addr| {
+                let mut addr: Multiaddr = addr
+                    .iter()
+                    .filter(|protocol| !matches!(protocol, AddrProtocol::P2p(_)))
+                    .collect();
+                addr.push(relay.clone());
+                addr.with(AddrProtocol::P2pCircuit)
+            })
+            .collect())
+    }
+}
+
+// a circuit opened by a remote peer, waiting to be upgraded
+struct Circuit {
+    stream: io::BoxedStream,
+    relay: Connection,
+}
+
+// RelayClient
+//
+// Client side of circuit relay v2, reserves slots on relays and dials or
+// accepts connections through them
+#[derive(Clone)]
+pub struct RelayClient {
+    timeout: Duration,
+    min_refresh_interval: Duration,
+    reservations: Arc<Mutex<HashMap<PeerId, Reservation>>>,
+    circuits_tx: mpsc::Sender<Circuit>,
+    circuits_rx: Arc<AsyncMutex<mpsc::Receiver<Circuit>>>,
+}
+
+impl RelayClient {
+    pub fn new() -> Self {
+        let (circuits_tx, circuits_rx) = mpsc::channel(MAX_PENDING_CIRCUITS);
+        Self {
+            timeout: DEFAULT_TIMEOUT,
+            min_refresh_interval: DEFAULT_MIN_REFRESH_INTERVAL,
+            reservations: Arc::new(Mutex::new(HashMap::new())),
+            circuits_tx,
+            circuits_rx: Arc::new(AsyncMutex::new(circuits_rx)),
+        }
+    }
+
+    // timeout of a hop or stop exchange
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // reservations are not renewed more often than the interval
+    pub fn with_min_refresh_interval(mut self, min_refresh_interval: Duration) -> Self {
+        self.min_refresh_interval = min_refresh_interval;
+        self
+    }
+
+    pub fn reservation(&self, relay: &PeerId) -> Option<Reservation> {
+        self.reservations.lock().unwrap().get(relay).cloned()
+    }
+
+    pub fn reservations(&self) -> Vec<Reservation> {
+        self.reservations
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    // reserve a slot on the relay, or renew the existing one
+    pub async fn reserve(
+        &self,
+        manager: &Manager,
+        relay: &PeerId,
+        addrs: &[Multiaddr],
+    ) -> Result<Reservation, Error> {
+        let connection = manager.dial_peer(relay, addrs).await?;
+        let reservation = io::timeout(self.timeout, async {
+            let mut stream = connection.open_stream(PROTOCOL_RELAY_HOP).await?;
+            let request = HopMessage {
+                type_pb: mod_HopMessage::Type::RESERVE,
+                ..Default::default()
+            };
+            write_message(&mut stream, &request).await?;
+            let response: HopMessage = read_message(&mut stream).await?;
+            stream.close().await?;
+            if response.type_pb != mod_HopMessage::Type::STATUS
+                || response.status != Some(Status::OK)
+            {
+                return Err(status_error(response.status));
+            }
+            let payload = response.reservation.ok_or(error::message_malformed())?;
+            let voucher = match payload.voucher {
+                Some(envelope) => Some(ReservationVoucher::from_signed_envelope(&envelope)?),
+                None => None,
+            };
+            if let Some(voucher) = &voucher {
+                if &voucher.relay != relay || voucher.peer != manager.peer_id()? {
+                    return Err(error::verification_failed());
+                }
+            }
+            // a reservation already expired would be renewed at once
+            let expire = UNIX_EPOCH
+                .checked_add(Duration::from_secs(payload.expire))
+                .filter(|expire| *expire > SystemTime::now())
+                .ok_or(error::invalid_data("reservation expiry not in the future"))?;
+            Ok(Reservation {
+                relay: relay.clone(),
+                expire,
+                addrs: payload
+                    .addrs
+                    .into_iter()
+                    .filter_map(|addr| Multiaddr::try_from(addr).ok())
+                    .collect(),
+                voucher,
+                limit: response.limit.map(RelayLimit::from).unwrap_or_default(),
+            })
+        })
+        .await?;
+        log::debug!("reserved on {:?} until {:?}", relay, reservation.expire);
+        self.reservations
+            .lock()
+            .unwrap()
+            .insert(relay.clone(), reservation.clone());
+        Ok(reservation)
+    }
+
+    // keep the reservation on the relay refreshed until renewing it fails
+    pub async fn keep_reserved(
+        &self,
+        manager: &Manager,
+        relay: &PeerId,
+        addrs: &[Multiaddr],
+    ) -> Result<(), Error> {
+        loop {
+            let reservation = match self.reserve(manager, relay, addrs).await {
+                Ok(reservation) => reservation,
+                Err(err) => {
+                    self.reservations.lock().unwrap().remove(relay);
+                    return Err(err);
+                }
+            };
+            let remaining = reservation
+                .expire
+                .duration_since(SystemTime::now())
+                .unwrap_or_default();
+            Timer::after(self.refresh_delay(remaining)).await;
+        }
+    }
+
+    // ahead of the expiry by the margin or half the time remaining, no
+    // sooner than the min interval, and while a quarter of it still remains
+    // so that short reservations do not expire first
+    fn refresh_delay(&self, remaining: Duration) -> Duration {
+        remaining
+            .saturating_sub(REFRESH_MARGIN)
+            .max(remaining / 2)
+            .max(self.min_refresh_interval.min(remaining - remaining / 4))
+    }
+
+    // keep a reservation on the relay only while the autonat of the manager
+    // finds the local peer private, until renewing it fails
+    pub async fn reserve_while_private(
+        &self,
+        manager: &Manager,
+        relay: &PeerId,
+        addrs: &[Multiaddr],
+    ) -> Result<(), Error> {
+        let autonat = manager
+            .autonat()
+            .ok_or(error::invalid_input("manager without autonat"))?;
+        let mut changes = autonat.subscribe();
+        loop {
+            if autonat.status() != NatStatus::Private {
+                match changes.next().await {
+                    Some(_) => continue,
+                    None => return Ok(()),
+                }
+            }
+            let public = Box::pin(async {
+                while let Some(status) = changes.next().await {
+                    if status != NatStatus::Private {
+                        break;
+                    }
+                }
+            });
+            let keep = Box::pin(self.keep_reserved(manager, relay, addrs));
+            if let Either::Right((res, _)) = future::select(public, keep).await {
+                return res;
+            }
+            // the relay is not needed any more, the slot expires unrenewed
+            log::debug!("reservation on {:?} dropped", relay);
+            self.reservations.lock().unwrap().remove(relay);
+        }
+    }
+
+    // dial the target of the circuit address through its relay
+    pub async fn dial(&self, manager: &Manager, addr: &Multiaddr) -> Result<Connection, Error> {
+        let circuit = CircuitAddr::try_from(addr)?;
+        let relay_addrs: Vec<Multiaddr> = match circuit.relay_addr.is_empty() {
+            true => Vec::new(),
+            false => vec![circuit.relay_addr.clone()],
+        };
+        let relay = manager.dial_peer(&circuit.relay, &relay_addrs).await?;
+
+        let stream = io::timeout(self.timeout, async {
+            let mut stream = relay.open_stream(PROTOCOL_RELAY_HOP).await?;
+            let request = HopMessage {
+                type_pb: mod_HopMessage::Type::CONNECT,
+                peer: Some(peer_payload(&circuit.target)?),
+                ..Default::default()
+            };
+            write_message(&mut stream, &request).await?;
+            let response: HopMessage = read_message(&mut stream).await?;
+            if response.type_pb != mod_HopMessage::Type::STATUS
+                || response.status != Some(Status::OK)
+            {
+                return Err(status_error(response.status));
+            }
+            Ok(stream)
+        })
+        .await?;
+
+        let connection = manager.connect_relayed(stream, &relay).await?;
+        if connection.info().peer_id != circuit.target {
+            log::debug!(
+                "{:?} is not {:?}",
+                connection.info().peer_id,
+                circuit.target
+            );
+            connection.close();
+            return Err(error::verification_failed());
+        }
+        Ok(connection)
+    }
+
+    // the next connection opened through a relay by a remote peer
+    pub async fn accept(&self, manager: &Manager) -> Result<Connection, Error> {
+        let circuit = self
+            .circuits_rx
+            .lock()
+            .await
+            .next()
+            .await
+            .ok_or(error::other("relay client dropped"))?;
+        manager.accept_relayed(circuit.stream, &circuit.relay).await
+    }
+
+    // circuits are accepted only from relays holding a reservation of ours
+    async fn handle_stop(
+        self,
+        connection: Connection,
+        mut stream: io::BoxedStream,
+    ) -> Result<(), Error> {
+        let request: StopMessage = io::timeout(self.timeout, read_message(&mut stream)).await?;
+        let status = match (request.type_pb, &request.peer) {
+            (mod_StopMessage::Type::CONNECT, Some(_)) => {
+                match self.reservation(&connection.info().peer_id) {
+                    Some(_) => Status::OK,
+                    None => Status::PERMISSION_DENIED,
+                }
+            }
+            (mod_StopMessage::Type::CONNECT, None) => Status::MALFORMED_MESSAGE,
+            _ => Status::UNEXPECTED_MESSAGE,
+        };
+        let response = StopMessage {
+            type_pb: mod_StopMessage::Type::STATUS,
+            status: Some(status),
+            ..Default::default()
+        };
+        write_message(&mut stream, &response).await?;
+        if status != Status::OK {
+            stream.close().await?;
+            return Err(status_error(Some(status)));
+        }
+
+        let circuit = Circuit {
+            stream,
+            relay: connection,
+        };
+        self.circuits_tx
+            .clone()
+            .try_send(circuit)
+            .map_err(|_| error::other("too many pending circuits"))
+    }
+}
+
+impl Default for RelayClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Protocol for RelayClient {
+    fn register(self, registry: &mut ProtocolRegistry) {
+        registry.register(PROTOCOL_RELAY_STOP, move |connection, stream| {
+            self.clone().handle_stop(connection, stream)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        identity::PrivateKey,
+        net::{
+            relay::tests::{spawn_peer, spawn_relay},
+            test_support::wait_until,
+            AutoNat, Ping, RelayResources,
+        },
+    };
+    use futures::FutureExt;
+
+    #[async_std::test]
+    async fn test_relay_client_dial_and_accept() -> Result<(), Error> {
+        let (relay, relay_addr, _) = spawn_relay(RelayResources::default())?;
+        let (listener, listener_client) = spawn_peer();
+        let (dialer, dialer_client) = spawn_peer();
+
+        let reservation = listener_client
+            .reserve(&listener, &relay, std::slice::from_ref(&relay_addr))
+            .await?;
+        assert_eq!(
+            listener_client.reservation(&relay),
+            Some(reservation.clone())
+        );
+        assert_eq!(
+            reservation.voucher.as_ref().unwrap().peer,
+            listener.peer_id()?
+        );
+        let circuit_addr = reservation.circuit_addrs()?[0]
+            .clone()
+            .with(p2p_protocol(&listener.peer_id()?)?);
+        assert_eq!(
+            CircuitAddr::try_from(&circuit_addr)?,
+            CircuitAddr {
+                relay_addr,
+                relay: relay.clone(),
+                target: listener.peer_id()?,
+            }
+        );
+
+        let (inbound, outbound) = futures::join!(
+            listener_client.accept(&listener),
+            dialer_client.dial(&dialer, &circuit_addr)
+        );
+        let (inbound, outbound) = (inbound?, outbound?);
+        assert_eq!(inbound.info().peer_id, dialer.peer_id()?);
+        assert_eq!(inbound.info().relay, Some(relay.clone()));
+        assert_eq!(outbound.info().peer_id, listener.peer_id()?);
+        assert_eq!(outbound.info().relay, Some(relay.clone()));
+        // the relay's address is not where the peers are seen at
+        assert_eq!(inbound.info().observed_addr(), None);
+        assert_eq!(outbound.info().observed_addr(), None);
+
+        // the relayed connection is muxed end to end
+        Ping::new().ping(&outbound).await?;
+        Ping::new().ping(&inbound).await?;
+
+        // peers without a reservation are not reachable
+        let unreserved = CircuitAddr {
+            target: PrivateKey::generate_ed25519().public().try_into()?,
+            ..CircuitAddr::try_from(&circuit_addr)?
+        };
+        let unreserved = dialer_client
+            .dial(&dialer, &unreserved.to_multiaddr()?)
+            .await;
+        assert!(unreserved.is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_relay_client_keep_reserved() -> Result<(), Error> {
+        let (relay, relay_addr, _) = spawn_relay(RelayResources {
+            reservation_ttl: Duration::from_secs(2),
+            ..Default::default()
+        })?;
+        let (peer, client) = spawn_peer();
+        let client = client.with_min_refresh_interval(Duration::from_secs(1));
+
+        let refresh = async_std::task::spawn({
+            let (peer, client, relay) = (peer.clone(), client.clone(), relay.clone());
+            async move { client.keep_reserved(&peer, &relay, &[relay_addr]).await }
+        });
+        wait_until(|| client.reservation(&relay).is_some()).await?;
+        let first = client.reservation(&relay).unwrap();
+        wait_until(|| {
+            client
+                .reservation(&relay)
+                .is_some_and(|renewed| renewed.expire > first.expire)
+        })
+        .await?;
+        let renewed = client.reservation(&relay).unwrap();
+        assert!(renewed.expire > SystemTime::now());
+        assert!(refresh.now_or_never().is_none());
+
+        // reservations expiring at once are refused
+        let (relay, relay_addr, _) = spawn_relay(RelayResources {
+            reservation_ttl: Duration::ZERO,
+            ..Default::default()
+        })?;
+        assert!(client.reserve(&peer, &relay, &[relay_addr]).await.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_relay_client_refresh_delay() {
+        let client = RelayClient::new();
+        assert_eq!(
+            client.refresh_delay(Duration::from_secs(3600)),
+            Duration::from_secs(3540)
+        );
+        assert_eq!(
+            client.refresh_delay(Duration::from_secs(90)),
+            Duration::from_secs(45)
+        );
+        assert_eq!(
+            client.refresh_delay(Duration::from_secs(40)),
+            Duration::from_secs(30)
+        );
+        // shorter than the min interval, renewed before it expires
+        assert_eq!(
+            client.refresh_delay(Duration::from_secs(10)),
+            Duration::from_millis(7500)
+        );
+    }
+
+    #[async_std::test]
+    async fn test_relay_client_reserve_while_private() -> Result<(), Error> {
+        let (relay, relay_addr, _) = spawn_relay(RelayResources::default())?;
+        let private_key = PrivateKey::generate_ed25519();
+        let autonat = AutoNat::new(private_key.public().try_into()?);
+        let client = RelayClient::new();
+        let peer = Arc::new(
+            Manager::new(private_key, ([127, 0, 0, 1], 0).into())
+                .with_protocol(client.clone())
+                .with_autonat(autonat.clone()),
+        );
+
+        let reserving = async_std::task::spawn({
+            let (peer, client, relay) = (peer.clone(), client.clone(), relay.clone());
+            async move {
+                client
+                    .reserve_while_private(&peer, &relay, &[relay_addr])
+                    .await
+            }
+        });
+        assert_eq!(client.reservation(&relay), None);
+
+        autonat.record(NatStatus::Private, None);
+        wait_until(|| client.reservation(&relay).is_some()).await?;
+
+        autonat.record(NatStatus::Public, None);
+        wait_until(|| client.reservation(&relay).is_none()).await?;
+        assert!(reserving.now_or_never().is_none());
+
+        // the status is needed to reserve
+        let (peer, client) = spawn_peer();
+        assert!(client
+            .reserve_while_private(&peer, &relay, &[])
+            .await
+            .is_err());
+        Ok(())
+    }
+}
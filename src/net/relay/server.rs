@@ -0,0 +1,469 @@
+use super::{
+    peer_payload, read_message, status_error, unix_secs, write_message, RelayLimit,
+    ReservationVoucher, PROTOCOL_RELAY_HOP, PROTOCOL_RELAY_STOP,
+};
+use crate::{
+    error::Error,
+    identity::{PeerId, PrivateKey},
+    io,
+    net::{p2p_protocol, Connection, Protocol, ProtocolRegistry},
+    payload::circuit::{
+        mod_HopMessage, mod_StopMessage, HopMessage, Reservation as ReservationPayload, Status,
+        StopMessage,
+    },
+};
+use futures::{future, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use multiaddr::Multiaddr;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+// RelayResources spent by the relay on other peers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RelayResources {
+    pub reservation_ttl: Duration,
+    // reservations held at once
+    pub max_reservations: usize,
+    // reservations held at once by peers sharing an ip address
+    pub max_reservations_per_ip: usize,
+    // circuits of a peer at once, as either end
+    pub max_circuits_per_peer: usize,
+    // caps of each relayed connection, the data cap is per direction
+    pub limit: RelayLimit,
+}
+
+impl Default for RelayResources {
+    fn default() -> Self {
+        Self {
+            reservation_ttl: Duration::from_secs(60 * 60),
+            max_reservations: 128,
+            max_reservations_per_ip: 8,
+            max_circuits_per_peer: 16,
+            limit: RelayLimit {
+                duration: Some(Duration::from_secs(2 * 60)),
+                data: Some(128 * 1024),
+            },
+        }
+    }
+}
+
+struct Reserved {
+    connection: Connection,
+    expire: SystemTime,
+}
+
+// counts a circuit against both of its ends until dropped
+struct CircuitGuard {
+    circuits: Arc<Mutex<HashMap<PeerId, usize>>>,
+    peers: [PeerId; 2],
+}
+
+impl Drop for CircuitGuard {
+    fn drop(&mut self) {
+        let mut circuits = self.circuits.lock().unwrap();
+        for peer in &self.peers {
+            if let Some(count) = circuits.get_mut(peer) {
+                *count -= 1;
+                if *count == 0 {
+                    circuits.remove(peer);
+                }
+            }
+        }
+    }
+}
+
+// RelayServer
+//
+// Hop side of circuit relay v2, grants reservations and splices the
+// connections opened to the reserved peers
+#[derive(Clone)]
+pub struct RelayServer {
+    private_key: PrivateKey,
+    peer_id: PeerId,
+    listen_addrs: Vec<Multiaddr>,
+    resources: RelayResources,
+    timeout: Duration,
+    reservations: Arc<Mutex<HashMap<PeerId, Reserved>>>,
+    circuits: Arc<Mutex<HashMap<PeerId, usize>>>,
+}
+
+impl RelayServer {
+    pub fn new(private_key: PrivateKey) -> Result<Self, Error> {
+        Ok(Self {
+            peer_id: private_key.public().try_into()?,
+            private_key,
+            listen_addrs: Vec::new(),
+            resources: RelayResources::default(),
+            timeout: DEFAULT_TIMEOUT,
+            reservations: Arc::new(Mutex::new(HashMap::new())),
+            circuits: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    // public addresses of the relay, handed to the reserving peers
+    pub fn with_listen_addrs(mut self, listen_addrs: Vec<Multiaddr>) -> Self {
+        self.listen_addrs = listen_addrs;
+        self
+    }
+
+    pub fn with_resources(mut self, resources: RelayResources) -> Self {
+        self.resources = resources;
+        self
+    }
+
+    // timeout of a hop or stop exchange
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // peers holding an unexpired reservation
+    pub fn reserved_peers(&self) -> Vec<PeerId> {
+        let mut reservations = self.reservations.lock().unwrap();
+        Self::remove_expired(&mut reservations);
+        reservations.keys().cloned().collect()
+    }
+
+    // circuits of the peer currently relayed
+    pub fn circuits(&self, peer_id: &PeerId) -> usize {
+        self.circuits
+            .lock()
+            .unwrap()
+            .get(peer_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn remove_expired(reservations: &mut HashMap<PeerId, Reserved>) {
+        let now = SystemTime::now();
+        reservations
+            .retain(|_, reserved| reserved.expire > now && !reserved.connection.is_closed());
+    }
+
+    async fn handle_hop(
+        self,
+        connection: Connection,
+        mut stream: io::BoxedStream,
+    ) -> Result<(), Error> {
+        let request: HopMessage = io::timeout(self.timeout, read_message(&mut stream)).await?;
+        let response = match request.type_pb {
+            mod_HopMessage::Type::RESERVE => self.reserve(&connection)?,
+            mod_HopMessage::Type::CONNECT => {
+                return self.connect(connection, stream, request).await;
+            }
+            mod_HopMessage::Type::STATUS => status_message(Status::UNEXPECTED_MESSAGE),
+        };
+        write_message(&mut stream, &response).await?;
+        stream.close().await
+    }
+
+    // reservations are renewed by reserving again, over any connection
+    fn reserve(&self, connection: &Connection) -> Result<HopMessage, Error> {
+        // a relayed peer could be reached through its own relay
+        if connection.info().relay.is_some() {
+            return Ok(status_message(Status::PERMISSION_DENIED));
+        }
+        let peer_id = &connection.info().peer_id;
+        let ip = connection.info().remote_addr.ip();
+        let expire = UNIX_EPOCH
+            + Duration::from_secs(unix_secs(
+                SystemTime::now() + self.resources.reservation_ttl,
+            ));
+
+        let mut reservations = self.reservations.lock().unwrap();
+        Self::remove_expired(&mut reservations);
+        if !reservations.contains_key(peer_id) {
+            let same_ip = reservations
+                .values()
+                .filter(|reserved| reserved.connection.info().remote_addr.ip() == ip)
+                .count();
+            if reservations.len() >= self.resources.max_reservations
+                || same_ip >= self.resources.max_reservations_per_ip
+            {
+                log::debug!("reservation of {:?} refused", peer_id);
+                return Ok(status_message(Status::RESOURCE_LIMIT_EXCEEDED));
+            }
+        }
+        reservations.insert(
+            peer_id.clone(),
+            Reserved {
+                connection: connection.clone(),
+                expire,
+            },
+        );
+        drop(reservations);
+
+        let voucher = ReservationVoucher {
+            relay: self.peer_id.clone(),
+            peer: peer_id.clone(),
+            expire,
+        };
+        let relay = p2p_protocol(&self.peer_id)?;
+        Ok(HopMessage {
+            reservation: Some(ReservationPayload {
+                expire: unix_secs(expire),
+                addrs: self
+                    .listen_addrs
+                    .iter()
+                    .map(|addr| addr.clone().with(relay.clone()).to_vec())
+                    .collect(),
+                voucher: Some(voucher.to_signed_envelope(&self.private_key)?),
+            }),
+            limit: Some(self.resources.limit.to_payload()),
+            ..status_message(Status::OK)
+        })
+    }
+
+    async fn connect(
+        &self,
+        connection: Connection,
+        mut stream: io::BoxedStream,
+        request: HopMessage,
+    ) -> Result<(), Error> {
+        let (stop, guard) = match self.open_circuit(&connection, request).await {
+            Ok(circuit) => circuit,
+            Err(status) => {
+                write_message(&mut stream, &status_message(status)).await?;
+                stream.close().await?;
+                return Err(status_error(Some(status)));
+            }
+        };
+        let response = HopMessage {
+            limit: Some(self.resources.limit.to_payload()),
+            ..status_message(Status::OK)
+        };
+        write_message(&mut stream, &response).await?;
+
+        splice(stream, stop, self.resources.limit).await;
+        drop(guard);
+        Ok(())
+    }
+
+    async fn open_circuit(
+        &self,
+        connection: &Connection,
+        request: HopMessage,
+    ) -> Result<(io::BoxedStream, CircuitGuard), Status> {
+        if connection.info().relay.is_some() {
+            return Err(Status::PERMISSION_DENIED);
+        }
+        let src = connection.info().peer_id.clone();
+        let dst = request
+            .peer
+            .and_then(|peer| PeerId::from_bytes(&peer.id).ok())
+            .ok_or(Status::MALFORMED_MESSAGE)?;
+
+        let target = {
+            let mut reservations = self.reservations.lock().unwrap();
+            Self::remove_expired(&mut reservations);
+            reservations
+                .get(&dst)
+                .map(|reserved| reserved.connection.clone())
+                .ok_or(Status::NO_RESERVATION)?
+        };
+        let guard = self
+            .acquire_circuit(src.clone(), dst.clone())
+            .ok_or(Status::RESOURCE_LIMIT_EXCEEDED)?;
+
+        let stop = io::timeout(self.timeout, self.stop(&target, &src))
+            .await
+            .map_err(|err| {
+                log::debug!("circuit from {:?} to {:?} failed, {:?}", src, dst, err);
+                Status::CONNECTION_FAILED
+            })?;
+        Ok((stop, guard))
+    }
+
+    fn acquire_circuit(&self, src: PeerId, dst: PeerId) -> Option<CircuitGuard> {
+        let mut circuits = self.circuits.lock().unwrap();
+        let max = self.resources.max_circuits_per_peer;
+        if [&src, &dst]
+            .iter()
+            .any(|peer| circuits.get(*peer).copied().unwrap_or_default() >= max)
+        {
+            return None;
+        }
+        for peer in [&src, &dst] {
+            *circuits.entry(peer.clone()).or_default() += 1;
+        }
+        Some(CircuitGuard {
+            circuits: self.circuits.clone(),
+            peers: [src, dst],
+        })
+    }
+
+    async fn stop(&self, target: &Connection, src: &PeerId) -> Result<io::BoxedStream, Error> {
+        let mut stream = target.open_stream(PROTOCOL_RELAY_STOP).await?;
+        let request = StopMessage {
+            type_pb: mod_StopMessage::Type::CONNECT,
+            peer: Some(peer_payload(src)?),
+            limit: Some(self.resources.limit.to_payload()),
+            ..Default::default()
+        };
+        write_message(&mut stream, &request).await?;
+        let response: StopMessage = read_message(&mut stream).await?;
+        if response.type_pb != mod_StopMessage::Type::STATUS || response.status != Some(Status::OK)
+        {
+            return Err(status_error(response.status));
+        }
+        Ok(stream)
+    }
+}
+
+impl Protocol for RelayServer {
+    fn register(self, registry: &mut ProtocolRegistry) {
+        registry.register(PROTOCOL_RELAY_HOP, move |connection, stream| {
+            self.clone().handle_hop(connection, stream)
+        });
+    }
+}
+
+fn status_message(status: Status) -> HopMessage {
+    HopMessage {
+        type_pb: mod_HopMessage::Type::STATUS,
+        status: Some(status),
+        ..Default::default()
+    }
+}
+
+// relay both directions until they end, the streams are reset once the
+// duration is over
+async fn splice(src: io::BoxedStream, dst: io::BoxedStream, limit: RelayLimit) {
+    let (mut src_reader, mut src_writer) = src.split();
+    let (mut dst_reader, mut dst_writer) = dst.split();
+    let relay = async {
+        let (forward, backward) = future::join(
+            relay_half(&mut src_reader, &mut dst_writer, limit.data),
+            relay_half(&mut dst_reader, &mut src_writer, limit.data),
+        )
+        .await;
+        log::debug!("circuit relayed {:?} and {:?}", forward, backward);
+        Ok(())
+    };
+    let res = match limit.duration {
+        Some(duration) => io::timeout(duration, relay).await,
+        None => relay.await,
+    };
+    if let Err(err) = res {
+        log::debug!("circuit ended, {:?}", err);
+    }
+}
+
+// the writer is closed once the reader ends or the data cap is reached
+async fn relay_half<R, W>(reader: R, writer: &mut W, data: Option<u64>) -> Result<u64, Error>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let relayed = match data {
+        Some(data) => futures::io::copy(reader.take(data), writer).await?,
+        None => futures::io::copy(reader, writer).await?,
+    };
+    writer.close().await?;
+    Ok(relayed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::{
+        relay::tests::{spawn_peer, spawn_relay},
+        test_support::wait_until,
+        Ping,
+    };
+    use async_io::Timer;
+
+    #[async_std::test]
+    async fn test_relay_server_reservation_limits() -> Result<(), Error> {
+        let (relay, relay_addr, server) = spawn_relay(RelayResources {
+            max_reservations_per_ip: 1,
+            max_circuits_per_peer: 1,
+            ..Default::default()
+        })?;
+        let addrs = [relay_addr];
+        let (first, first_client) = spawn_peer();
+        let (second, second_client) = spawn_peer();
+
+        // renewing counts once, the other peer on the same ip is refused
+        first_client.reserve(&first, &relay, &addrs).await?;
+        first_client.reserve(&first, &relay, &addrs).await?;
+        assert!(second_client
+            .reserve(&second, &relay, &addrs)
+            .await
+            .is_err());
+        assert_eq!(server.reserved_peers(), vec![first.peer_id()?]);
+
+        // a single circuit of the peer at once
+        let circuit_addr = first_client.reservation(&relay).unwrap().circuit_addrs()?[0]
+            .clone()
+            .with(p2p_protocol(&first.peer_id()?)?);
+        let (inbound, outbound) = futures::join!(
+            first_client.accept(&first),
+            second_client.dial(&second, &circuit_addr)
+        );
+        let (_inbound, outbound) = (inbound?, outbound?);
+        assert_eq!(server.circuits(&first.peer_id()?), 1);
+        assert_eq!(server.circuits(&second.peer_id()?), 1);
+        let (third, third_client) = spawn_peer();
+        assert!(third_client.dial(&third, &circuit_addr).await.is_err());
+
+        // released once the relayed connection is closed
+        outbound.close();
+        let first = first.peer_id()?;
+        wait_until(|| server.circuits(&first) == 0).await?;
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_relay_server_circuit_caps() -> Result<(), Error> {
+        for limit in [
+            RelayLimit {
+                duration: None,
+                data: Some(8 * 1024),
+            },
+            RelayLimit {
+                duration: Some(Duration::from_secs(1)),
+                data: None,
+            },
+        ] {
+            let (relay, relay_addr, server) = spawn_relay(RelayResources {
+                limit,
+                ..Default::default()
+            })?;
+            let (listener, listener_client) = spawn_peer();
+            let (dialer, dialer_client) = spawn_peer();
+            let reservation = listener_client
+                .reserve(&listener, &relay, &[relay_addr])
+                .await?;
+            assert_eq!(reservation.limit, limit);
+
+            let circuit_addr = reservation.circuit_addrs()?[0]
+                .clone()
+                .with(p2p_protocol(&listener.peer_id()?)?);
+            let (inbound, outbound) = futures::join!(
+                listener_client.accept(&listener),
+                dialer_client.dial(&dialer, &circuit_addr)
+            );
+            let (_inbound, outbound) = (inbound?, outbound?);
+
+            // the circuit ends within the caps and is released
+            let ping = Ping::new();
+            let mut pings = 0;
+            while io::timeout(Duration::from_millis(500), ping.ping(&outbound))
+                .await
+                .is_ok()
+            {
+                pings += 1;
+                Timer::after(Duration::from_millis(10)).await;
+            }
+            assert!(pings > 0);
+            let dialer = dialer.peer_id()?;
+            wait_until(|| server.circuits(&dialer) == 0).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,28 @@
+mod client;
+mod server;
+mod store;
+
+use crate::{error::Error, io, payload::rendezvous::Message};
+use std::time::Duration;
+
+pub use client::Rendezvous;
+pub use server::RendezvousServer;
+pub use store::{Registration, RegistrationStore};
+
+pub const PROTOCOL_RENDEZVOUS: &str = "/rendezvous/1.0.0";
+const MAX_RENDEZVOUS_MESSAGE_SIZE: usize = 1024 * 1024;
+pub const MAX_NAMESPACE_LEN: usize = 255;
+// of the registrations without a ttl of their own
+pub const DEFAULT_REGISTRATION_TTL: Duration = Duration::from_secs(2 * 60 * 60);
+
+async fn write_message(stream: &mut io::BoxedStream, message: &Message) -> Result<(), Error> {
+    io::write_protobuf_prefixed(stream, message).await
+}
+
+async fn read_message(stream: &mut io::BoxedStream) -> Result<Message, Error> {
+    io::read_protobuf_prefixed(stream, MAX_RENDEZVOUS_MESSAGE_SIZE).await
+}
+
+fn is_valid_namespace(namespace: &str) -> bool {
+    !namespace.is_empty() && namespace.len() <= MAX_NAMESPACE_LEN
+}
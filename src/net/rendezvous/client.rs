@@ -0,0 +1,180 @@
+use super::{
+    is_valid_namespace, read_message, write_message, Registration, MAX_NAMESPACE_LEN,
+    PROTOCOL_RENDEZVOUS,
+};
+use crate::{
+    error::{self, Error},
+    identity::{PeerId, PrivateKey},
+    io,
+    net::{Connection, PeerRecord},
+    payload::rendezvous::{
+        mod_Message::{Discover, MessageType, Register, ResponseStatus, Unregister},
+        Message,
+    },
+};
+use futures::AsyncWriteExt;
+use multiaddr::Multiaddr;
+use std::time::Duration;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Rendezvous
+//
+// Registers the local peer in namespaces of a rendezvous server, and
+// discovers the peers registered there
+#[derive(Clone)]
+pub struct Rendezvous {
+    private_key: PrivateKey,
+    listen_addrs: Vec<Multiaddr>,
+    timeout: Duration,
+}
+
+impl Rendezvous {
+    pub fn new(private_key: PrivateKey) -> Self {
+        Self {
+            private_key,
+            listen_addrs: Vec::new(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    // addresses of the signed peer record registered
+    pub fn with_listen_addrs(mut self, listen_addrs: Vec<Multiaddr>) -> Self {
+        self.listen_addrs = listen_addrs;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // register in the namespace for the ttl, or the default one of the
+    // server, returns the ttl granted
+    pub async fn register(
+        &self,
+        connection: &Connection,
+        namespace: &str,
+        ttl: Option<Duration>,
+    ) -> Result<Duration, Error> {
+        if !is_valid_namespace(namespace) {
+            return Err(error::invalid_input("invalid namespace"));
+        }
+        if self.listen_addrs.is_empty() {
+            return Err(error::invalid_input("no listen addresses to register"));
+        }
+        let peer_id: PeerId = self.private_key.public().try_into()?;
+        let record = PeerRecord::new(peer_id, self.listen_addrs.clone());
+        let request = Message {
+            type_pb: Some(MessageType::REGISTER),
+            register: Some(Register {
+                ns: Some(namespace.to_string()),
+                signedPeerRecord: Some(record.to_signed_envelope(&self.private_key)?),
+                ttl: ttl.map(|ttl| ttl.as_secs()),
+            }),
+            ..Default::default()
+        };
+        let response = self.request(connection, &request).await?;
+        let response = match (response.type_pb, response.registerResponse) {
+            (Some(MessageType::REGISTER_RESPONSE), Some(response)) => response,
+            _ => return Err(error::message_malformed()),
+        };
+        match response.status {
+            Some(ResponseStatus::OK) => {
+                let ttl = response.ttl.ok_or(error::message_malformed())?;
+                Ok(Duration::from_secs(ttl))
+            }
+            status => Err(error::other(&format!(
+                "rendezvous {:?} {}",
+                status,
+                response.statusText.unwrap_or_default()
+            ))),
+        }
+    }
+
+    // the server sends no response
+    pub async fn unregister(&self, connection: &Connection, namespace: &str) -> Result<(), Error> {
+        let request = Message {
+            type_pb: Some(MessageType::UNREGISTER),
+            unregister: Some(Unregister {
+                ns: Some(namespace.to_string()),
+            }),
+            ..Default::default()
+        };
+        io::timeout(self.timeout, async {
+            let mut stream = connection.open_stream(PROTOCOL_RENDEZVOUS).await?;
+            write_message(&mut stream, &request).await?;
+            stream.close().await
+        })
+        .await
+    }
+
+    // the registrations in the namespace, or in all of them, after those of
+    // the cookie. Returns the cookie to discover the next ones with,
+    // registrations with an invalid peer record are dropped.
+    pub async fn discover(
+        &self,
+        connection: &Connection,
+        namespace: Option<&str>,
+        limit: Option<u64>,
+        cookie: Option<&[u8]>,
+    ) -> Result<(Vec<Registration>, Vec<u8>), Error> {
+        if namespace.is_some_and(|namespace| namespace.len() > MAX_NAMESPACE_LEN) {
+            return Err(error::invalid_input("invalid namespace"));
+        }
+        let request = Message {
+            type_pb: Some(MessageType::DISCOVER),
+            discover: Some(Discover {
+                ns: namespace.map(str::to_string),
+                limit,
+                // an empty cookie, as returned by some servers, is no cookie
+                cookie: cookie
+                    .filter(|cookie| !cookie.is_empty())
+                    .map(<[u8]>::to_vec),
+            }),
+            ..Default::default()
+        };
+        let response = self.request(connection, &request).await?;
+        let response = match (response.type_pb, response.discoverResponse) {
+            (Some(MessageType::DISCOVER_RESPONSE), Some(response)) => response,
+            _ => return Err(error::message_malformed()),
+        };
+        if response.status != Some(ResponseStatus::OK) {
+            return Err(error::other(&format!(
+                "rendezvous {:?} {}",
+                response.status,
+                response.statusText.unwrap_or_default()
+            )));
+        }
+
+        let mut registrations = Vec::new();
+        for register in response.registrations {
+            let signed_peer_record = register.signedPeerRecord.unwrap_or_default();
+            let peer_record = match PeerRecord::from_signed_envelope(&signed_peer_record) {
+                Ok(peer_record) => peer_record,
+                Err(err) => {
+                    log::debug!("rendezvous registration dropped, {:?}", err);
+                    continue;
+                }
+            };
+            registrations.push(Registration {
+                namespace: register.ns.unwrap_or_default(),
+                peer_record,
+                signed_peer_record,
+                ttl: Duration::from_secs(register.ttl.unwrap_or_default()),
+            });
+        }
+        Ok((registrations, response.cookie.unwrap_or_default()))
+    }
+
+    async fn request(&self, connection: &Connection, request: &Message) -> Result<Message, Error> {
+        io::timeout(self.timeout, async {
+            let mut stream = connection.open_stream(PROTOCOL_RENDEZVOUS).await?;
+            write_message(&mut stream, request).await?;
+            let response = read_message(&mut stream).await?;
+            stream.close().await?;
+            Ok(response)
+        })
+        .await
+    }
+}
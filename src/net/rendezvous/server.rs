@@ -0,0 +1,351 @@
+use super::{
+    is_valid_namespace, read_message, write_message, Registration, RegistrationStore,
+    DEFAULT_REGISTRATION_TTL, MAX_NAMESPACE_LEN, PROTOCOL_RENDEZVOUS,
+};
+use crate::{
+    error::{self, Error},
+    io,
+    net::{Connection, PeerRecord, Protocol, ProtocolRegistry},
+    payload::rendezvous::{
+        mod_Message::{
+            Discover, DiscoverResponse, MessageType, Register, RegisterResponse, ResponseStatus,
+        },
+        Message,
+    },
+};
+use futures::AsyncWriteExt;
+use std::time::Duration;
+
+const DEFAULT_MIN_TTL: Duration = Duration::from_secs(2 * 60 * 60);
+const DEFAULT_MAX_TTL: Duration = Duration::from_secs(72 * 60 * 60);
+const DEFAULT_MAX_DISCOVER_LIMIT: usize = 1000;
+
+// RendezvousServer
+//
+// Keeps the registrations of peers in namespaces and returns them to the
+// peers discovering a namespace, a page at a time
+#[derive(Clone)]
+pub struct RendezvousServer {
+    store: RegistrationStore,
+    min_ttl: Duration,
+    max_ttl: Duration,
+    max_discover_limit: usize,
+}
+
+impl Default for RendezvousServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RendezvousServer {
+    pub fn new() -> Self {
+        Self {
+            store: RegistrationStore::new(),
+            min_ttl: DEFAULT_MIN_TTL,
+            max_ttl: DEFAULT_MAX_TTL,
+            max_discover_limit: DEFAULT_MAX_DISCOVER_LIMIT,
+        }
+    }
+
+    pub fn with_store(mut self, store: RegistrationStore) -> Self {
+        self.store = store;
+        self
+    }
+
+    // registrations asking for a ttl out of the bounds are refused
+    pub fn with_ttl_bounds(mut self, min_ttl: Duration, max_ttl: Duration) -> Self {
+        self.min_ttl = min_ttl;
+        self.max_ttl = max_ttl;
+        self
+    }
+
+    // registrations returned at most by a discover
+    pub fn with_max_discover_limit(mut self, max_discover_limit: usize) -> Self {
+        self.max_discover_limit = max_discover_limit;
+        self
+    }
+
+    pub fn store(&self) -> &RegistrationStore {
+        &self.store
+    }
+
+    // answer requests of the remote until it closes the stream
+    async fn respond(
+        self,
+        connection: Connection,
+        mut stream: io::BoxedStream,
+    ) -> Result<(), Error> {
+        loop {
+            let request = match read_message(&mut stream).await {
+                Ok(request) => request,
+                Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err),
+            };
+            if let Some(response) = self.handle_request(&connection, request)? {
+                write_message(&mut stream, &response).await?;
+            }
+        }
+        stream.close().await
+    }
+
+    fn handle_request(
+        &self,
+        connection: &Connection,
+        request: Message,
+    ) -> Result<Option<Message>, Error> {
+        let peer_id = &connection.info().peer_id;
+        match request.type_pb {
+            Some(MessageType::REGISTER) => {
+                let register = request.register.ok_or(error::message_malformed())?;
+                let response = self.handle_register(connection, register);
+                log::debug!("rendezvous register of {:?} {:?}", peer_id, response.status);
+                Ok(Some(Message {
+                    type_pb: Some(MessageType::REGISTER_RESPONSE),
+                    registerResponse: Some(response),
+                    ..Default::default()
+                }))
+            }
+            Some(MessageType::UNREGISTER) => {
+                let unregister = request.unregister.ok_or(error::message_malformed())?;
+                self.store
+                    .remove(&unregister.ns.unwrap_or_default(), peer_id);
+                Ok(None)
+            }
+            Some(MessageType::DISCOVER) => {
+                let discover = request.discover.ok_or(error::message_malformed())?;
+                Ok(Some(self.handle_discover(discover)))
+            }
+            _ => Err(error::message_malformed()),
+        }
+    }
+
+    fn handle_discover(&self, discover: Discover) -> Message {
+        // an empty namespace is every namespace
+        let namespace = discover.ns.filter(|namespace| !namespace.is_empty());
+        if namespace
+            .as_ref()
+            .is_some_and(|namespace| namespace.len() > MAX_NAMESPACE_LEN)
+        {
+            return discover_response(ResponseStatus::E_INVALID_NAMESPACE, "invalid namespace");
+        }
+        let limit = discover.limit.map_or(self.max_discover_limit, |limit| {
+            limit.min(self.max_discover_limit as u64) as usize
+        });
+        let found = self
+            .store
+            .discover(namespace.as_deref(), limit, discover.cookie.as_deref());
+        let Ok((registrations, cookie)) = found else {
+            return discover_response(ResponseStatus::E_INVALID_COOKIE, "invalid cookie");
+        };
+        Message {
+            type_pb: Some(MessageType::DISCOVER_RESPONSE),
+            discoverResponse: Some(DiscoverResponse {
+                registrations: registrations
+                    .into_iter()
+                    .map(|registration| Register {
+                        ns: Some(registration.namespace),
+                        signedPeerRecord: Some(registration.signed_peer_record),
+                        ttl: Some(registration.ttl.as_secs()),
+                    })
+                    .collect(),
+                cookie: Some(cookie),
+                status: Some(ResponseStatus::OK),
+                statusText: None,
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn handle_register(&self, connection: &Connection, register: Register) -> RegisterResponse {
+        let namespace = register.ns.unwrap_or_default();
+        if !is_valid_namespace(&namespace) {
+            return register_response(ResponseStatus::E_INVALID_NAMESPACE, "invalid namespace");
+        }
+        let ttl = register
+            .ttl
+            .map_or(DEFAULT_REGISTRATION_TTL, Duration::from_secs);
+        if ttl < self.min_ttl || ttl > self.max_ttl {
+            return register_response(ResponseStatus::E_INVALID_TTL, "ttl out of bounds");
+        }
+        let signed_peer_record = register.signedPeerRecord.unwrap_or_default();
+        let Ok(peer_record) = PeerRecord::from_signed_envelope(&signed_peer_record) else {
+            return register_response(
+                ResponseStatus::E_INVALID_SIGNED_PEER_RECORD,
+                "invalid signed peer record",
+            );
+        };
+        // peers register themselves only
+        if peer_record.peer_id != connection.info().peer_id {
+            return register_response(
+                ResponseStatus::E_NOT_AUTHORIZED,
+                "peer record of another peer",
+            );
+        }
+
+        let registration = Registration {
+            namespace,
+            peer_record,
+            signed_peer_record,
+            ttl,
+        };
+        if let Err(err) = self.store.add(registration) {
+            log::debug!("rendezvous registration refused, {:?}", err);
+            return register_response(ResponseStatus::E_UNAVAILABLE, "too many registrations");
+        }
+        RegisterResponse {
+            status: Some(ResponseStatus::OK),
+            statusText: None,
+            ttl: Some(ttl.as_secs()),
+        }
+    }
+}
+
+impl Protocol for RendezvousServer {
+    fn register(self, registry: &mut ProtocolRegistry) {
+        registry.register(PROTOCOL_RENDEZVOUS, move |connection, stream| {
+            self.clone().respond(connection, stream)
+        });
+    }
+}
+
+fn register_response(status: ResponseStatus, status_text: &str) -> RegisterResponse {
+    RegisterResponse {
+        status: Some(status),
+        statusText: Some(status_text.to_string()),
+        ttl: None,
+    }
+}
+
+fn discover_response(status: ResponseStatus, status_text: &str) -> Message {
+    Message {
+        type_pb: Some(MessageType::DISCOVER_RESPONSE),
+        discoverResponse: Some(DiscoverResponse {
+            status: Some(status),
+            statusText: Some(status_text.to_string()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        identity::{PeerId, PrivateKey},
+        net::{tcpaddr_to_multiaddr, Manager, Rendezvous},
+    };
+    use async_io::{Async, Timer};
+    use std::net::{SocketAddr, TcpListener};
+
+    fn spawn_server(server: RendezvousServer) -> Result<SocketAddr, Error> {
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let socket_addr = listener.get_ref().local_addr()?;
+        let manager =
+            Manager::new(PrivateKey::generate_ed25519(), socket_addr).with_protocol(server);
+        async_std::task::spawn(async move { manager.listen(listener).await });
+        Ok(socket_addr)
+    }
+
+    // a peer connected to the server, announcing a made up address
+    async fn connect(
+        server_addr: SocketAddr,
+        port: u16,
+    ) -> Result<(Rendezvous, Connection, PrivateKey), Error> {
+        let private_key = PrivateKey::generate_ed25519();
+        let manager = Manager::new(private_key.clone(), "127.0.0.1:0".parse().unwrap());
+        let addr = tcpaddr_to_multiaddr(&SocketAddr::from(([127, 0, 0, 1], port)));
+        let rendezvous = Rendezvous::new(private_key.clone()).with_listen_addrs(vec![addr]);
+        Ok((rendezvous, manager.connect(server_addr).await?, private_key))
+    }
+
+    #[async_std::test]
+    async fn test_rendezvous() -> Result<(), Error> {
+        let server = RendezvousServer::new();
+        let server_addr = spawn_server(server.clone())?;
+        let mut clients = Vec::new();
+        let mut first_peer_id = None;
+        for port in 4001..4004 {
+            let (rendezvous, connection, private_key) = connect(server_addr, port).await?;
+            let peer_id: PeerId = private_key.public().try_into()?;
+            first_peer_id.get_or_insert(peer_id);
+            let ttl = rendezvous.register(&connection, "chat", None).await?;
+            assert_eq!(ttl, DEFAULT_REGISTRATION_TTL);
+            clients.push((rendezvous, connection));
+        }
+        let (rendezvous, connection) = &clients[0];
+        let ttl = Duration::from_secs(3 * 60 * 60);
+        assert_eq!(
+            rendezvous.register(connection, "files", Some(ttl)).await?,
+            ttl
+        );
+
+        // a page of two then the last one
+        let (page, cookie) = rendezvous
+            .discover(connection, Some("chat"), Some(2), None)
+            .await?;
+        assert_eq!(page.len(), 2);
+        assert_eq!(Some(&page[0].peer_record.peer_id), first_peer_id.as_ref());
+        let (page, cookie) = rendezvous
+            .discover(connection, Some("chat"), Some(2), Some(&cookie))
+            .await?;
+        assert_eq!(page.len(), 1);
+        assert_eq!(
+            page[0].peer_record.addrs[0].to_string(),
+            "/ip4/127.0.0.1/tcp/4003"
+        );
+        let (page, _) = rendezvous
+            .discover(connection, Some("chat"), None, Some(&cookie))
+            .await?;
+        assert!(page.is_empty());
+        let (page, _) = rendezvous.discover(connection, None, None, None).await?;
+        assert_eq!(page.len(), 4);
+
+        // unregistered without a response
+        let (other, other_connection) = &clients[1];
+        other.unregister(other_connection, "chat").await?;
+        io::timeout(Duration::from_secs(5), async {
+            while server.store().len() != 3 {
+                Timer::after(Duration::from_millis(10)).await;
+            }
+            Ok(())
+        })
+        .await?;
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_rendezvous_refusals() -> Result<(), Error> {
+        let server = RendezvousServer::new();
+        let server_addr = spawn_server(server.clone())?;
+        let (rendezvous, connection, _) = connect(server_addr, 4001).await?;
+
+        let short = Some(Duration::from_secs(60));
+        assert!(rendezvous
+            .register(&connection, "chat", short)
+            .await
+            .is_err());
+        assert!(rendezvous.register(&connection, "", None).await.is_err());
+        assert!(rendezvous
+            .discover(&connection, Some("chat"), None, Some(b"bad"))
+            .await
+            .is_err());
+        let unannounced = Rendezvous::new(PrivateKey::generate_ed25519());
+        assert!(unannounced
+            .register(&connection, "chat", None)
+            .await
+            .is_err());
+
+        // the record of another peer over the connection
+        let impostor = Rendezvous::new(PrivateKey::generate_ed25519())
+            .with_listen_addrs(vec!["/ip4/127.0.0.1/tcp/4002".parse().unwrap()]);
+        let err = impostor
+            .register(&connection, "chat", None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("E_NOT_AUTHORIZED"));
+        assert!(server.store().is_empty());
+        Ok(())
+    }
+}
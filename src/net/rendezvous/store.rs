@@ -0,0 +1,268 @@
+use crate::{
+    error::{self, Error},
+    identity::PeerId,
+    net::PeerRecord,
+};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+const DEFAULT_MAX_REGISTRATIONS_PER_PEER: usize = 100;
+const DEFAULT_MAX_REGISTRATIONS: usize = 10_000;
+
+// Registration of a peer in a namespace, with the peer record it signed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Registration {
+    pub namespace: String,
+    pub peer_record: PeerRecord,
+    pub signed_peer_record: Vec<u8>,
+    // left until the registration expires
+    pub ttl: Duration,
+}
+
+#[derive(Default)]
+struct State {
+    // by the order of registration, which cookies point into
+    registrations: BTreeMap<u64, (Registration, Instant)>,
+    next_id: u64,
+}
+
+// RegistrationStore
+//
+// The registrations of a rendezvous server. A cookie holds the namespace it
+// was returned for and the position to resume from, registering again moves
+// a registration to the end.
+#[derive(Clone)]
+pub struct RegistrationStore {
+    max_registrations_per_peer: usize,
+    max_registrations: usize,
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for RegistrationStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RegistrationStore {
+    pub fn new() -> Self {
+        Self {
+            max_registrations_per_peer: DEFAULT_MAX_REGISTRATIONS_PER_PEER,
+            max_registrations: DEFAULT_MAX_REGISTRATIONS,
+            state: Arc::new(Mutex::new(State::default())),
+        }
+    }
+
+    // namespaces a single peer can be registered in
+    pub fn with_max_registrations_per_peer(mut self, max_registrations_per_peer: usize) -> Self {
+        self.max_registrations_per_peer = max_registrations_per_peer;
+        self
+    }
+
+    // registrations of all the peers, refreshing one is still allowed once
+    // the store is full
+    pub fn with_max_registrations(mut self, max_registrations: usize) -> Self {
+        self.max_registrations = max_registrations;
+        self
+    }
+
+    // add or refresh the registration of the peer of the record
+    pub fn add(&self, registration: Registration) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        state.registrations.retain(|_, (_, expires)| *expires > now);
+
+        let peer_id = &registration.peer_record.peer_id;
+        let previous = state
+            .registrations
+            .iter()
+            .find(|(_, (entry, _))| {
+                &entry.peer_record.peer_id == peer_id && entry.namespace == registration.namespace
+            })
+            .map(|(id, _)| *id);
+        match previous {
+            Some(id) => {
+                state.registrations.remove(&id);
+            }
+            None => {
+                let count = state
+                    .registrations
+                    .values()
+                    .filter(|(entry, _)| &entry.peer_record.peer_id == peer_id)
+                    .count();
+                if count >= self.max_registrations_per_peer
+                    || state.registrations.len() >= self.max_registrations
+                {
+                    return Err(error::other("too many registrations"));
+                }
+            }
+        }
+
+        let id = state.next_id;
+        state.next_id += 1;
+        let expires = now + registration.ttl;
+        state.registrations.insert(id, (registration, expires));
+        Ok(())
+    }
+
+    pub fn remove(&self, namespace: &str, peer_id: &PeerId) {
+        self.state
+            .lock()
+            .unwrap()
+            .registrations
+            .retain(|_, (entry, _)| {
+                entry.namespace != namespace || &entry.peer_record.peer_id != peer_id
+            });
+    }
+
+    // the registrations in the namespace, or in all of them, after those
+    // returned along with the cookie; the expired ones are dropped. An empty
+    // cookie is the same as none.
+    pub fn discover(
+        &self,
+        namespace: Option<&str>,
+        limit: usize,
+        cookie: Option<&[u8]>,
+    ) -> Result<(Vec<Registration>, Vec<u8>), Error> {
+        let namespace = namespace.unwrap_or_default();
+        let mut start = 0;
+        if let Some(cookie) = cookie.filter(|cookie| !cookie.is_empty()) {
+            if cookie.len() < 8 || &cookie[8..] != namespace.as_bytes() {
+                return Err(error::invalid_input("invalid cookie"));
+            }
+            start = u64::from_be_bytes(cookie[..8].try_into().unwrap());
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        state.registrations.retain(|_, (_, expires)| *expires > now);
+        let found: Vec<(u64, Registration)> = state
+            .registrations
+            .range(start..)
+            .filter(|(_, (entry, _))| namespace.is_empty() || entry.namespace == namespace)
+            .take(limit)
+            .map(|(id, (entry, expires))| {
+                let ttl = *expires - now;
+                (
+                    *id,
+                    Registration {
+                        ttl,
+                        ..entry.clone()
+                    },
+                )
+            })
+            .collect();
+
+        // a short page has seen every registration, so polling with the
+        // cookie returns only those registered later
+        let resume = match found.last() {
+            Some((id, _)) if found.len() == limit => id + 1,
+            _ => state.next_id.max(start),
+        };
+        let cookie = [resume.to_be_bytes().as_slice(), namespace.as_bytes()].concat();
+        Ok((found.into_iter().map(|(_, entry)| entry).collect(), cookie))
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().registrations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::PrivateKey;
+
+    fn registration(namespace: &str, peer_id: &PeerId, ttl: Duration) -> Registration {
+        Registration {
+            namespace: namespace.to_string(),
+            peer_record: PeerRecord::new(peer_id.clone(), Vec::new()),
+            signed_peer_record: Vec::new(),
+            ttl,
+        }
+    }
+
+    #[test]
+    fn test_registration_store() -> Result<(), Error> {
+        let store = RegistrationStore::new().with_max_registrations_per_peer(2);
+        let peers: Vec<PeerId> = (0..3)
+            .map(|_| PrivateKey::generate_ed25519().public().try_into())
+            .collect::<Result<_, _>>()?;
+        let ttl = Duration::from_secs(60);
+        for peer_id in &peers {
+            store.add(registration("chat", peer_id, ttl))?;
+        }
+        store.add(registration("files", &peers[0], ttl))?;
+        assert!(store.add(registration("other", &peers[0], ttl)).is_err());
+
+        // pages of two, the cookie goes with its namespace only
+        let (page, cookie) = store.discover(Some("chat"), 2, None)?;
+        assert_eq!(page.len(), 2);
+        assert!(store.discover(None, 2, Some(&cookie)).is_err());
+        let (page, cookie) = store.discover(Some("chat"), 2, Some(&cookie))?;
+        assert_eq!(page[0].peer_record.peer_id, peers[2]);
+        let (page, last) = store.discover(Some("chat"), 2, Some(&cookie))?;
+        assert!(page.is_empty());
+        assert_eq!(last, cookie);
+
+        // registered again, it comes after the cookie
+        store.add(registration("chat", &peers[0], ttl))?;
+        let (page, _) = store.discover(Some("chat"), 2, Some(&cookie))?;
+        assert_eq!(page[0].peer_record.peer_id, peers[0]);
+        assert_eq!(store.discover(None, 10, None)?.0.len(), 4);
+
+        store.remove("chat", &peers[1]);
+        store.add(registration("short", &peers[1], Duration::ZERO))?;
+        assert_eq!(store.discover(None, 10, None)?.0.len(), 3);
+        assert!(store.discover(None, 10, Some(b"bad")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_registration_store_max() -> Result<(), Error> {
+        let store = RegistrationStore::new().with_max_registrations(2);
+        let peers: Vec<PeerId> = (0..3)
+            .map(|_| PrivateKey::generate_ed25519().public().try_into())
+            .collect::<Result<_, _>>()?;
+        let ttl = Duration::from_secs(60);
+        store.add(registration("chat", &peers[0], ttl))?;
+        store.add(registration("short", &peers[1], Duration::from_millis(10)))?;
+        assert!(store.add(registration("chat", &peers[2], ttl)).is_err());
+        // refreshed while full
+        store.add(registration("chat", &peers[0], ttl))?;
+
+        // the expired registration is dropped by discover, making room
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.discover(Some("chat"), 10, None)?.0.len(), 1);
+        assert_eq!(store.len(), 1);
+        store.add(registration("chat", &peers[2], ttl))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_registration_store_empty_cookie() -> Result<(), Error> {
+        let store = RegistrationStore::new();
+        let peer_id: PeerId = PrivateKey::generate_ed25519().public().try_into()?;
+
+        // the cookie of an empty namespace can be sent back
+        let (page, cookie) = store.discover(Some("chat"), 10, None)?;
+        assert!(page.is_empty());
+        let (page, cookie) = store.discover(Some("chat"), 10, Some(&cookie))?;
+        assert!(page.is_empty());
+
+        // and picks up the later registrations
+        store.add(registration("chat", &peer_id, Duration::from_secs(60)))?;
+        let (page, _) = store.discover(Some("chat"), 10, Some(&cookie))?;
+        assert_eq!(page.len(), 1);
+        assert_eq!(store.discover(Some("chat"), 10, Some(b""))?.0.len(), 1);
+        Ok(())
+    }
+}
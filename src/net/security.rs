@@ -0,0 +1,199 @@
+#[cfg(feature = "plaintext")]
+use super::Plaintext;
+use super::{Multistream, Noise, ProtocolId, Tls, UpgradeInbound, UpgradeOutbound};
+use crate::{
+    error::{self, Error},
+    identity::{PeerId, PrivateKey},
+    io,
+};
+use futures::{AsyncRead, AsyncWrite, Future, FutureExt};
+use std::{boxed::Box, pin::Pin};
+
+// SecurityProtocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityProtocol {
+    Noise,
+    Tls,
+    #[cfg(feature = "plaintext")]
+    Plaintext,
+}
+
+impl SecurityProtocol {
+    // protocols enabled by default, in preference order
+    pub fn defaults() -> Vec<Self> {
+        vec![Self::Noise, Self::Tls]
+    }
+
+    pub fn protocol_id(&self) -> &'static str {
+        match self {
+            Self::Noise => Noise::protocol_id(),
+            Self::Tls => Tls::protocol_id(),
+            #[cfg(feature = "plaintext")]
+            Self::Plaintext => Plaintext::protocol_id(),
+        }
+    }
+
+    pub fn from_protocol_id(protocol_id: &[u8]) -> Option<Self> {
+        [
+            Self::Noise,
+            Self::Tls,
+            #[cfg(feature = "plaintext")]
+            Self::Plaintext,
+        ]
+        .into_iter()
+        .find(|protocol| protocol.protocol_id().as_bytes() == protocol_id)
+    }
+}
+
+// SecurityUpgrader
+//
+// Negotiates one of the enabled protocols by multistream select, and then
+// runs its handshake
+pub struct SecurityUpgrader {
+    private_key: PrivateKey,
+    protocols: Vec<SecurityProtocol>,
+}
+
+impl SecurityUpgrader {
+    pub fn new(private_key: PrivateKey, protocols: Vec<SecurityProtocol>) -> Self {
+        Self {
+            private_key,
+            protocols,
+        }
+    }
+
+    fn multistream(&self) -> Multistream {
+        Multistream::new(
+            self.protocols
+                .iter()
+                .map(|protocol| protocol.protocol_id().as_bytes().to_vec())
+                .collect(),
+        )
+    }
+
+    fn agreed_protocol(agreed: &[u8]) -> Result<SecurityProtocol, Error> {
+        SecurityProtocol::from_protocol_id(agreed).ok_or(error::unsupported("security protocol"))
+    }
+}
+
+impl<'a, T> UpgradeOutbound<'a, T> for SecurityUpgrader
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'a,
+{
+    type Output = (io::SecuredStream<T>, PeerId, SecurityProtocol);
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send + 'a>>;
+
+    fn upgrade_outbound(self, stream: T) -> Self::Future {
+        async move {
+            let (stream, agreed) = self.multistream().upgrade_outbound(stream).await?;
+            let protocol = Self::agreed_protocol(&agreed)?;
+            let Self { private_key, .. } = self;
+            let (stream, peer_id) = match protocol {
+                SecurityProtocol::Noise => {
+                    let (stream, peer_id) =
+                        Noise::new(private_key).upgrade_outbound(stream).await?;
+                    (io::SecuredStream::Noise(Box::new(stream)), peer_id)
+                }
+                SecurityProtocol::Tls => {
+                    let (stream, peer_id) = Tls::new(private_key).upgrade_outbound(stream).await?;
+                    (io::SecuredStream::Tls(Box::new(stream)), peer_id)
+                }
+                #[cfg(feature = "plaintext")]
+                SecurityProtocol::Plaintext => {
+                    let (stream, peer_id) =
+                        Plaintext::new(private_key).upgrade_outbound(stream).await?;
+                    (io::SecuredStream::Plaintext(stream), peer_id)
+                }
+            };
+            Ok((stream, peer_id, protocol))
+        }
+        .boxed()
+    }
+}
+
+impl<'a, T> UpgradeInbound<'a, T> for SecurityUpgrader
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'a,
+{
+    type Output = (io::SecuredStream<T>, PeerId, SecurityProtocol);
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send + 'a>>;
+
+    fn upgrade_inbound(self, stream: T) -> Self::Future {
+        async move {
+            let (stream, agreed) = self.multistream().upgrade_inbound(stream).await?;
+            let protocol = Self::agreed_protocol(&agreed)?;
+            let Self { private_key, .. } = self;
+            let (stream, peer_id) = match protocol {
+                SecurityProtocol::Noise => {
+                    let (stream, peer_id) = Noise::new(private_key).upgrade_inbound(stream).await?;
+                    (io::SecuredStream::Noise(Box::new(stream)), peer_id)
+                }
+                SecurityProtocol::Tls => {
+                    let (stream, peer_id) = Tls::new(private_key).upgrade_inbound(stream).await?;
+                    (io::SecuredStream::Tls(Box::new(stream)), peer_id)
+                }
+                #[cfg(feature = "plaintext")]
+                SecurityProtocol::Plaintext => {
+                    let (stream, peer_id) =
+                        Plaintext::new(private_key).upgrade_inbound(stream).await?;
+                    (io::SecuredStream::Plaintext(stream), peer_id)
+                }
+            };
+            Ok((stream, peer_id, protocol))
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_io::Async;
+    use std::net::{TcpListener, TcpStream};
+
+    async fn negotiate(
+        server_protocols: Vec<SecurityProtocol>,
+        client_protocols: Vec<SecurityProtocol>,
+    ) -> Result<(SecurityProtocol, SecurityProtocol), Error> {
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let addr = listener.get_ref().local_addr()?;
+        let server_key = PrivateKey::generate_ed25519();
+        let client_key = PrivateKey::generate_ed25519();
+
+        let server = async {
+            let (stream, _) = listener.accept().await?;
+            SecurityUpgrader::new(server_key.clone(), server_protocols)
+                .upgrade_inbound(stream)
+                .await
+        };
+        let client = async {
+            let stream = Async::<TcpStream>::connect(addr).await?;
+            SecurityUpgrader::new(client_key.clone(), client_protocols)
+                .upgrade_outbound(stream)
+                .await
+        };
+        let (server, client) = futures::join!(server, client);
+        let (_, client_peer_id, server_agreed) = server?;
+        let (_, server_peer_id, client_agreed) = client?;
+        assert_eq!(server_peer_id, server_key.public().try_into()?);
+        assert_eq!(client_peer_id, client_key.public().try_into()?);
+        Ok((server_agreed, client_agreed))
+    }
+
+    #[async_std::test]
+    async fn test_security_preference() -> Result<(), Error> {
+        use SecurityProtocol::*;
+
+        assert_eq!(
+            negotiate(vec![Noise, Tls], vec![Tls, Noise]).await?,
+            (Tls, Tls)
+        );
+        assert_eq!(
+            negotiate(vec![Noise], vec![Tls, Noise]).await?,
+            (Noise, Noise)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,47 @@
+use crate::error::Error;
+use async_io::Async;
+use socket2::{Domain, SockAddr, Socket, Type};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+const LISTEN_BACKLOG: i32 = 1024;
+
+// bind with the port shareable by other sockets of the process, so that
+// outbound dials can leave from the listening port
+fn reuse_port_socket(local_addr: SocketAddr) -> Result<Socket, Error> {
+    let socket = Socket::new(Domain::for_address(local_addr), Type::STREAM, None)?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SockAddr::from(local_addr))?;
+    Ok(socket)
+}
+
+// listen on a port which outbound dials can share, as needed for hole punching
+pub fn tcp_listen_reuse_port(local_addr: SocketAddr) -> Result<Async<TcpListener>, Error> {
+    let socket = reuse_port_socket(local_addr)?;
+    socket.listen(LISTEN_BACKLOG)?;
+    Async::new_nonblocking(TcpListener::from(socket))
+}
+
+// dial from the local address, which may be shared with a listener
+pub async fn tcp_connect_reuse_port(
+    local_addr: SocketAddr,
+    remote_addr: SocketAddr,
+) -> Result<Async<TcpStream>, Error> {
+    let socket = reuse_port_socket(local_addr)?;
+    match socket.connect(&SockAddr::from(remote_addr)) {
+        Ok(()) => {}
+        #[cfg(unix)]
+        Err(err) if err.raw_os_error() == Some(libc::EINPROGRESS) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {}
+        Err(err) => return Err(err),
+    }
+    let stream = Async::new_nonblocking(TcpStream::from(socket))?;
+    // connected once writable, unless the connect failed
+    stream.writable().await?;
+    match stream.get_ref().take_error()? {
+        Some(err) => Err(err),
+        None => Ok(stream),
+    }
+}
@@ -0,0 +1,138 @@
+// Fixtures shared by the tests of several modules
+
+use super::{
+    tcpaddr_to_multiaddr, Identify, KadPeer, Kademlia, Manager, Protocol, RecordValidator,
+};
+use crate::{error::Error, identity::PrivateKey, io};
+use async_io::{Async, Timer};
+use futures::{
+    task::{Context, Poll},
+    AsyncRead, AsyncWrite,
+};
+use std::{
+    net::{SocketAddr, TcpListener},
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
+
+// any value is valid under /test/, the first one is the best
+struct AnyValue;
+
+impl RecordValidator for AnyValue {
+    fn validate(&self, _key: &[u8], _value: &[u8]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn select(&self, _key: &[u8], _values: &[Vec<u8>]) -> Result<usize, Error> {
+        Ok(0)
+    }
+}
+
+// poll the condition until it holds, failing after five seconds
+pub(crate) async fn wait_until(condition: impl Fn() -> bool) -> Result<(), Error> {
+    io::timeout(Duration::from_secs(5), async {
+        while !condition() {
+            Timer::after(Duration::from_millis(10)).await;
+        }
+        Ok(())
+    })
+    .await
+}
+
+// a node listening on a local port, serving the protocol made with its key
+pub(crate) fn spawn_node<P>(
+    protocol: impl FnOnce(PrivateKey) -> Result<P, Error>,
+) -> Result<(Arc<Manager>, P, SocketAddr), Error>
+where
+    P: Protocol + Clone,
+{
+    let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+    let addr = listener.get_ref().local_addr()?;
+    let private_key = PrivateKey::generate_ed25519();
+    let protocol = protocol(private_key.clone())?;
+    let manager = Arc::new(Manager::new(private_key, addr).with_protocol(protocol.clone()));
+    let listening = manager.clone();
+    async_std::task::spawn(async move { listening.listen(listener).await });
+    Ok((manager, protocol, addr))
+}
+
+// nodes serving the DHT and identify, the first node knows every other
+// node, which know only the first and then bootstrap from it
+pub(crate) async fn spawn_kad_servers(
+    count: usize,
+) -> Result<Vec<(Arc<Manager>, Kademlia, KadPeer)>, Error> {
+    let mut nodes = Vec::new();
+    for _ in 0..count {
+        let private_key = PrivateKey::generate_ed25519();
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let addr = listener.get_ref().local_addr()?;
+        let peer = KadPeer {
+            peer_id: private_key.public().try_into()?,
+            addrs: vec![tcpaddr_to_multiaddr(&addr)],
+            signed_record: None,
+        };
+        let identify = Identify::new(private_key.clone()).with_listen_addrs(peer.addrs.clone());
+        let kad = Kademlia::new(peer.peer_id.clone())?
+            .with_listen_addrs(peer.addrs.clone())
+            .with_private_key(private_key.clone())
+            .with_identify(identify.clone())
+            .with_validator("test", AnyValue);
+        let manager = Arc::new(
+            Manager::new(private_key, addr)
+                .with_protocol(kad.clone())
+                .with_protocol(identify),
+        );
+        let listening = manager.clone();
+        async_std::task::spawn(async move { listening.listen(listener).await });
+        nodes.push((manager, kad, peer));
+    }
+
+    let (_, first, first_peer) = &nodes[0];
+    for (_, _, peer) in &nodes[1..] {
+        first.add_address(peer.peer_id.clone(), peer.addrs[0].clone());
+    }
+    for (manager, kad, _) in &nodes[1..] {
+        kad.add_address(first_peer.peer_id.clone(), first_peer.addrs[0].clone());
+        kad.bootstrap(manager).await?;
+        assert_eq!(kad.routing_table_len(), count - 1);
+    }
+    Ok(nodes)
+}
+
+// an io whose writes never complete, as over a remote which does not read
+pub(crate) struct Stalled<R>(pub(crate) R);
+
+impl<R: AsyncRead + Unpin> AsyncRead for Stalled<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, futures::io::Error>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl<R: Unpin> AsyncWrite for Stalled<R> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &[u8],
+    ) -> Poll<Result<usize, futures::io::Error>> {
+        Poll::Pending
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), futures::io::Error>> {
+        Poll::Pending
+    }
+
+    fn poll_close(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), futures::io::Error>> {
+        Poll::Pending
+    }
+}
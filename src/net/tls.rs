@@ -0,0 +1,195 @@
+mod certificate;
+mod resolver;
+mod verifier;
+
+use super::upgrade::{ProtocolId, UpgradeInbound, UpgradeOutbound};
+use crate::{
+    error::{self, Error},
+    identity::{PeerId, PrivateKey},
+    io,
+};
+use futures::{AsyncRead, AsyncWrite, Future, FutureExt};
+use futures_rustls::{TlsAcceptor, TlsConnector};
+use rustls::{
+    crypto::CryptoProvider,
+    pki_types::{CertificateDer, ServerName},
+    sign::CertifiedKey,
+    version::TLS13,
+    ClientConfig, ServerConfig,
+};
+use std::{
+    boxed::Box,
+    net::{IpAddr, Ipv4Addr},
+    pin::Pin,
+    sync::Arc,
+};
+
+pub use certificate::{generate_certificate, Certificate};
+
+const PROTOCOL_TLS: &str = "/tls/1.0.0";
+const ALPN_LIBP2P: &[u8] = b"libp2p";
+
+// Tls
+pub struct Tls {
+    private_key: PrivateKey,
+}
+
+impl Tls {
+    pub fn new(private_key: PrivateKey) -> Self {
+        Self { private_key }
+    }
+
+    fn resolver(&self, provider: &CryptoProvider) -> Result<Arc<resolver::Resolver>, Error> {
+        let (cert, key) = generate_certificate(&self.private_key)?;
+        let signing_key = provider
+            .key_provider
+            .load_private_key(key)
+            .map_err(|_| error::other("tls certificate key"))?;
+        Ok(Arc::new(resolver::Resolver::new(CertifiedKey::new(
+            vec![cert],
+            signing_key,
+        ))))
+    }
+
+    fn client_config(&self) -> Result<ClientConfig, Error> {
+        let provider = rustls::crypto::ring::default_provider();
+        let resolver = self.resolver(&provider)?;
+        let mut config = ClientConfig::builder_with_provider(Arc::new(provider))
+            .with_protocol_versions(&[&TLS13])
+            .map_err(|_| error::other("tls client config"))?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier::Verifier::new()))
+            .with_client_cert_resolver(resolver);
+        config.alpn_protocols = vec![ALPN_LIBP2P.to_vec()];
+        Ok(config)
+    }
+
+    fn server_config(&self) -> Result<ServerConfig, Error> {
+        let provider = rustls::crypto::ring::default_provider();
+        let resolver = self.resolver(&provider)?;
+        let mut config = ServerConfig::builder_with_provider(Arc::new(provider))
+            .with_protocol_versions(&[&TLS13])
+            .map_err(|_| error::other("tls server config"))?
+            .with_client_cert_verifier(Arc::new(verifier::Verifier::new()))
+            .with_cert_resolver(resolver);
+        config.alpn_protocols = vec![ALPN_LIBP2P.to_vec()];
+        Ok(config)
+    }
+}
+
+impl ProtocolId for Tls {
+    fn protocol_id() -> &'static str {
+        PROTOCOL_TLS
+    }
+}
+
+impl<'a, T> UpgradeOutbound<'a, T> for Tls
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'a,
+{
+    type Output = (io::TlsUpgradedStream<T>, PeerId);
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send + 'a>>;
+
+    fn upgrade_outbound(self, stream: T) -> Self::Future {
+        async move {
+            let connector = TlsConnector::from(Arc::new(self.client_config()?));
+            // the spec forbids SNI, which rustls skips for ip addresses
+            let server_name = ServerName::IpAddress(IpAddr::V4(Ipv4Addr::UNSPECIFIED).into());
+            let stream = connector
+                .connect(server_name, stream)
+                .await
+                .map_err(|err| {
+                    log::debug!("tls handshake failed, {:?}", err);
+                    error::verification_failed()
+                })?;
+            log::info!("tls handshake complete");
+
+            let peer_id = remote_peer_id(stream.get_ref().1.peer_certificates())?;
+            log::info!(
+                "handshake complete my peer_id: {:?} and remote peer_id: {:?}",
+                TryInto::<PeerId>::try_into(self.private_key.public())?,
+                peer_id
+            );
+            Ok((stream.into(), peer_id))
+        }
+        .boxed()
+    }
+}
+
+impl<'a, T> UpgradeInbound<'a, T> for Tls
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'a,
+{
+    type Output = (io::TlsUpgradedStream<T>, PeerId);
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send + 'a>>;
+
+    fn upgrade_inbound(self, stream: T) -> Self::Future {
+        async move {
+            let acceptor = TlsAcceptor::from(Arc::new(self.server_config()?));
+            let stream = acceptor.accept(stream).await.map_err(|err| {
+                log::debug!("tls handshake failed, {:?}", err);
+                error::verification_failed()
+            })?;
+            log::info!("tls handshake complete");
+
+            let peer_id = remote_peer_id(stream.get_ref().1.peer_certificates())?;
+            log::info!(
+                "handshake complete my peer_id: {:?} and remote peer_id: {:?}",
+                TryInto::<PeerId>::try_into(self.private_key.public())?,
+                peer_id
+            );
+            Ok((stream.into(), peer_id))
+        }
+        .boxed()
+    }
+}
+
+fn remote_peer_id(certs: Option<&[CertificateDer<'_>]>) -> Result<PeerId, Error> {
+    match certs {
+        Some([cert]) => Certificate::parse(cert)?.peer_id(),
+        _ => {
+            log::debug!("remote certificate missing");
+            Err(error::verification_failed())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_io::Async;
+    use std::net::{TcpListener, TcpStream};
+
+    #[async_std::test]
+    async fn test_tls_upgrade() -> Result<(), Error> {
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let addr = listener.get_ref().local_addr()?;
+        let server_key = PrivateKey::generate_ed25519();
+        let client_key = PrivateKey::generate_ed25519();
+
+        let server = async {
+            let (stream, _) = listener.accept().await?;
+            Tls::new(server_key.clone()).upgrade_inbound(stream).await
+        };
+        let client = async {
+            let stream = Async::<TcpStream>::connect(addr).await?;
+            Tls::new(client_key.clone()).upgrade_outbound(stream).await
+        };
+        let (server, client) = futures::join!(server, client);
+        let (mut server_stream, client_peer_id) = server?;
+        let (mut client_stream, server_peer_id) = client?;
+
+        assert_eq!(server_peer_id, server_key.public().try_into()?);
+        assert_eq!(client_peer_id, client_key.public().try_into()?);
+
+        use futures::{AsyncReadExt, AsyncWriteExt};
+        client_stream.write_all(b"ping").await?;
+        client_stream.flush().await?;
+        let mut buf = [0u8; 4];
+        server_stream.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"ping");
+        Ok(())
+    }
+}
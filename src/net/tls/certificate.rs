@@ -0,0 +1,234 @@
+use crate::{
+    error::{self, Error},
+    identity::{PeerId, PrivateKey, PublicKey},
+    io,
+    payload::keys::PublicKey as PublicKeyPayload,
+};
+use rustls::{
+    pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer},
+    SignatureScheme,
+};
+use x509_parser::{certificate::X509Certificate, extensions::ParsedExtension, prelude::FromDer};
+
+const LIBP2P_EXTENSION_OID: [u64; 9] = [1, 3, 6, 1, 4, 1, 53594, 1, 1];
+const LIBP2P_EXTENSION_OID_STR: &str = "1.3.6.1.4.1.53594.1.1";
+const CERTIFICATE_PREFIX: &str = "libp2p-tls-handshake:";
+
+const DER_SEQUENCE: u8 = 0x30;
+const DER_OCTET_STRING: u8 = 0x04;
+
+// generate a self-signed certificate carrying the libp2p public key extension
+pub fn generate_certificate(
+    private_key: &PrivateKey,
+) -> Result<(CertificateDer<'static>, PrivateKeyDer<'static>), Error> {
+    let cert_keypair = rcgen::KeyPair::generate_for(&rcgen::PKCS_ECDSA_P256_SHA256)
+        .map_err(|_| error::other("tls certificate key"))?;
+
+    let signature = private_key.sign(
+        &[
+            CERTIFICATE_PREFIX.as_bytes(),
+            &cert_keypair.public_key_der(),
+        ]
+        .concat(),
+    );
+    let signed_key = der_encode_signed_key(&private_key.public().to_protobuf_bytes()?, &signature);
+    let mut extension = rcgen::CustomExtension::from_oid_content(&LIBP2P_EXTENSION_OID, signed_key);
+    extension.set_criticality(true);
+
+    let mut params = rcgen::CertificateParams::default();
+    params.custom_extensions.push(extension);
+    let cert = params
+        .self_signed(&cert_keypair)
+        .map_err(|_| error::other("tls certificate"))?;
+
+    Ok((
+        cert.der().clone(),
+        PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(cert_keypair.serialize_der())),
+    ))
+}
+
+// Certificate
+pub struct Certificate<'a> {
+    x509: X509Certificate<'a>,
+    public_key: PublicKey,
+}
+
+impl<'a> Certificate<'a> {
+    // parse and validate the certificate as required by the libp2p tls spec
+    pub fn parse(cert: &'a CertificateDer<'a>) -> Result<Self, Error> {
+        let (_, x509) =
+            X509Certificate::from_der(cert.as_ref()).map_err(|_| error::parse_error())?;
+
+        if !x509.validity().is_valid() {
+            log::debug!("tls certificate expired or not yet valid");
+            return Err(error::verification_failed());
+        }
+        x509.verify_signature(None).map_err(|_| {
+            log::debug!("tls certificate not self-signed");
+            error::verification_failed()
+        })?;
+
+        let mut signed_key = None;
+        for ext in x509.extensions() {
+            if ext.oid.to_id_string() == LIBP2P_EXTENSION_OID_STR {
+                if signed_key.is_some() {
+                    return Err(error::verification_failed());
+                }
+                signed_key = Some(ext.value);
+            } else if ext.critical
+                && matches!(
+                    ext.parsed_extension(),
+                    ParsedExtension::UnsupportedExtension { .. }
+                )
+            {
+                log::debug!("tls certificate has unknown critical extension {}", ext.oid);
+                return Err(error::verification_failed());
+            }
+        }
+        let (public_key, signature) = match signed_key {
+            Some(buf) => der_decode_signed_key(buf)?,
+            None => {
+                log::debug!("tls certificate libp2p extension missing");
+                return Err(error::verification_failed());
+            }
+        };
+
+        let payload: PublicKeyPayload = io::protobuf_decode(&public_key)?;
+        let public_key = PublicKey::try_from(payload)?;
+        public_key.verify(
+            &[CERTIFICATE_PREFIX.as_bytes(), x509.public_key().raw].concat(),
+            &signature,
+        )?;
+
+        Ok(Self { x509, public_key })
+    }
+
+    pub fn peer_id(&self) -> Result<PeerId, Error> {
+        self.public_key.clone().try_into()
+    }
+
+    // verify a handshake signature made by the certificate key
+    pub fn verify_signature(
+        &self,
+        scheme: SignatureScheme,
+        msg: &[u8],
+        sig: &[u8],
+    ) -> Result<(), Error> {
+        use ring::signature;
+
+        let algorithm: &dyn signature::VerificationAlgorithm = match scheme {
+            SignatureScheme::ECDSA_NISTP256_SHA256 => &signature::ECDSA_P256_SHA256_ASN1,
+            SignatureScheme::ECDSA_NISTP384_SHA384 => &signature::ECDSA_P384_SHA384_ASN1,
+            SignatureScheme::ED25519 => &signature::ED25519,
+            SignatureScheme::RSA_PSS_SHA256 => &signature::RSA_PSS_2048_8192_SHA256,
+            SignatureScheme::RSA_PSS_SHA384 => &signature::RSA_PSS_2048_8192_SHA384,
+            SignatureScheme::RSA_PSS_SHA512 => &signature::RSA_PSS_2048_8192_SHA512,
+            _ => return Err(error::unsupported("tls signature scheme")),
+        };
+        signature::UnparsedPublicKey::new(
+            algorithm,
+            self.x509.public_key().subject_public_key.data.as_ref(),
+        )
+        .verify(msg, sig)
+        .map_err(|_| error::verification_failed())
+    }
+}
+
+pub fn supported_schemes() -> Vec<SignatureScheme> {
+    vec![
+        SignatureScheme::ECDSA_NISTP256_SHA256,
+        SignatureScheme::ECDSA_NISTP384_SHA384,
+        SignatureScheme::ED25519,
+        SignatureScheme::RSA_PSS_SHA256,
+        SignatureScheme::RSA_PSS_SHA384,
+        SignatureScheme::RSA_PSS_SHA512,
+    ]
+}
+
+// SignedKey ::= SEQUENCE { publicKey OCTET STRING, signature OCTET STRING }
+fn der_encode_signed_key(public_key: &[u8], signature: &[u8]) -> Vec<u8> {
+    let content = [
+        der_encode(DER_OCTET_STRING, public_key),
+        der_encode(DER_OCTET_STRING, signature),
+    ]
+    .concat();
+    der_encode(DER_SEQUENCE, &content)
+}
+
+fn der_decode_signed_key(buf: &[u8]) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    let (content, rest) = der_decode(DER_SEQUENCE, buf)?;
+    if !rest.is_empty() {
+        return Err(error::parse_error());
+    }
+    let (public_key, content) = der_decode(DER_OCTET_STRING, content)?;
+    let (signature, content) = der_decode(DER_OCTET_STRING, content)?;
+    if !content.is_empty() {
+        return Err(error::parse_error());
+    }
+    Ok((public_key.to_vec(), signature.to_vec()))
+}
+
+fn der_encode(tag: u8, content: &[u8]) -> Vec<u8> {
+    let len = content.len();
+    let mut buf = vec![tag];
+    if len < 0x80 {
+        buf.push(len as u8);
+    } else {
+        let len_bytes: Vec<u8> = len
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|b| *b == 0)
+            .collect();
+        buf.push(0x80 | len_bytes.len() as u8);
+        buf.extend_from_slice(&len_bytes);
+    }
+    buf.extend_from_slice(content);
+    buf
+}
+
+fn der_decode(tag: u8, buf: &[u8]) -> Result<(&[u8], &[u8]), Error> {
+    if buf.len() < 2 || buf[0] != tag {
+        return Err(error::parse_error());
+    }
+    let (len, offset) = match buf[1] {
+        len if len < 0x80 => (usize::from(len), 2),
+        len => {
+            let n = usize::from(len & 0x7f);
+            if n == 0 || n > 4 || buf.len() < 2 + n {
+                return Err(error::parse_error());
+            }
+            let len = buf[2..2 + n]
+                .iter()
+                .fold(0usize, |acc, b| (acc << 8) | usize::from(*b));
+            (len, 2 + n)
+        }
+    };
+    if buf.len() < offset + len {
+        return Err(error::parse_error());
+    }
+    Ok((&buf[offset..offset + len], &buf[offset + len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_certificate_peer_id() -> Result<(), Error> {
+        let private_key = PrivateKey::generate_ed25519();
+        let (cert, _) = generate_certificate(&private_key)?;
+        let parsed = Certificate::parse(&cert)?;
+        assert_eq!(parsed.peer_id()?, private_key.public().try_into()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_der_signed_key() -> Result<(), Error> {
+        let public_key = vec![1u8; 36];
+        let signature = vec![2u8; 200];
+        let encoded = der_encode_signed_key(&public_key, &signature);
+        assert_eq!(&encoded[..4], &[0x30, 0x81, 0xf1, 0x04]);
+        assert_eq!(der_decode_signed_key(&encoded)?, (public_key, signature));
+        Ok(())
+    }
+}
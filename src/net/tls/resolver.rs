@@ -0,0 +1,45 @@
+use rustls::{
+    client::ResolvesClientCert,
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    SignatureScheme,
+};
+use std::sync::Arc;
+
+// Resolver
+//
+// rustls validates certificates passed to `with_single_cert` via webpki,
+// which rejects the critical libp2p extension, so the certificate is
+// always served from a resolver instead
+#[derive(Debug)]
+pub struct Resolver {
+    certified_key: Arc<CertifiedKey>,
+}
+
+impl Resolver {
+    pub fn new(certified_key: CertifiedKey) -> Self {
+        Self {
+            certified_key: Arc::new(certified_key),
+        }
+    }
+}
+
+impl ResolvesClientCert for Resolver {
+    fn resolve(
+        &self,
+        _root_hint_subjects: &[&[u8]],
+        _sigschemes: &[SignatureScheme],
+    ) -> Option<Arc<CertifiedKey>> {
+        Some(self.certified_key.clone())
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+impl ResolvesServerCert for Resolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.certified_key.clone())
+    }
+}
@@ -13,3 +13,11 @@ pub trait UpgradeOutbound<'a, T> {
 
     fn upgrade_outbound(self, stream: T) -> Self::Future;
 }
+
+pub trait UpgradeInbound<'a, T> {
+    type Output;
+    type Error;
+    type Future: Future<Output = Result<Self::Output, Self::Error>> + 'a;
+
+    fn upgrade_inbound(self, stream: T) -> Self::Future;
+}
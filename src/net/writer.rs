@@ -0,0 +1,133 @@
+use crate::{error::Error, identity::PeerId, io, net::Connection};
+use futures::{channel::mpsc, AsyncWriteExt, Future, Stream, StreamExt};
+use quick_protobuf::{MessageRead, MessageWrite};
+use std::{
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+static NEXT_STREAM_ID: AtomicU64 = AtomicU64::new(1);
+
+// PeerSender of the messages to a peer, queued for the task writing them to
+// the outbound stream of the peer; shared by the pubsub routers and bitswap
+pub(crate) struct PeerSender<T> {
+    stream_id: u64,
+    sender: mpsc::Sender<T>,
+}
+
+impl<T> PeerSender<T> {
+    // the receiver is written to the stream by spawn_writer
+    pub(crate) fn channel(max_queued: usize) -> (Self, mpsc::Receiver<T>) {
+        let (sender, receiver) = mpsc::channel(max_queued);
+        let stream_id = NEXT_STREAM_ID.fetch_add(1, Ordering::Relaxed);
+        (Self { stream_id, sender }, receiver)
+    }
+
+    // the stream written to, told apart from a later one of the same peer
+    pub(crate) fn stream_id(&self) -> u64 {
+        self.stream_id
+    }
+
+    // a slow peer misses messages rather than growing its queue
+    pub(crate) fn send(&mut self, message: T) {
+        if let Err(err) = self.sender.try_send(message) {
+            if err.is_full() {
+                log::debug!("queue of stream {} full, message dropped", self.stream_id);
+            }
+        }
+    }
+}
+
+// PeerProtocol
+//
+// A protocol sending to each peer over one outbound stream, written by
+// spawn_writer, and reading the inbound streams of the peer with serve_peer.
+// A peer is removed only with the stream its sender writes to, so that an
+// older stream of the peer ending does not evict a newer one.
+pub(crate) trait PeerProtocol: Clone + Send + Sync + 'static {
+    type Message: for<'a> MessageRead<'a> + Send;
+
+    fn name(&self) -> &'static str;
+
+    fn max_message_size(&self) -> usize;
+
+    // open the stream to the peer over the connection, unless it has one
+    fn add_peer<'a>(
+        &'a self,
+        connection: &'a Connection,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>>;
+
+    // the stream written to the peer, if it is added
+    fn stream_id(&self, peer_id: &PeerId) -> Option<u64>;
+
+    // remove the peer if its sender still writes to the stream
+    fn remove_peer(&self, peer_id: &PeerId, stream_id: u64);
+
+    fn handle_message<'a>(
+        &'a self,
+        from: &'a PeerId,
+        message: Self::Message,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
+}
+
+// add the peer, inbound streams of peers not added yet add them, and read
+// its messages until the stream is closed
+pub(crate) async fn serve_peer<P: PeerProtocol>(
+    protocol: P,
+    connection: Connection,
+    mut stream: io::BoxedStream,
+) -> Result<(), Error> {
+    let peer_id = connection.info().peer_id.clone();
+    protocol.add_peer(&connection).await?;
+    let stream_id = protocol.stream_id(&peer_id);
+    loop {
+        let message =
+            match io::read_protobuf_prefixed(&mut stream, protocol.max_message_size()).await {
+                Ok(message) => message,
+                Err(err) => {
+                    log::debug!(
+                        "{} stream of {:?} closed, {:?}",
+                        protocol.name(),
+                        peer_id,
+                        err
+                    );
+                    break;
+                }
+            };
+        protocol.handle_message(&peer_id, message).await;
+    }
+    if let Some(stream_id) = stream_id {
+        protocol.remove_peer(&peer_id, stream_id);
+    }
+    Ok(())
+}
+
+// write the messages to the stream until a write fails or the senders are
+// dropped, then close the stream and remove the peer
+pub(crate) fn spawn_writer<P, T, S>(
+    protocol: P,
+    peer_id: PeerId,
+    stream_id: u64,
+    mut stream: io::BoxedStream,
+    mut messages: S,
+) where
+    P: PeerProtocol,
+    T: MessageWrite + Send + Sync,
+    S: Stream<Item = T> + Unpin + Send + 'static,
+{
+    async_std::task::spawn(async move {
+        while let Some(message) = messages.next().await {
+            if let Err(err) = io::write_protobuf_prefixed(&mut stream, &message).await {
+                log::debug!(
+                    "{} write to {:?} failed, {:?}",
+                    protocol.name(),
+                    peer_id,
+                    err
+                );
+                break;
+            }
+        }
+        let _ = stream.close().await;
+        protocol.remove_peer(&peer_id, stream_id);
+    });
+}
@@ -0,0 +1,244 @@
+mod connection;
+mod stream;
+
+use super::upgrade::{ProtocolId, UpgradeInbound, UpgradeOutbound};
+use crate::error::Error;
+use futures::{
+    future::{self, Ready},
+    AsyncRead, AsyncWrite,
+};
+
+pub use connection::{YamuxConnection, YamuxMode};
+pub use stream::YamuxStream;
+
+const PROTOCOL_YAMUX: &str = "/yamux/1.0.0";
+const DEFAULT_MAX_STREAMS: usize = 512;
+
+// Yamux
+#[derive(Debug, Clone)]
+pub struct Yamux {
+    max_streams: usize,
+}
+
+impl Yamux {
+    pub fn new() -> Self {
+        Self {
+            max_streams: DEFAULT_MAX_STREAMS,
+        }
+    }
+
+    // streams open at once, either side opening more is refused
+    pub fn with_max_streams(mut self, max_streams: usize) -> Self {
+        self.max_streams = max_streams;
+        self
+    }
+}
+
+impl Default for Yamux {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProtocolId for Yamux {
+    fn protocol_id() -> &'static str {
+        PROTOCOL_YAMUX
+    }
+}
+
+impl<'a, T> UpgradeOutbound<'a, T> for Yamux
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Output = YamuxConnection;
+    type Error = Error;
+    type Future = Ready<Result<Self::Output, Self::Error>>;
+
+    fn upgrade_outbound(self, stream: T) -> Self::Future {
+        future::ready(Ok(YamuxConnection::new(
+            stream,
+            YamuxMode::Client,
+            self.max_streams,
+        )))
+    }
+}
+
+impl<'a, T> UpgradeInbound<'a, T> for Yamux
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Output = YamuxConnection;
+    type Error = Error;
+    type Future = Ready<Result<Self::Output, Self::Error>>;
+
+    fn upgrade_inbound(self, stream: T) -> Self::Future {
+        future::ready(Ok(YamuxConnection::new(
+            stream,
+            YamuxMode::Server,
+            self.max_streams,
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        io::{YamuxCodec, YamuxFrame, YAMUX_FLAG_SYN},
+        net::test_support::Stalled,
+    };
+    use asynchronous_codec::Encoder;
+    use bytes::BytesMut;
+    use futures::{channel::mpsc, AsyncReadExt, AsyncWriteExt, TryStreamExt};
+    use std::{
+        net::{TcpListener, TcpStream},
+        time::Duration,
+    };
+
+    async fn connection_pair() -> Result<(YamuxConnection, YamuxConnection), Error> {
+        connection_pair_with(Yamux::new()).await
+    }
+
+    async fn connection_pair_with(
+        yamux: Yamux,
+    ) -> Result<(YamuxConnection, YamuxConnection), Error> {
+        let listener = async_io::Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let addr = listener.get_ref().local_addr()?;
+        let (server, client) = futures::join!(
+            listener.accept(),
+            async_io::Async::<TcpStream>::connect(addr)
+        );
+        Ok((
+            yamux.upgrade_inbound(server?.0).await?,
+            Yamux::new().upgrade_outbound(client?).await?,
+        ))
+    }
+
+    #[async_std::test]
+    async fn test_yamux_streams() -> Result<(), Error> {
+        let (server, client) = connection_pair().await?;
+
+        // more than a window in one direction, to go through window updates
+        let payload: Vec<u8> = (0..1024 * 1024).map(|i| i as u8).collect();
+        let echo = async_std::task::spawn({
+            let server = server.clone();
+            async move {
+                let mut stream = server.accept_stream().await?;
+                let mut buf = Vec::new();
+                stream.read_to_end(&mut buf).await?;
+                stream.write_all(&buf[..16]).await?;
+                stream.close().await?;
+                Ok::<_, Error>(buf)
+            }
+        });
+
+        let mut stream = client.open_stream().await?;
+        assert_eq!(stream.id(), 1);
+        stream.write_all(&payload).await?;
+        // half-close, the response is still readable
+        stream.close().await?;
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await?;
+        assert_eq!(buf, payload[..16].to_vec());
+        assert_eq!(echo.await?, payload);
+
+        // streams are multiplexed
+        let (mut a, mut b) = (client.open_stream().await?, client.open_stream().await?);
+        assert_eq!((a.id(), b.id()), (3, 5));
+        b.write_all(b"b").await?;
+        a.write_all(b"a").await?;
+        let mut first = server.accept_stream().await?;
+        let mut buf = [0u8; 1];
+        first.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"a");
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_yamux_ping_and_go_away() -> Result<(), Error> {
+        let (server, client) = connection_pair().await?;
+        client.ping().await?;
+        server.ping().await?;
+
+        // stream reset by the remote
+        let mut stream = client.open_stream().await?;
+        drop(server.accept_stream().await?);
+        let mut buf = [0u8; 1];
+        assert!(stream.read(&mut buf).await.is_err());
+
+        // the connection ends once the go away is received
+        server.close();
+        assert!(client.accept_stream().await.is_err());
+        assert!(client.open_stream().await.is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_yamux_stream_limit() -> Result<(), Error> {
+        let (server, client) = connection_pair_with(Yamux::new().with_max_streams(1)).await?;
+
+        let mut first = client.open_stream().await?;
+        first.write_all(b"first").await?;
+        let _inbound = server.accept_stream().await?;
+        // over the limit of the server, the stream is reset
+        let mut second = client.open_stream().await?;
+        let mut buf = [0u8; 1];
+        assert!(second.read(&mut buf).await.is_err());
+
+        // and the server opens no more than its limit
+        assert!(server.open_stream().await.is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_yamux_dropped_half_closed_stream() -> Result<(), Error> {
+        let (server, client) = connection_pair_with(Yamux::new().with_max_streams(1)).await?;
+
+        let mut first = client.open_stream().await?;
+        first.write_all(b"first").await?;
+        let mut inbound = server.accept_stream().await?;
+        inbound.write_all(b"reply").await?;
+        inbound.close().await?;
+        drop(inbound);
+        // the remote reads to the end, not reset
+        let mut buf = Vec::new();
+        first.read_to_end(&mut buf).await?;
+        assert_eq!(buf, b"reply");
+
+        // the slot is freed before the client closes the stream
+        let mut second = client.open_stream().await?;
+        second.write_all(b"second").await?;
+        let mut inbound = server.accept_stream().await?;
+        let mut buf = [0u8; 6];
+        inbound.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"second");
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_yamux_reads_while_writes_block() -> Result<(), Error> {
+        let (frames, received) = mpsc::unbounded();
+        let io = Stalled(received.into_async_read());
+        let connection = YamuxConnection::new(io, YamuxMode::Client, DEFAULT_MAX_STREAMS);
+
+        // the acknowledgement of the stream is never written, its data is
+        // still read
+        let mut codec = YamuxCodec::new(stream::DEFAULT_WINDOW_SIZE as usize);
+        for frame in [
+            YamuxFrame::window_update(2, YAMUX_FLAG_SYN, 0),
+            YamuxFrame::data(2, 0, b"hello".to_vec()),
+        ] {
+            let mut buf = BytesMut::new();
+            codec.encode(&frame, &mut buf)?;
+            let _ = frames.unbounded_send(Ok(buf.to_vec()));
+        }
+        crate::io::timeout(Duration::from_secs(5), async {
+            let mut stream = connection.accept_stream().await?;
+            let mut buf = [0u8; 5];
+            stream.read_exact(&mut buf).await?;
+            assert_eq!(&buf, b"hello");
+            Ok(())
+        })
+        .await
+    }
+}
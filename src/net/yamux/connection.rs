@@ -0,0 +1,366 @@
+use super::stream::{Shared, YamuxStream, DEFAULT_WINDOW_SIZE};
+use crate::{
+    error::{self, Error},
+    io::{
+        YamuxCodec, YamuxFrame, YamuxFrameType, YAMUX_FLAG_ACK, YAMUX_FLAG_FIN, YAMUX_FLAG_RST,
+        YAMUX_FLAG_SYN, YAMUX_GO_AWAY_NORMAL, YAMUX_GO_AWAY_PROTOCOL_ERROR,
+    },
+};
+use asynchronous_codec::{Encoder, FramedRead};
+use bytes::BytesMut;
+use futures::{
+    channel::{mpsc, oneshot},
+    io::{ReadHalf, WriteHalf},
+    lock::Mutex as AsyncMutex,
+    stream, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, StreamExt,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+const MAX_INCOMING_BACKLOG: usize = 32;
+
+// YamuxMode decides the parity of the stream ids opened locally
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YamuxMode {
+    Client,
+    Server,
+}
+
+pub(super) enum Command {
+    Open(oneshot::Sender<Result<YamuxStream, Error>>),
+    Send(YamuxFrame),
+    // the handle of the stream is dropped
+    Dropped(u32),
+    Ping(oneshot::Sender<Duration>),
+    Close,
+}
+
+enum Event {
+    Frame(Result<YamuxFrame, Error>),
+    ReadClosed,
+    Command(Command),
+}
+
+// YamuxConnection
+//
+// A handle of the connection, the frames are read by a driver task spawned
+// along with it, which queues the frames it sends to a writer task
+#[derive(Clone)]
+pub struct YamuxConnection {
+    commands: mpsc::UnboundedSender<Command>,
+    incoming: Arc<AsyncMutex<mpsc::Receiver<YamuxStream>>>,
+}
+
+impl YamuxConnection {
+    // no more than max_streams are open at once
+    pub fn new<T>(io: T, mode: YamuxMode, max_streams: usize) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (commands_tx, commands_rx) = mpsc::unbounded();
+        let (incoming_tx, incoming_rx) = mpsc::channel(MAX_INCOMING_BACKLOG);
+        let (reader, writer) = io.split();
+        let (frames_tx, frames_rx) = mpsc::unbounded();
+        async_std::task::spawn(write_frames(writer, frames_rx, commands_tx.clone()));
+
+        let driver = Driver {
+            frames: frames_tx,
+            next_id: match mode {
+                YamuxMode::Client => 1,
+                YamuxMode::Server => 2,
+            },
+            streams: HashMap::new(),
+            max_streams,
+            commands: commands_tx.clone(),
+            incoming: incoming_tx,
+            pings: HashMap::new(),
+            next_ping: 0,
+            go_away_recv: false,
+        };
+        async_std::task::spawn(driver.run(reader, commands_rx));
+
+        Self {
+            commands: commands_tx,
+            incoming: Arc::new(AsyncMutex::new(incoming_rx)),
+        }
+    }
+
+    pub async fn open_stream(&self) -> Result<YamuxStream, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.commands
+            .unbounded_send(Command::Open(tx))
+            .map_err(|_| error::other("yamux connection closed"))?;
+        rx.await
+            .map_err(|_| error::other("yamux connection closed"))?
+    }
+
+    pub async fn accept_stream(&self) -> Result<YamuxStream, Error> {
+        self.incoming
+            .lock()
+            .await
+            .next()
+            .await
+            .ok_or(error::other("yamux connection closed"))
+    }
+
+    // round trip time of a ping frame
+    pub async fn ping(&self) -> Result<Duration, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.commands
+            .unbounded_send(Command::Ping(tx))
+            .map_err(|_| error::other("yamux connection closed"))?;
+        rx.await
+            .map_err(|_| error::other("yamux connection closed"))
+    }
+
+    // go away and close the underlying io
+    pub fn close(&self) {
+        let _ = self.commands.unbounded_send(Command::Close);
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.commands.is_closed()
+    }
+}
+
+// write the frames queued by the driver, so that it keeps reading while the
+// remote is slow to read, and end the connection once a write fails
+async fn write_frames<T>(
+    mut writer: WriteHalf<T>,
+    mut frames: mpsc::UnboundedReceiver<YamuxFrame>,
+    commands: mpsc::UnboundedSender<Command>,
+) where
+    T: AsyncWrite + Unpin,
+{
+    let mut codec = YamuxCodec::new(DEFAULT_WINDOW_SIZE as usize);
+    while let Some(frame) = frames.next().await {
+        let mut buf = BytesMut::new();
+        let res = match codec.encode(&frame, &mut buf) {
+            Ok(()) => match writer.write_all(&buf).await {
+                Ok(()) => writer.flush().await,
+                Err(err) => Err(err),
+            },
+            Err(err) => Err(err),
+        };
+        if let Err(err) = res {
+            log::debug!("yamux write failed, {:?}", err);
+            let _ = commands.unbounded_send(Command::Close);
+            break;
+        }
+    }
+    let _ = writer.close().await;
+}
+
+// Driver
+struct Driver {
+    frames: mpsc::UnboundedSender<YamuxFrame>,
+    next_id: u32,
+    streams: HashMap<u32, Arc<Mutex<Shared>>>,
+    max_streams: usize,
+    commands: mpsc::UnboundedSender<Command>,
+    incoming: mpsc::Sender<YamuxStream>,
+    pings: HashMap<u32, (Instant, oneshot::Sender<Duration>)>,
+    next_ping: u32,
+    go_away_recv: bool,
+}
+
+impl Driver {
+    async fn run<T>(mut self, reader: ReadHalf<T>, commands: mpsc::UnboundedReceiver<Command>)
+    where
+        T: AsyncRead + Unpin,
+    {
+        let frames = FramedRead::new(reader, YamuxCodec::new(DEFAULT_WINDOW_SIZE as usize))
+            .map(Event::Frame)
+            .chain(stream::iter([Event::ReadClosed]));
+        let mut events = stream::select(frames, commands.map(Event::Command));
+
+        while let Some(event) = events.next().await {
+            let res = match event {
+                Event::Frame(Ok(frame)) => self.on_frame(frame),
+                Event::Frame(Err(err)) => {
+                    log::debug!("yamux frame invalid, {:?}", err);
+                    let _ = self.write(YamuxFrame::go_away(YAMUX_GO_AWAY_PROTOCOL_ERROR));
+                    Err(err)
+                }
+                Event::ReadClosed => Err(error::other("yamux connection closed")),
+                Event::Command(Command::Close) => {
+                    let _ = self.write(YamuxFrame::go_away(YAMUX_GO_AWAY_NORMAL));
+                    Err(error::other("yamux connection closed"))
+                }
+                Event::Command(command) => self.on_command(command),
+            };
+            if let Err(err) = res {
+                log::debug!("yamux connection end, {:?}", err);
+                break;
+            }
+        }
+
+        // fail everything still waiting on the connection, the writer closes
+        // the io once the frames queued so far are written
+        self.frames.close_channel();
+        self.commands.close_channel();
+        for shared in self.streams.values() {
+            let mut shared = shared.lock().unwrap();
+            if !shared.remote_closed {
+                shared.reset = true;
+            }
+            shared.wake();
+        }
+    }
+
+    fn write(&mut self, frame: YamuxFrame) -> Result<(), Error> {
+        self.frames
+            .unbounded_send(frame)
+            .map_err(|_| error::other("yamux connection closed"))
+    }
+
+    fn new_stream(&mut self, id: u32) -> YamuxStream {
+        let shared = Arc::new(Mutex::new(Shared::new()));
+        self.streams.insert(id, shared.clone());
+        YamuxStream::new(id, shared, self.commands.clone())
+    }
+
+    // remove the stream once both sides are closed, it is reset or its handle
+    // is dropped
+    fn gc_stream(&mut self, id: u32) {
+        let closed = match self.streams.get(&id) {
+            Some(shared) => shared.lock().unwrap().is_closed(),
+            None => false,
+        };
+        if closed {
+            self.streams.remove(&id);
+        }
+    }
+
+    fn on_command(&mut self, command: Command) -> Result<(), Error> {
+        match command {
+            Command::Open(reply) => {
+                if self.go_away_recv {
+                    let _ = reply.send(Err(error::other("yamux remote went away")));
+                    return Ok(());
+                }
+                if self.streams.len() >= self.max_streams {
+                    let _ = reply.send(Err(error::other("too many yamux streams")));
+                    return Ok(());
+                }
+                let id = self.next_id;
+                self.next_id += 2;
+                let stream = self.new_stream(id);
+                self.write(YamuxFrame::window_update(id, YAMUX_FLAG_SYN, 0))?;
+                log::debug!("yamux stream {} opened", id);
+                let _ = reply.send(Ok(stream));
+            }
+            Command::Send(frame) => {
+                let id = frame.stream_id;
+                // frames of streams reset by the remote are dropped, while
+                // frames queued before the stream is removed are still sent
+                let reset = match self.streams.get(&id) {
+                    Some(shared) => shared.lock().unwrap().reset && !frame.has_flag(YAMUX_FLAG_RST),
+                    None => false,
+                };
+                if !reset {
+                    self.write(frame)?;
+                }
+                self.gc_stream(id);
+            }
+            Command::Dropped(id) => self.gc_stream(id),
+            Command::Ping(reply) => {
+                let opaque = self.next_ping;
+                self.next_ping = self.next_ping.wrapping_add(1);
+                self.pings.insert(opaque, (Instant::now(), reply));
+                self.write(YamuxFrame::ping(YAMUX_FLAG_SYN, opaque))?;
+            }
+            Command::Close => unreachable!(),
+        }
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: YamuxFrame) -> Result<(), Error> {
+        match frame.frame_type {
+            YamuxFrameType::Ping => {
+                if frame.has_flag(YAMUX_FLAG_SYN) {
+                    self.write(YamuxFrame::ping(YAMUX_FLAG_ACK, frame.length))?;
+                } else if let Some((sent_at, reply)) = self.pings.remove(&frame.length) {
+                    let _ = reply.send(sent_at.elapsed());
+                }
+                Ok(())
+            }
+            YamuxFrameType::GoAway => {
+                log::debug!("yamux remote go away with code {}", frame.length);
+                self.go_away_recv = true;
+                Ok(())
+            }
+            YamuxFrameType::Data | YamuxFrameType::WindowUpdate => self.on_stream_frame(frame),
+        }
+    }
+
+    fn on_stream_frame(&mut self, frame: YamuxFrame) -> Result<(), Error> {
+        let id = frame.stream_id;
+
+        if frame.has_flag(YAMUX_FLAG_SYN) {
+            if self.streams.contains_key(&id) || id == 0 || id % 2 == self.next_id % 2 {
+                return Err(error::message_malformed());
+            }
+            if self.streams.len() >= self.max_streams {
+                log::debug!("yamux stream {} refused, too many streams", id);
+                self.write(YamuxFrame::window_update(id, YAMUX_FLAG_RST, 0))?;
+                return Ok(());
+            }
+            let stream = self.new_stream(id);
+            if let Err(err) = self.incoming.try_send(stream) {
+                // backlog is full or nobody accepts streams
+                log::debug!("yamux stream {} refused", id);
+                drop(err.into_inner());
+                return Ok(());
+            }
+            self.write(YamuxFrame::window_update(id, YAMUX_FLAG_ACK, 0))?;
+            log::debug!("yamux stream {} accepted", id);
+        }
+
+        let shared = match self.streams.get(&id) {
+            Some(shared) => shared.clone(),
+            None => {
+                // late frames of removed streams are ignored
+                log::debug!("yamux stream {} unknown", id);
+                return Ok(());
+            }
+        };
+
+        let exceeded = {
+            let mut shared = shared.lock().unwrap();
+            match frame.frame_type {
+                YamuxFrameType::Data if frame.length > shared.recv_window => true,
+                YamuxFrameType::Data => {
+                    shared.recv_window -= frame.length;
+                    shared.recv_buffer.extend_from_slice(&frame.body);
+                    false
+                }
+                _ => {
+                    shared.send_window = shared.send_window.saturating_add(frame.length);
+                    false
+                }
+            }
+        };
+        if exceeded {
+            log::debug!("yamux stream {} exceeded receive window", id);
+            self.write(YamuxFrame::go_away(YAMUX_GO_AWAY_PROTOCOL_ERROR))?;
+            return Err(error::message_malformed());
+        }
+
+        {
+            let mut shared = shared.lock().unwrap();
+            if frame.has_flag(YAMUX_FLAG_FIN) {
+                shared.remote_closed = true;
+            }
+            if frame.has_flag(YAMUX_FLAG_RST) {
+                shared.reset = true;
+            }
+            shared.wake();
+        }
+        self.gc_stream(id);
+        Ok(())
+    }
+}
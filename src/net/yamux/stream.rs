@@ -0,0 +1,208 @@
+use super::connection::Command;
+use crate::io::{YamuxFrame, YAMUX_FLAG_FIN, YAMUX_FLAG_RST};
+use bytes::BytesMut;
+use futures::{
+    channel::mpsc,
+    task::{Context, Poll, Waker},
+    AsyncRead, AsyncWrite,
+};
+use std::{
+    cmp::min,
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+
+pub const DEFAULT_WINDOW_SIZE: u32 = 256 * 1024;
+pub const MAX_DATA_FRAME_SIZE: usize = 16 * 1024;
+
+// Shared state of a stream between its handle and the connection driver
+pub struct Shared {
+    pub recv_buffer: BytesMut,
+    // credit granted to the remote
+    pub recv_window: u32,
+    // bytes read by the handle but not yet granted back to the remote
+    pub recv_consumed: u32,
+    pub send_window: u32,
+    pub local_closed: bool,
+    pub remote_closed: bool,
+    pub reset: bool,
+    // the handle is gone, the stream is removed without waiting for the
+    // remote to close it
+    pub dropped: bool,
+    pub reader: Option<Waker>,
+    pub writer: Option<Waker>,
+}
+
+impl Shared {
+    pub fn new() -> Self {
+        Self {
+            recv_buffer: BytesMut::new(),
+            recv_window: DEFAULT_WINDOW_SIZE,
+            recv_consumed: 0,
+            send_window: DEFAULT_WINDOW_SIZE,
+            local_closed: false,
+            remote_closed: false,
+            reset: false,
+            dropped: false,
+            reader: None,
+            writer: None,
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.reset || self.dropped || (self.local_closed && self.remote_closed)
+    }
+
+    pub fn wake(&mut self) {
+        if let Some(waker) = self.reader.take() {
+            waker.wake();
+        }
+        if let Some(waker) = self.writer.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Default for Shared {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// YamuxStream
+pub struct YamuxStream {
+    id: u32,
+    shared: Arc<Mutex<Shared>>,
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+impl YamuxStream {
+    pub(super) fn new(
+        id: u32,
+        shared: Arc<Mutex<Shared>>,
+        commands: mpsc::UnboundedSender<Command>,
+    ) -> Self {
+        Self {
+            id,
+            shared,
+            commands,
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn send(&self, frame: YamuxFrame) -> Result<(), futures::io::Error> {
+        self.commands
+            .unbounded_send(Command::Send(frame))
+            .map_err(|_| futures::io::ErrorKind::BrokenPipe.into())
+    }
+}
+
+impl AsyncRead for YamuxStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, futures::io::Error>> {
+        let this = self.get_mut();
+        let mut shared = this.shared.lock().unwrap();
+        if !shared.recv_buffer.is_empty() {
+            let n = min(buf.len(), shared.recv_buffer.len());
+            buf[..n].copy_from_slice(&shared.recv_buffer.split_to(n));
+            shared.recv_consumed += n as u32;
+            // grant the credit back once half of the window is consumed
+            if shared.recv_consumed >= DEFAULT_WINDOW_SIZE / 2 && !shared.remote_closed {
+                let delta = shared.recv_consumed;
+                shared.recv_consumed = 0;
+                shared.recv_window += delta;
+                drop(shared);
+                // the stream is gone if the driver is, but the data is still valid
+                let _ = this.send(YamuxFrame::window_update(this.id, 0, delta));
+            }
+            return Poll::Ready(Ok(n));
+        }
+        if shared.reset {
+            return Poll::Ready(Err(futures::io::ErrorKind::ConnectionReset.into()));
+        }
+        if shared.remote_closed {
+            return Poll::Ready(Ok(0));
+        }
+        shared.reader = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl AsyncWrite for YamuxStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, futures::io::Error>> {
+        let this = self.get_mut();
+        let mut shared = this.shared.lock().unwrap();
+        if shared.reset {
+            return Poll::Ready(Err(futures::io::ErrorKind::ConnectionReset.into()));
+        }
+        if shared.local_closed {
+            return Poll::Ready(Err(futures::io::ErrorKind::BrokenPipe.into()));
+        }
+        if shared.send_window == 0 {
+            shared.writer = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let n = min(
+            min(buf.len(), shared.send_window as usize),
+            MAX_DATA_FRAME_SIZE,
+        );
+        shared.send_window -= n as u32;
+        drop(shared);
+        this.send(YamuxFrame::data(this.id, 0, buf[..n].to_vec()))?;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), futures::io::Error>> {
+        // frames are written out by the connection driver
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), futures::io::Error>> {
+        let this = self.get_mut();
+        let mut shared = this.shared.lock().unwrap();
+        if shared.local_closed || shared.reset {
+            return Poll::Ready(Ok(()));
+        }
+        shared.local_closed = true;
+        drop(shared);
+        // half-close, the remote can still send until it closes as well
+        this.send(YamuxFrame::data(this.id, YAMUX_FLAG_FIN, Vec::new()))?;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for YamuxStream {
+    fn drop(&mut self) {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.is_closed() {
+            return;
+        }
+        // a half-closed stream is not reset, so that the remote reads to the
+        // end, but its slot is freed without waiting for the remote to close
+        if shared.local_closed {
+            shared.dropped = true;
+            drop(shared);
+            let _ = self.commands.unbounded_send(Command::Dropped(self.id));
+            return;
+        }
+        shared.reset = true;
+        drop(shared);
+        let _ = self.send(YamuxFrame::data(self.id, YAMUX_FLAG_RST, Vec::new()));
+    }
+}
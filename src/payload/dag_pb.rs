@@ -0,0 +1,90 @@
+// Automatically generated rust module for 'dag_pb.proto' file
+
+#![allow(non_snake_case)]
+#![allow(non_upper_case_globals)]
+#![allow(non_camel_case_types)]
+#![allow(unused_imports)]
+#![allow(unknown_lints)]
+#![allow(clippy::all)]
+#![cfg_attr(rustfmt, rustfmt_skip)]
+
+
+use quick_protobuf::{MessageInfo, MessageRead, MessageWrite, BytesReader, Writer, WriterBackend, Result};
+use quick_protobuf::sizeofs::*;
+use super::*;
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct PBLink {
+    pub Hash: Option<Vec<u8>>,
+    pub Name: Option<String>,
+    pub Tsize: Option<u64>,
+}
+
+impl<'a> MessageRead<'a> for PBLink {
+    fn from_reader(r: &mut BytesReader, bytes: &'a [u8]) -> Result<Self> {
+        let mut msg = Self::default();
+        while !r.is_eof() {
+            match r.next_tag(bytes) {
+                Ok(10) => msg.Hash = Some(r.read_bytes(bytes).map(Vec::from)?),
+                Ok(18) => msg.Name = Some(r.read_string(bytes)?.to_owned()),
+                Ok(24) => msg.Tsize = Some(r.read_uint64(bytes)?),
+                Ok(t) => { r.read_unknown(bytes, t)?; }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(msg)
+    }
+}
+
+impl MessageWrite for PBLink {
+    fn get_size(&self) -> usize {
+        0
+        + self.Hash.as_ref().map_or(0, |m| 1 + sizeof_len((m).len()))
+        + self.Name.as_ref().map_or(0, |m| 1 + sizeof_len((m).len()))
+        + self.Tsize.as_ref().map_or(0, |m| 1 + sizeof_varint(*(m) as u64))
+    }
+
+    fn write_message<W: WriterBackend>(&self, w: &mut Writer<W>) -> Result<()> {
+        if let Some(ref s) = self.Hash { w.write_with_tag(10, |w| w.write_bytes(&**s))?; }
+        if let Some(ref s) = self.Name { w.write_with_tag(18, |w| w.write_string(&**s))?; }
+        if let Some(ref s) = self.Tsize { w.write_with_tag(24, |w| w.write_uint64(*s))?; }
+        Ok(())
+    }
+}
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct PBNode {
+    pub Links: Vec<PBLink>,
+    pub Data: Option<Vec<u8>>,
+}
+
+impl<'a> MessageRead<'a> for PBNode {
+    fn from_reader(r: &mut BytesReader, bytes: &'a [u8]) -> Result<Self> {
+        let mut msg = Self::default();
+        while !r.is_eof() {
+            match r.next_tag(bytes) {
+                Ok(18) => msg.Links.push(r.read_message::<PBLink>(bytes)?),
+                Ok(10) => msg.Data = Some(r.read_bytes(bytes).map(Vec::from)?),
+                Ok(t) => { r.read_unknown(bytes, t)?; }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(msg)
+    }
+}
+
+impl MessageWrite for PBNode {
+    fn get_size(&self) -> usize {
+        0
+        + self.Links.iter().map(|s| 1 + sizeof_len((s).get_size())).sum::<usize>()
+        + self.Data.as_ref().map_or(0, |m| 1 + sizeof_len((m).len()))
+    }
+
+    fn write_message<W: WriterBackend>(&self, w: &mut Writer<W>) -> Result<()> {
+        for s in &self.Links { w.write_with_tag(18, |w| w.write_message(s))?; }
+        if let Some(ref s) = self.Data { w.write_with_tag(10, |w| w.write_bytes(&**s))?; }
+        Ok(())
+    }
+}
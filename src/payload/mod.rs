@@ -1,3 +1,18 @@
 // Automatically generated mod.rs
+pub mod autonat;
+pub mod bitswap;
+pub mod circuit;
+pub mod dag_pb;
+pub mod envelope;
+pub mod holepunch;
+pub mod identify;
+pub mod ipns;
+pub mod kad;
 pub mod keys;
 pub mod noise;
+pub mod peer_record;
+pub mod plaintext;
+pub mod pubsub;
+pub mod rendezvous;
+pub mod unixfs;
+pub mod voucher;
@@ -0,0 +1,713 @@
+use crate::{
+    cid::{Cid, CODEC_DAG_PB, CODEC_RAW},
+    error::{self, Error},
+    io::protobuf_decode,
+    net::{Bitswap, Blockstore, Session},
+    payload::{
+        dag_pb::{PBLink, PBNode},
+        unixfs::{mod_Data::DataType, Data},
+    },
+};
+use async_std::fs;
+use futures::{stream, AsyncWriteExt, Future, FutureExt, Stream, StreamExt};
+use std::{
+    collections::HashSet,
+    path::{Component, Path},
+    pin::Pin,
+};
+
+// murmur3-x64-64, the only hash of sharded directories
+const HAMT_HASH_MURMUR3: u64 = 0x22;
+
+type BlockFuture<'a> = Pin<Box<dyn Future<Output = Result<Vec<u8>, Error>> + Send + 'a>>;
+
+// BlockSource
+//
+// Where the blocks of a dag are read from
+pub trait BlockSource: Send + Sync {
+    fn block<'a>(&'a self, cid: &'a Cid) -> BlockFuture<'a>;
+}
+
+impl<B: Blockstore> BlockSource for B {
+    fn block<'a>(&'a self, cid: &'a Cid) -> BlockFuture<'a> {
+        let block = self
+            .get(cid)
+            .and_then(|block| block.ok_or_else(|| error::not_found("block not found")));
+        futures::future::ready(block).boxed()
+    }
+}
+
+impl BlockSource for Bitswap {
+    fn block<'a>(&'a self, cid: &'a Cid) -> BlockFuture<'a> {
+        self.get(cid).boxed()
+    }
+}
+
+impl BlockSource for Session {
+    fn block<'a>(&'a self, cid: &'a Cid) -> BlockFuture<'a> {
+        self.get(cid).boxed()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+}
+
+// DirEntry
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub cid: Cid,
+    // the size of the dag below the link
+    pub size: u64,
+}
+
+enum Node {
+    Raw(Vec<u8>),
+    Pb(PBNode, Data),
+}
+
+impl Node {
+    fn kind(&self) -> Result<EntryKind, Error> {
+        match self {
+            Self::Raw(_) => Ok(EntryKind::File),
+            Self::Pb(_, data) => match data.Type {
+                DataType::Raw | DataType::File => Ok(EntryKind::File),
+                DataType::Directory | DataType::HAMTShard => Ok(EntryKind::Directory),
+                DataType::Symlink => Ok(EntryKind::Symlink),
+                DataType::Metadata => Err(error::unsupported("unsupported unixfs type")),
+            },
+        }
+    }
+}
+
+fn link_cid(link: &PBLink) -> Result<Cid, Error> {
+    Cid::from_bytes(link.Hash.as_deref().ok_or_else(error::decode_error)?)
+}
+
+// names are single path components, which alone do not keep a dag within
+// the directory it is written to, see get
+fn check_name(name: &str) -> Result<&str, Error> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\', '\0']) {
+        return Err(error::invalid_data("invalid unixfs name"));
+    }
+    Ok(name)
+}
+
+// UnixFs
+//
+// Files and directories of the UnixFS dags, every block read is checked
+// against its cid
+pub struct UnixFs<S> {
+    source: S,
+}
+
+impl<S: BlockSource> UnixFs<S> {
+    pub fn new(source: S) -> Self {
+        Self { source }
+    }
+
+    pub async fn kind(&self, cid: &Cid) -> Result<EntryKind, Error> {
+        self.node(cid).await?.kind()
+    }
+
+    // the entries of a directory, sharded or not
+    pub async fn ls(&self, cid: &Cid) -> Result<Vec<DirEntry>, Error> {
+        match self.node(cid).await? {
+            Node::Pb(node, data) => self.entries(node, data).await,
+            Node::Raw(_) => Err(error::invalid_input("not a directory")),
+        }
+    }
+
+    // the cid at the slash separated path below the directory
+    pub async fn resolve(&self, cid: &Cid, path: &str) -> Result<Cid, Error> {
+        let mut cid = cid.clone();
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            cid = self.lookup(&cid, name).await?;
+        }
+        Ok(cid)
+    }
+
+    // the bytes of the file in order, a block at a time
+    pub fn cat<'a>(&'a self, cid: &Cid) -> impl Stream<Item = Result<Vec<u8>, Error>> + 'a {
+        self.file_stream(vec![cid.clone()], Vec::new())
+    }
+
+    pub async fn read_file(&self, cid: &Cid) -> Result<Vec<u8>, Error> {
+        let mut file = Vec::new();
+        let mut chunks = Box::pin(self.cat(cid));
+        while let Some(chunk) = chunks.next().await {
+            file.extend(chunk?);
+        }
+        Ok(file)
+    }
+
+    // write the file, or the directory and all below it, at the path; the
+    // entries are created anew, never through what is there already, and
+    // symlinks lead only downwards, so that a dag can not write outside the
+    // path
+    pub async fn get(&self, cid: &Cid, path: impl AsRef<Path>) -> Result<(), Error> {
+        let root = path.as_ref().to_path_buf();
+        let mut pending = vec![(cid.clone(), root.clone())];
+        while let Some((cid, path)) = pending.pop() {
+            let node = self.node(&cid).await?;
+            match (node.kind()?, node) {
+                (EntryKind::File, Node::Raw(data)) => {
+                    write_file(&path, stream::once(async { Ok(data) })).await?
+                }
+                (EntryKind::File, Node::Pb(node, data)) => {
+                    let links = node
+                        .Links
+                        .iter()
+                        .rev()
+                        .map(link_cid)
+                        .collect::<Result<_, _>>()?;
+                    let chunks = self.file_stream(links, data.Data.unwrap_or_default());
+                    write_file(&path, chunks).await?;
+                }
+                (EntryKind::Directory, Node::Pb(node, data)) => {
+                    if path == root {
+                        fs::create_dir_all(&path).await?;
+                    } else {
+                        fs::create_dir(&path).await?;
+                    }
+                    let mut names = HashSet::new();
+                    for entry in self.entries(node, data).await? {
+                        if !names.insert(entry.name.clone()) {
+                            return Err(error::invalid_data("duplicate unixfs name"));
+                        }
+                        let entry_path = path.join(check_name(&entry.name)?);
+                        pending.push((entry.cid, entry_path));
+                    }
+                }
+                (EntryKind::Symlink, Node::Pb(_, data)) => {
+                    let target = String::from_utf8(data.Data.unwrap_or_default())
+                        .map_err(|_| error::decode_error())?;
+                    check_symlink_target(&target)?;
+                    symlink(&target, &path).await?;
+                }
+                _ => return Err(error::decode_error()),
+            }
+        }
+        Ok(())
+    }
+
+    async fn node(&self, cid: &Cid) -> Result<Node, Error> {
+        let block = self.source.block(cid).await?;
+        cid.verify(&block)?;
+        match cid.codec() {
+            CODEC_RAW => Ok(Node::Raw(block)),
+            CODEC_DAG_PB => {
+                let node: PBNode = protobuf_decode(&block)?;
+                let data: Data = protobuf_decode(node.Data.as_deref().unwrap_or_default())?;
+                Ok(Node::Pb(node, data))
+            }
+            _ => Err(error::unsupported("unsupported codec")),
+        }
+    }
+
+    // the file blocks depth first, the data of a node comes before the data
+    // of its links
+    fn file_stream<'a>(
+        &'a self,
+        stack: Vec<Cid>,
+        first: Vec<u8>,
+    ) -> impl Stream<Item = Result<Vec<u8>, Error>> + 'a {
+        let chunks = stream::try_unfold(stack, move |mut stack| async move {
+            while let Some(cid) = stack.pop() {
+                let data = match self.node(&cid).await? {
+                    Node::Raw(data) => data,
+                    Node::Pb(node, data) => {
+                        if !matches!(data.Type, DataType::File | DataType::Raw) {
+                            return Err(error::invalid_input("not a file"));
+                        }
+                        for link in node.Links.iter().rev() {
+                            stack.push(link_cid(link)?);
+                        }
+                        data.Data.unwrap_or_default()
+                    }
+                };
+                if !data.is_empty() {
+                    return Ok(Some((data, stack)));
+                }
+            }
+            Ok(None)
+        });
+        let first = (!first.is_empty()).then_some(Ok(first));
+        stream::iter(first).chain(chunks)
+    }
+
+    // the entry of a directory by name, in a shard only the buckets of the
+    // hashed name are followed
+    async fn lookup(&self, cid: &Cid, name: &str) -> Result<Cid, Error> {
+        let (mut node, mut data) = match self.node(cid).await? {
+            Node::Pb(node, data) => (node, data),
+            Node::Raw(_) => return Err(error::invalid_input("not a directory")),
+        };
+        match data.Type {
+            DataType::Directory => {
+                let link = node
+                    .Links
+                    .iter()
+                    .find(|link| link.Name.as_deref() == Some(name))
+                    .ok_or_else(|| error::not_found("path not found"))?;
+                return link_cid(link);
+            }
+            DataType::HAMTShard => (),
+            _ => return Err(error::invalid_input("not a directory")),
+        }
+        let hash = murmur3_x64_64(name.as_bytes());
+        let mut used_bits = 0;
+        loop {
+            let fanout = shard_fanout(&data)?;
+            let bits = fanout.trailing_zeros();
+            if used_bits + bits > u64::BITS {
+                return Err(error::unsupported("hamt shard too deep"));
+            }
+            let bucket = (hash << used_bits) >> (u64::BITS - bits);
+            used_bits += bits;
+            let prefix = format!("{:0width$X}", bucket, width = prefix_len(fanout));
+            let mut shard = None;
+            for link in &node.Links {
+                let Some(rest) = link.Name.as_deref().and_then(|n| n.strip_prefix(&prefix)) else {
+                    continue;
+                };
+                if rest == name {
+                    return link_cid(link);
+                }
+                if rest.is_empty() {
+                    shard = Some(link_cid(link)?);
+                }
+            }
+            let shard = shard.ok_or_else(|| error::not_found("path not found"))?;
+            (node, data) = match self.node(&shard).await? {
+                Node::Pb(node, data) if data.Type == DataType::HAMTShard => (node, data),
+                _ => return Err(error::decode_error()),
+            };
+        }
+    }
+
+    // the links of a directory, the links of a shard are prefixed by the
+    // bucket in hex and lead to entries or, bare, to other shards
+    async fn entries(&self, node: PBNode, data: Data) -> Result<Vec<DirEntry>, Error> {
+        let mut entries = Vec::new();
+        match data.Type {
+            DataType::Directory => {
+                for link in &node.Links {
+                    entries.push(DirEntry {
+                        name: link.Name.clone().unwrap_or_default(),
+                        cid: link_cid(link)?,
+                        size: link.Tsize.unwrap_or(0),
+                    });
+                }
+            }
+            DataType::HAMTShard => {
+                let mut shards = vec![(node, data)];
+                while let Some((node, data)) = shards.pop() {
+                    let prefix_len = prefix_len(shard_fanout(&data)?);
+                    for link in &node.Links {
+                        let name = link.Name.as_deref().unwrap_or_default();
+                        if name.len() < prefix_len || !name.is_char_boundary(prefix_len) {
+                            return Err(error::decode_error());
+                        }
+                        let cid = link_cid(link)?;
+                        if name.len() > prefix_len {
+                            entries.push(DirEntry {
+                                name: name[prefix_len..].to_string(),
+                                cid,
+                                size: link.Tsize.unwrap_or(0),
+                            });
+                            continue;
+                        }
+                        match self.node(&cid).await? {
+                            Node::Pb(node, data) if data.Type == DataType::HAMTShard => {
+                                shards.push((node, data))
+                            }
+                            _ => return Err(error::decode_error()),
+                        }
+                    }
+                }
+            }
+            _ => return Err(error::invalid_input("not a directory")),
+        }
+        Ok(entries)
+    }
+}
+
+// the fanout of a shard, a power of two of names hashed by murmur3
+fn shard_fanout(data: &Data) -> Result<u64, Error> {
+    let Some(fanout) = data
+        .fanout
+        .filter(|fanout| fanout.is_power_of_two() && *fanout > 1)
+        .filter(|_| data.hashType == Some(HAMT_HASH_MURMUR3))
+    else {
+        return Err(error::unsupported("unsupported hamt shard"));
+    };
+    Ok(fanout)
+}
+
+// the hex digits of the bucket before the names of a shard
+fn prefix_len(fanout: u64) -> usize {
+    format!("{:X}", fanout - 1).len()
+}
+
+// the first half of murmur3-x64-128 with a zero seed, the bits of the hash
+// pick the buckets from the most significant down
+fn murmur3_x64_64(data: &[u8]) -> u64 {
+    const C1: u64 = 0x87c3_7b91_1142_53d5;
+    const C2: u64 = 0x4cf5_ad43_2745_937f;
+    fn fmix(mut k: u64) -> u64 {
+        k ^= k >> 33;
+        k = k.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        k ^= k >> 33;
+        k = k.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        k ^ (k >> 33)
+    }
+    fn word(bytes: &[u8]) -> u64 {
+        let mut buf = [0; 8];
+        buf[..bytes.len()].copy_from_slice(bytes);
+        u64::from_le_bytes(buf)
+    }
+
+    let (mut h1, mut h2) = (0u64, 0u64);
+    let mut blocks = data.chunks_exact(16);
+    for block in &mut blocks {
+        let k1 = word(&block[..8])
+            .wrapping_mul(C1)
+            .rotate_left(31)
+            .wrapping_mul(C2);
+        h1 ^= k1;
+        h1 = h1
+            .rotate_left(27)
+            .wrapping_add(h2)
+            .wrapping_mul(5)
+            .wrapping_add(0x52dc_e729);
+        let k2 = word(&block[8..])
+            .wrapping_mul(C2)
+            .rotate_left(33)
+            .wrapping_mul(C1);
+        h2 ^= k2;
+        h2 = h2
+            .rotate_left(31)
+            .wrapping_add(h1)
+            .wrapping_mul(5)
+            .wrapping_add(0x3849_5ab5);
+    }
+    let tail = blocks.remainder();
+    if tail.len() > 8 {
+        h2 ^= word(&tail[8..])
+            .wrapping_mul(C2)
+            .rotate_left(33)
+            .wrapping_mul(C1);
+    }
+    if !tail.is_empty() {
+        h1 ^= word(&tail[..tail.len().min(8)])
+            .wrapping_mul(C1)
+            .rotate_left(31)
+            .wrapping_mul(C2);
+    }
+    h1 ^= data.len() as u64;
+    h2 ^= data.len() as u64;
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+    fmix(h1).wrapping_add(fmix(h2))
+}
+
+// targets are relative and do not climb up, they may still lead to entries
+// not written yet
+fn check_symlink_target(target: &str) -> Result<(), Error> {
+    let downwards = Path::new(target)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    if target.is_empty() || !downwards {
+        return Err(error::invalid_data("invalid unixfs symlink target"));
+    }
+    Ok(())
+}
+
+// the file must not exist, so that a symlink in its place is not followed
+async fn write_file<T>(path: &Path, chunks: T) -> Result<(), Error>
+where
+    T: Stream<Item = Result<Vec<u8>, Error>>,
+{
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .await?;
+    let mut chunks = Box::pin(chunks);
+    while let Some(chunk) = chunks.next().await {
+        file.write_all(&chunk?).await?;
+    }
+    file.flush().await
+}
+
+#[cfg(unix)]
+async fn symlink(target: &str, path: &Path) -> Result<(), Error> {
+    async_std::os::unix::fs::symlink(target, path).await
+}
+
+#[cfg(not(unix))]
+async fn symlink(_target: &str, _path: &Path) -> Result<(), Error> {
+    Err(error::unsupported("symlinks are not supported"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cid::Multihash,
+        identity::PrivateKey,
+        io::{self, protobuf_encode},
+        net::{Manager, MemoryBlockstore},
+    };
+    use async_io::Async;
+    use std::{net::TcpListener, time::Duration};
+
+    fn put_raw(blockstore: &MemoryBlockstore, data: &[u8]) -> Cid {
+        let cid = Cid::new_v1(CODEC_RAW, Multihash::sha2_256(data));
+        blockstore.put(&cid, data).unwrap();
+        cid
+    }
+
+    fn put_node(blockstore: &MemoryBlockstore, links: Vec<(&str, Cid)>, data: Data) -> Cid {
+        let node = PBNode {
+            Links: links
+                .into_iter()
+                .map(|(name, cid)| PBLink {
+                    Hash: Some(cid.to_bytes()),
+                    Name: Some(name.to_string()),
+                    Tsize: Some(0),
+                })
+                .collect(),
+            Data: Some(protobuf_encode(&data).unwrap()),
+        };
+        let block = protobuf_encode(&node).unwrap();
+        let cid = Cid::new_v0(Multihash::sha2_256(&block)).unwrap();
+        blockstore.put(&cid, &block).unwrap();
+        cid
+    }
+
+    fn data(data_type: DataType, data: &[u8]) -> Data {
+        Data {
+            Type: data_type,
+            Data: (!data.is_empty()).then(|| data.to_vec()),
+            ..Default::default()
+        }
+    }
+
+    // a file of 10 chunks under two intermediate nodes, the first with
+    // inline data of its own
+    fn chunked_file(blockstore: &MemoryBlockstore) -> (Cid, Vec<u8>) {
+        let content: Vec<u8> = (0..10_000u32).map(|n| n as u8).collect();
+        let leaves: Vec<_> = content[100..]
+            .chunks(1000)
+            .map(|chunk| ("", put_raw(blockstore, chunk)))
+            .collect();
+        let first = put_node(
+            blockstore,
+            leaves[..5].to_vec(),
+            data(DataType::File, &content[..100]),
+        );
+        let second = put_node(blockstore, leaves[5..].to_vec(), data(DataType::File, &[]));
+        let root = put_node(
+            blockstore,
+            vec![("", first), ("", second)],
+            data(DataType::File, &[]),
+        );
+        (root, content)
+    }
+
+    #[async_std::test]
+    async fn test_unixfs_files() -> Result<(), Error> {
+        let blockstore = MemoryBlockstore::new();
+        let (root, content) = chunked_file(&blockstore);
+        let small = put_node(&blockstore, vec![], data(DataType::File, b"small"));
+        let dir = put_node(
+            &blockstore,
+            vec![("a", small.clone())],
+            data(DataType::Directory, &[]),
+        );
+        let unixfs = UnixFs::new(blockstore.clone());
+
+        let chunks: Vec<_> = unixfs.cat(&root).collect().await;
+        assert_eq!(chunks.len(), 11);
+        assert_eq!(unixfs.read_file(&root).await?, content);
+        assert_eq!(unixfs.read_file(&small).await?, b"small");
+        assert_eq!(unixfs.kind(&dir).await?, EntryKind::Directory);
+        assert!(unixfs.read_file(&dir).await.is_err());
+
+        // a block not matching its cid
+        let leaf = Cid::new_v1(CODEC_RAW, Multihash::sha2_256(b"leaf"));
+        blockstore.put(&leaf, b"not the leaf")?;
+        assert!(unixfs.read_file(&leaf).await.is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_unixfs_directories() -> Result<(), Error> {
+        let blockstore = MemoryBlockstore::new();
+        let (big, content) = chunked_file(&blockstore);
+        let leaf = put_raw(&blockstore, b"leaf");
+        let link = put_node(&blockstore, vec![], data(DataType::Symlink, b"big"));
+        let sub = put_node(
+            &blockstore,
+            vec![("c", leaf.clone())],
+            data(DataType::Directory, &[]),
+        );
+
+        // a shard of 256 buckets with a shard below it
+        let shard = |fanout| Data {
+            Type: DataType::HAMTShard,
+            hashType: Some(HAMT_HASH_MURMUR3),
+            fanout: Some(fanout),
+            ..Default::default()
+        };
+        let child = put_node(&blockstore, vec![("72d", leaf.clone())], shard(256));
+        let hamt = put_node(
+            &blockstore,
+            vec![("7Ab", big.clone()), ("CB", child)],
+            shard(256),
+        );
+
+        let root = put_node(
+            &blockstore,
+            vec![
+                ("big", big.clone()),
+                ("link", link),
+                ("sub", sub.clone()),
+                ("hamt", hamt),
+            ],
+            data(DataType::Directory, &[]),
+        );
+        let unixfs = UnixFs::new(blockstore.clone());
+        let names: Vec<_> = unixfs
+            .ls(&root)
+            .await?
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        assert_eq!(names, ["big", "link", "sub", "hamt"]);
+        assert_eq!(unixfs.resolve(&root, "/sub/c").await?, leaf);
+        assert_eq!(unixfs.resolve(&root, "hamt/d").await?, leaf);
+        assert_eq!(unixfs.resolve(&root, "hamt/b").await?, big);
+        assert!(unixfs.resolve(&root, "sub/d").await.is_err());
+        assert!(unixfs.resolve(&root, "hamt/c").await.is_err());
+
+        // only the bucket of the name is fetched, the other shard is missing
+        let missing = Cid::new_v0(Multihash::sha2_256(b"missing"))?;
+        let partial = put_node(
+            &blockstore,
+            vec![("7Ab", big.clone()), ("8E", missing)],
+            shard(256),
+        );
+        assert_eq!(unixfs.resolve(&partial, "b").await?, big);
+        assert!(unixfs.resolve(&partial, "c").await.is_err());
+
+        let path = std::env::temp_dir().join(format!("unixfs-{}", rand::random::<u64>()));
+        let res = async {
+            unixfs.get(&root, &path).await?;
+            assert_eq!(fs::read(path.join("big")).await?, content);
+            assert_eq!(fs::read(path.join("sub/c")).await?, b"leaf");
+            assert_eq!(fs::read(path.join("hamt/d")).await?, b"leaf");
+            #[cfg(unix)]
+            assert_eq!(fs::read(path.join("link")).await?, content);
+
+            // names leading out of the directory
+            let escape = put_node(
+                &blockstore,
+                vec![("..", sub)],
+                data(DataType::Directory, &[]),
+            );
+            assert!(unixfs.get(&escape, path.join("escape")).await.is_err());
+            Ok::<_, Error>(())
+        }
+        .await;
+        fs::remove_dir_all(&path).await?;
+        res
+    }
+
+    #[test]
+    fn test_murmur3() {
+        assert_eq!(murmur3_x64_64(b""), 0);
+        assert_eq!(murmur3_x64_64(b"foo"), 0xe271_8657_01f5_4561);
+        assert_eq!(murmur3_x64_64(b"hello"), 0xcbd8_a7b3_41bd_9b02);
+        assert_eq!(
+            murmur3_x64_64(b"a long name over sixteen bytes"),
+            0xc14b_3fde_ce80_1b70
+        );
+    }
+
+    #[async_std::test]
+    async fn test_unixfs_get_escape() -> Result<(), Error> {
+        let blockstore = MemoryBlockstore::new();
+        let base = std::env::temp_dir().join(format!("unixfs-{}", rand::random::<u64>()));
+        let victim = base.join("outside/victim.txt");
+        let link = put_node(
+            &blockstore,
+            vec![],
+            data(DataType::Symlink, victim.to_str().unwrap().as_bytes()),
+        );
+        let file = put_raw(&blockstore, b"pwned");
+        let unixfs = UnixFs::new(blockstore.clone());
+
+        let res = async {
+            fs::create_dir_all(base.join("outside")).await?;
+            fs::write(&victim, b"victim").await?;
+
+            // a symlink to the victim, then a file written through it
+            let crafted = put_node(
+                &blockstore,
+                vec![("x", link.clone()), ("x", file.clone())],
+                data(DataType::Directory, &[]),
+            );
+            assert!(unixfs.get(&crafted, base.join("crafted")).await.is_err());
+            assert!(fs::symlink_metadata(base.join("crafted/x")).await.is_err());
+
+            // symlinks leading out, alone
+            let absolute = put_node(
+                &blockstore,
+                vec![("x", link.clone())],
+                data(DataType::Directory, &[]),
+            );
+            assert!(unixfs.get(&absolute, base.join("absolute")).await.is_err());
+            let up = put_node(&blockstore, vec![], data(DataType::Symlink, b"a/../.."));
+            let up = put_node(&blockstore, vec![("x", up)], data(DataType::Directory, &[]));
+            assert!(unixfs.get(&up, base.join("up")).await.is_err());
+
+            // an existing file is not written over
+            assert!(unixfs.get(&file, &victim).await.is_err());
+            assert_eq!(fs::read(&victim).await?, b"victim");
+            Ok::<_, Error>(())
+        }
+        .await;
+        fs::remove_dir_all(&base).await?;
+        res
+    }
+
+    #[async_std::test]
+    async fn test_unixfs_bitswap() -> Result<(), Error> {
+        let blockstore = MemoryBlockstore::new();
+        let (root, content) = chunked_file(&blockstore);
+        let server = Bitswap::new().with_blockstore(blockstore);
+        let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0))?;
+        let addr = listener.get_ref().local_addr()?;
+        let manager = Manager::new(PrivateKey::generate_ed25519(), addr).with_protocol(server);
+        async_std::task::spawn(async move { manager.listen(listener).await });
+
+        let client = Bitswap::new();
+        let manager = Manager::new(
+            PrivateKey::generate_ed25519(),
+            "127.0.0.1:0".parse().unwrap(),
+        )
+        .with_protocol(client.clone());
+        client.add_peer(&manager.connect(addr).await?).await?;
+        let unixfs = UnixFs::new(client.session());
+        let file = io::timeout(Duration::from_secs(5), unixfs.read_file(&root)).await?;
+        assert_eq!(file, content);
+        Ok(())
+    }
+}